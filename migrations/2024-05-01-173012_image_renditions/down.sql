@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE image_renditions;
+
+ALTER TABLE images
+    DROP COLUMN width,
+    DROP COLUMN height;
\ No newline at end of file
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE images
+    ADD COLUMN width INT,
+    ADD COLUMN height INT;
+
+CREATE TABLE image_renditions (
+    id SERIAL PRIMARY KEY,
+    image_id INT NOT NULL references images(id),
+    kind VARCHAR(32) NOT NULL,
+    format VARCHAR(16) NOT NULL,
+    url VARCHAR(255) NOT NULL,
+    width INT NOT NULL,
+    height INT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
\ No newline at end of file
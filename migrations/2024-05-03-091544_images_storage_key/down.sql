@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE images RENAME COLUMN storage_key TO url;
+ALTER TABLE image_renditions RENAME COLUMN storage_key TO url;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE images RENAME COLUMN url TO storage_key;
+ALTER TABLE image_renditions RENAME COLUMN url TO storage_key;
\ No newline at end of file
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX items_images_one_primary_per_item;
+
+ALTER TABLE items_images
+    DROP CONSTRAINT items_images_item_id_image_id_key,
+    DROP COLUMN position,
+    DROP COLUMN is_primary,
+    DROP COLUMN alt_text;
\ No newline at end of file
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+ALTER TABLE items_images
+    ADD COLUMN position INT NOT NULL DEFAULT 0,
+    ADD COLUMN is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN alt_text VARCHAR(255);
+
+-- Existing galleries keep their upload order and get their first image as primary
+UPDATE items_images SET position = ordered.position
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY item_id ORDER BY id) - 1 AS position
+    FROM items_images
+) AS ordered
+WHERE items_images.id = ordered.id;
+
+UPDATE items_images SET is_primary = TRUE WHERE position = 0;
+
+ALTER TABLE items_images ADD CONSTRAINT items_images_item_id_image_id_key UNIQUE (item_id, image_id);
+CREATE UNIQUE INDEX items_images_one_primary_per_item ON items_images (item_id) WHERE is_primary;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE item_slug_redirects;
+
+ALTER TABLE items DROP COLUMN slug;
\ No newline at end of file
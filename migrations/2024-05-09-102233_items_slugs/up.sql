@@ -0,0 +1,26 @@
+-- Your SQL goes here
+ALTER TABLE items ADD COLUMN slug VARCHAR(255);
+
+-- Same rules as `slugify` in models.rs, duplicates get the item id appended. Slugs are cut at 240
+-- characters like there, so that `<slug>-<id>` fits in the column.
+WITH slugs AS (
+    SELECT id, COALESCE(NULLIF(TRIM(TRAILING '-' FROM LEFT(TRIM(LEADING '-' FROM LOWER(REGEXP_REPLACE(name, '[^a-zA-Z0-9]+', '-', 'g'))), 240)), ''), 'item') AS base
+    FROM items
+), ranked AS (
+    SELECT id, base, ROW_NUMBER() OVER (PARTITION BY base ORDER BY id) AS rank
+    FROM slugs
+)
+UPDATE items SET slug = CASE WHEN ranked.rank = 1 THEN ranked.base ELSE ranked.base || '-' || items.id END
+FROM ranked
+WHERE items.id = ranked.id;
+
+ALTER TABLE items ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE items ADD CONSTRAINT items_slug_key UNIQUE (slug);
+
+-- Former slugs of renamed items, so that old links keep working
+CREATE TABLE item_slug_redirects (
+    id SERIAL PRIMARY KEY,
+    item_id INT NOT NULL references items(id) ON DELETE CASCADE,
+    slug VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE items ADD COLUMN sku VARCHAR(64) UNIQUE;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE scheduled_prices;
+
+DROP TABLE price_history;
+
+ALTER TABLE items DROP COLUMN was_price;
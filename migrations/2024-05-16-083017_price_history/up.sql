@@ -0,0 +1,33 @@
+-- Your SQL goes here
+-- Set while a scheduled sale is running, the price the item had before it
+ALTER TABLE items ADD COLUMN was_price DECIMAL(10, 2);
+
+CREATE TABLE price_history (
+  id SERIAL PRIMARY KEY,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  previous_price DECIMAL(10, 2),
+  price DECIMAL(10, 2) NOT NULL,
+  source VARCHAR(32) NOT NULL,
+  changed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX price_history_item_id_changed_at ON price_history (item_id, changed_at);
+
+-- Current prices become the first entry of every item's history
+INSERT INTO price_history (item_id, price, source, changed_at)
+SELECT id, price, 'created', COALESCE(created_at, CURRENT_TIMESTAMP) FROM items;
+
+CREATE TABLE scheduled_prices (
+  id SERIAL PRIMARY KEY,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  price DECIMAL(10, 2) NOT NULL,
+  starts_at TIMESTAMP NOT NULL,
+  ends_at TIMESTAMP CHECK (ends_at > starts_at),
+  -- Filled in when the price is applied, restored at `ends_at`
+  previous_price DECIMAL(10, 2),
+  started_at TIMESTAMP,
+  ended_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX scheduled_prices_pending ON scheduled_prices (starts_at) WHERE ended_at IS NULL;
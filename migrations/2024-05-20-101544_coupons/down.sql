@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE coupon_redemptions;
+
+DROP TABLE coupons_items;
+
+DROP TABLE coupons;
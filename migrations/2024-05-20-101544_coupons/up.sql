@@ -0,0 +1,36 @@
+-- Your SQL goes here
+CREATE TABLE coupons (
+  id SERIAL PRIMARY KEY,
+  -- Stored upper case, customers may type it in any case
+  code VARCHAR(64) NOT NULL UNIQUE,
+  kind VARCHAR(32) NOT NULL CHECK (kind IN ('percentage', 'fixed_amount', 'free_shipping')),
+  -- Percent off for `percentage`, amount off for `fixed_amount`, unused for `free_shipping`
+  value DECIMAL(10, 2) NOT NULL DEFAULT 0 CHECK (value >= 0 AND (kind <> 'percentage' OR value <= 100)),
+  starts_at TIMESTAMP,
+  ends_at TIMESTAMP CHECK (ends_at > starts_at),
+  min_subtotal DECIMAL(10, 2),
+  max_redemptions INT CHECK (max_redemptions > 0),
+  max_redemptions_per_user INT CHECK (max_redemptions_per_user > 0),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- The items a coupon is limited to, a coupon without any applies to every item
+CREATE TABLE coupons_items (
+  id SERIAL PRIMARY KEY,
+  coupon_id INT NOT NULL REFERENCES coupons(id) ON DELETE CASCADE,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  UNIQUE (coupon_id, item_id)
+);
+
+-- Redeemed coupons cannot be deleted, the discounts granted stay accountable
+CREATE TABLE coupon_redemptions (
+  id SERIAL PRIMARY KEY,
+  coupon_id INT NOT NULL REFERENCES coupons(id),
+  user_id INT REFERENCES users(id) ON DELETE SET NULL,
+  order_reference VARCHAR(64) NOT NULL,
+  discount DECIMAL(10, 2) NOT NULL,
+  redeemed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (coupon_id, order_reference)
+);
+
+CREATE INDEX coupon_redemptions_coupon_id_user_id ON coupon_redemptions (coupon_id, user_id);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE tax_rates;
+
+ALTER TABLE items DROP COLUMN tax_class;
+
+DROP TABLE tax_classes;
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+CREATE TABLE tax_classes (
+  id SERIAL PRIMARY KEY,
+  code VARCHAR(32) NOT NULL UNIQUE,
+  name VARCHAR(64) NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO tax_classes (code, name) VALUES ('standard', 'Standard rate');
+
+ALTER TABLE items ADD COLUMN tax_class VARCHAR(32) NOT NULL DEFAULT 'standard'
+  REFERENCES tax_classes(code) ON UPDATE CASCADE;
+
+-- Countries are ISO 3166-1 alpha-2 codes. An empty region applies to the whole country,
+-- a region's own rate takes precedence.
+CREATE TABLE tax_rates (
+  id SERIAL PRIMARY KEY,
+  tax_class VARCHAR(32) NOT NULL REFERENCES tax_classes(code) ON UPDATE CASCADE ON DELETE CASCADE,
+  country CHAR(2) NOT NULL,
+  region VARCHAR(64) NOT NULL DEFAULT '',
+  name VARCHAR(64) NOT NULL,
+  -- Percent, e.g. 20 for 20%
+  rate DECIMAL(7, 4) NOT NULL CHECK (rate >= 0),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (tax_class, country, region)
+);
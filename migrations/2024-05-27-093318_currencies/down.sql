@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE item_prices;
+
+DROP TABLE exchange_rates;
+
+DROP TYPE money_amount;
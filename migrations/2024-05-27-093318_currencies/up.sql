@@ -0,0 +1,25 @@
+-- Your SQL goes here
+-- An amount and its ISO 4217 currency code, see `money::Money`. The code is TEXT rather than
+-- CHAR(3) since binary records must carry the exact field types.
+CREATE TYPE money_amount AS (
+  amount NUMERIC,
+  currency TEXT
+);
+
+-- How many units of a currency one unit of the base currency (`base_currency`) buys.
+-- Only the base currency and the currencies listed here can be selected.
+CREATE TABLE exchange_rates (
+  currency CHAR(3) PRIMARY KEY,
+  rate NUMERIC(18, 8) NOT NULL CHECK (rate > 0),
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Prices set by hand in other currencies, they take precedence over converting `items.price`
+CREATE TABLE item_prices (
+  id SERIAL PRIMARY KEY,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  price money_amount NOT NULL CHECK ((price).amount >= 0),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX item_prices_item_id_currency ON item_prices (item_id, ((price).currency));
@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE shipping_rates;
+
+DROP TABLE shipping_method_countries;
+
+DROP TABLE shipping_methods;
+
+DROP TABLE addresses;
+
+ALTER TABLE items
+  DROP COLUMN weight_grams,
+  DROP COLUMN length_mm,
+  DROP COLUMN width_mm,
+  DROP COLUMN height_mm;
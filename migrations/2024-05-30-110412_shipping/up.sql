@@ -0,0 +1,58 @@
+-- Your SQL goes here
+-- Needed by weight-based shipping, items without a weight cannot be shipped by weight
+ALTER TABLE items
+  ADD COLUMN weight_grams INT CHECK (weight_grams >= 0),
+  ADD COLUMN length_mm INT CHECK (length_mm > 0),
+  ADD COLUMN width_mm INT CHECK (width_mm > 0),
+  ADD COLUMN height_mm INT CHECK (height_mm > 0);
+
+-- Where customers have their orders shipped. Countries are ISO 3166-1 alpha-2 codes, stored upper case.
+CREATE TABLE addresses (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name VARCHAR(128) NOT NULL,
+  line1 VARCHAR(255) NOT NULL,
+  line2 VARCHAR(255),
+  city VARCHAR(128) NOT NULL,
+  region VARCHAR(64),
+  postal_code VARCHAR(16),
+  country CHAR(2) NOT NULL,
+  phone VARCHAR(32),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX addresses_user_id ON addresses (user_id);
+
+CREATE TABLE shipping_methods (
+  id SERIAL PRIMARY KEY,
+  code VARCHAR(32) NOT NULL UNIQUE,
+  name VARCHAR(128) NOT NULL,
+  -- What the rates are looked up by: nothing, the parcel's weight or the cart's subtotal
+  kind VARCHAR(16) NOT NULL CHECK (kind IN ('flat', 'weight', 'price')),
+  -- Charged on every shipment, on top of the rate for `weight` and `price` methods
+  price DECIMAL(10, 2) NOT NULL DEFAULT 0 CHECK (price >= 0),
+  -- Subtotal from which the method is free
+  free_above DECIMAL(10, 2) CHECK (free_above >= 0),
+  -- Cubic centimetres per kilogram; when set, bulky items weigh their volume divided by it
+  volumetric_divisor INT CHECK (volumetric_divisor > 0),
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- The countries a method ships to, a method without any ships everywhere
+CREATE TABLE shipping_method_countries (
+  id SERIAL PRIMARY KEY,
+  shipping_method_id INT NOT NULL REFERENCES shipping_methods(id) ON DELETE CASCADE,
+  country CHAR(2) NOT NULL,
+  UNIQUE (shipping_method_id, country)
+);
+
+-- Brackets of a `weight` or `price` method: the rate with the highest `min_value` not above the
+-- parcel's weight in grams, or the cart's subtotal, applies. Below the first bracket the method is unavailable.
+CREATE TABLE shipping_rates (
+  id SERIAL PRIMARY KEY,
+  shipping_method_id INT NOT NULL REFERENCES shipping_methods(id) ON DELETE CASCADE,
+  min_value DECIMAL(12, 2) NOT NULL CHECK (min_value >= 0),
+  price DECIMAL(10, 2) NOT NULL CHECK (price >= 0),
+  UNIQUE (shipping_method_id, min_value)
+);
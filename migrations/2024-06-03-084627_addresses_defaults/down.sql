@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX addresses_default_billing;
+DROP INDEX addresses_default_shipping;
+
+ALTER TABLE addresses
+  DROP COLUMN is_default_shipping,
+  DROP COLUMN is_default_billing;
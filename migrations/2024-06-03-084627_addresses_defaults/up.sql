@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE addresses
+  ADD COLUMN is_default_shipping BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN is_default_billing BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- A user has at most one default address of each kind
+CREATE UNIQUE INDEX addresses_default_shipping ON addresses (user_id) WHERE is_default_shipping;
+CREATE UNIQUE INDEX addresses_default_billing ON addresses (user_id) WHERE is_default_billing;
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE refunds;
+
+DROP TABLE return_lines;
+
+DROP TABLE returns;
+
+DROP TABLE inventory_adjustments;
@@ -0,0 +1,57 @@
+-- Your SQL goes here
+-- Every change of `items.quantity` made outside of item updates, with the stock it left
+CREATE TABLE inventory_adjustments (
+  id SERIAL PRIMARY KEY,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  delta INT NOT NULL CHECK (delta <> 0),
+  quantity INT NOT NULL,
+  reason VARCHAR(16) NOT NULL CHECK (reason IN ('receipt', 'return', 'correction')),
+  -- What caused it, e.g. a supplier delivery note or `RMA-12`
+  reference VARCHAR(64),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX inventory_adjustments_item_id ON inventory_adjustments (item_id);
+
+-- Return merchandise authorizations. Orders are referred to by number, like coupon redemptions.
+CREATE TABLE returns (
+  id SERIAL PRIMARY KEY,
+  user_id INT REFERENCES users(id) ON DELETE SET NULL,
+  order_reference VARCHAR(64) NOT NULL,
+  status VARCHAR(16) NOT NULL DEFAULT 'requested'
+    CHECK (status IN ('requested', 'approved', 'rejected', 'received', 'refunded')),
+  -- Set on approval: the most that can be refunded, in the currency the order was paid in
+  refundable money_amount CHECK ((refundable).amount >= 0),
+  -- From the merchant to the customer, e.g. why the return was rejected
+  note TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX returns_user_id ON returns (user_id);
+
+CREATE TABLE return_lines (
+  id SERIAL PRIMARY KEY,
+  return_id INT NOT NULL REFERENCES returns(id) ON DELETE CASCADE,
+  -- Kept when the item is deleted, the return stays accountable
+  item_id INT REFERENCES items(id) ON DELETE SET NULL,
+  quantity INT NOT NULL CHECK (quantity > 0),
+  reason VARCHAR(32) NOT NULL
+    CHECK (reason IN ('damaged', 'defective', 'wrong_item', 'not_as_described', 'no_longer_needed', 'other')),
+  comment TEXT,
+  received_quantity INT NOT NULL DEFAULT 0 CHECK (received_quantity BETWEEN 0 AND quantity),
+  restocked_quantity INT NOT NULL DEFAULT 0 CHECK (restocked_quantity BETWEEN 0 AND received_quantity),
+  UNIQUE (return_id, item_id)
+);
+
+-- Refunds of a return, several of them for partial refunds. They cannot be deleted once recorded.
+CREATE TABLE refunds (
+  id SERIAL PRIMARY KEY,
+  return_id INT NOT NULL REFERENCES returns(id),
+  amount money_amount NOT NULL CHECK ((amount).amount > 0),
+  status VARCHAR(16) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'issued')),
+  -- The payment provider's id of the refund, once issued
+  provider_reference VARCHAR(128),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  issued_at TIMESTAMP
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE invoices;
+DROP TABLE invoice_sequences;
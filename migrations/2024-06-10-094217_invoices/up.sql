@@ -0,0 +1,26 @@
+-- Your SQL goes here
+-- The last invoice number given out each year. Numbers are taken by updating the year's row in the
+-- transaction that records the invoice, so a failed invoice gives its number back and none are skipped.
+CREATE TABLE invoice_sequences (
+  year INT PRIMARY KEY,
+  last_number INT NOT NULL
+);
+
+-- One invoice per order, its rendered documents are kept in the image store
+CREATE TABLE invoices (
+  id SERIAL PRIMARY KEY,
+  -- `<year>-<sequence>`, e.g. `2024-000042`
+  number VARCHAR(32) NOT NULL UNIQUE,
+  year INT NOT NULL,
+  sequence INT NOT NULL CHECK (sequence > 0),
+  user_id INT REFERENCES users(id) ON DELETE SET NULL,
+  order_reference VARCHAR(64) NOT NULL UNIQUE,
+  total money_amount NOT NULL,
+  tax money_amount NOT NULL,
+  pdf_key VARCHAR(255) NOT NULL,
+  html_key VARCHAR(255) NOT NULL,
+  issued_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (year, sequence)
+);
+
+CREATE INDEX invoices_user_id ON invoices (user_id);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE items
+  DROP COLUMN rating_count,
+  DROP COLUMN rating_sum;
+
+DROP TABLE reviews;
+DROP TABLE invoice_items;
@@ -0,0 +1,36 @@
+-- Your SQL goes here
+-- Items an invoice billed, what makes a review a verified purchase
+CREATE TABLE invoice_items (
+  invoice_id INT NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  PRIMARY KEY (invoice_id, item_id)
+);
+
+CREATE INDEX invoice_items_item_id ON invoice_items (item_id);
+
+-- One review per customer and item, shown once a moderator approved it
+CREATE TABLE reviews (
+  id SERIAL PRIMARY KEY,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  -- Reviews of deleted users stay, without their author
+  user_id INT REFERENCES users(id) ON DELETE SET NULL,
+  rating INT NOT NULL CHECK (rating BETWEEN 1 AND 5),
+  title VARCHAR(128),
+  body TEXT,
+  status VARCHAR(16) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+  -- The author was invoiced for the item when writing the review
+  verified_purchase BOOLEAN NOT NULL DEFAULT false,
+  -- From the moderator to the author, e.g. why the review was rejected
+  moderation_note TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (item_id, user_id)
+);
+
+CREATE INDEX reviews_item_id_status ON reviews (item_id, status, created_at);
+CREATE INDEX reviews_status ON reviews (status, created_at);
+
+-- Approved reviews only, kept up to date with them so listings do not aggregate reviews
+ALTER TABLE items
+  ADD COLUMN rating_count INT NOT NULL DEFAULT 0 CHECK (rating_count >= 0),
+  ADD COLUMN rating_sum INT NOT NULL DEFAULT 0 CHECK (rating_sum >= 0);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE notifications;
+DROP TABLE wishlist_items;
+DROP TABLE wishlists;
@@ -0,0 +1,39 @@
+-- Your SQL goes here
+-- Named lists of items a customer keeps for later. A list is public to whoever has its share token.
+CREATE TABLE wishlists (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name VARCHAR(64) NOT NULL,
+  share_token VARCHAR(64) UNIQUE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (user_id, name)
+);
+
+CREATE TABLE wishlist_items (
+  wishlist_id INT NOT NULL REFERENCES wishlists(id) ON DELETE CASCADE,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  quantity INT NOT NULL DEFAULT 1 CHECK (quantity > 0),
+  -- The item's price when it was added, to show how much it dropped since
+  added_price NUMERIC NOT NULL,
+  added_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (wishlist_id, item_id)
+);
+
+CREATE INDEX wishlist_items_item_id ON wishlist_items (item_id);
+
+-- Outbox of messages to customers, written in the transaction of the change they are about and
+-- delivered by whatever sends them, e.g. a mailer draining the undelivered ones
+CREATE TABLE notifications (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  kind VARCHAR(32) NOT NULL CHECK (kind IN ('price_drop', 'back_in_stock')),
+  -- The prices before and after a price drop
+  previous_price NUMERIC,
+  price NUMERIC,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at TIMESTAMP
+);
+
+CREATE INDEX notifications_user_id ON notifications (user_id, created_at);
+CREATE INDEX notifications_undelivered ON notifications (id) WHERE delivered_at IS NULL;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- Customers waiting for an out-of-stock item. The restock that notifies them removes their
+-- subscriptions in its transaction, so each restock notifies a subscriber once.
+CREATE TABLE stock_subscriptions (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  item_id INT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (item_id, user_id)
+);
+
+CREATE INDEX stock_subscriptions_user_id ON stock_subscriptions (user_id);
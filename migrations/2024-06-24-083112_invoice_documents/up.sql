@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- What the invoice says, so that its documents can be rendered again when storing them failed. Invoices
+-- issued before this column have none.
+ALTER TABLE invoices ADD COLUMN document JSONB;
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM invoice_items WHERE item_id IS NULL;
+ALTER TABLE invoice_items DROP CONSTRAINT invoice_items_invoice_id_item_id_key;
+ALTER TABLE invoice_items
+  DROP CONSTRAINT invoice_items_item_id_fkey,
+  ADD CONSTRAINT invoice_items_item_id_fkey FOREIGN KEY (item_id) REFERENCES items(id) ON DELETE CASCADE;
+ALTER TABLE invoice_items ALTER COLUMN item_id SET NOT NULL;
+ALTER TABLE invoice_items DROP COLUMN id;
+ALTER TABLE invoice_items ADD PRIMARY KEY (invoice_id, item_id);
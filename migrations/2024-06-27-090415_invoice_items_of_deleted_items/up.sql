@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- Invoices are financial records: deleting an item keeps the lines that billed it, without their item
+ALTER TABLE invoice_items DROP CONSTRAINT invoice_items_pkey;
+ALTER TABLE invoice_items ADD COLUMN id SERIAL PRIMARY KEY;
+ALTER TABLE invoice_items ALTER COLUMN item_id DROP NOT NULL;
+ALTER TABLE invoice_items
+  DROP CONSTRAINT invoice_items_item_id_fkey,
+  ADD CONSTRAINT invoice_items_item_id_fkey FOREIGN KEY (item_id) REFERENCES items(id) ON DELETE SET NULL;
+ALTER TABLE invoice_items ADD CONSTRAINT invoice_items_invoice_id_item_id_key UNIQUE (invoice_id, item_id);
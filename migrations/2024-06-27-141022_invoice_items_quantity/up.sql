@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- How many of the item the invoice billed, the most that can be returned. Taken from the kept document for
+-- invoices issued before; it stays NULL for those without one, and their items cannot be returned.
+ALTER TABLE invoice_items ADD COLUMN quantity INT CHECK (quantity > 0);
+
+UPDATE invoice_items
+SET quantity = billed.quantity
+FROM (
+  SELECT invoices.id AS invoice_id, (line->>'item_id')::INT AS item_id, SUM((line->>'quantity')::INT) AS quantity
+  FROM invoices, jsonb_array_elements(invoices.document->'lines') AS line
+  WHERE line->>'item_id' IS NOT NULL
+  GROUP BY 1, 2
+) AS billed
+WHERE invoice_items.invoice_id = billed.invoice_id AND invoice_items.item_id = billed.item_id;
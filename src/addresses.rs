@@ -0,0 +1,269 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Address, NewAddress};
+
+// Postal code formats by country: `9` stands for a digit, `A` for a letter, anything else for itself.
+// Codes are matched without their spaces and dashes and written back in the format they matched.
+const POSTAL_CODE_FORMATS: [(&str, &[&str]); 20] = [
+  ("AT", &["9999"]),
+  ("AU", &["9999"]),
+  ("BE", &["9999"]),
+  ("BR", &["99999-999"]),
+  ("CA", &["A9A 9A9"]),
+  ("CH", &["9999"]),
+  ("DE", &["99999"]),
+  ("DK", &["9999"]),
+  ("ES", &["99999"]),
+  ("FR", &["99999"]),
+  ("GB", &["A9 9AA", "A99 9AA", "A9A 9AA", "AA9 9AA", "AA99 9AA", "AA9A 9AA"]),
+  ("IN", &["999999"]),
+  ("IT", &["99999"]),
+  ("JP", &["999-9999"]),
+  ("NL", &["9999 AA"]),
+  ("NO", &["9999"]),
+  ("PL", &["99-999"]),
+  ("PT", &["9999-999"]),
+  ("SE", &["999 99"]),
+  ("US", &["99999", "99999-9999"]),
+];
+
+// Countries without postal codes, an address there must not have one
+const WITHOUT_POSTAL_CODE: [&str; 5] = ["AE", "AO", "BS", "HK", "QA"];
+
+// Countries where carriers need the state or province
+const WITH_REGION: [&str; 3] = ["AU", "CA", "US"];
+
+// Column lengths of the `addresses` table
+const MAX_NAME_LENGTH: usize = 128;
+const MAX_LINE_LENGTH: usize = 255;
+const MAX_CITY_LENGTH: usize = 128;
+const MAX_REGION_LENGTH: usize = 64;
+const MAX_POSTAL_CODE_LENGTH: usize = 16;
+const MAX_PHONE_LENGTH: usize = 32;
+
+fn is_separator(c: char) -> bool {
+  c == ' ' || c == '-'
+}
+
+// `code` written in `format`, if it matches it
+fn format_postal_code(code: &str, format: &str) -> Option<String> {
+  let mut chars = code.chars().filter(|c| !is_separator(*c));
+  let mut formatted = String::with_capacity(format.len());
+  for expected in format.chars() {
+    if is_separator(expected) {
+      formatted.push(expected);
+      continue;
+    }
+    let c = chars.next()?;
+    let matches = match expected {
+      '9' => c.is_ascii_digit(),
+      'A' => c.is_ascii_alphabetic(),
+      _ => c == expected,
+    };
+    if !matches {
+      return None;
+    }
+    formatted.push(c);
+  }
+  if chars.next().is_some() { None } else { Some(formatted) }
+}
+
+fn postal_code(country: &str, code: Option<String>) -> Result<Option<String>, String> {
+  let code = code.map(|code| code.trim().to_uppercase()).filter(|code| !code.is_empty());
+  if WITHOUT_POSTAL_CODE.contains(&country) {
+    return match code {
+      Some(_) => Err(format!("addresses in {} have no postal code", country)),
+      None => Ok(None),
+    };
+  }
+  let code = code.ok_or_else(|| "postal_code is required".to_string())?;
+  match POSTAL_CODE_FORMATS.iter().find(|(other, _)| *other == country) {
+    Some((_, formats)) => formats.iter()
+      .find_map(|format| format_postal_code(&code, format))
+      .map(Some)
+      .ok_or_else(|| format!("`{}` is not a postal code of {}, expected {}", code, country, formats.join(" or "))),
+    // Other countries get a loose check, carriers know their formats better than we do
+    None if code.len() <= MAX_POSTAL_CODE_LENGTH && code.chars().all(|c| c.is_ascii_alphanumeric() || is_separator(c)) => Ok(Some(code)),
+    None => Err(format!("`{}` is not a postal code", code)),
+  }
+}
+
+fn required(field: &str, value: String, max_length: usize) -> Result<String, String> {
+  let value = value.trim().to_string();
+  if value.is_empty() {
+    return Err(format!("{} must not be empty", field));
+  }
+  if value.chars().count() > max_length {
+    return Err(format!("{} is longer than {} characters", field, max_length));
+  }
+  Ok(value)
+}
+
+fn optional(field: &str, value: Option<String>, max_length: usize) -> Result<Option<String>, String> {
+  value.map(|value| value.trim().to_string())
+    .filter(|value| !value.is_empty())
+    .map(|value| required(field, value, max_length))
+    .transpose()
+}
+
+// Trims and checks the fields of an address, normalizing the country, region and postal code
+pub fn validate(address: NewAddress) -> Result<NewAddress, String> {
+  let country = address.country.trim().to_uppercase();
+  if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+    return Err("country must be an ISO 3166-1 alpha-2 code".to_string());
+  }
+  let region = optional("region", address.region, MAX_REGION_LENGTH)?.map(|region| region.to_uppercase());
+  if region.is_none() && WITH_REGION.contains(&country.as_str()) {
+    return Err(format!("region is required in {}", country));
+  }
+  let phone = optional("phone", address.phone, MAX_PHONE_LENGTH)?;
+  if let Some(phone) = &phone {
+    let valid = phone.chars().all(|c| c.is_ascii_digit() || " +-().".contains(c))
+      && phone.chars().filter(|c| c.is_ascii_digit()).count() >= 6;
+    if !valid {
+      return Err("phone must be a phone number".to_string());
+    }
+  }
+
+  Ok(NewAddress {
+    name: required("name", address.name, MAX_NAME_LENGTH)?,
+    line1: required("line1", address.line1, MAX_LINE_LENGTH)?,
+    line2: optional("line2", address.line2, MAX_LINE_LENGTH)?,
+    city: required("city", address.city, MAX_CITY_LENGTH)?,
+    postal_code: postal_code(&country, address.postal_code)?,
+    region,
+    country,
+    phone,
+    ..address
+  })
+}
+
+// An address as it was when an order was placed. Orders keep this copy rather than the address id,
+// so that editing or deleting the address later does not rewrite where past orders went.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct AddressSnapshot {
+  pub name: String,
+  pub line1: String,
+  pub line2: Option<String>,
+  pub city: String,
+  pub region: Option<String>,
+  pub postal_code: Option<String>,
+  pub country: String,
+  pub phone: Option<String>,
+}
+
+impl From<&Address> for AddressSnapshot {
+  fn from(address: &Address) -> Self {
+    AddressSnapshot {
+      name: address.name.clone(),
+      line1: address.line1.clone(),
+      line2: address.line2.clone(),
+      city: address.city.clone(),
+      region: address.region.clone(),
+      postal_code: address.postal_code.clone(),
+      country: address.country.clone(),
+      phone: address.phone.clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn address(country: &str, region: Option<&str>, postal_code: Option<&str>) -> NewAddress {
+    NewAddress {
+      user_id: 1,
+      name: " Ada Lovelace ".to_string(),
+      line1: "1 Main Street".to_string(),
+      line2: Some(" ".to_string()),
+      city: "Springfield".to_string(),
+      region: region.map(str::to_string),
+      postal_code: postal_code.map(str::to_string),
+      country: country.to_string(),
+      phone: None,
+      is_default_shipping: false,
+      is_default_billing: false,
+    }
+  }
+
+  fn code(country: &str, code: &str) -> Result<Option<String>, String> {
+    postal_code(country, Some(code.to_string()))
+  }
+
+  #[test]
+  fn codes_are_written_in_the_format_they_match() {
+    assert_eq!(format_postal_code("SW1A1AA", "AA9A 9AA").as_deref(), Some("SW1A 1AA"));
+    assert_eq!(format_postal_code("9999-AA", "9999 AA").as_deref(), Some("9999 AA"));
+    assert_eq!(format_postal_code("12345", "99999-9999"), None);
+    assert_eq!(format_postal_code("123456", "99999"), None);
+    assert_eq!(format_postal_code("1234A", "99999"), None);
+  }
+
+  #[test]
+  fn codes_are_normalized_by_country() {
+    assert_eq!(code("GB", "sw1a1aa").unwrap().as_deref(), Some("SW1A 1AA"));
+    assert_eq!(code("GB", " m1 1ae ").unwrap().as_deref(), Some("M1 1AE"));
+    assert_eq!(code("NL", "9999aa").unwrap().as_deref(), Some("9999 AA"));
+    assert_eq!(code("NL", "9999 AA").unwrap().as_deref(), Some("9999 AA"));
+    assert_eq!(code("US", "12345").unwrap().as_deref(), Some("12345"));
+    assert_eq!(code("US", "12345 6789").unwrap().as_deref(), Some("12345-6789"));
+    assert_eq!(code("CA", "k1a0b1").unwrap().as_deref(), Some("K1A 0B1"));
+  }
+
+  #[test]
+  fn codes_of_the_wrong_shape_are_refused() {
+    assert_eq!(code("CA", "1KA 0B1").unwrap_err(), "`1KA 0B1` is not a postal code of CA, expected A9A 9A9");
+    assert!(code("CA", "K1A 0BB").is_err());
+    assert!(code("US", "1234").is_err());
+    assert!(code("US", "12345-678").is_err());
+    assert!(code("NL", "999 AA").is_err());
+  }
+
+  #[test]
+  fn other_countries_get_a_loose_check() {
+    assert_eq!(code("ZA", " 0001 ").unwrap().as_deref(), Some("0001"));
+    assert_eq!(code("ZA", "0001/2").unwrap_err(), "`0001/2` is not a postal code");
+    assert!(code("ZA", &"1".repeat(17)).is_err());
+    assert_eq!(postal_code("ZA", Some(" ".to_string())).unwrap_err(), "postal_code is required");
+  }
+
+  #[test]
+  fn some_countries_have_no_postal_codes() {
+    assert_eq!(postal_code("HK", None), Ok(None));
+    assert_eq!(postal_code("HK", Some(" ".to_string())), Ok(None));
+    assert_eq!(code("HK", "999077").unwrap_err(), "addresses in HK have no postal code");
+  }
+
+  #[test]
+  fn addresses_are_trimmed_and_normalized() {
+    let valid = validate(address(" gb ", None, Some("sw1a1aa"))).unwrap();
+    assert_eq!(valid.country, "GB");
+    assert_eq!(valid.postal_code.as_deref(), Some("SW1A 1AA"));
+    assert_eq!(valid.name, "Ada Lovelace");
+    assert_eq!(valid.line2, None);
+    assert_eq!(valid.region, None);
+  }
+
+  #[test]
+  fn some_countries_require_a_region() {
+    assert_eq!(validate(address("US", None, Some("12345"))).err().unwrap(), "region is required in US");
+    assert_eq!(validate(address("CA", Some(" "), Some("K1A 0B1"))).err().unwrap(), "region is required in CA");
+    assert_eq!(validate(address("US", Some("ny"), Some("12345"))).unwrap().region.as_deref(), Some("NY"));
+  }
+
+  #[test]
+  fn invalid_fields_say_why() {
+    assert_eq!(validate(address("GBR", None, None)).err().unwrap(), "country must be an ISO 3166-1 alpha-2 code");
+    let mut unnamed = address("HK", None, None);
+    unnamed.name = " ".to_string();
+    assert_eq!(validate(unnamed).err().unwrap(), "name must not be empty");
+    let mut phone = address("HK", None, None);
+    phone.phone = Some("call me".to_string());
+    assert_eq!(validate(phone).err().unwrap(), "phone must be a phone number");
+    let mut phone = address("HK", None, None);
+    phone.phone = Some("+852 2123 4567".to_string());
+    assert!(validate(phone).is_ok());
+  }
+}
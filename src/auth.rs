@@ -6,7 +6,7 @@ use rand::distributions::Alphanumeric;
 
 use crate::models::User;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, schemars::JsonSchema)]
 pub struct Credentials {
   pub username: String,
   pub password: String,
@@ -38,4 +38,14 @@ pub fn hash_password(password: &str) -> Result<String, Error> {
   let argon2 = Argon2::default();
   let password_hash = argon2.hash_password(password.as_bytes(), &salt)?;
   Ok(password_hash.to_string())
-}
\ No newline at end of file
+}
+
+// Redis key under which the id of the user owning a session token is stored
+pub fn session_key(session_id: &str) -> String {
+  format!("sessions/{}", session_id)
+}
+
+// Session tokens are 128 alphanumeric characters, see `authorize_user`
+pub fn is_well_formed_token(token: &str) -> bool {
+  token.len() == 128 && token.chars().all(|c| c.is_ascii_alphanumeric())
+}
@@ -0,0 +1,350 @@
+extern crate diesel_eshop_db;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, IsTerminal};
+use std::process::{self, Stdio};
+
+use clap::{Parser, Subcommand};
+use diesel::{Connection, OptionalExtension, PgConnection};
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use rocket::figment::Figment;
+use rocket_db_pools::deadpool_redis::redis::{self, Commands};
+
+use diesel_eshop_db::auth::{hash_password, session_key};
+use diesel_eshop_db::image_gc::collect_garbage;
+use diesel_eshop_db::catalog::{self, CatalogFormat};
+use diesel_eshop_db::models::{NewUser, Role, RoleCode, User};
+use diesel_eshop_db::repository::{InvoiceRepository, RoleRepository, UserRepository};
+use diesel_eshop_db::seed::{seed, SeedConfig, FAKE_USER_PASSWORD};
+use diesel_eshop_db::storage::{ImageStoreConfig, StoreError};
+
+// Operational tasks against the same database, cache and image store as the server.
+// Connections are read from DATABASE_URL and REDIS_URL, or from the server's Rocket configuration.
+#[derive(Parser)]
+#[command(name = "admin", about = "Administration tasks for the eshop")]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Create a user, optionally with the admin role
+  CreateUser {
+    #[arg(long)]
+    username: String,
+    #[arg(long)]
+    email: String,
+    /// Read the password from stdin, a random one is generated and printed otherwise
+    #[arg(long)]
+    password_stdin: bool,
+    #[arg(long)]
+    admin: bool,
+  },
+  /// Give a role (admin or user) to a user
+  AssignRole {
+    #[arg(long)]
+    username: String,
+    #[arg(long)]
+    role: String,
+  },
+  /// Take a role (admin or user) away from a user
+  RevokeRole {
+    #[arg(long)]
+    username: String,
+    #[arg(long)]
+    role: String,
+  },
+  /// Create or update items from a CSV or JSON catalog, matched by sku or name
+  ImportItems {
+    file: String,
+    /// csv or json, guessed from the file extension by default
+    #[arg(long)]
+    format: Option<CatalogFormat>,
+  },
+  /// Write the catalog with stock and image URLs to a file or stdout
+  ExportItems {
+    #[arg(long, default_value = "csv")]
+    format: CatalogFormat,
+    #[arg(long)]
+    output: Option<String>,
+  },
+  /// Set a new password and end the user's sessions
+  ResetPassword {
+    #[arg(long)]
+    username: String,
+    /// Read the password from stdin, a random one is generated and printed otherwise
+    #[arg(long)]
+    password_stdin: bool,
+  },
+  /// List the active sessions with their user and remaining lifetime
+  ListSessions,
+  /// Delete stored image files no image refers to
+  PurgeImages {
+    /// Only report what would be deleted
+    #[arg(long)]
+    dry_run: bool,
+  },
+  /// Move invoice documents issued before invoices had their own store out of the image store
+  MoveInvoices,
+  /// Insert the roles, an admin account and fake data; safe to run repeatedly
+  Seed {
+    #[arg(long, default_value = "admin")]
+    admin_username: String,
+    #[arg(long, default_value = "admin@example.com")]
+    admin_email: String,
+    /// Password of the admin account when it gets created, a random one is generated and printed when omitted
+    #[arg(long)]
+    admin_password: Option<String>,
+    #[arg(long, default_value_t = 50)]
+    items: usize,
+    #[arg(long, default_value_t = 1)]
+    images_per_item: usize,
+    #[arg(long, default_value_t = 10)]
+    users: usize,
+  },
+}
+
+fn config_value(figment: &Figment, env: &str, key: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+  match std::env::var(env) {
+    Ok(value) => Ok(value),
+    Err(_) => figment.extract_inner::<String>(key)
+      .map_err(|_| format!("Set {} or `{}` in Rocket.toml", env, key).into()),
+  }
+}
+
+fn parse_role(role: &str) -> Result<RoleCode, Box<dyn Error + Send + Sync>> {
+  role.parse().map_err(|_| format!("Unknown role `{}`, expected admin or user", role).into())
+}
+
+fn find_role(c: &mut PgConnection, code: RoleCode) -> Result<Role, Box<dyn Error + Send + Sync>> {
+  RoleRepository::find_by_code(c, code.clone()).optional()?
+    .ok_or_else(|| format!("Role `{}` is missing from the roles table", code).into())
+}
+
+fn find_user(c: &mut PgConnection, username: &str) -> Result<User, Box<dyn Error + Send + Sync>> {
+  UserRepository::find_by_username(c, username).optional()?
+    .ok_or_else(|| format!("No user named `{}`", username).into())
+}
+
+fn random_password() -> String {
+  rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(20)
+    .map(char::from)
+    .collect()
+}
+
+fn set_echo(on: bool) {
+  let _ = process::Command::new("stty").arg(if on { "echo" } else { "-echo" }).stdin(Stdio::inherit()).status();
+}
+
+// A password read from the first line of stdin, prompted for without echo on a terminal. Passwords are
+// not taken as arguments, which end up in the shell history and the process list.
+fn read_password() -> Result<String, Box<dyn Error + Send + Sync>> {
+  let terminal = io::stdin().is_terminal();
+  if terminal {
+    eprint!("Password: ");
+    set_echo(false);
+  }
+  let mut line = String::new();
+  let read = io::stdin().lock().read_line(&mut line);
+  if terminal {
+    set_echo(true);
+    eprintln!();
+  }
+  read?;
+  let password = line.trim_end_matches(['\r', '\n']);
+  if password.is_empty() {
+    return Err("The password must not be empty".into());
+  }
+  Ok(password.to_string())
+}
+
+// The password to set and whether it was generated
+fn new_password(password_stdin: bool) -> Result<(String, bool), Box<dyn Error + Send + Sync>> {
+  match password_stdin {
+    true => Ok((read_password()?, false)),
+    false => Ok((random_password(), true)),
+  }
+}
+
+// Deletes the sessions of the user, returns how many there were
+fn end_sessions(cache: &mut redis::Connection, user_id: i32) -> redis::RedisResult<usize> {
+  let keys: Vec<String> = cache.scan_match(session_key("*"))?.collect();
+  let mut ended = 0;
+  for key in keys {
+    if cache.get::<_, Option<i32>>(&key)? == Some(user_id) {
+      cache.del::<_, ()>(&key)?;
+      ended += 1;
+    }
+  }
+  Ok(ended)
+}
+
+fn redis_connection(figment: &Figment) -> Result<redis::Connection, Box<dyn Error + Send + Sync>> {
+  let redis_url = config_value(figment, "REDIS_URL", "databases.redis.url")?;
+  Ok(redis::Client::open(redis_url)?.get_connection()?)
+}
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+  dotenvy::dotenv().ok();
+  let cli = Cli::parse();
+  let figment = rocket::Config::figment();
+
+  let database_url = config_value(&figment, "DATABASE_URL", "databases.postgres.url")?;
+  let c = &mut PgConnection::establish(&database_url)?;
+
+  match cli.command {
+    Command::CreateUser { username, email, password_stdin, admin } => {
+      let (password, generated) = new_password(password_stdin)?;
+      let user = c.transaction(|c| {
+        let user = UserRepository::create(c, NewUser {
+          username,
+          email,
+          password: hash_password(&password).map_err(|e| e.to_string())?,
+        })?;
+        let mut codes = vec![RoleCode::User];
+        if admin {
+          codes.push(RoleCode::Admin);
+        }
+        for code in codes {
+          let role = find_role(c, code)?;
+          UserRepository::assign_role(c, &user, &role)?;
+        }
+        Ok::<_, Box<dyn Error + Send + Sync>>(user)
+      })?;
+      match generated {
+        true => println!("Created user {} with id {} and the password {}", user.username, user.id, password),
+        false => println!("Created user {} with id {}", user.username, user.id),
+      }
+    },
+    Command::AssignRole { username, role } => {
+      let user = find_user(c, &username)?;
+      let role = find_role(c, parse_role(&role)?)?;
+      match UserRepository::assign_role(c, &user, &role)? {
+        0 => println!("{} already has the {} role", user.username, role.code),
+        _ => println!("Assigned the {} role to {}", role.code, user.username),
+      }
+    },
+    Command::RevokeRole { username, role } => {
+      let user = find_user(c, &username)?;
+      let role = find_role(c, parse_role(&role)?)?;
+      match UserRepository::revoke_role(c, &user, &role)? {
+        0 => println!("{} does not have the {} role", user.username, role.code),
+        _ => println!("Revoked the {} role from {}", role.code, user.username),
+      }
+    },
+    Command::ImportItems { file, format } => {
+      let format = format.or_else(|| CatalogFormat::from_path(&file))
+        .ok_or_else(|| format!("Cannot tell the format of {}, pass --format", file))?;
+      let report = catalog::import(c, format, File::open(&file)?);
+      for error in &report.errors {
+        eprintln!("row {}: {}", error.row, error.message);
+      }
+      println!("Created {} and updated {} items from {}, {} rows failed", report.created, report.updated, file, report.errors.len());
+    },
+    Command::ExportItems { format, output } => {
+      let store = ImageStoreConfig::from_figment(&figment)?.build()?;
+      match output {
+        Some(path) => catalog::export(c, store.as_ref(), format, BufWriter::new(File::create(path)?))?,
+        None => catalog::export(c, store.as_ref(), format, io::stdout().lock())?,
+      }
+    },
+    Command::ResetPassword { username, password_stdin } => {
+      let mut user = find_user(c, &username)?;
+      let (password, generated) = new_password(password_stdin)?;
+      user.password = hash_password(&password).map_err(|e| e.to_string())?;
+      let user = UserRepository::update(c, user.id, user)?;
+      match generated {
+        true => println!("New password of {}: {}", user.username, password),
+        false => println!("Password of {} updated", user.username),
+      }
+      // Whoever knew the old password may still be logged in
+      let ended = redis_connection(&figment)
+        .and_then(|mut cache| Ok(end_sessions(&mut cache, user.id)?))
+        .map_err(|e| format!("The password was changed but the sessions of {} could not be ended: {}", user.username, e))?;
+      println!("Ended {} sessions", ended);
+    },
+    Command::ListSessions => {
+      let mut cache = redis_connection(&figment)?;
+      let keys: Vec<String> = cache.scan_match(session_key("*"))?.collect();
+
+      let mut sessions = Vec::with_capacity(keys.len());
+      for key in keys {
+        // Sessions may expire between the scan and the read
+        if let Some(user_id) = cache.get::<_, Option<i32>>(&key)? {
+          let ttl: i64 = cache.ttl(&key)?;
+          sessions.push((key, user_id, ttl));
+        }
+      }
+
+      let users = UserRepository::find_by_ids(c, sessions.iter().map(|(_, user_id, _)| *user_id).collect())?;
+      for (key, user_id, ttl) in &sessions {
+        let username = users.iter().find(|u| u.id == *user_id).map_or("<deleted>", |u| u.username.as_str());
+        // Only show the start of the token, the full token grants access
+        let token = key.trim_start_matches(&session_key(""));
+        println!("{}...  user {} ({})  expires in {}s", &token[..token.len().min(8)], username, user_id, ttl);
+      }
+      println!("{} active sessions", sessions.len());
+    },
+    Command::PurgeImages { dry_run } => {
+      let store = ImageStoreConfig::from_figment(&figment)?.build()?;
+      let report = collect_garbage(c, store.as_ref(), !dry_run)?;
+      for key in &report.orphaned_files {
+        println!("orphaned file: {}", key);
+      }
+      for id in &report.images_without_files {
+        println!("image {} has no file", id);
+      }
+      for id in &report.renditions_without_files {
+        println!("rendition {} has no file", id);
+      }
+      match dry_run {
+        true => println!("{} orphaned files would be deleted", report.orphaned_files.len()),
+        false => println!("Deleted {} orphaned files", report.purged_files),
+      }
+    },
+    Command::MoveInvoices => {
+      let images = ImageStoreConfig::from_figment(&figment)?.build()?;
+      let invoices = ImageStoreConfig::invoices_from_figment(&figment)?.build()?;
+      let mut moved = 0;
+      for invoice in InvoiceRepository::find_all(c)? {
+        for (key, content_type) in [(&invoice.pdf_key, "application/pdf"), (&invoice.html_key, "text/html; charset=utf-8")] {
+          let bytes = match images.get(key) {
+            Ok(bytes) => bytes,
+            Err(StoreError::NotFound(_)) => continue,
+            Err(e) => return Err(e.into()),
+          };
+          invoices.put(key, &bytes, content_type)?;
+          images.delete(key)?;
+          moved += 1;
+        }
+      }
+      println!("Moved {} invoice documents out of the image store", moved);
+    },
+    Command::Seed { admin_username, admin_email, admin_password, items, images_per_item, users } => {
+      let store = ImageStoreConfig::from_figment(&figment)?.build()?;
+      let generated = admin_password.is_none();
+      let admin_password = admin_password.unwrap_or_else(random_password);
+      let config = SeedConfig { admin_username, admin_email, admin_password, items, images_per_item, users };
+      let report = seed(c, store.as_ref(), &config)?;
+      println!(
+        "Created {} roles, {} items, {} images and {} users",
+        report.roles, report.items, report.images, report.users,
+      );
+      match (report.admin_created, generated) {
+        (true, true) => println!("Created admin account {} with the password {}", config.admin_username, config.admin_password),
+        (true, false) => println!("Created admin account {}", config.admin_username),
+        (false, _) => (),
+      }
+      if report.users > 0 {
+        println!("Fake users log in with the password `{}`", FAKE_USER_PASSWORD);
+      }
+    },
+  }
+
+  Ok(())
+}
@@ -1,14 +1,14 @@
 extern crate diesel_eshop_db;
 
-use rocket_db_pools::Database;
-
 #[rocket::main]
 async fn main() {
-  let _ = rocket::build()
-  .mount("/", rocket::routes![
-    diesel_eshop_db::rocket_routes::items::get_items
-    ])
-    .attach(diesel_eshop_db::rocket_routes::DbConn::fairing())
-    .attach(diesel_eshop_db::rocket_routes::CacheConn::init())
-    .launch();
-}
\ No newline at end of file
+  let launched = diesel_eshop_db::rocket_routes::build_rocket(rocket::Config::figment())
+    .launch()
+    .await;
+
+  if let Err(e) = launched {
+    // Logs the reason the server failed to ignite or launch
+    log::error!("{}", e.pretty_print());
+    std::process::exit(1);
+  }
+}
@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufReader, Read, Write};
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use diesel::{Connection, PgConnection, QueryResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde_json::{json, Value};
+
+use crate::models::{Image, Item, ItemsImage, NewItem};
+use crate::repository::{CopiedItems, ItemRepository, ItemsImageRepository};
+use crate::storage::ImageStore;
+
+// Rows written to the database per transaction; a failing batch reports an error for each of its rows
+pub const BATCH_SIZE: usize = 1000;
+
+const MAX_NAME_LENGTH: usize = 255;
+const MAX_SKU_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CatalogFormat {
+  Csv,
+  Json,
+}
+
+impl CatalogFormat {
+  pub fn from_path(path: &str) -> Option<Self> {
+    path.rsplit_once('.').and_then(|(_, extension)| extension.to_lowercase().parse().ok())
+  }
+
+  pub fn mime_type(&self) -> &'static str {
+    match self {
+      CatalogFormat::Csv => "text/csv",
+      CatalogFormat::Json => "application/json",
+    }
+  }
+}
+
+impl FromStr for CatalogFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "csv" => Ok(CatalogFormat::Csv),
+      "json" => Ok(CatalogFormat::Json),
+      _ => Err(format!("Unknown catalog format `{}`, expected csv or json", s)),
+    }
+  }
+}
+
+impl fmt::Display for CatalogFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CatalogFormat::Csv => f.write_str("csv"),
+      CatalogFormat::Json => f.write_str("json"),
+    }
+  }
+}
+
+// One item of an imported catalog. Items are matched by SKU, or by name when the row has none.
+#[derive(Deserialize, JsonSchema)]
+pub struct CatalogRow {
+  #[serde(default)]
+  pub sku: Option<String>,
+  pub name: String,
+  #[serde(default)]
+  pub description: Option<String>,
+  #[schemars(with = "String")]
+  #[serde(deserialize_with = "deserialize_price")]
+  pub price: BigDecimal,
+  pub quantity: i32,
+  #[serde(default)]
+  pub tax_class: Option<String>,
+  #[serde(default)]
+  pub weight_grams: Option<i32>,
+  #[serde(default)]
+  pub length_mm: Option<i32>,
+  #[serde(default)]
+  pub width_mm: Option<i32>,
+  #[serde(default)]
+  pub height_mm: Option<i32>,
+}
+
+// CSV fields that look like numbers, and JSON numbers, arrive as floats; going through their shortest
+// decimal representation keeps `9.99` from becoming `9.9900000000000002131628…`
+fn deserialize_price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigDecimal, D::Error> {
+  struct PriceVisitor;
+
+  impl Visitor<'_> for PriceVisitor {
+    type Value = BigDecimal;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      f.write_str("a decimal price")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<BigDecimal, E> {
+      value.trim().parse().map_err(|_| E::custom(format!("invalid price `{}`", value)))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<BigDecimal, E> {
+      Ok(BigDecimal::from(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<BigDecimal, E> {
+      Ok(BigDecimal::from(value))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<BigDecimal, E> {
+      self.visit_str(&value.to_string())
+    }
+  }
+
+  deserializer.deserialize_any(PriceVisitor)
+}
+
+impl CatalogRow {
+  fn validate(self) -> Result<NewItem, String> {
+    let name = self.name.trim().to_string();
+    let sku = self.sku.map(|sku| sku.trim().to_string()).filter(|sku| !sku.is_empty());
+    let description = self.description.filter(|description| !description.trim().is_empty());
+
+    if name.is_empty() {
+      return Err("name is empty".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+      return Err(format!("name is longer than {} characters", MAX_NAME_LENGTH));
+    }
+    if sku.as_ref().is_some_and(|sku| sku.chars().count() > MAX_SKU_LENGTH) {
+      return Err(format!("sku is longer than {} characters", MAX_SKU_LENGTH));
+    }
+    // Prices are stored as DECIMAL(10, 2)
+    if self.price < BigDecimal::from(0) || self.price >= BigDecimal::from(100_000_000) {
+      return Err("price must be between 0 and 99999999.99".to_string());
+    }
+    if self.price.with_scale(2) != self.price {
+      return Err("price has more than 2 decimals".to_string());
+    }
+    if self.quantity < 0 {
+      return Err("quantity is negative".to_string());
+    }
+    if self.weight_grams.is_some_and(|weight| weight < 0) {
+      return Err("weight_grams is negative".to_string());
+    }
+    if [self.length_mm, self.width_mm, self.height_mm].iter().flatten().any(|dimension| *dimension <= 0) {
+      return Err("dimensions must be positive".to_string());
+    }
+
+    Ok(NewItem {
+      name,
+      description,
+      price: self.price.with_scale(2),
+      quantity: self.quantity,
+      sku,
+      tax_class: self.tax_class.map(|tax_class| tax_class.trim().to_string()).filter(|tax_class| !tax_class.is_empty()),
+      weight_grams: self.weight_grams,
+      length_mm: self.length_mm,
+      width_mm: self.width_mm,
+      height_mm: self.height_mm,
+    })
+  }
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct RowError {
+  // Position of the item in the file, starting at 1 with the first item after the CSV header
+  pub row: usize,
+  pub message: String,
+}
+
+#[derive(Serialize, JsonSchema, Default, Debug)]
+pub struct ImportReport {
+  pub created: usize,
+  pub updated: usize,
+  pub errors: Vec<RowError>,
+}
+
+// Collects valid rows and writes them in batches: new items through `COPY`, existing ones one by one
+// so that renames keep their slug redirects
+struct Importer<'a> {
+  c: &'a mut PgConnection,
+  batch: Vec<(usize, NewItem)>,
+  rows: usize,
+  report: ImportReport,
+}
+
+impl<'a> Importer<'a> {
+  fn new(c: &'a mut PgConnection) -> Self {
+    Importer { c, batch: Vec::with_capacity(BATCH_SIZE), rows: 0, report: ImportReport::default() }
+  }
+
+  fn push(&mut self, parsed: Result<CatalogRow, String>) {
+    self.rows += 1;
+    let row = self.rows;
+    let new_item = match parsed.and_then(CatalogRow::validate) {
+      Ok(new_item) => new_item,
+      Err(message) => return self.report.errors.push(RowError { row, message }),
+    };
+    // A repeated item must see the outcome of its first occurrence, so it goes to the next batch
+    let repeated = self.batch.iter().any(|(_, other)| match (&other.sku, &new_item.sku) {
+      (Some(a), Some(b)) => a == b,
+      (None, None) => other.name == new_item.name,
+      _ => false,
+    });
+    if repeated || self.batch.len() >= BATCH_SIZE {
+      self.flush();
+    }
+    self.batch.push((row, new_item));
+  }
+
+  fn flush(&mut self) {
+    if self.batch.is_empty() {
+      return;
+    }
+    let batch = std::mem::take(&mut self.batch);
+    let rows: Vec<usize> = batch.iter().map(|(row, _)| *row).collect();
+    let mut row_errors = Vec::new();
+
+    match self.c.transaction(|c| write_batch(c, batch, &mut row_errors)) {
+      Ok((created, updated)) => {
+        self.report.created += created;
+        self.report.updated += updated;
+        self.report.errors.append(&mut row_errors);
+      },
+      Err(e) => self.report.errors.extend(rows.into_iter().map(|row| RowError {
+        row,
+        message: format!("not imported, its batch failed: {}", e),
+      })),
+    }
+  }
+
+  fn finish(mut self) -> ImportReport {
+    self.flush();
+    self.report.errors.sort_by_key(|error| error.row);
+    self.report
+  }
+}
+
+fn write_batch(c: &mut PgConnection, batch: Vec<(usize, NewItem)>, row_errors: &mut Vec<RowError>) -> QueryResult<(usize, usize)> {
+  let skus: Vec<&str> = batch.iter().filter_map(|(_, new_item)| new_item.sku.as_deref()).collect();
+  let names: Vec<&str> = batch.iter()
+    .filter(|(_, new_item)| new_item.sku.is_none())
+    .map(|(_, new_item)| new_item.name.as_str())
+    .collect();
+
+  let by_sku: HashMap<String, Item> = ItemRepository::find_by_skus(c, skus)?
+    .into_iter()
+    .filter_map(|item| Some((item.sku.clone()?, item)))
+    .collect();
+  let mut by_name: HashMap<String, Vec<Item>> = HashMap::new();
+  for item in ItemRepository::find_by_names(c, names)? {
+    by_name.entry(item.name.clone()).or_default().push(item);
+  }
+
+  let mut new_items = Vec::new();
+  let mut updated = 0;
+  for (row, new_item) in batch {
+    let existing = match &new_item.sku {
+      Some(sku) => by_sku.get(sku),
+      None => match by_name.get(&new_item.name).map(Vec::as_slice) {
+        Some([item]) => Some(item),
+        Some(_) => {
+          row_errors.push(RowError { row, message: format!("several items are named `{}`, give it a sku", new_item.name) });
+          continue;
+        },
+        None => None,
+      },
+    };
+    match existing {
+      Some(item) => {
+        ItemRepository::update(c, item.id, Item {
+          id: item.id,
+          name: new_item.name,
+          description: new_item.description,
+          price: new_item.price,
+          created_at: item.created_at,
+          quantity: new_item.quantity,
+          slug: item.slug.clone(),
+          // Rows without a sku keep the one the item has
+          sku: new_item.sku.or_else(|| item.sku.clone()),
+          was_price: item.was_price.clone(),
+          tax_class: new_item.tax_class.unwrap_or_else(|| item.tax_class.clone()),
+          // Like the sku, empty fields keep what the item has
+          weight_grams: new_item.weight_grams.or(item.weight_grams),
+          length_mm: new_item.length_mm.or(item.length_mm),
+          width_mm: new_item.width_mm.or(item.width_mm),
+          height_mm: new_item.height_mm.or(item.height_mm),
+          rating_count: item.rating_count,
+          rating_sum: item.rating_sum,
+        })?;
+        updated += 1;
+      },
+      None => new_items.push(new_item),
+    }
+  }
+
+  let created = ItemRepository::create_many(c, new_items)?;
+  Ok((created, updated))
+}
+
+// Imports a CSV catalog with a header row naming the `CatalogRow` columns; other columns are ignored
+pub fn import_csv(c: &mut PgConnection, reader: impl Read) -> ImportReport {
+  let mut importer = Importer::new(c);
+  let mut csv = csv::ReaderBuilder::new().trim(csv::Trim::Headers).from_reader(reader);
+  for record in csv.deserialize::<CatalogRow>() {
+    importer.push(record.map_err(|e| e.to_string()));
+  }
+  importer.finish()
+}
+
+// Reads the items of a JSON array one at a time, so that an invalid item only fails its own row
+struct RowVisitor<'i, 'c>(&'i mut Importer<'c>);
+
+impl<'de> Visitor<'de> for RowVisitor<'_, '_> {
+  type Value = ();
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("an array of items")
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+    while let Some(value) = seq.next_element::<Value>()? {
+      self.0.push(serde_json::from_value(value).map_err(|e| e.to_string()));
+    }
+    Ok(())
+  }
+}
+
+// Imports a JSON array of `CatalogRow`. A syntax error stops the import; rows read before it are kept.
+pub fn import_json(c: &mut PgConnection, reader: impl Read) -> ImportReport {
+  let mut importer = Importer::new(c);
+  let mut json = serde_json::Deserializer::from_reader(BufReader::new(reader));
+  let result = json.deserialize_seq(RowVisitor(&mut importer)).and_then(|_| json.end());
+  if let Err(e) = result {
+    let row = importer.rows + 1;
+    importer.report.errors.push(RowError { row, message: format!("invalid JSON: {}", e) });
+  }
+  importer.finish()
+}
+
+pub fn import(c: &mut PgConnection, format: CatalogFormat, reader: impl Read) -> ImportReport {
+  match format {
+    CatalogFormat::Csv => import_csv(c, reader),
+    CatalogFormat::Json => import_json(c, reader),
+  }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+  Database(diesel::result::Error),
+  Write(std::io::Error),
+  Encode(String),
+}
+
+impl fmt::Display for ExportError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ExportError::Database(e) => write!(f, "Database error: {}", e),
+      ExportError::Write(e) => write!(f, "Write error: {}", e),
+      ExportError::Encode(e) => write!(f, "Encoding error: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<diesel::result::Error> for ExportError {
+  fn from(e: diesel::result::Error) -> Self {
+    ExportError::Database(e)
+  }
+}
+
+impl From<std::io::Error> for ExportError {
+  fn from(e: std::io::Error) -> Self {
+    ExportError::Write(e)
+  }
+}
+
+impl From<csv::Error> for ExportError {
+  fn from(e: csv::Error) -> Self {
+    ExportError::Encode(e.to_string())
+  }
+}
+
+impl From<serde_json::Error> for ExportError {
+  fn from(e: serde_json::Error) -> Self {
+    ExportError::Encode(e.to_string())
+  }
+}
+
+// Serializes an item with its gallery embedded in display order, as the API and JSON exports show it
+pub fn item_json(item: &Item, gallery: &[(ItemsImage, Image)], store: &dyn ImageStore) -> Value {
+  let mut value = json!(item);
+  value["rating_average"] = json!(item.rating_average());
+  value["images"] = gallery.iter()
+    .map(|(entry, image)| json!({
+      "id": image.id,
+      "url": image.url(store),
+      "width": image.width,
+      "height": image.height,
+      "alt_text": entry.alt_text,
+      "position": entry.position,
+      "is_primary": entry.is_primary,
+    }))
+    .collect();
+  value
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+  id: i32,
+  sku: Option<&'a str>,
+  name: &'a str,
+  description: Option<&'a str>,
+  price: String,
+  quantity: i32,
+  tax_class: &'a str,
+  weight_grams: Option<i32>,
+  length_mm: Option<i32>,
+  width_mm: Option<i32>,
+  height_mm: Option<i32>,
+  slug: &'a str,
+  // Image URLs in gallery order, separated by spaces
+  images: String,
+}
+
+fn galleries(c: &mut PgConnection) -> QueryResult<HashMap<i32, Vec<(ItemsImage, Image)>>> {
+  let mut galleries: HashMap<i32, Vec<(ItemsImage, Image)>> = HashMap::new();
+  for (entry, image) in ItemsImageRepository::find_all(c)? {
+    galleries.entry(entry.item_id).or_default().push((entry, image));
+  }
+  Ok(galleries)
+}
+
+// The items and galleries an export writes, read up front so that writing them needs no connection
+pub struct CatalogSnapshot {
+  galleries: HashMap<i32, Vec<(ItemsImage, Image)>>,
+  items: CopiedItems,
+}
+
+pub fn snapshot(c: &mut PgConnection) -> QueryResult<CatalogSnapshot> {
+  Ok(CatalogSnapshot { galleries: galleries(c)?, items: ItemRepository::copy_all(c)? })
+}
+
+impl CatalogSnapshot {
+  // Writes every item with its stock and images; the CSV can be imported back as is
+  pub fn write(&self, store: &dyn ImageStore, format: CatalogFormat, writer: impl Write) -> Result<(), ExportError> {
+    let galleries = &self.galleries;
+    let items = self.items.items();
+
+    match format {
+      CatalogFormat::Csv => {
+        let mut csv = csv::Writer::from_writer(writer);
+        for item in items {
+          let item = item?;
+          let gallery = galleries.get(&item.id).map(Vec::as_slice).unwrap_or_default();
+          csv.serialize(CsvRow {
+            id: item.id,
+            sku: item.sku.as_deref(),
+            name: &item.name,
+            description: item.description.as_deref(),
+            price: item.price.to_string(),
+            quantity: item.quantity,
+            tax_class: &item.tax_class,
+            weight_grams: item.weight_grams,
+            length_mm: item.length_mm,
+            width_mm: item.width_mm,
+            height_mm: item.height_mm,
+            slug: &item.slug,
+            images: gallery.iter().map(|(_, image)| image.url(store)).collect::<Vec<_>>().join(" "),
+          })?;
+        }
+        csv.flush()?;
+      },
+      CatalogFormat::Json => {
+        let mut writer = writer;
+        writer.write_all(b"[")?;
+        for (index, item) in items.enumerate() {
+          let item = item?;
+          let gallery = galleries.get(&item.id).map(Vec::as_slice).unwrap_or_default();
+          if index > 0 {
+            writer.write_all(b",")?;
+          }
+          writer.write_all(b"\n")?;
+          serde_json::to_writer(&mut writer, &item_json(&item, gallery, store))?;
+        }
+        writer.write_all(b"\n]\n")?;
+        writer.flush()?;
+      },
+    }
+    Ok(())
+  }
+}
+
+// Reads the catalog and writes it out, holding the connection throughout; `snapshot` lets it go earlier
+pub fn export(c: &mut PgConnection, store: &dyn ImageStore, format: CatalogFormat, writer: impl Write) -> Result<(), ExportError> {
+  snapshot(c)?.write(store, format, writer)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn row(json: Value) -> Result<CatalogRow, String> {
+    serde_json::from_value(json).map_err(|e| e.to_string())
+  }
+
+  fn validated(json: Value) -> Result<NewItem, String> {
+    row(json).and_then(CatalogRow::validate)
+  }
+
+  #[test]
+  fn prices_keep_their_decimals() {
+    let price = |price: Value| row(json!({ "name": "Mug", "price": price, "quantity": 1 })).map(|row| row.price.to_string());
+    assert_eq!(price(json!(9.99)).unwrap(), "9.99");
+    assert_eq!(price(json!(0.1)).unwrap(), "0.1");
+    assert_eq!(price(json!(12)).unwrap(), "12");
+    assert_eq!(price(json!(" 4.50 ")).unwrap(), "4.50");
+    assert!(price(json!("4,50")).unwrap_err().contains("invalid price `4,50`"));
+    assert!(price(json!(true)).is_err());
+  }
+
+  #[test]
+  fn csv_prices_keep_their_decimals() {
+    let csv = "name,price,quantity\nMug,19.99,3\n";
+    let rows: Vec<CatalogRow> = csv::Reader::from_reader(csv.as_bytes()).deserialize().collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows[0].price.to_string(), "19.99");
+  }
+
+  #[test]
+  fn valid_rows_are_trimmed() {
+    let new_item = validated(json!({
+      "sku": " MUG-1 ", "name": " Mug ", "description": "  ", "price": "4.5", "quantity": 0, "tax_class": " ",
+    })).unwrap();
+    assert_eq!(new_item.name, "Mug");
+    assert_eq!(new_item.sku.as_deref(), Some("MUG-1"));
+    assert_eq!(new_item.description, None);
+    assert_eq!(new_item.tax_class, None);
+    assert_eq!(new_item.price.to_string(), "4.50");
+  }
+
+  #[test]
+  fn invalid_rows_say_why() {
+    let error = |json: Value| validated(json).err().unwrap();
+    assert_eq!(error(json!({ "name": " ", "price": 1, "quantity": 1 })), "name is empty");
+    assert_eq!(error(json!({ "name": "é".repeat(256), "price": 1, "quantity": 1 })), "name is longer than 255 characters");
+    assert_eq!(error(json!({ "sku": "s".repeat(65), "name": "Mug", "price": 1, "quantity": 1 })), "sku is longer than 64 characters");
+    assert_eq!(error(json!({ "name": "Mug", "price": -1, "quantity": 1 })), "price must be between 0 and 99999999.99");
+    assert_eq!(error(json!({ "name": "Mug", "price": "100000000", "quantity": 1 })), "price must be between 0 and 99999999.99");
+    assert_eq!(error(json!({ "name": "Mug", "price": "1.005", "quantity": 1 })), "price has more than 2 decimals");
+    assert_eq!(error(json!({ "name": "Mug", "price": 1, "quantity": -1 })), "quantity is negative");
+    assert_eq!(error(json!({ "name": "Mug", "price": 1, "quantity": 1, "weight_grams": -1 })), "weight_grams is negative");
+    assert_eq!(error(json!({ "name": "Mug", "price": 1, "quantity": 1, "width_mm": 0 })), "dimensions must be positive");
+  }
+
+  #[test]
+  fn limits_are_inclusive() {
+    assert!(validated(json!({ "name": "é".repeat(255), "price": "99999999.99", "quantity": 0, "weight_grams": 0 })).is_ok());
+  }
+}
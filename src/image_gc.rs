@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::Utc;
+use diesel::PgConnection;
+use rocket::fairing::AdHoc;
+use rocket::figment::{self, Figment};
+use rocket::tokio::time;
+use serde::Serialize;
+
+use crate::repository::{ImageError, ImageRenditionRepository, ImageRepository, InvoiceRepository};
+use crate::rocket_routes::DbConn;
+use crate::storage::{ImageStore, SharedImageStore};
+
+// Files younger than this may belong to an upload whose rows are not committed yet
+const GRACE_PERIOD_MINUTES: i64 = 60;
+
+const DEFAULT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Default, Debug)]
+pub struct GarbageReport {
+  // Stored files no image, rendition or invoice refers to
+  pub orphaned_files: Vec<String>,
+  // Ids of images whose original file is missing from the store
+  pub images_without_files: Vec<i32>,
+  // Ids of renditions whose file is missing from the store
+  pub renditions_without_files: Vec<i32>,
+  pub purged_files: usize,
+}
+
+// Compares the store with the images, renditions and invoices tables. With `purge`, orphaned files are deleted;
+// rows without files are only reported since they usually point at a misconfigured store.
+pub fn collect_garbage(c: &mut PgConnection, store: &dyn ImageStore, purge: bool) -> Result<GarbageReport, ImageError> {
+  let objects = store.list()?;
+  let images = ImageRepository::find_all_storage_keys(c)?;
+  let renditions = ImageRenditionRepository::find_all_storage_keys(c)?;
+  // Invoices issued before they had their own store keep their documents here until `admin move-invoices`
+  let invoices = InvoiceRepository::find_all_storage_keys(c)?;
+
+  let stored_keys: HashSet<&str> = objects.iter().map(|o| o.key.as_str()).collect();
+  let known_keys: HashSet<&str> = images.iter()
+    .chain(renditions.iter())
+    .map(|(_, key)| key.as_str())
+    .chain(invoices.iter().map(String::as_str))
+    .collect();
+  let cutoff = Utc::now() - chrono::Duration::minutes(GRACE_PERIOD_MINUTES);
+
+  let mut report = GarbageReport {
+    orphaned_files: objects.iter()
+      .filter(|o| !known_keys.contains(o.key.as_str()))
+      .filter(|o| o.last_modified.is_none_or(|modified| modified < cutoff))
+      .map(|o| o.key.clone())
+      .collect(),
+    images_without_files: images.iter()
+      .filter(|(_, key)| !stored_keys.contains(key.as_str()))
+      .map(|(id, _)| *id)
+      .collect(),
+    renditions_without_files: renditions.iter()
+      .filter(|(_, key)| !stored_keys.contains(key.as_str()))
+      .map(|(id, _)| *id)
+      .collect(),
+    purged_files: 0,
+  };
+
+  if purge {
+    for key in &report.orphaned_files {
+      match store.delete(key) {
+        Ok(()) => report.purged_files += 1,
+        Err(e) => log::error!("Failed to purge orphaned file {}: {}", key, e),
+      }
+    }
+  }
+
+  Ok(report)
+}
+
+// Reads an optional setting, `default` when it is missing
+fn setting<T: serde::de::DeserializeOwned>(figment: &Figment, key: &str, default: T) -> Result<T, Box<figment::Error>> {
+  match figment.find_value(key) {
+    Ok(_) => figment.extract_inner(key).map_err(Box::new),
+    Err(_) => Ok(default),
+  }
+}
+
+// `image_gc_interval` and `image_gc_purge`
+fn settings(figment: &Figment) -> Result<(u64, bool), Box<figment::Error>> {
+  Ok((setting(figment, "image_gc_interval", DEFAULT_INTERVAL_SECS)?, setting(figment, "image_gc_purge", false)?))
+}
+
+// Periodically compares the store with the database, first one interval after launch. The interval is read from
+// `image_gc_interval` (seconds, 0 disables it). Runs only report orphaned files unless `image_gc_purge` is true,
+// since a misconfigured or shared store would otherwise lose every file the database does not know about.
+pub fn fairing() -> AdHoc {
+  AdHoc::try_on_ignite("Image garbage collection", |rocket| async {
+    let (interval, purge) = match settings(rocket.figment()) {
+      Ok(settings) => settings,
+      Err(e) => {
+        log::error!("Invalid image_gc_interval or image_gc_purge, expected seconds and a boolean: {}", e);
+        return Err(rocket);
+      }
+    };
+    if interval == 0 {
+      return Ok(rocket);
+    }
+
+    Ok(rocket.attach(AdHoc::on_liftoff("Image garbage collection loop", move |rocket| Box::pin(async move {
+      let pool = match DbConn::pool(rocket) {
+        Some(pool) => pool.clone(),
+        None => return log::error!("Image garbage collection requires the postgres pool"),
+      };
+      let store = match rocket.state::<SharedImageStore>() {
+        Some(store) => store.clone(),
+        None => return log::error!("Image garbage collection requires the image store"),
+      };
+
+      rocket::tokio::spawn(async move {
+        let period = Duration::from_secs(interval);
+        let mut ticker = time::interval_at(time::Instant::now() + period, period);
+        loop {
+          ticker.tick().await;
+          let store = store.clone();
+          let result = match pool.get().await {
+            Some(conn) => conn.run(move |c| collect_garbage(c, store.as_ref(), purge)).await,
+            None => {
+              log::error!("Cannot connect to postgres in image garbage collection");
+              continue;
+            }
+          };
+          match result {
+            Ok(report) => log::info!("Image garbage collection: {:?}", report),
+            Err(e) => log::error!("Image garbage collection failed: {}", e),
+          }
+        }
+      });
+    }))))
+  })
+}
@@ -0,0 +1,398 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use diesel::{Connection, PgConnection};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, ImageResult};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use rocket::fairing::AdHoc;
+use rocket::tokio::sync::mpsc::{self, Sender};
+use rocket::tokio::task::spawn_blocking;
+
+use crate::models::NewImageRendition;
+use crate::repository::{ImageError, ImageRenditionRepository, ImageRepository};
+use crate::rocket_routes::DbConn;
+use crate::storage::{ImageStore, SharedImageStore};
+
+// Sizes generated for every uploaded image, bounded by the longest edge in pixels
+pub const RENDITION_SIZES: [(&str, u32); 3] = [
+  ("thumbnail", 150),
+  ("medium", 600),
+  ("large", 1200),
+];
+
+const JPEG_QUALITY: u8 = 85;
+
+// Uploads waiting for their renditions, each holding the uploaded bytes
+const QUEUE_CAPACITY: usize = 16;
+
+// JPEG segments kept when stripping metadata: JFIF (APP0), ICC profiles (APP2) and Adobe's color transform (APP14)
+const KEPT_JPEG_SEGMENTS: [u8; 3] = [0xE0, 0xE2, 0xEE];
+
+pub struct EncodedRendition {
+  pub kind: &'static str,
+  pub format: ImageFormat,
+  pub width: u32,
+  pub height: u32,
+  pub bytes: Vec<u8>,
+}
+
+impl EncodedRendition {
+  // Builds the storage key of the rendition from the original one, e.g. `shoe.jpg` -> `shoe_thumbnail.webp`
+  pub fn storage_key(&self, original: &str) -> String {
+    let stem = Path::new(original)
+      .file_stem()
+      .and_then(|s| s.to_str())
+      .unwrap_or(original);
+    format!("{}_{}.{}", stem, self.kind, format_name(self.format))
+  }
+
+  pub fn to_new_rendition(&self, image_id: i32, original: &str) -> NewImageRendition {
+    NewImageRendition {
+      image_id,
+      kind: self.kind.to_string(),
+      format: format_name(self.format).to_string(),
+      storage_key: self.storage_key(original),
+      width: self.width as i32,
+      height: self.height as i32,
+    }
+  }
+}
+
+pub struct ProcessedImage {
+  pub width: u32,
+  pub height: u32,
+  // The original without its metadata, if its format can be written back. JPEGs keep their
+  // compressed data, other formats are re-encoded losslessly.
+  pub stripped_original: Option<Vec<u8>>,
+  pub renditions: Vec<EncodedRendition>,
+}
+
+// Short name used both as the file extension and in the `format` column
+pub fn format_name(format: ImageFormat) -> &'static str {
+  match format {
+    ImageFormat::Jpeg => "jpeg",
+    ImageFormat::WebP => "webp",
+    _ => "png",
+  }
+}
+
+// Decodes an uploaded image and produces every rendition of it.
+// Decoding drops EXIF and other metadata, so re-encoding the pixels is what strips it;
+// the EXIF orientation is applied first so that stripped images are not displayed rotated.
+// JPEG originals are not re-encoded, which would lose quality, but have their metadata segments cut out.
+pub fn process(bytes: &[u8]) -> ImageResult<ProcessedImage> {
+  let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+  let source_format = reader.format();
+  let mut decoder = reader.into_decoder()?;
+  let orientation = decoder.orientation()?;
+  let mut image = DynamicImage::from_decoder(decoder)?;
+  image.apply_orientation(orientation);
+
+  // Formats we keep in the format they were uploaded in; anything else is served as PNG
+  let output_format = match source_format {
+    Some(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => format,
+    _ => ImageFormat::Png,
+  };
+
+  let stripped_original = match source_format {
+    Some(ImageFormat::Jpeg) => match strip_jpeg_metadata(bytes, orientation.to_exif()) {
+      Some(stripped) => Some(stripped),
+      // Segments the decoder read past, re-encoding still strips them
+      None => Some(encode(&image, ImageFormat::Jpeg)?),
+    },
+    Some(format) if format == output_format => Some(encode(&image, format)?),
+    _ => None,
+  };
+
+  let mut renditions = Vec::new();
+  if output_format != ImageFormat::WebP {
+    renditions.push(rendition("original", &image, ImageFormat::WebP)?);
+  }
+  for (kind, max_edge) in RENDITION_SIZES {
+    // Never upscale images that are already smaller than the rendition
+    let resized = if image.width() > max_edge || image.height() > max_edge {
+      image.resize(max_edge, max_edge, image::imageops::FilterType::Lanczos3)
+    } else {
+      image.clone()
+    };
+    renditions.push(rendition(kind, &resized, output_format)?);
+    if output_format != ImageFormat::WebP {
+      renditions.push(rendition(kind, &resized, ImageFormat::WebP)?);
+    }
+  }
+
+  Ok(ProcessedImage {
+    width: image.width(),
+    height: image.height(),
+    stripped_original,
+    renditions,
+  })
+}
+
+// The JPEG without its EXIF, XMP, IPTC and comment segments, the compressed image untouched. Since its
+// pixels are not rotated, an `orientation` other than 1 is kept in an EXIF segment of its own.
+// None when the segments cannot be walked.
+fn strip_jpeg_metadata(bytes: &[u8], orientation: u8) -> Option<Vec<u8>> {
+  let mut rest = bytes.strip_prefix(&[0xFF, 0xD8])?;
+  let mut stripped = Vec::with_capacity(bytes.len());
+  stripped.extend_from_slice(&[0xFF, 0xD8]);
+  let mut orientation_written = orientation == 1;
+  loop {
+    if rest.len() < 4 || rest[0] != 0xFF {
+      return None;
+    }
+    let marker = rest[1];
+    // Markers may be preceded by fill bytes
+    if marker == 0xFF {
+      rest = &rest[1..];
+      continue;
+    }
+    // JFIF requires its segment to come first, the orientation follows it
+    if !orientation_written && marker != 0xE0 {
+      stripped.extend_from_slice(&exif_orientation_segment(orientation));
+      orientation_written = true;
+    }
+    // The compressed data starts with the scan, everything from there on is kept
+    if marker == 0xDA {
+      stripped.extend_from_slice(rest);
+      return Some(stripped);
+    }
+    let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+    if length < 2 || rest.len() < 2 + length {
+      return None;
+    }
+    let (segment, tail) = rest.split_at(2 + length);
+    let metadata = (0xE1..=0xEF).contains(&marker) && !KEPT_JPEG_SEGMENTS.contains(&marker) || marker == 0xFE;
+    if !metadata {
+      stripped.extend_from_slice(segment);
+    }
+    rest = tail;
+  }
+}
+
+// An APP1 segment with an EXIF block holding only the orientation tag
+fn exif_orientation_segment(orientation: u8) -> Vec<u8> {
+  let mut segment = vec![0xFF, 0xE1, 0x00, 0x22];
+  segment.extend_from_slice(b"Exif\0\0");
+  // Big-endian TIFF header, its first directory right after it
+  segment.extend_from_slice(&[b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08]);
+  // One entry: tag 0x0112 (orientation), type SHORT, one value; then no next directory
+  segment.extend_from_slice(&[0x00, 0x01, 0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, orientation, 0x00, 0x00]);
+  segment.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+  segment
+}
+
+// Guesses the MIME type of a stored file from the extension of its key
+pub fn mime_type(storage_key: &str) -> String {
+  ImageFormat::from_path(storage_key)
+    .map(|format| format.to_mime_type().to_string())
+    .unwrap_or_else(|_| "application/octet-stream".to_string())
+}
+
+fn rendition(kind: &'static str, image: &DynamicImage, format: ImageFormat) -> ImageResult<EncodedRendition> {
+  Ok(EncodedRendition {
+    kind,
+    format,
+    width: image.width(),
+    height: image.height(),
+    bytes: encode(image, format)?,
+  })
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+  let mut bytes = Cursor::new(Vec::new());
+  match format {
+    // Neither encoder accepts every pixel layout, so convert to one they support
+    ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+      .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY))?,
+    ImageFormat::WebP => DynamicImage::ImageRgba8(image.to_rgba8())
+      .write_with_encoder(WebPEncoder::new_lossless(&mut bytes))?,
+    _ => image.write_to(&mut bytes, format)?,
+  }
+  Ok(bytes.into_inner())
+}
+
+pub struct RenditionJob {
+  pub image_id: i32,
+  pub storage_key: String,
+  pub bytes: Vec<u8>,
+}
+
+// Handle used by the routes to hand uploads over to the rendition worker
+pub struct RenditionQueue(Sender<RenditionJob>);
+
+impl RenditionQueue {
+  // Waits while the queue is full, so that a burst of uploads slows down instead of piling up in memory
+  pub async fn enqueue(&self, job: RenditionJob) {
+    if self.0.send(job).await.is_err() {
+      log::error!("Image rendition worker is not running, job dropped");
+    }
+  }
+}
+
+// Processes uploaded images in the background so that uploads can return as soon as the original is stored
+pub fn worker() -> AdHoc {
+  AdHoc::on_ignite("Image rendition worker", |rocket| async {
+    let (sender, mut receiver) = mpsc::channel::<RenditionJob>(QUEUE_CAPACITY);
+
+    rocket
+      .manage(RenditionQueue(sender))
+      .attach(AdHoc::on_liftoff("Image rendition worker loop", |rocket| Box::pin(async move {
+        let pool = match DbConn::pool(rocket) {
+          Some(pool) => pool.clone(),
+          None => return log::error!("Image rendition worker requires the postgres pool"),
+        };
+        let store = match rocket.state::<SharedImageStore>() {
+          Some(store) => store.clone(),
+          None => return log::error!("Image rendition worker requires the image store"),
+        };
+
+        rocket::tokio::spawn(async move {
+          while let Some(job) = receiver.recv().await {
+            let image_id = job.image_id;
+            // Decoding and encoding take a while, a database connection is only taken to record the result
+            let writer = store.clone();
+            let result = match spawn_blocking(move || store_renditions(writer.as_ref(), job)).await {
+              Ok(Ok(stored)) => match pool.get().await {
+                Some(conn) => {
+                  let store = store.clone();
+                  conn.run(move |c| record_renditions(c, store.as_ref(), stored)).await
+                },
+                None => {
+                  remove_renditions(store.as_ref(), &stored);
+                  Err("Cannot connect to postgres in rendition worker".into())
+                },
+              },
+              Ok(Err(e)) => Err(e),
+              Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+              log::error!("Failed to generate renditions for image {}: {}", image_id, e);
+            }
+          }
+        });
+      })))
+  })
+}
+
+// The stored files of an upload's renditions, to be recorded
+pub struct StoredRenditions {
+  pub image_id: i32,
+  pub width: u32,
+  pub height: u32,
+  pub renditions: Vec<NewImageRendition>,
+}
+
+// Stores the stripped original and the renditions of an uploaded image and records them
+pub fn run_job(c: &mut PgConnection, store: &dyn ImageStore, job: RenditionJob) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+  let stored = store_renditions(store, job)?;
+  record_renditions(c, store, stored)
+}
+
+// Processes an upload and stores its stripped original and renditions, without a database connection
+pub fn store_renditions(store: &dyn ImageStore, job: RenditionJob) -> Result<StoredRenditions, Box<dyn std::error::Error + Send + Sync>> {
+  let processed = process(&job.bytes)?;
+
+  if let Some(stripped) = &processed.stripped_original {
+    store.put(&job.storage_key, stripped, &mime_type(&job.storage_key))?;
+  }
+
+  let mut stored = StoredRenditions {
+    image_id: job.image_id,
+    width: processed.width,
+    height: processed.height,
+    renditions: Vec::with_capacity(processed.renditions.len()),
+  };
+  for rendition in &processed.renditions {
+    let new_rendition = rendition.to_new_rendition(job.image_id, &job.storage_key);
+    if let Err(e) = store.put(&new_rendition.storage_key, &rendition.bytes, rendition.format.to_mime_type()) {
+      remove_renditions(store, &stored);
+      return Err(e.into());
+    }
+    stored.renditions.push(new_rendition);
+  }
+  Ok(stored)
+}
+
+// Records stored renditions with the image's dimensions
+pub fn record_renditions(c: &mut PgConnection, store: &dyn ImageStore, stored: StoredRenditions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+  let StoredRenditions { image_id, width, height, renditions } = stored;
+  let keys: Vec<String> = renditions.iter().map(|rendition| rendition.storage_key.clone()).collect();
+  let result = c.transaction(|c| {
+    ImageRepository::set_dimensions(c, image_id, width as i32, height as i32)?;
+    ImageRenditionRepository::create_many(c, renditions)?;
+    Ok::<_, ImageError>(())
+  });
+  // Don't leave rendition files behind that no row refers to
+  if result.is_err() {
+    remove_files(store, image_id, &keys);
+  }
+  Ok(result?)
+}
+
+fn remove_renditions(store: &dyn ImageStore, stored: &StoredRenditions) {
+  let keys: Vec<String> = stored.renditions.iter().map(|rendition| rendition.storage_key.clone()).collect();
+  remove_files(store, stored.image_id, &keys);
+}
+
+fn remove_files(store: &dyn ImageStore, image_id: i32, keys: &[String]) {
+  for key in keys {
+    if let Err(e) = store.delete(key) {
+      log::error!("Failed to remove rendition {} of image {}: {}", key, image_id, e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use image::metadata::Orientation;
+
+  fn jpeg_with_metadata() -> Vec<u8> {
+    let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(40, 20, |x, y| image::Rgb([x as u8 * 6, y as u8 * 12, 128])));
+    let encoded = encode(&image, ImageFormat::Jpeg).unwrap();
+    let mut comment = vec![0xFF, 0xFE, 0x00, 0x08];
+    comment.extend_from_slice(b"secret");
+    [&encoded[..2], &exif_orientation_segment(6), &comment, &encoded[2..]].concat()
+  }
+
+  fn scan(bytes: &[u8]) -> &[u8] {
+    let start = bytes.windows(2).position(|marker| marker == [0xFF, 0xDA]).unwrap();
+    &bytes[start..]
+  }
+
+  #[test]
+  fn jpeg_metadata_is_stripped_without_reencoding() {
+    let original = jpeg_with_metadata();
+    let stripped = strip_jpeg_metadata(&original, 6).unwrap();
+    assert!(!stripped.windows(6).any(|window| window == b"secret"));
+    assert_eq!(scan(&stripped), scan(&original));
+    let mut decoder = ImageReader::new(Cursor::new(&stripped)).with_guessed_format().unwrap().into_decoder().unwrap();
+    assert_eq!(decoder.orientation().unwrap(), Orientation::Rotate90);
+  }
+
+  #[test]
+  fn upright_jpegs_get_no_exif_segment() {
+    let original = jpeg_with_metadata();
+    let stripped = strip_jpeg_metadata(&original, 1).unwrap();
+    assert!(!stripped.windows(4).any(|window| window == b"Exif"));
+    assert_eq!(scan(&stripped), scan(&original));
+  }
+
+  #[test]
+  fn processed_jpeg_originals_keep_their_scan() {
+    let original = jpeg_with_metadata();
+    let processed = process(&original).unwrap();
+    // Rotated a quarter turn by the orientation
+    assert_eq!((processed.width, processed.height), (20, 40));
+    assert_eq!(scan(processed.stripped_original.as_deref().unwrap()), scan(&original));
+  }
+
+  #[test]
+  fn truncated_jpegs_are_not_walked() {
+    let original = jpeg_with_metadata();
+    assert!(strip_jpeg_metadata(&original[..10], 6).is_none());
+    assert!(strip_jpeg_metadata(b"not a jpeg", 1).is_none());
+  }
+}
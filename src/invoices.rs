@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::NaiveDate;
+use rocket::fairing::AdHoc;
+use serde::{Deserialize, Serialize};
+
+use crate::addresses::AddressSnapshot;
+use crate::models::{Invoice, Item};
+use crate::money::Currency;
+use crate::pdf::{self, Document, Page, PAGE_HEIGHT, PAGE_WIDTH};
+use crate::promotions::{Cart, Discount};
+use crate::storage::{ImageStore, StoreResult};
+use crate::taxes::{PriceMode, RateTax, TaxBreakdown};
+
+// `{{name}}` placeholders are filled in, `{{#rows}}...{{/rows}}` sections repeated once per row
+const HTML_TEMPLATE: &str = include_str!("../templates/invoice.html");
+
+// The seller printed at the top of invoices, configured with `invoice_issuer`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Issuer {
+  pub name: String,
+  #[serde(default)]
+  pub address: Vec<String>,
+  // VAT or other tax registration number
+  pub tax_id: Option<String>,
+}
+
+impl Default for Issuer {
+  fn default() -> Self {
+    Issuer { name: "eshop".to_string(), address: Vec::new(), tax_id: None }
+  }
+}
+
+// Manages the configured `Issuer`
+pub fn fairing() -> AdHoc {
+  AdHoc::try_on_ignite("Invoice issuer", |rocket| async {
+    let figment = rocket.figment();
+    let issuer = match figment.find_value("invoice_issuer") {
+      Ok(_) => figment.extract_inner::<Issuer>("invoice_issuer"),
+      Err(_) => {
+        log::warn!("No invoice_issuer configured, invoices are issued by `{}`", Issuer::default().name);
+        Ok(Issuer::default())
+      }
+    };
+    match issuer {
+      Ok(issuer) => Ok(rocket.manage(issuer)),
+      Err(e) => {
+        log::error!("Invalid invoice_issuer, expected a name, address lines and an optional tax_id: {}", e);
+        Err(rocket)
+      }
+    }
+  })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InvoiceLine {
+  // None for the shipping
+  pub item_id: Option<i32>,
+  pub description: String,
+  pub quantity: i32,
+  pub unit_price: BigDecimal,
+  pub discount: BigDecimal,
+  pub net: BigDecimal,
+  pub tax_rate: BigDecimal,
+  pub tax: BigDecimal,
+  pub gross: BigDecimal,
+}
+
+// What an invoice says, amounts in `currency`. Issued invoices keep it, see `Invoice::document`.
+#[derive(Serialize, Deserialize)]
+pub struct InvoiceDocument {
+  // Set when the invoice is issued, see `InvoiceRepository::issue`
+  pub number: String,
+  pub issued_on: NaiveDate,
+  pub order_reference: String,
+  pub issuer: Issuer,
+  pub billing: AddressSnapshot,
+  pub currency: Currency,
+  pub mode: PriceMode,
+  pub lines: Vec<InvoiceLine>,
+  pub rates: Vec<RateTax>,
+  pub net: BigDecimal,
+  pub tax: BigDecimal,
+  pub gross: BigDecimal,
+}
+
+impl InvoiceDocument {
+  // The invoice of a cart priced like `/cart/totals`: `taxes` has a line per cart line then one for the
+  // shipping. `items` are the cart's items, for their names.
+  pub fn new(issuer: Issuer, order_reference: String, billing: AddressSnapshot, cart: &Cart, items: &[Item], discount: Option<&Discount>, taxes: TaxBreakdown) -> Self {
+    let items: HashMap<i32, &Item> = items.iter().map(|item| (item.id, item)).collect();
+    let mut lines: Vec<InvoiceLine> = cart.lines.iter()
+      .zip(&taxes.lines)
+      .enumerate()
+      .map(|(i, (line, tax))| InvoiceLine {
+        item_id: Some(line.item_id),
+        description: match items.get(&line.item_id) {
+          Some(Item { name, sku: Some(sku), .. }) => format!("{} ({})", name, sku),
+          Some(item) => item.name.clone(),
+          None => format!("Item {}", line.item_id),
+        },
+        quantity: line.quantity,
+        unit_price: line.unit_price.clone(),
+        discount: discount.map(|discount| discount.lines[i].amount.clone()).unwrap_or_default(),
+        net: tax.net.clone(),
+        tax_rate: tax.rate.clone(),
+        tax: tax.tax.clone(),
+        gross: tax.gross.clone(),
+      })
+      .collect();
+    if let Some(tax) = taxes.lines.get(cart.lines.len()).filter(|_| !cart.shipping.is_zero()) {
+      lines.push(InvoiceLine {
+        item_id: None,
+        description: "Shipping".to_string(),
+        quantity: 1,
+        unit_price: cart.shipping.clone(),
+        discount: discount.map(|discount| discount.shipping.clone()).unwrap_or_default(),
+        net: tax.net.clone(),
+        tax_rate: tax.rate.clone(),
+        tax: tax.tax.clone(),
+        gross: tax.gross.clone(),
+      });
+    }
+
+    InvoiceDocument {
+      number: String::new(),
+      issued_on: NaiveDate::default(),
+      order_reference,
+      issuer,
+      billing,
+      currency: taxes.currency,
+      mode: taxes.mode,
+      lines,
+      rates: taxes.rates,
+      net: taxes.net,
+      tax: taxes.tax,
+      gross: taxes.gross,
+    }
+  }
+
+  // Characters of the document the PDF shows as `?`, each once; the HTML document shows them all
+  pub fn unprintable(&self) -> Vec<char> {
+    let texts = [self.issuer.name.clone(), self.number.clone(), self.order_reference.clone()].into_iter()
+      .chain(self.issuer.address.iter().cloned())
+      .chain(self.issuer.tax_id.iter().cloned())
+      .chain(address_lines(&self.billing))
+      .chain(self.lines.iter().map(|line| line.description.clone()))
+      .chain(self.rates.iter().map(Self::rate_name));
+    let mut missing: Vec<char> = Vec::new();
+    for c in texts.flat_map(|text| pdf::unprintable(&text)) {
+      if !missing.contains(&c) {
+        missing.push(c);
+      }
+    }
+    missing
+  }
+
+  // The document an issued invoice keeps, None for invoices issued before it was kept
+  pub fn of(invoice: &Invoice) -> Option<Self> {
+    let document = invoice.document.clone()?;
+    serde_json::from_value(document)
+      .inspect_err(|e| log::error!("Cannot read the document of invoice {}: {}", invoice.number, e))
+      .ok()
+  }
+
+  // Renders both documents and stores them under the invoice's keys
+  pub fn store(&self, store: &dyn ImageStore, invoice: &Invoice) -> StoreResult<()> {
+    store.put(&invoice.pdf_key, &self.render_pdf(), "application/pdf")?;
+    store.put(&invoice.html_key, self.render_html().as_bytes(), "text/html; charset=utf-8")
+  }
+
+  pub fn file_name(&self, extension: &str) -> String {
+    format!("invoice-{}.{}", self.number, extension)
+  }
+
+  fn amount(&self, amount: &BigDecimal) -> String {
+    amount.with_scale(self.currency.minor_units()).to_string()
+  }
+
+  fn discount(&self, amount: &BigDecimal) -> String {
+    if amount.is_zero() { String::new() } else { format!("-{}", self.amount(amount)) }
+  }
+
+  fn price_note(&self) -> &'static str {
+    match self.mode {
+      PriceMode::Exclusive => "Prices are net, tax is added on top.",
+      PriceMode::Inclusive => "Prices include tax.",
+    }
+  }
+
+  fn rate_name(rate: &RateTax) -> String {
+    rate.name.clone().unwrap_or_else(|| rate.tax_class.clone())
+  }
+
+  pub fn render_html(&self) -> String {
+    let address = |lines: Vec<String>| lines.into_iter().map(|line| vec![("line", line)]).collect();
+    let sections = [
+      ("issuer_address", address(self.issuer.address.clone())),
+      ("issuer_tax_id", self.issuer.tax_id.iter().map(|tax_id| vec![("tax_id", tax_id.clone())]).collect()),
+      ("billing_address", address(address_lines(&self.billing))),
+      ("lines", self.lines.iter()
+        .map(|line| vec![
+          ("description", line.description.clone()),
+          ("quantity", line.quantity.to_string()),
+          ("unit_price", self.amount(&line.unit_price)),
+          ("discount", self.discount(&line.discount)),
+          ("net", self.amount(&line.net)),
+          ("tax_rate", percent(&line.tax_rate)),
+          ("tax", self.amount(&line.tax)),
+          ("gross", self.amount(&line.gross)),
+        ])
+        .collect()),
+      ("rates", self.rates.iter()
+        .map(|rate| vec![
+          ("name", Self::rate_name(rate)),
+          ("rate", percent(&rate.rate)),
+          ("net", self.amount(&rate.net)),
+          ("tax", self.amount(&rate.tax)),
+        ])
+        .collect()),
+    ];
+    let values = [
+      ("number", self.number.clone()),
+      ("issued_on", self.issued_on.to_string()),
+      ("order_reference", self.order_reference.clone()),
+      ("issuer_name", self.issuer.name.clone()),
+      ("currency", self.currency.to_string()),
+      ("net", self.amount(&self.net)),
+      ("tax", self.amount(&self.tax)),
+      ("gross", self.amount(&self.gross)),
+      ("price_note", self.price_note().to_string()),
+    ];
+    render_template(HTML_TEMPLATE, &values, &sections)
+  }
+
+  pub fn render_pdf(&self) -> Vec<u8> {
+    let mut layout = Layout::new();
+
+    // Issuer on the left, the invoice's references on the right
+    let top = layout.y;
+    layout.page.text(MARGIN, layout.y, 16.0, true, &self.issuer.name);
+    layout.page.text_right(RIGHT, layout.y, 16.0, true, "INVOICE");
+    layout.y -= 16.0;
+    let references = [
+      format!("Number {}", self.number),
+      format!("Date {}", self.issued_on),
+      format!("Order {}", self.order_reference),
+    ];
+    let mut right = layout.y;
+    for reference in &references {
+      layout.page.text_right(RIGHT, right, SIZE, false, reference);
+      right -= ROW;
+    }
+    let issuer_lines = self.issuer.address.iter().cloned()
+      .chain(self.issuer.tax_id.iter().map(|tax_id| format!("Tax ID {}", tax_id)));
+    for line in issuer_lines {
+      layout.page.text(MARGIN, layout.y, SIZE, false, &pdf::fit(&line, 250.0, SIZE));
+      layout.y -= ROW;
+    }
+    layout.y = layout.y.min(right).min(top - 60.0) - ROW;
+
+    layout.page.text(MARGIN, layout.y, SIZE, true, "Bill to");
+    layout.y -= ROW;
+    for line in address_lines(&self.billing) {
+      layout.page.text(MARGIN, layout.y, SIZE, false, &pdf::fit(&line, 250.0, SIZE));
+      layout.y -= ROW;
+    }
+    layout.y -= 2.0 * ROW;
+
+    let header = |page: &mut Page, y: f32| {
+      page.text(MARGIN, y, SIZE, true, "Item");
+      for (column, title) in COLUMNS.iter().zip(["Qty", "Unit price", "Discount", "Net", "Rate", "Tax", "Total"]) {
+        page.text_right(*column, y, SIZE, true, title);
+      }
+      page.rule(MARGIN, RIGHT, y - 4.0);
+      y - ROW - 4.0
+    };
+    layout.y = header(&mut layout.page, layout.y);
+    for line in &self.lines {
+      if layout.needs(ROW) {
+        layout.y = header(&mut layout.page, layout.y);
+      }
+      layout.page.text(MARGIN, layout.y, SIZE, false, &pdf::fit(&line.description, COLUMNS[0] - MARGIN - 30.0, SIZE));
+      let cells = [
+        line.quantity.to_string(),
+        self.amount(&line.unit_price),
+        self.discount(&line.discount),
+        self.amount(&line.net),
+        percent(&line.tax_rate),
+        self.amount(&line.tax),
+        self.amount(&line.gross),
+      ];
+      for (column, cell) in COLUMNS.iter().zip(&cells) {
+        layout.page.text_right(*column, layout.y, SIZE, false, cell);
+      }
+      layout.y -= ROW;
+    }
+    layout.page.rule(MARGIN, RIGHT, layout.y + ROW - 4.0);
+    layout.y -= ROW;
+
+    // The tax breakdown by rate, then the totals, kept together
+    layout.needs((self.rates.len() + 6) as f32 * ROW);
+    layout.page.text(MARGIN, layout.y, SIZE, true, "Tax");
+    for (column, title) in [(COLUMNS[3], "Net"), (COLUMNS[4], "Rate"), (COLUMNS[5], "Tax")] {
+      layout.page.text_right(column, layout.y, SIZE, true, title);
+    }
+    layout.y -= ROW;
+    for rate in &self.rates {
+      layout.page.text(MARGIN, layout.y, SIZE, false, &pdf::fit(&Self::rate_name(rate), COLUMNS[2] - MARGIN, SIZE));
+      layout.page.text_right(COLUMNS[3], layout.y, SIZE, false, &self.amount(&rate.net));
+      layout.page.text_right(COLUMNS[4], layout.y, SIZE, false, &percent(&rate.rate));
+      layout.page.text_right(COLUMNS[5], layout.y, SIZE, false, &self.amount(&rate.tax));
+      layout.y -= ROW;
+    }
+    layout.y -= ROW;
+    let totals = [
+      ("Net", &self.net, false),
+      ("Tax", &self.tax, false),
+      ("Total", &self.gross, true),
+    ];
+    for (title, amount, bold) in totals {
+      layout.page.text_right(COLUMNS[4], layout.y, SIZE, bold, title);
+      layout.page.text_right(RIGHT, layout.y, SIZE, bold, &format!("{} {}", self.amount(amount), self.currency));
+      layout.y -= ROW;
+    }
+    layout.y -= ROW;
+    layout.page.text(MARGIN, layout.y, SIZE, false, self.price_note());
+
+    let mut pages = layout.finish();
+    let count = pages.len();
+    for (i, page) in pages.iter_mut().enumerate() {
+      page.text_right(RIGHT, MARGIN / 2.0, SIZE, false, &format!("Invoice {} - page {} of {}", self.number, i + 1, count));
+    }
+    Document { title: format!("Invoice {}", self.number), pages }.to_bytes()
+  }
+}
+
+const MARGIN: f32 = 50.0;
+const RIGHT: f32 = PAGE_WIDTH - MARGIN;
+const SIZE: f32 = 8.0;
+const ROW: f32 = 12.0;
+// Right edges of the quantity, unit price, discount, net, rate, tax and total columns
+const COLUMNS: [f32; 7] = [235.0, 295.0, 350.0, 410.0, 445.0, 490.0, RIGHT];
+
+// Fills pages from the top, starting a new one when the next rows do not fit
+struct Layout {
+  pages: Vec<Page>,
+  page: Page,
+  y: f32,
+}
+
+impl Layout {
+  fn new() -> Self {
+    Layout { pages: Vec::new(), page: Page::default(), y: PAGE_HEIGHT - MARGIN }
+  }
+
+  // Whether a new page was started to fit `height` more points
+  fn needs(&mut self, height: f32) -> bool {
+    if self.y - height >= MARGIN {
+      return false;
+    }
+    self.pages.push(std::mem::take(&mut self.page));
+    self.y = PAGE_HEIGHT - MARGIN;
+    true
+  }
+
+  fn finish(mut self) -> Vec<Page> {
+    self.pages.push(self.page);
+    self.pages
+  }
+}
+
+fn address_lines(address: &AddressSnapshot) -> Vec<String> {
+  let city = match &address.postal_code {
+    Some(postal_code) => format!("{} {}", postal_code, address.city),
+    None => address.city.clone(),
+  };
+  [Some(address.name.clone()), Some(address.line1.clone()), address.line2.clone(), Some(city), address.region.clone(), Some(address.country.clone())]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+// `19.00` -> `19%`, `7.50` -> `7.5%`
+fn percent(rate: &BigDecimal) -> String {
+  let rate = rate.with_scale(4).to_string();
+  format!("{}%", rate.trim_end_matches('0').trim_end_matches('.'))
+}
+
+fn escape_html(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      // Keeps values from forming placeholders of their own
+      '{' => escaped.push_str("&#123;"),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+// Values of the placeholders, by name
+type Row<'a> = Vec<(&'a str, String)>;
+
+fn fill(template: &str, values: &[(&str, String)]) -> String {
+  values.iter().fold(template.to_string(), |filled, (name, value)| {
+    filled.replace(&format!("{{{{{}}}}}", name), &escape_html(value))
+  })
+}
+
+// Values are HTML-escaped. Sections are expanded first, so their rows may use the names of values.
+fn render_template(template: &str, values: &[(&str, String)], sections: &[(&str, Vec<Row>)]) -> String {
+  let mut rendered = template.to_string();
+  for (name, rows) in sections {
+    let (open, close) = (format!("{{{{#{}}}}}", name), format!("{{{{/{}}}}}", name));
+    let (Some(start), Some(end)) = (rendered.find(&open), rendered.find(&close)) else {
+      continue;
+    };
+    let row_template = &rendered[start + open.len()..end];
+    let expanded: String = rows.iter().map(|row| fill(row_template, row)).collect();
+    rendered.replace_range(start..end + close.len(), &expanded);
+  }
+  fill(&rendered, values)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn decimal(value: &str) -> BigDecimal {
+    value.parse().unwrap()
+  }
+
+  fn line(description: &str) -> InvoiceLine {
+    InvoiceLine {
+      item_id: Some(1),
+      description: description.to_string(),
+      quantity: 2,
+      unit_price: decimal("10"),
+      discount: decimal("0"),
+      net: decimal("20"),
+      tax_rate: decimal("19"),
+      tax: decimal("3.8"),
+      gross: decimal("23.8"),
+    }
+  }
+
+  fn document(name: &str, descriptions: &[&str]) -> InvoiceDocument {
+    InvoiceDocument {
+      number: "2024-000042".to_string(),
+      issued_on: NaiveDate::from_ymd_opt(2024, 6, 24).unwrap(),
+      order_reference: "R-1".to_string(),
+      issuer: Issuer::default(),
+      billing: AddressSnapshot {
+        name: name.to_string(),
+        line1: "Tverskaya 1".to_string(),
+        line2: None,
+        city: "Москва".to_string(),
+        region: None,
+        postal_code: Some("125009".to_string()),
+        country: "RU".to_string(),
+        phone: None,
+      },
+      currency: "EUR".parse().unwrap(),
+      mode: PriceMode::Exclusive,
+      lines: descriptions.iter().map(|description| line(description)).collect(),
+      rates: vec![RateTax { tax_class: "standard".to_string(), name: Some("VAT".to_string()), rate: decimal("19"), net: decimal("20"), tax: decimal("3.8") }],
+      net: decimal("20"),
+      tax: decimal("3.8"),
+      gross: decimal("23.8"),
+    }
+  }
+
+  #[test]
+  fn html_shows_every_script() {
+    let html = document("Иван Петров", &["Tee → 東京"]).render_html();
+    assert!(html.contains("<p>Иван Петров</p>"));
+    assert!(html.contains("<p>125009 Москва</p>"));
+    assert!(html.contains("<td>Tee → 東京</td>"));
+    assert!(html.contains("<p>Number 2024-000042</p>"));
+    assert!(html.contains("<td class=\"amount\">10.00</td>"));
+    assert!(html.contains("<td class=\"amount\">19%</td>"));
+    assert!(!html.contains("{{"));
+  }
+
+  #[test]
+  fn html_escapes_values() {
+    let html = document("<script>alert(1)</script>", &["{{number}} & \"more\""]).render_html();
+    assert!(html.contains("<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>"));
+    assert!(html.contains("<td>&#123;&#123;number}} &amp; &quot;more&quot;</td>"));
+  }
+
+  #[test]
+  fn reports_only_what_the_pdf_cannot_show() {
+    assert_eq!(document("Łukasz Żak", &["Ελιά → Жук"]).unprintable(), Vec::<char>::new());
+    assert_eq!(document("東京", &["東 \u{1F600}"]).unprintable(), vec!['東', '京', '\u{1F600}']);
+  }
+
+  #[test]
+  fn pdf_embeds_what_it_shows() {
+    let pdf = document("Иван Петров", &["Tee"]).render_pdf();
+    let text = String::from_utf8_lossy(&pdf);
+    assert!(text.starts_with("%PDF-1.4"));
+    // Searchable: the glyphs map back to Cyrillic
+    assert!(text.contains("<0418>"));
+    assert!(text.contains("<041F>"));
+  }
+}
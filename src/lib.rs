@@ -2,4 +2,17 @@ pub mod schema;
 pub mod repository;
 pub mod models;
 pub mod rocket_routes;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod image_processing;
+pub mod storage;
+pub mod image_gc;
+pub mod seed;
+pub mod catalog;
+pub mod price_schedule;
+pub mod promotions;
+pub mod money;
+pub mod taxes;
+pub mod shipping;
+pub mod addresses;
+pub mod pdf;
+pub mod invoices;
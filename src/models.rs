@@ -1,33 +1,83 @@
-use std::{io::Write, str::FromStr};
+use std::{fmt, io::Write, str::FromStr};
 
 use chrono::NaiveDateTime;
 use bigdecimal::BigDecimal;
 use diesel::{expression::AsExpression, prelude::*, sql_types::Text, pg::{Pg, PgValue}, serialize::{Output, ToSql}, deserialize::{FromSql, FromSqlRow}};
 use rocket::serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use crate::schema::*;
+use crate::storage::ImageStore;
+use crate::money::{Currency, Money};
+use crate::taxes::STANDARD_TAX_CLASS;
 
-#[derive(Serialize, Deserialize, Queryable, AsChangeset)]
+#[derive(Serialize, Deserialize, Queryable, Identifiable, AsChangeset, JsonSchema)]
 pub struct Image {
     pub id: i32,
-    pub url: String,
+    pub storage_key: String,
     #[serde(skip_deserializing)]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(skip_deserializing)]
+    pub width: Option<i32>,
+    #[serde(skip_deserializing)]
+    pub height: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Insertable)]
 #[diesel(table_name=images)]
 pub struct NewImage {
-    pub url: String,
+    pub storage_key: String,
 }
 
-#[derive(Queryable, Associations, Identifiable, Debug)]
-#[belongs_to(Item)]
-#[belongs_to(Image)]
+impl Image {
+    // The URL depends on the store the file was written to, so it is not persisted
+    pub fn url(&self, store: &dyn ImageStore) -> String {
+        store.url(&self.storage_key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Queryable, Associations, Identifiable, Debug, JsonSchema)]
+#[diesel(belongs_to(Image))]
+#[diesel(table_name=image_renditions)]
+pub struct ImageRendition {
+    pub id: i32,
+    pub image_id: i32,
+    pub kind: String,
+    pub format: String,
+    pub storage_key: String,
+    pub width: i32,
+    pub height: i32,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=image_renditions)]
+pub struct NewImageRendition {
+    pub image_id: i32,
+    pub kind: String,
+    pub format: String,
+    pub storage_key: String,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl ImageRendition {
+    pub fn url(&self, store: &dyn ImageStore) -> String {
+        store.url(&self.storage_key)
+    }
+}
+
+#[derive(Serialize, Queryable, Associations, Identifiable, Debug, JsonSchema)]
+#[diesel(belongs_to(Item))]
+#[diesel(belongs_to(Image))]
 #[diesel(table_name=items_images)]
 pub struct ItemsImage {
     pub id: i32,
     pub item_id: i32,
     pub image_id: i32,
+    pub created_at: Option<NaiveDateTime>,
+    pub position: i32,
+    pub is_primary: bool,
+    pub alt_text: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -35,27 +85,1083 @@ pub struct ItemsImage {
 pub struct NewItemsImage {
     pub item_id: i32,
     pub image_id: i32,
+    pub position: i32,
+    pub is_primary: bool,
+    pub alt_text: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Queryable, Identifiable, AsChangeset)]
+#[derive(Serialize, Deserialize, Queryable, Identifiable, AsChangeset, JsonSchema, Clone)]
 pub struct Item {
     #[serde(skip_deserializing)]
     pub id: i32,
     pub name: String,
     pub description: Option<String>,
+    #[schemars(with = "String")]
     pub price: BigDecimal,
     #[serde(skip_deserializing)]
     pub created_at: Option<NaiveDateTime>,
     pub quantity: i32,
+    // Derived from the name, see `slugify`
+    #[serde(skip_deserializing)]
+    pub slug: String,
+    // Merchandisers' stock keeping unit, identifies the item in catalog imports
+    pub sku: Option<String>,
+    // The regular price while a scheduled sale is running, shown as "was" next to `price`
+    #[serde(skip_deserializing)]
+    #[schemars(with = "Option<String>")]
+    pub was_price: Option<BigDecimal>,
+    // Code of the `TaxClass` deciding the item's tax rates
+    #[serde(default = "default_tax_class")]
+    pub tax_class: String,
+    // Shipping weight and packed dimensions, see `shipping::Parcel`
+    #[serde(default)]
+    pub weight_grams: Option<i32>,
+    #[serde(default)]
+    pub length_mm: Option<i32>,
+    #[serde(default)]
+    pub width_mm: Option<i32>,
+    #[serde(default)]
+    pub height_mm: Option<i32>,
+    // Of approved reviews, see `ReviewRepository`
+    #[serde(skip_deserializing)]
+    pub rating_count: i32,
+    #[serde(skip)]
+    pub rating_sum: i32,
 }
 
-#[derive(Serialize, Deserialize, Insertable)]
+impl Item {
+    // Mean rating of the approved reviews, to two decimals
+    pub fn rating_average(&self) -> Option<BigDecimal> {
+        if self.rating_count == 0 {
+            return None;
+        }
+        Some((BigDecimal::from(self.rating_sum) / BigDecimal::from(self.rating_count)).with_scale_round(2, bigdecimal::RoundingMode::HalfUp))
+    }
+}
+
+fn default_tax_class() -> String {
+    STANDARD_TAX_CLASS.to_string()
+}
+
+#[derive(Serialize, Deserialize, Insertable, JsonSchema)]
 #[diesel(table_name=items)]
 pub struct NewItem {
     pub name: String,
     pub description: Option<String>,
+    #[schemars(with = "String")]
     pub price: BigDecimal,
     pub quantity: i32,
+    #[serde(default)]
+    pub sku: Option<String>,
+    // The standard class when absent
+    #[serde(default)]
+    pub tax_class: Option<String>,
+    #[serde(default)]
+    pub weight_grams: Option<i32>,
+    #[serde(default)]
+    pub length_mm: Option<i32>,
+    #[serde(default)]
+    pub width_mm: Option<i32>,
+    #[serde(default)]
+    pub height_mm: Option<i32>,
+}
+
+// A `NewItem` with its slug, as bulk inserted with `COPY` which cannot use column defaults
+#[derive(Insertable)]
+#[diesel(table_name=items)]
+#[diesel(treat_none_as_default_value = false)]
+pub struct NewItemRow {
+    pub name: String,
+    pub description: Option<String>,
+    pub price: BigDecimal,
+    pub quantity: i32,
+    pub sku: Option<String>,
+    pub slug: String,
+    pub tax_class: String,
+    pub weight_grams: Option<i32>,
+    pub length_mm: Option<i32>,
+    pub width_mm: Option<i32>,
+    pub height_mm: Option<i32>,
+}
+
+// Fields to change on an item, absent ones are kept
+#[derive(Deserialize, JsonSchema, Default)]
+pub struct ItemChanges {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[schemars(with = "Option<String>")]
+    pub price: Option<BigDecimal>,
+    pub quantity: Option<i32>,
+    pub sku: Option<String>,
+    pub tax_class: Option<String>,
+    pub weight_grams: Option<i32>,
+    pub length_mm: Option<i32>,
+    pub width_mm: Option<i32>,
+    pub height_mm: Option<i32>,
+}
+
+impl ItemChanges {
+    pub fn apply(self, item: Item) -> Item {
+        Item {
+            name: self.name.unwrap_or(item.name),
+            description: self.description.or(item.description),
+            price: self.price.unwrap_or(item.price),
+            quantity: self.quantity.unwrap_or(item.quantity),
+            sku: self.sku.or(item.sku),
+            tax_class: self.tax_class.unwrap_or(item.tax_class),
+            weight_grams: self.weight_grams.or(item.weight_grams),
+            length_mm: self.length_mm.or(item.length_mm),
+            width_mm: self.width_mm.or(item.width_mm),
+            height_mm: self.height_mm.or(item.height_mm),
+            ..item
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ItemOperation {
+    Create { item: NewItem },
+    Update { id: i32, changes: ItemChanges },
+    Delete { id: i32 },
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    // The first failure rolls back every operation of the batch
+    AllOrNothing,
+    // Failed operations are rolled back alone and the others are committed
+    BestEffort,
+}
+
+#[derive(Queryable, Associations, Identifiable, Debug)]
+#[diesel(belongs_to(Item))]
+#[diesel(table_name=item_slug_redirects)]
+pub struct ItemSlugRedirect {
+    pub id: i32,
+    pub item_id: i32,
+    pub slug: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=item_slug_redirects)]
+pub struct NewItemSlugRedirect {
+    pub item_id: i32,
+    pub slug: String,
+}
+
+// Why an item's price changed, stored in `price_history.source`
+#[derive(Clone, Copy, Debug)]
+pub enum PriceChangeSource {
+    Created,
+    Updated,
+    Scheduled,
+    SaleEnded,
+}
+
+impl PriceChangeSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceChangeSource::Created => "created",
+            PriceChangeSource::Updated => "updated",
+            PriceChangeSource::Scheduled => "scheduled",
+            PriceChangeSource::SaleEnded => "sale_ended",
+        }
+    }
+}
+
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Item))]
+#[diesel(table_name=price_history)]
+pub struct PriceHistory {
+    pub id: i32,
+    pub item_id: i32,
+    #[schemars(with = "Option<String>")]
+    pub previous_price: Option<BigDecimal>,
+    #[schemars(with = "String")]
+    pub price: BigDecimal,
+    // One of `PriceChangeSource`
+    pub source: String,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=price_history)]
+pub struct NewPriceHistory {
+    pub item_id: i32,
+    pub previous_price: Option<BigDecimal>,
+    pub price: BigDecimal,
+    pub source: String,
+}
+
+// A price applied from `starts_at` on. With `ends_at` it is a sale: the previous price comes back
+// at the end and is shown as the item's `was_price` meanwhile. Times are UTC.
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema, Clone)]
+#[diesel(belongs_to(Item))]
+#[diesel(table_name=scheduled_prices)]
+pub struct ScheduledPrice {
+    pub id: i32,
+    pub item_id: i32,
+    #[schemars(with = "String")]
+    pub price: BigDecimal,
+    pub starts_at: NaiveDateTime,
+    pub ends_at: Option<NaiveDateTime>,
+    #[schemars(with = "Option<String>")]
+    pub previous_price: Option<BigDecimal>,
+    pub started_at: Option<NaiveDateTime>,
+    pub ended_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=scheduled_prices)]
+pub struct NewScheduledPrice {
+    #[serde(skip_deserializing)]
+    pub item_id: i32,
+    #[schemars(with = "String")]
+    pub price: BigDecimal,
+    pub starts_at: NaiveDateTime,
+    pub ends_at: Option<NaiveDateTime>,
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum CouponKind {
+    // `value` percent off the eligible items
+    Percentage,
+    // `value` off the eligible items, spread over them in proportion to their amount
+    FixedAmount,
+    // The whole shipping cost off
+    FreeShipping,
+}
+
+impl CouponKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CouponKind::Percentage => "percentage",
+            CouponKind::FixedAmount => "fixed_amount",
+            CouponKind::FreeShipping => "free_shipping",
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for CouponKind {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"percentage" => Ok(CouponKind::Percentage),
+            b"fixed_amount" => Ok(CouponKind::FixedAmount),
+            b"free_shipping" => Ok(CouponKind::FreeShipping),
+            _ => Err("Unrecognized coupon kind".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for CouponKind {
+    fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+// A discount code. Every limit is optional; times are UTC and `min_subtotal` applies to the whole cart.
+#[derive(Queryable, Identifiable, Serialize, JsonSchema, Clone)]
+pub struct Coupon {
+    pub id: i32,
+    pub code: String,
+    pub kind: CouponKind,
+    #[schemars(with = "String")]
+    pub value: BigDecimal,
+    pub starts_at: Option<NaiveDateTime>,
+    pub ends_at: Option<NaiveDateTime>,
+    #[schemars(with = "Option<String>")]
+    pub min_subtotal: Option<BigDecimal>,
+    pub max_redemptions: Option<i32>,
+    pub max_redemptions_per_user: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=coupons)]
+pub struct NewCoupon {
+    pub code: String,
+    pub kind: CouponKind,
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub value: BigDecimal,
+    pub starts_at: Option<NaiveDateTime>,
+    pub ends_at: Option<NaiveDateTime>,
+    #[schemars(with = "Option<String>")]
+    pub min_subtotal: Option<BigDecimal>,
+    pub max_redemptions: Option<i32>,
+    pub max_redemptions_per_user: Option<i32>,
+}
+
+#[derive(Queryable, Associations, Identifiable, Debug)]
+#[diesel(belongs_to(Coupon))]
+#[diesel(belongs_to(Item))]
+#[diesel(table_name=coupons_items)]
+pub struct CouponItem {
+    pub id: i32,
+    pub coupon_id: i32,
+    pub item_id: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=coupons_items)]
+pub struct NewCouponItem {
+    pub coupon_id: i32,
+    pub item_id: i32,
+}
+
+// A coupon used by an order, `order_reference` being the order's number
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Coupon))]
+#[diesel(table_name=coupon_redemptions)]
+pub struct CouponRedemption {
+    pub id: i32,
+    pub coupon_id: i32,
+    pub user_id: Option<i32>,
+    pub order_reference: String,
+    #[schemars(with = "String")]
+    pub discount: BigDecimal,
+    pub redeemed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=coupon_redemptions)]
+pub struct NewCouponRedemption {
+    pub coupon_id: i32,
+    pub user_id: Option<i32>,
+    pub order_reference: String,
+    pub discount: BigDecimal,
+}
+
+#[derive(Queryable, Identifiable, Serialize, JsonSchema)]
+#[diesel(table_name=tax_classes)]
+pub struct TaxClass {
+    pub id: i32,
+    pub code: String,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=tax_classes)]
+pub struct NewTaxClass {
+    pub code: String,
+    pub name: String,
+}
+
+// The rate of a tax class in a country, or in one of its regions when `region` is not empty
+#[derive(Queryable, Identifiable, Serialize, JsonSchema, Clone)]
+pub struct TaxRate {
+    pub id: i32,
+    pub tax_class: String,
+    pub country: String,
+    pub region: String,
+    pub name: String,
+    // Percent
+    #[schemars(with = "String")]
+    pub rate: BigDecimal,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=tax_rates)]
+pub struct NewTaxRate {
+    pub tax_class: String,
+    pub country: String,
+    #[serde(default)]
+    pub region: String,
+    pub name: String,
+    #[schemars(with = "String")]
+    pub rate: BigDecimal,
+}
+
+#[derive(Queryable, Identifiable, Serialize, JsonSchema)]
+#[diesel(primary_key(currency))]
+pub struct ExchangeRate {
+    pub currency: Currency,
+    // Units of `currency` per unit of the base currency
+    #[schemars(with = "String")]
+    pub rate: BigDecimal,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=exchange_rates)]
+pub struct NewExchangeRate {
+    pub currency: Currency,
+    pub rate: BigDecimal,
+}
+
+// The price of an item in another currency than the base one, set by hand
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Item))]
+pub struct ItemPrice {
+    pub id: i32,
+    pub item_id: i32,
+    pub price: Money,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=item_prices)]
+pub struct NewItemPrice {
+    pub item_id: i32,
+    pub price: Money,
+}
+
+// A customer's address, owned by the user who created it
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name=addresses)]
+pub struct Address {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    // Who receives the parcel
+    pub name: String,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: String,
+    pub phone: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub is_default_shipping: bool,
+    pub is_default_billing: bool,
+}
+
+// Validated with `addresses::validate` before it is written
+#[derive(Deserialize, Insertable, AsChangeset, JsonSchema)]
+#[diesel(table_name=addresses)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewAddress {
+    #[serde(skip_deserializing)]
+    pub user_id: i32,
+    pub name: String,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: String,
+    pub phone: Option<String>,
+    // Makes it the user's default address of that kind, in place of the current one
+    #[serde(default)]
+    pub is_default_shipping: bool,
+    #[serde(default)]
+    pub is_default_billing: bool,
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ShippingMethodKind {
+    // Always `price`
+    Flat,
+    // By the parcel's weight in grams
+    Weight,
+    // By the cart's subtotal
+    Price,
+}
+
+impl ShippingMethodKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShippingMethodKind::Flat => "flat",
+            ShippingMethodKind::Weight => "weight",
+            ShippingMethodKind::Price => "price",
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for ShippingMethodKind {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"flat" => Ok(ShippingMethodKind::Flat),
+            b"weight" => Ok(ShippingMethodKind::Weight),
+            b"price" => Ok(ShippingMethodKind::Price),
+            _ => Err("Unrecognized shipping method kind".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ShippingMethodKind {
+    fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+// A way to ship orders. Amounts are in the base currency.
+#[derive(Queryable, Identifiable, Serialize, JsonSchema, Clone)]
+pub struct ShippingMethod {
+    pub id: i32,
+    pub code: String,
+    pub name: String,
+    pub kind: ShippingMethodKind,
+    // Charged on every shipment, on top of the rate for `weight` and `price` methods
+    #[schemars(with = "String")]
+    pub price: BigDecimal,
+    // Subtotal from which the method is free
+    #[schemars(with = "Option<String>")]
+    pub free_above: Option<BigDecimal>,
+    // Cubic centimetres per kilogram, for the volumetric weight of bulky items
+    pub volumetric_divisor: Option<i32>,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Insertable, AsChangeset, JsonSchema)]
+#[diesel(table_name=shipping_methods)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewShippingMethod {
+    pub code: String,
+    pub name: String,
+    pub kind: ShippingMethodKind,
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub price: BigDecimal,
+    #[schemars(with = "Option<String>")]
+    pub free_above: Option<BigDecimal>,
+    pub volumetric_divisor: Option<i32>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+#[derive(Queryable, Associations, Identifiable, Debug)]
+#[diesel(belongs_to(ShippingMethod))]
+#[diesel(table_name=shipping_method_countries)]
+pub struct ShippingMethodCountry {
+    pub id: i32,
+    pub shipping_method_id: i32,
+    pub country: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=shipping_method_countries)]
+pub struct NewShippingMethodCountry {
+    pub shipping_method_id: i32,
+    pub country: String,
+}
+
+// A bracket of a `weight` or `price` method, from `min_value` grams or subtotal up to the next bracket
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema, Clone)]
+#[diesel(belongs_to(ShippingMethod))]
+pub struct ShippingRate {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub shipping_method_id: i32,
+    #[schemars(with = "String")]
+    pub min_value: BigDecimal,
+    #[schemars(with = "String")]
+    pub price: BigDecimal,
+}
+
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=shipping_rates)]
+pub struct NewShippingRate {
+    #[serde(skip_deserializing)]
+    pub shipping_method_id: i32,
+    #[schemars(with = "String")]
+    pub min_value: BigDecimal,
+    #[schemars(with = "String")]
+    pub price: BigDecimal,
+}
+
+// Why the stock of an item was adjusted, stored in `inventory_adjustments.reason`
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InventoryReason {
+    // Goods received from a supplier
+    Receipt,
+    // Goods sent back by a customer and put back on sale
+    Return,
+    // A stock count found a different quantity
+    Correction,
+}
+
+impl InventoryReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InventoryReason::Receipt => "receipt",
+            InventoryReason::Return => "return",
+            InventoryReason::Correction => "correction",
+        }
+    }
+}
+
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Item))]
+#[diesel(table_name=inventory_adjustments)]
+pub struct InventoryAdjustment {
+    pub id: i32,
+    pub item_id: i32,
+    pub delta: i32,
+    // The item's stock after the adjustment
+    pub quantity: i32,
+    // One of `InventoryReason`
+    pub reason: String,
+    pub reference: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=inventory_adjustments)]
+pub struct NewInventoryAdjustment {
+    pub item_id: i32,
+    pub delta: i32,
+    pub quantity: i32,
+    pub reason: String,
+    pub reference: Option<String>,
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnStatus {
+    // Waiting for the merchant's decision
+    Requested,
+    // The customer may send the items back
+    Approved,
+    Rejected,
+    // Items arrived back, see the lines' `received_quantity`
+    Received,
+    // The whole refundable amount was refunded
+    Refunded,
+}
+
+impl ReturnStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReturnStatus::Requested => "requested",
+            ReturnStatus::Approved => "approved",
+            ReturnStatus::Rejected => "rejected",
+            ReturnStatus::Received => "received",
+            ReturnStatus::Refunded => "refunded",
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for ReturnStatus {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"requested" => Ok(ReturnStatus::Requested),
+            b"approved" => Ok(ReturnStatus::Approved),
+            b"rejected" => Ok(ReturnStatus::Rejected),
+            b"received" => Ok(ReturnStatus::Received),
+            b"refunded" => Ok(ReturnStatus::Refunded),
+            _ => Err("Unrecognized return status".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ReturnStatus {
+    fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnReason {
+    Damaged,
+    Defective,
+    WrongItem,
+    NotAsDescribed,
+    NoLongerNeeded,
+    Other,
+}
+
+impl ReturnReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReturnReason::Damaged => "damaged",
+            ReturnReason::Defective => "defective",
+            ReturnReason::WrongItem => "wrong_item",
+            ReturnReason::NotAsDescribed => "not_as_described",
+            ReturnReason::NoLongerNeeded => "no_longer_needed",
+            ReturnReason::Other => "other",
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for ReturnReason {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"damaged" => Ok(ReturnReason::Damaged),
+            b"defective" => Ok(ReturnReason::Defective),
+            b"wrong_item" => Ok(ReturnReason::WrongItem),
+            b"not_as_described" => Ok(ReturnReason::NotAsDescribed),
+            b"no_longer_needed" => Ok(ReturnReason::NoLongerNeeded),
+            b"other" => Ok(ReturnReason::Other),
+            _ => Err("Unrecognized return reason".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ReturnReason {
+    fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+// A return merchandise authorization for items of an order, identified by its number
+#[derive(Queryable, Identifiable, Serialize, JsonSchema)]
+#[diesel(table_name=returns)]
+pub struct Return {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub order_reference: String,
+    pub status: ReturnStatus,
+    // Set on approval, refunds add up to it at most
+    pub refundable: Option<Money>,
+    pub note: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=returns)]
+pub struct NewReturn {
+    pub user_id: Option<i32>,
+    pub order_reference: String,
+}
+
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Return))]
+#[diesel(table_name=return_lines)]
+pub struct ReturnLine {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub return_id: i32,
+    // None once the item was deleted
+    pub item_id: Option<i32>,
+    pub quantity: i32,
+    pub reason: ReturnReason,
+    pub comment: Option<String>,
+    pub received_quantity: i32,
+    // Received items put back in stock, the others were not fit for sale
+    pub restocked_quantity: i32,
+}
+
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=return_lines)]
+pub struct NewReturnLine {
+    #[serde(skip_deserializing)]
+    pub return_id: i32,
+    pub item_id: Option<i32>,
+    pub quantity: i32,
+    pub reason: ReturnReason,
+    pub comment: Option<String>,
+}
+
+// Items of a return line that arrived back at the warehouse
+#[derive(Deserialize, JsonSchema)]
+pub struct ReceivedLine {
+    pub line_id: i32,
+    pub quantity: i32,
+    // Puts them back in stock, leave false for items not fit for sale
+    #[serde(default)]
+    pub restock: bool,
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundStatus {
+    // Recorded, to be paid back through the payment provider
+    Pending,
+    // Paid back, `provider_reference` identifies the refund at the provider
+    Issued,
+}
+
+impl RefundStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefundStatus::Pending => "pending",
+            RefundStatus::Issued => "issued",
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for RefundStatus {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"pending" => Ok(RefundStatus::Pending),
+            b"issued" => Ok(RefundStatus::Issued),
+            _ => Err("Unrecognized refund status".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for RefundStatus {
+    fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Return))]
+pub struct Refund {
+    pub id: i32,
+    pub return_id: i32,
+    pub amount: Money,
+    pub status: RefundStatus,
+    pub provider_reference: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub issued_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=refunds)]
+pub struct NewRefund {
+    pub return_id: i32,
+    pub amount: Money,
+}
+
+// An issued invoice. What it says is in its documents, the row keeps what finance looks invoices up by.
+#[derive(Queryable, Identifiable, Serialize, JsonSchema)]
+pub struct Invoice {
+    pub id: i32,
+    pub number: String,
+    pub year: i32,
+    pub sequence: i32,
+    pub user_id: Option<i32>,
+    pub order_reference: String,
+    pub total: Money,
+    pub tax: Money,
+    #[serde(skip_serializing)]
+    pub pdf_key: String,
+    #[serde(skip_serializing)]
+    pub html_key: String,
+    pub issued_at: NaiveDateTime,
+    // The `InvoiceDocument`, None for invoices issued before it was kept
+    #[serde(skip_serializing)]
+    pub document: Option<serde_json::Value>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=invoices)]
+pub struct NewInvoice {
+    pub number: String,
+    pub year: i32,
+    pub sequence: i32,
+    pub user_id: Option<i32>,
+    pub order_reference: String,
+    pub total: Money,
+    pub tax: Money,
+    pub pdf_key: String,
+    pub html_key: String,
+    pub issued_at: NaiveDateTime,
+    pub document: serde_json::Value,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=invoice_items)]
+pub struct NewInvoiceItem {
+    pub invoice_id: i32,
+    pub item_id: i32,
+    pub quantity: i32,
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    // Waiting for a moderator, only its author sees it
+    Pending,
+    // Shown with the item and counted in its rating
+    Approved,
+    Rejected,
+}
+
+impl ReviewStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewStatus::Pending => "pending",
+            ReviewStatus::Approved => "approved",
+            ReviewStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for ReviewStatus {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"pending" => Ok(ReviewStatus::Pending),
+            b"approved" => Ok(ReviewStatus::Approved),
+            b"rejected" => Ok(ReviewStatus::Rejected),
+            _ => Err("Unrecognized review status".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ReviewStatus {
+    fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Item))]
+pub struct Review {
+    pub id: i32,
+    pub item_id: i32,
+    pub user_id: Option<i32>,
+    pub rating: i32,
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub status: ReviewStatus,
+    pub verified_purchase: bool,
+    pub moderation_note: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+// What the author writes, the rest is set by the server
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=reviews)]
+pub struct NewReview {
+    #[serde(skip_deserializing)]
+    pub item_id: i32,
+    #[serde(skip_deserializing)]
+    pub user_id: Option<i32>,
+    // 1 to 5
+    pub rating: i32,
+    pub title: Option<String>,
+    pub body: Option<String>,
+    #[serde(skip_deserializing)]
+    pub verified_purchase: bool,
+}
+
+#[derive(Queryable, Identifiable, Serialize, JsonSchema)]
+pub struct Wishlist {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    // Set while the list is shared, anyone with it can view the list
+    pub share_token: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Insertable, JsonSchema)]
+#[diesel(table_name=wishlists)]
+pub struct NewWishlist {
+    #[serde(skip_deserializing)]
+    pub user_id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Associations, Serialize, JsonSchema)]
+#[diesel(belongs_to(Wishlist))]
+#[diesel(belongs_to(Item))]
+#[diesel(primary_key(wishlist_id, item_id))]
+pub struct WishlistItem {
+    pub wishlist_id: i32,
+    pub item_id: i32,
+    pub quantity: i32,
+    #[schemars(with = "String")]
+    pub added_price: BigDecimal,
+    pub added_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=wishlist_items)]
+pub struct NewWishlistItem {
+    pub wishlist_id: i32,
+    pub item_id: i32,
+    pub quantity: i32,
+    pub added_price: BigDecimal,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NotificationKind {
+    // The price of an item the user wishes for went down
+    PriceDrop,
+    // An item the user wishes for or subscribed to was out of stock and is available again
+    BackInStock,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::PriceDrop => "price_drop",
+            NotificationKind::BackInStock => "back_in_stock",
+        }
+    }
+}
+
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Item))]
+pub struct Notification {
+    pub id: i32,
+    pub user_id: i32,
+    pub item_id: i32,
+    // One of `NotificationKind`
+    pub kind: String,
+    #[schemars(with = "Option<String>")]
+    pub previous_price: Option<BigDecimal>,
+    #[schemars(with = "Option<String>")]
+    pub price: Option<BigDecimal>,
+    pub created_at: NaiveDateTime,
+    // None until the notification was sent
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=notifications)]
+pub struct NewNotification {
+    pub user_id: i32,
+    pub item_id: i32,
+    pub kind: String,
+}
+
+#[derive(Queryable, Associations, Identifiable, Serialize, JsonSchema)]
+#[diesel(belongs_to(Item))]
+pub struct StockSubscription {
+    pub id: i32,
+    pub user_id: i32,
+    pub item_id: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=stock_subscriptions)]
+pub struct NewStockSubscription {
+    pub user_id: i32,
+    pub item_id: i32,
+}
+
+// Turns an item name into a URL-safe slug, e.g. "Blue Shoes (42)" -> "blue-shoes-42"
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    // Leave room for the suffix that makes slugs unique
+    slug.truncate(240);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() { "item".to_string() } else { slug.to_string() }
 }
 
 #[derive(Serialize, Deserialize, Queryable, Identifiable, AsChangeset)]
@@ -91,8 +1197,8 @@ pub struct NewRole {
 }
 
 #[derive(Queryable, Associations, Identifiable, Debug)]
-#[belongs_to(User)]
-#[belongs_to(Role)]
+#[diesel(belongs_to(User))]
+#[diesel(belongs_to(Role))]
 #[diesel(table_name=users_roles)]
 pub struct UserRole {
     pub id: i32,
@@ -108,7 +1214,7 @@ pub struct NewUserRole {
 }
 
 #[derive(AsExpression, FromSqlRow, Serialize, Deserialize, Debug, Clone)]
-#[sql_type="Text"]
+#[diesel(sql_type = Text)]
 pub enum RoleCode {
     Admin,
     User,
@@ -130,11 +1236,11 @@ impl FromStr for RoleCode {
 // Implement the `FromSql` trait for `RoleCode`.
 // We don't need an Error case because there are only two possible states
 // And we know them in advance
-impl ToString for RoleCode {
-    fn to_string(&self) -> String {
+impl fmt::Display for RoleCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            RoleCode::Admin => "admin".to_string(),
-            RoleCode::User => "user".to_string(),
+            RoleCode::Admin => f.write_str("admin"),
+            RoleCode::User => f.write_str("user"),
         }
     }
 }
@@ -159,4 +1265,38 @@ impl ToSql<Text, Pg> for RoleCode {
         }
         Ok(diesel::serialize::IsNull::No)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_keep_lowercase_letters_and_digits() {
+        assert_eq!(slugify("Blue Shoes (42)"), "blue-shoes-42");
+        assert_eq!(slugify("USB-C  /  Lightning cable"), "usb-c-lightning-cable");
+    }
+
+    #[test]
+    fn slugs_have_no_leading_or_trailing_dashes() {
+        assert_eq!(slugify("--Hello,  World!--"), "hello-world");
+        assert_eq!(slugify(" (new) "), "new");
+    }
+
+    #[test]
+    fn other_characters_are_separators() {
+        assert_eq!(slugify("Café Crème"), "caf-cr-me");
+        assert_eq!(slugify("İstanbul"), "stanbul");
+        assert_eq!(slugify("日本"), "item");
+        assert_eq!(slugify(""), "item");
+    }
+
+    #[test]
+    fn slugs_leave_room_for_a_suffix() {
+        assert_eq!(slugify(&"a".repeat(255)), "a".repeat(240));
+        // A cut at a separator does not leave it dangling
+        let slug = slugify(&"ab ".repeat(85));
+        assert_eq!(slug.len(), 239);
+        assert!(slug.ends_with("-ab"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive};
+use diesel::{expression::AsExpression, deserialize::{self, FromSql, FromSqlRow}, pg::{Pg, PgValue}, serialize::{self, Output, ToSql, WriteTuple}, sql_types::{Numeric, Record, Text}};
+use diesel::{OptionalExtension, PgConnection, QueryResult};
+use rocket::fairing::AdHoc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Item, ItemPrice};
+use crate::repository::ExchangeRateRepository;
+use crate::schema::sql_types::MoneyAmount;
+
+// An ISO 4217 currency code, e.g. "EUR". Always 3 upper case letters.
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq, Hash, Debug)]
+#[diesel(sql_type = Text)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency(String);
+
+impl Currency {
+  pub fn code(&self) -> &str {
+    &self.0
+  }
+
+  // Digits after the decimal point, amounts are rounded to them
+  pub fn minor_units(&self) -> i64 {
+    match self.code() {
+      "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+      "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+      _ => 2,
+    }
+  }
+
+  // Rounds to the currency's minor unit, halves away from zero
+  pub fn round(&self, amount: &BigDecimal) -> BigDecimal {
+    amount.with_scale_round(self.minor_units(), RoundingMode::HalfUp)
+  }
+}
+
+impl FromStr for Currency {
+  type Err = String;
+
+  fn from_str(code: &str) -> Result<Self, Self::Err> {
+    let code = code.trim();
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+      Ok(Currency(code.to_ascii_uppercase()))
+    } else {
+      Err(format!("`{}` is not an ISO 4217 currency code", code))
+    }
+  }
+}
+
+impl TryFrom<String> for Currency {
+  type Error = String;
+
+  fn try_from(code: String) -> Result<Self, Self::Error> {
+    code.parse()
+  }
+}
+
+impl From<Currency> for String {
+  fn from(currency: Currency) -> Self {
+    currency.0
+  }
+}
+
+impl fmt::Display for Currency {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl FromSql<Text, Pg> for Currency {
+  fn from_sql(value: PgValue) -> deserialize::Result<Self> {
+    let code = <String as FromSql<Text, Pg>>::from_sql(value)?;
+    Ok(code.parse()?)
+  }
+}
+
+impl ToSql<Text, Pg> for Currency {
+  fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+    out.write_all(self.0.as_bytes())?;
+    Ok(serialize::IsNull::No)
+  }
+}
+
+// An amount in a currency, stored as the `money_amount` composite type
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+#[diesel(sql_type = MoneyAmount)]
+pub struct Money {
+  #[schemars(with = "String")]
+  pub amount: BigDecimal,
+  pub currency: Currency,
+}
+
+impl Money {
+  // Rounds `amount` to the currency's minor unit
+  pub fn new(amount: &BigDecimal, currency: &Currency) -> Self {
+    Money { amount: currency.round(amount), currency: currency.clone() }
+  }
+}
+
+impl FromSql<MoneyAmount, Pg> for Money {
+  fn from_sql(value: PgValue) -> deserialize::Result<Self> {
+    let (amount, currency): (BigDecimal, String) = FromSql::<Record<(Numeric, Text)>, Pg>::from_sql(value)?;
+    Ok(Money { amount, currency: currency.parse()? })
+  }
+}
+
+impl ToSql<MoneyAmount, Pg> for Money {
+  fn to_sql<'b>(&self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+    WriteTuple::<(Numeric, Text)>::write_tuple(&(&self.amount, self.currency.code()), out)
+  }
+}
+
+// The currency of `items.price` and of the amounts of coupons, read from `base_currency`
+pub struct BaseCurrency(pub Currency);
+
+pub fn fairing() -> AdHoc {
+  AdHoc::try_on_ignite("Base currency", |rocket| async {
+    let figment = rocket.figment();
+    let code = match figment.find_value("base_currency") {
+      Ok(_) => figment.extract_inner::<String>("base_currency").map_err(|e| e.to_string()),
+      Err(_) => Ok("USD".to_string()),
+    };
+    match code.and_then(|code| code.parse()) {
+      Ok(currency) => Ok(rocket.manage(BaseCurrency(currency))),
+      Err(e) => {
+        log::error!("Invalid base_currency: {}", e);
+        Err(rocket)
+      }
+    }
+  })
+}
+
+// Converts base currency amounts into the selected currency
+#[derive(Clone)]
+pub struct CurrencyConverter {
+  pub currency: Currency,
+  // Units of `currency` per unit of the base currency
+  pub rate: BigDecimal,
+}
+
+impl CurrencyConverter {
+  // None when `currency` is neither the base currency nor has an exchange rate
+  pub fn load(c: &mut PgConnection, base: &Currency, currency: &Currency) -> QueryResult<Option<Self>> {
+    if currency == base {
+      return Ok(Some(CurrencyConverter { currency: currency.clone(), rate: BigDecimal::from(1) }));
+    }
+    Ok(ExchangeRateRepository::find(c, currency)
+      .optional()?
+      .map(|exchange_rate| CurrencyConverter { currency: exchange_rate.currency, rate: exchange_rate.rate }))
+  }
+
+  pub fn convert(&self, amount: &BigDecimal) -> Money {
+    Money::new(&(amount * &self.rate), &self.currency)
+  }
+
+  pub fn round(&self, amount: &BigDecimal) -> BigDecimal {
+    self.currency.round(amount)
+  }
+
+  // The item's price and "was" price in the currency. A price list entry is the regular price:
+  // it is shown as is, or as the "was" price while a sale converts the sale price.
+  pub fn item_price(&self, item: &Item, list_price: Option<&Money>) -> (Money, Option<Money>) {
+    let list_price = list_price.filter(|price| price.currency == self.currency).cloned();
+    match (&item.was_price, list_price) {
+      (Some(was_price), list_price) => (self.convert(&item.price), Some(list_price.unwrap_or_else(|| self.convert(was_price)))),
+      (None, Some(list_price)) => (list_price, None),
+      (None, None) => (self.convert(&item.price), None),
+    }
+  }
+
+  // `item_price` of many items, given the price list entries of the items in any currency
+  pub fn item_prices(&self, items: &[Item], list_prices: Vec<ItemPrice>) -> Vec<(Money, Option<Money>)> {
+    let by_item: HashMap<i32, Money> = list_prices.into_iter()
+      .filter(|entry| entry.price.currency == self.currency)
+      .map(|entry| (entry.item_id, entry.price))
+      .collect();
+    items.iter().map(|item| self.item_price(item, by_item.get(&item.id))).collect()
+  }
+}
+
+// Splits `total`, a whole number of minor units (`scale` digits), following the `exact` shares. Shares
+// are rounded down and the units left over go to the shares that lost the most to rounding, so the
+// parts add up to `total` and none is more than a unit away from its exact share.
+pub fn allocate(total: &BigDecimal, exact: &[BigDecimal], scale: i64) -> Vec<BigDecimal> {
+  let mut parts: Vec<BigDecimal> = exact.iter().map(|share| share.with_scale_round(scale, RoundingMode::Down)).collect();
+
+  let unit = BigDecimal::new(1.into(), scale);
+  let leftover = total - parts.iter().sum::<BigDecimal>();
+  let leftover_units = (leftover / &unit).to_usize().unwrap_or(0);
+  let mut by_remainder: Vec<usize> = (0..exact.len()).collect();
+  by_remainder.sort_by(|a, b| (&exact[*b] - &parts[*b]).cmp(&(&exact[*a] - &parts[*a])));
+  for i in by_remainder.into_iter().take(leftover_units) {
+    parts[i] += &unit;
+  }
+  parts
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn decimal(value: &str) -> BigDecimal {
+    value.parse().unwrap()
+  }
+
+  fn currency(code: &str) -> Currency {
+    code.parse().unwrap()
+  }
+
+  #[test]
+  fn round_halves_away_from_zero_without_minor_units() {
+    let yen = currency("JPY");
+    assert_eq!(yen.round(&decimal("2.5")), decimal("3"));
+    assert_eq!(yen.round(&decimal("-2.5")), decimal("-3"));
+    assert_eq!(yen.round(&decimal("2.49")), decimal("2"));
+    assert_eq!(yen.round(&decimal("1234.5")).to_string(), "1235");
+  }
+
+  #[test]
+  fn round_halves_away_from_zero_with_three_minor_units() {
+    let dinar = currency("KWD");
+    assert_eq!(dinar.round(&decimal("1.0005")), decimal("1.001"));
+    assert_eq!(dinar.round(&decimal("-1.0005")), decimal("-1.001"));
+    assert_eq!(dinar.round(&decimal("1.0004")), decimal("1.000"));
+    assert_eq!(dinar.round(&decimal("7")).to_string(), "7.000");
+  }
+
+  #[test]
+  fn round_to_cents_by_default() {
+    let euro = currency("eur");
+    assert_eq!(euro.code(), "EUR");
+    assert_eq!(euro.round(&decimal("0.125")), decimal("0.13"));
+    assert_eq!(euro.round(&decimal("-0.125")), decimal("-0.13"));
+  }
+
+  #[test]
+  fn allocate_gives_leftover_units_to_the_largest_remainders() {
+    let third = decimal("10") / decimal("3");
+    let parts = allocate(&decimal("10.00"), &[third.clone(), third.clone(), third], 2);
+    assert_eq!(parts, vec![decimal("3.34"), decimal("3.33"), decimal("3.33")]);
+
+    let parts = allocate(&decimal("1.00"), &[decimal("0.104"), decimal("0.447"), decimal("0.449")], 2);
+    assert_eq!(parts, vec![decimal("0.10"), decimal("0.45"), decimal("0.45")]);
+    assert_eq!(parts.iter().sum::<BigDecimal>(), decimal("1.00"));
+  }
+
+  #[test]
+  fn allocate_whole_units() {
+    let exact = [decimal("333.33"), decimal("333.33"), decimal("333.34")];
+    let parts = allocate(&decimal("1000"), &exact, 0);
+    assert_eq!(parts.iter().sum::<BigDecimal>(), decimal("1000"));
+    for (part, share) in parts.iter().zip(&exact) {
+      assert!((part - share).abs() < decimal("1"));
+    }
+  }
+
+  #[test]
+  fn convert_rounds_to_the_target_currency() {
+    let converter = CurrencyConverter { currency: currency("JPY"), rate: decimal("161.25") };
+    assert_eq!(converter.convert(&decimal("9.99")), Money { amount: decimal("1611"), currency: currency("JPY") });
+  }
+}
@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::sync::LazyLock;
+
+mod font;
+
+use font::Font;
+
+// A4 in points
+pub const PAGE_WIDTH: f32 = 595.0;
+pub const PAGE_HEIGHT: f32 = 842.0;
+
+// Text is set in DejaVu Sans Mono, embedded in each document so that it shows the same everywhere. Being
+// monospaced, every glyph is 1233/2048 em wide, which is what lets text be aligned without font metrics.
+static FONTS: LazyLock<[Font; 2]> = LazyLock::new(|| [
+  Font::parse("DejaVuSansMono", include_bytes!("../fonts/DejaVuSansMono.ttf")).expect("DejaVu Sans Mono is a TrueType font"),
+  Font::parse("DejaVuSansMono-Bold", include_bytes!("../fonts/DejaVuSansMono-Bold.ttf")).expect("DejaVu Sans Mono Bold is a TrueType font"),
+]);
+const CHAR_WIDTH_EM: f32 = 1233.0 / 2048.0;
+
+// One page of text and rules. Coordinates are in points from the bottom left corner.
+#[derive(Default)]
+pub struct Page {
+  content: Vec<u8>,
+  // The glyphs used of the regular and the bold font, with the character each stands for
+  glyphs: [BTreeMap<u16, char>; 2],
+}
+
+impl Page {
+  pub fn text(&mut self, x: f32, y: f32, size: f32, bold: bool, text: &str) {
+    let font = bold as usize;
+    let _ = write!(self.content, "BT /F{} {} Tf {:.2} {:.2} Td <", font + 1, size, x, y);
+    for c in text.chars() {
+      // Characters the font lacks, such as CJK, are set as `?`; the HTML documents show them
+      let (glyph, c) = match FONTS[font].glyph(c) {
+        Some(glyph) => (glyph, c),
+        None => (FONTS[font].glyph('?').unwrap_or(0), '?'),
+      };
+      self.glyphs[font].entry(glyph).or_insert(c);
+      let _ = write!(self.content, "{:04X}", glyph);
+    }
+    self.content.extend_from_slice(b"> Tj ET\n");
+  }
+
+  // Text ending at `x`
+  pub fn text_right(&mut self, x: f32, y: f32, size: f32, bold: bool, text: &str) {
+    self.text(x - text_width(text, size), y, size, bold, text);
+  }
+
+  pub fn rule(&mut self, x1: f32, x2: f32, y: f32) {
+    let _ = writeln!(self.content, "0.5 w {:.2} {:.2} m {:.2} {:.2} l S", x1, y, x2, y);
+  }
+}
+
+pub fn text_width(text: &str, size: f32) -> f32 {
+  text.chars().count() as f32 * CHAR_WIDTH_EM * size
+}
+
+// `text` cut to fit in `width`, ending with `...` when it was cut
+pub fn fit(text: &str, width: f32, size: f32) -> String {
+  let max_chars = (width / (CHAR_WIDTH_EM * size)) as usize;
+  if text.chars().count() <= max_chars {
+    return text.to_string();
+  }
+  let mut fitted: String = text.chars().take(max_chars.saturating_sub(3)).collect();
+  fitted.push_str("...");
+  fitted
+}
+
+// The characters of `text` the font has no glyph for, each once. Pages show them as `?`.
+pub fn unprintable(text: &str) -> Vec<char> {
+  let mut missing: Vec<char> = Vec::new();
+  for c in text.chars().filter(|c| FONTS[0].glyph(*c).is_none()) {
+    if !missing.contains(&c) {
+      missing.push(c);
+    }
+  }
+  missing
+}
+
+// Text as a PDF text string, in UTF-16 so that any character can be part of it
+fn text_string(text: &str) -> String {
+  let units: String = text.encode_utf16().map(|unit| format!("{:04X}", unit)).collect();
+  format!("<FEFF{}>", units)
+}
+
+// Tag of a font subset, six capital letters that change with the glyphs it has
+fn subset_tag(glyphs: &BTreeMap<u16, char>) -> String {
+  let mut hash: u32 = 0x811c9dc5;
+  for glyph in glyphs.keys() {
+    for byte in glyph.to_be_bytes() {
+      hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+    }
+  }
+  (0..6).map(|i| (b'A' + (hash >> (5 * i) & 0x1f) as u8 % 26) as char).collect()
+}
+
+// Maps the glyphs back to their characters, so that text can be searched and copied
+fn to_unicode(glyphs: &BTreeMap<u16, char>) -> Vec<u8> {
+  let mut cmap = String::from("/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+    /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+    /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+    1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n");
+  let glyphs: Vec<(&u16, &char)> = glyphs.iter().collect();
+  // At most 100 mappings per block
+  for block in glyphs.chunks(100) {
+    cmap.push_str(&format!("{} beginbfchar\n", block.len()));
+    for (glyph, c) in block {
+      let units: String = c.encode_utf16(&mut [0; 2]).iter().map(|unit| format!("{:04X}", unit)).collect();
+      cmap.push_str(&format!("<{:04X}> <{}>\n", glyph, units));
+    }
+    cmap.push_str("endbfchar\n");
+  }
+  cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+  cmap.into_bytes()
+}
+
+fn stream(dictionary: &str, data: &[u8]) -> Vec<u8> {
+  [
+    format!("<< /Length {}{} >>\nstream\n", data.len(), dictionary).as_bytes(),
+    data,
+    b"\nendstream",
+  ].concat()
+}
+
+// The objects embedding a subset of `font` with `glyphs`, numbered from `first`: the composite font pages
+// refer to, its descendant font and descriptor, the font file and the map back to Unicode
+fn font_objects(font: &Font, glyphs: &BTreeMap<u16, char>, first: usize) -> Vec<Vec<u8>> {
+  let name = format!("{}+{}", subset_tag(glyphs), font.name);
+  let scale = |units: i32| units * 1000 / font.units_per_em as i32;
+  let widths: String = glyphs.keys()
+    .map(|glyph| format!("{} [{}]", glyph, scale(font.advance(*glyph) as i32)))
+    .collect::<Vec<_>>()
+    .join(" ");
+  let [x_min, y_min, x_max, y_max] = font.bbox.map(|units| scale(units as i32));
+  let file = font.subset(&glyphs.keys().copied().collect::<BTreeSet<u16>>());
+  vec![
+    format!("<< /Type /Font /Subtype /Type0 /BaseFont /{} /Encoding /Identity-H /DescendantFonts [{} 0 R] /ToUnicode {} 0 R >>",
+      name, first + 1, first + 4).into_bytes(),
+    format!("<< /Type /Font /Subtype /CIDFontType2 /BaseFont /{} /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> \
+      /FontDescriptor {} 0 R /CIDToGIDMap /Identity /W [{}] >>", name, first + 2, widths).into_bytes(),
+    format!("<< /Type /FontDescriptor /FontName /{} /Flags 5 /FontBBox [{} {} {} {}] /ItalicAngle 0 /Ascent {} /Descent {} \
+      /CapHeight {} /StemV 80 /FontFile2 {} 0 R >>",
+      name, x_min, y_min, x_max, y_max, scale(font.ascent as i32), scale(font.descent as i32), scale(font.ascent as i32), first + 3).into_bytes(),
+    stream(&format!(" /Length1 {}", file.len()), &file),
+    stream("", &to_unicode(glyphs)),
+  ]
+}
+
+// A document of text pages, enough for invoices and other printouts without a PDF library
+pub struct Document {
+  pub title: String,
+  pub pages: Vec<Page>,
+}
+
+impl Document {
+  pub fn to_bytes(&self) -> Vec<u8> {
+    // 1: catalog, 2: page tree, 3: info, 4 to 13: both fonts, then each page and its content
+    let mut glyphs: [BTreeMap<u16, char>; 2] = Default::default();
+    for page in &self.pages {
+      for (used, page_glyphs) in glyphs.iter_mut().zip(&page.glyphs) {
+        used.extend(page_glyphs);
+      }
+    }
+    let first_page = 14;
+    let mut objects: Vec<Vec<u8>> = vec![
+      b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+      format!("<< /Type /Pages /Kids [{}] /Count {} >>",
+        (0..self.pages.len()).map(|i| format!("{} 0 R", first_page + 2 * i)).collect::<Vec<_>>().join(" "),
+        self.pages.len()).into_bytes(),
+      format!("<< /Title {} >>", text_string(&self.title)).into_bytes(),
+    ];
+    objects.extend(font_objects(&FONTS[0], &glyphs[0], 4));
+    objects.extend(font_objects(&FONTS[1], &glyphs[1], 9));
+    for (i, page) in self.pages.iter().enumerate() {
+      objects.push(format!(
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 4 0 R /F2 9 0 R >> >> /Contents {} 0 R >>",
+        PAGE_WIDTH, PAGE_HEIGHT, first_page + 2 * i + 1,
+      ).into_bytes());
+      objects.push(stream("", &page.content));
+    }
+
+    let mut bytes = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+      offsets.push(bytes.len());
+      let _ = writeln!(bytes, "{} 0 obj", i + 1);
+      bytes.extend_from_slice(object);
+      bytes.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = bytes.len();
+    let _ = write!(bytes, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+      let _ = writeln!(bytes, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(bytes, "trailer\n<< /Size {} /Root 1 0 R /Info 3 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref);
+    bytes
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn every_character_is_as_wide() {
+    for font in FONTS.iter() {
+      for c in "Aa0 .,?(€)Łжω→".chars() {
+        let width = font.advance(font.glyph(c).unwrap()) as f32 / font.units_per_em as f32;
+        assert_eq!(width, CHAR_WIDTH_EM, "{} of {}", c, font.name);
+      }
+    }
+  }
+
+  #[test]
+  fn reports_each_unprintable_character_once() {
+    assert_eq!(unprintable("Łódź, Ελλάδα, Москва → 5 €"), Vec::<char>::new());
+    assert_eq!(unprintable("東京 東 \u{1F600}"), vec!['東', '京', '\u{1F600}']);
+  }
+
+  #[test]
+  fn sets_text_in_glyphs_it_can_map_back() {
+    let mut page = Page::default();
+    page.text(0.0, 0.0, 8.0, false, "Жук (東)");
+    let glyph = |c| FONTS[0].glyph(c).unwrap();
+    let expected: String = ['Ж', 'у', 'к', ' ', '(', '?', ')'].iter().map(|c| format!("{:04X}", glyph(*c))).collect();
+    assert!(String::from_utf8_lossy(&page.content).contains(&format!("<{}> Tj", expected)));
+    assert_eq!(page.glyphs[0].get(&glyph('Ж')), Some(&'Ж'));
+    assert_eq!(page.glyphs[0].get(&glyph('?')), Some(&'?'));
+    assert!(page.glyphs[1].is_empty());
+  }
+
+  #[test]
+  fn embeds_the_fonts_used() {
+    let mut page = Page::default();
+    page.text(0.0, 0.0, 8.0, true, "Ωmega");
+    let bytes = Document { title: "Ωmega".to_string(), pages: vec![page] }.to_bytes();
+    let text = String::from_utf8_lossy(&bytes);
+    assert!(text.starts_with("%PDF-1.4"));
+    assert!(text.contains("+DejaVuSansMono-Bold /Encoding /Identity-H"));
+    assert_eq!(text.matches("/FontFile2").count(), 2);
+    assert!(text.contains("/Title <FEFF03A9006D006500670061>"));
+    assert!(text.contains(&format!("<{:04X}> <03A9>", FONTS[1].glyph('Ω').unwrap())));
+  }
+}
@@ -0,0 +1,312 @@
+// Reads a TrueType font far enough to embed it in a PDF: the glyph of each character, glyph widths, and
+// subsets that keep the glyph ids but drop the outlines of glyphs a document does not use
+use std::collections::BTreeSet;
+
+pub struct Font {
+  pub name: &'static str,
+  data: &'static [u8],
+  // Tag, offset and length of each table
+  tables: Vec<([u8; 4], usize, usize)>,
+  pub units_per_em: u16,
+  pub bbox: [i16; 4],
+  pub ascent: i16,
+  pub descent: i16,
+  long_loca: bool,
+  num_glyphs: u16,
+  num_h_metrics: u16,
+  // Offset of the Unicode character map, format 4 or 12. Subsets have none.
+  cmap: Option<usize>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+  data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn i16_at(data: &[u8], offset: usize) -> Option<i16> {
+  u16_at(data, offset).map(|value| value as i16)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+  data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Composite glyph flags, see the `glyf` table
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+// Tables a PDF reader needs to draw the glyphs of an embedded TrueType font
+const SUBSET_TABLES: [&[u8; 4]; 9] = [b"cvt ", b"fpgm", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"prep"];
+
+impl Font {
+  // None when `data` is not a TrueType font
+  pub fn parse(name: &'static str, data: &'static [u8]) -> Option<Self> {
+    let num_tables = u16_at(data, 4)?;
+    let tables = (0..num_tables as usize)
+      .map(|i| {
+        let record = 12 + 16 * i;
+        let tag = data.get(record..record + 4)?.try_into().ok()?;
+        Some((tag, u32_at(data, record + 8)? as usize, u32_at(data, record + 12)? as usize))
+      })
+      .collect::<Option<Vec<_>>>()?;
+    let mut font = Font {
+      name,
+      data,
+      tables,
+      units_per_em: 0,
+      bbox: [0; 4],
+      ascent: 0,
+      descent: 0,
+      long_loca: false,
+      num_glyphs: 0,
+      num_h_metrics: 0,
+      cmap: None,
+    };
+
+    let head = font.table(b"head")?;
+    font.units_per_em = u16_at(head, 18)?;
+    font.bbox = [i16_at(head, 36)?, i16_at(head, 38)?, i16_at(head, 40)?, i16_at(head, 42)?];
+    font.long_loca = i16_at(head, 50)? == 1;
+    let hhea = font.table(b"hhea")?;
+    font.ascent = i16_at(hhea, 4)?;
+    font.descent = i16_at(hhea, 6)?;
+    font.num_h_metrics = u16_at(hhea, 34)?;
+    font.num_glyphs = u16_at(font.table(b"maxp")?, 4)?;
+    font.cmap = font.unicode_cmap();
+    font.table(b"glyf")?;
+    font.table(b"loca")?;
+    font.table(b"hmtx")?;
+    Some(font)
+  }
+
+  fn table_range(&self, tag: &[u8; 4]) -> Option<(usize, usize)> {
+    self.tables.iter().find(|(found, _, _)| found == tag).map(|(_, offset, length)| (*offset, *length))
+  }
+
+  fn table(&self, tag: &[u8; 4]) -> Option<&'static [u8]> {
+    let (offset, length) = self.table_range(tag)?;
+    self.data.get(offset..offset + length)
+  }
+
+  // The full Unicode map (3, 10) in format 12 when there is one, the BMP map (3, 1) in format 4 otherwise
+  fn unicode_cmap(&self) -> Option<usize> {
+    let (cmap, _) = self.table_range(b"cmap")?;
+    let subtables: Vec<(u16, u16, usize)> = (0..u16_at(self.data, cmap + 2)? as usize)
+      .map(|i| {
+        let record = cmap + 4 + 8 * i;
+        Some((u16_at(self.data, record)?, u16_at(self.data, record + 2)?, cmap + u32_at(self.data, record + 4)? as usize))
+      })
+      .collect::<Option<_>>()?;
+    let find = |platform, encoding, format| subtables.iter()
+      .find(|(p, e, offset)| *p == platform && *e == encoding && u16_at(self.data, *offset) == Some(format))
+      .map(|(_, _, offset)| *offset);
+    find(3, 10, 12).or_else(|| find(3, 1, 4))
+  }
+
+  // The glyph the font draws `c` with, None when it has none
+  pub fn glyph(&self, c: char) -> Option<u16> {
+    let cmap = self.cmap?;
+    let glyph = match u16_at(self.data, cmap)? {
+      12 => self.glyph_in_groups(cmap, c as u32)?,
+      _ => self.glyph_in_segments(cmap, u16::try_from(c as u32).ok()?)?,
+    };
+    (glyph != 0).then_some(glyph)
+  }
+
+  fn glyph_in_groups(&self, cmap: usize, c: u32) -> Option<u16> {
+    let groups = u32_at(self.data, cmap + 12)? as usize;
+    (0..groups).find_map(|i| {
+      let group = cmap + 16 + 12 * i;
+      let (start, end) = (u32_at(self.data, group)?, u32_at(self.data, group + 4)?);
+      let first_glyph = u32_at(self.data, group + 8)?;
+      (start..=end).contains(&c).then(|| (first_glyph + c - start) as u16)
+    })
+  }
+
+  fn glyph_in_segments(&self, cmap: usize, c: u16) -> Option<u16> {
+    let segments = u16_at(self.data, cmap + 6)? as usize / 2;
+    let ends = cmap + 14;
+    let starts = ends + 2 * segments + 2;
+    let deltas = starts + 2 * segments;
+    let range_offsets = deltas + 2 * segments;
+    let segment = (0..segments).find(|i| u16_at(self.data, ends + 2 * i).is_some_and(|end| end >= c))?;
+    let start = u16_at(self.data, starts + 2 * segment)?;
+    if start > c {
+      return None;
+    }
+    let delta = u16_at(self.data, deltas + 2 * segment)?;
+    let range_offset = u16_at(self.data, range_offsets + 2 * segment)?;
+    if range_offset == 0 {
+      return Some(c.wrapping_add(delta));
+    }
+    let glyph = u16_at(self.data, range_offsets + 2 * segment + range_offset as usize + 2 * (c - start) as usize)?;
+    (glyph != 0).then(|| glyph.wrapping_add(delta))
+  }
+
+  // Advance width of the glyph, in font units
+  pub fn advance(&self, glyph: u16) -> u16 {
+    let metric = glyph.min(self.num_h_metrics.saturating_sub(1)) as usize;
+    self.table(b"hmtx").and_then(|hmtx| u16_at(hmtx, 4 * metric)).unwrap_or(0)
+  }
+
+  // Where the outline of the glyph is in the `glyf` table
+  fn outline_range(&self, glyph: u16) -> Option<(usize, usize)> {
+    let loca = self.table(b"loca")?;
+    let glyph = glyph as usize;
+    if self.long_loca {
+      Some((u32_at(loca, 4 * glyph)? as usize, u32_at(loca, 4 * glyph + 4)? as usize))
+    } else {
+      Some((2 * u16_at(loca, 2 * glyph)? as usize, 2 * u16_at(loca, 2 * glyph + 2)? as usize))
+    }
+  }
+
+  fn outline(&self, glyph: u16) -> &'static [u8] {
+    let glyf = self.table(b"glyf").unwrap_or_default();
+    self.outline_range(glyph)
+      .and_then(|(start, end)| glyf.get(start..end))
+      .unwrap_or_default()
+  }
+
+  // Glyphs a composite glyph is made of
+  fn components(&self, glyph: u16) -> Vec<u16> {
+    let outline = self.outline(glyph);
+    if i16_at(outline, 0).is_none_or(|contours| contours >= 0) {
+      return Vec::new();
+    }
+    let mut components = Vec::new();
+    let mut offset = 10;
+    while let (Some(flags), Some(component)) = (u16_at(outline, offset), u16_at(outline, offset + 2)) {
+      components.push(component);
+      offset += 4 + if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+      offset += match flags {
+        _ if flags & WE_HAVE_A_SCALE != 0 => 2,
+        _ if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 => 4,
+        _ if flags & WE_HAVE_A_TWO_BY_TWO != 0 => 8,
+        _ => 0,
+      };
+      if flags & MORE_COMPONENTS == 0 {
+        break;
+      }
+    }
+    components
+  }
+
+  // The font with the outlines of `glyphs`, the glyphs they are composed of and `.notdef`. Glyph ids stay
+  // the same, so that text can refer to them directly.
+  pub fn subset(&self, glyphs: &BTreeSet<u16>) -> Vec<u8> {
+    let mut kept: BTreeSet<u16> = BTreeSet::from([0]);
+    let mut pending: Vec<u16> = glyphs.iter().copied().collect();
+    while let Some(glyph) = pending.pop() {
+      if glyph < self.num_glyphs && kept.insert(glyph) {
+        pending.extend(self.components(glyph));
+      }
+    }
+
+    let mut glyf = Vec::new();
+    let mut loca = Vec::with_capacity(4 * (self.num_glyphs as usize + 1));
+    for glyph in 0..self.num_glyphs {
+      loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+      if kept.contains(&glyph) {
+        glyf.extend_from_slice(self.outline(glyph));
+        glyf.resize(glyf.len().next_multiple_of(4), 0);
+      }
+    }
+    loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+    let mut head = self.table(b"head").unwrap_or_default().to_vec();
+    // Offsets in `loca` are now 32 bits, the checksum adjustment is set once the file is assembled
+    head[8..12].fill(0);
+    head[50..52].copy_from_slice(&1u16.to_be_bytes());
+
+    let tables: Vec<(&[u8; 4], Vec<u8>)> = SUBSET_TABLES.iter()
+      .filter_map(|tag| match *tag {
+        b"glyf" => Some((*tag, std::mem::take(&mut glyf))),
+        b"loca" => Some((*tag, std::mem::take(&mut loca))),
+        b"head" => Some((*tag, std::mem::take(&mut head))),
+        _ => self.table(tag).map(|table| (*tag, table.to_vec())),
+      })
+      .collect();
+    let mut font = assemble(&tables);
+    let (head_offset, _) = table_record(&font, b"head").unwrap_or_default();
+    let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&font));
+    font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    font
+  }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+  data.chunks(4).fold(0u32, |sum, chunk| {
+    let mut word = [0; 4];
+    word[..chunk.len()].copy_from_slice(chunk);
+    sum.wrapping_add(u32::from_be_bytes(word))
+  })
+}
+
+fn table_record(font: &[u8], tag: &[u8; 4]) -> Option<(usize, usize)> {
+  (0..u16_at(font, 4)? as usize)
+    .map(|i| 12 + 16 * i)
+    .find(|record| font.get(*record..*record + 4) == Some(tag.as_slice()))
+    .and_then(|record| Some((u32_at(font, record + 8)? as usize, u32_at(font, record + 12)? as usize)))
+}
+
+// A font file of `tables`, which are sorted by tag
+fn assemble(tables: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+  let count = tables.len() as u16;
+  let entry_selector = 15 - count.leading_zeros() as u16;
+  let search_range = 16 << entry_selector;
+  let mut font = Vec::new();
+  font.extend_from_slice(&0x00010000u32.to_be_bytes());
+  for value in [count, search_range, entry_selector, count * 16 - search_range] {
+    font.extend_from_slice(&value.to_be_bytes());
+  }
+  let mut offset = 12 + 16 * tables.len();
+  for (tag, table) in tables {
+    font.extend_from_slice(*tag);
+    font.extend_from_slice(&checksum(table).to_be_bytes());
+    font.extend_from_slice(&(offset as u32).to_be_bytes());
+    font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+    offset += table.len().next_multiple_of(4);
+  }
+  for (_, table) in tables {
+    font.extend_from_slice(table);
+    font.resize(font.len().next_multiple_of(4), 0);
+  }
+  font
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn font() -> Font {
+    Font::parse("DejaVuSansMono", include_bytes!("../../fonts/DejaVuSansMono.ttf")).expect("a TrueType font")
+  }
+
+  #[test]
+  fn finds_glyphs_of_many_scripts() {
+    let font = font();
+    for c in ['A', 'é', 'Ł', 'ж', 'Ω', '→', '€'] {
+      assert!(font.glyph(c).is_some(), "no glyph for {}", c);
+    }
+    assert_ne!(font.glyph('A'), font.glyph('B'));
+    assert_eq!(font.glyph('東'), None);
+    assert_eq!(font.glyph('\u{1F600}'), None);
+  }
+
+  #[test]
+  fn subsets_keep_only_the_outlines_used() {
+    let font = font();
+    let glyphs: BTreeSet<u16> = "Łódź".chars().filter_map(|c| font.glyph(c)).collect();
+    let data: &'static [u8] = Box::leak(font.subset(&glyphs).into_boxed_slice());
+    let subset = Font::parse("subset", data).expect("a TrueType font");
+    assert!(data.len() < font.data.len() / 10);
+    assert_eq!(checksum(data), 0xB1B0AFBA);
+    for glyph in &glyphs {
+      assert_eq!(subset.outline(*glyph), font.outline(*glyph));
+      assert_eq!(subset.advance(*glyph), font.advance(*glyph));
+    }
+    let unused = font.glyph('Q').unwrap();
+    assert!(subset.outline(unused).is_empty());
+  }
+}
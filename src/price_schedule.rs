@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::fairing::AdHoc;
+
+use crate::repository::ScheduledPriceRepository;
+use crate::rocket_routes::DbConn;
+
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+
+// Applies scheduled prices. The interval is read from `price_schedule_interval` (seconds, 0 disables it),
+// prices therefore change up to that long after their scheduled time.
+pub fn fairing() -> AdHoc {
+  AdHoc::on_liftoff("Scheduled prices", |rocket| Box::pin(async move {
+    let interval = rocket.figment()
+      .extract_inner::<u64>("price_schedule_interval")
+      .unwrap_or(DEFAULT_INTERVAL_SECS);
+    if interval == 0 {
+      return;
+    }
+
+    let pool = match DbConn::pool(rocket) {
+      Some(pool) => pool.clone(),
+      None => return log::error!("Scheduled prices require the postgres pool"),
+    };
+
+    rocket::tokio::spawn(async move {
+      let mut ticker = rocket::tokio::time::interval(Duration::from_secs(interval));
+      loop {
+        ticker.tick().await;
+        let result = match pool.get().await {
+          Some(conn) => conn.run(|c| ScheduledPriceRepository::apply_due(c, Utc::now().naive_utc())).await,
+          None => {
+            log::error!("Cannot connect to postgres to apply scheduled prices");
+            continue;
+          }
+        };
+        match result {
+          Ok((0, 0)) => (),
+          Ok((started, ended)) => log::info!("Scheduled prices: {} started, {} ended", started, ended),
+          Err(e) => log::error!("Failed to apply scheduled prices: {}", e),
+        }
+      }
+    });
+  }))
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::NaiveDateTime;
+use diesel::{PgConnection, QueryResult};
+use diesel::result::Error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Coupon, CouponKind, Item};
+use crate::money::{allocate, CurrencyConverter, Money};
+use crate::repository::{ItemPriceRepository, ItemRepository};
+
+// An item and how many of it the customer buys, as sent by clients
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct CartItem {
+  pub item_id: i32,
+  pub quantity: i32,
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct CartLine {
+  pub item_id: i32,
+  #[schemars(with = "String")]
+  pub unit_price: BigDecimal,
+  pub quantity: i32,
+  pub tax_class: String,
+}
+
+impl CartLine {
+  pub fn amount(&self) -> BigDecimal {
+    &self.unit_price * BigDecimal::from(self.quantity)
+  }
+}
+
+// Amounts of a cart are in the currency of its converter
+pub struct Cart {
+  pub lines: Vec<CartLine>,
+  pub shipping: BigDecimal,
+  pub converter: CurrencyConverter,
+}
+
+impl Cart {
+  // Prices the items at their current price in the converter's currency, `shipping` is in that
+  // currency already. Unknown items fail with `NotFound`.
+  pub fn price(c: &mut PgConnection, items: &[CartItem], shipping: BigDecimal, converter: CurrencyConverter) -> QueryResult<Cart> {
+    let ids = items.iter().map(|item| item.item_id).collect();
+    let found = ItemRepository::find_by_ids(c, ids)?;
+    let list_prices = ItemPriceRepository::find_by_items(c, &found, &converter.currency)?;
+    let found: HashMap<i32, (Item, Money)> = converter.item_prices(&found, list_prices)
+      .into_iter()
+      .zip(found)
+      .map(|((price, _), item)| (item.id, (item, price)))
+      .collect();
+
+    let lines = items.iter()
+      .map(|item| {
+        let (found, price) = found.get(&item.item_id).ok_or(Error::NotFound)?;
+        Ok(CartLine {
+          item_id: item.item_id,
+          unit_price: price.amount.clone(),
+          quantity: item.quantity,
+          tax_class: found.tax_class.clone(),
+        })
+      })
+      .collect::<QueryResult<_>>()?;
+    Ok(Cart { lines, shipping: converter.round(&shipping), converter })
+  }
+
+  pub fn subtotal(&self) -> BigDecimal {
+    self.lines.iter().map(CartLine::amount).sum()
+  }
+}
+
+// How often a coupon was redeemed, overall and by the customer evaluating it
+pub struct Usage {
+  pub total: i64,
+  pub by_user: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct LineDiscount {
+  pub item_id: i32,
+  #[schemars(with = "String")]
+  pub amount: BigDecimal,
+}
+
+// What a coupon takes off a cart: one entry per cart line, in the cart's order, and the shipping
+#[derive(Serialize, JsonSchema)]
+pub struct Discount {
+  pub lines: Vec<LineDiscount>,
+  #[schemars(with = "String")]
+  pub shipping: BigDecimal,
+  #[schemars(with = "String")]
+  pub total: BigDecimal,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+  NotStarted,
+  Expired,
+  Exhausted,
+  UserLimitReached,
+  // The minimum in the cart's currency
+  BelowMinimum(Money),
+  NoEligibleItems,
+}
+
+impl Rejection {
+  // Stable identifier for clients, the message may change
+  pub fn code(&self) -> &'static str {
+    match self {
+      Rejection::NotStarted => "not_started",
+      Rejection::Expired => "expired",
+      Rejection::Exhausted => "exhausted",
+      Rejection::UserLimitReached => "user_limit_reached",
+      Rejection::BelowMinimum(_) => "below_minimum",
+      Rejection::NoEligibleItems => "no_eligible_items",
+    }
+  }
+}
+
+impl fmt::Display for Rejection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Rejection::NotStarted => f.write_str("This code is not valid yet"),
+      Rejection::Expired => f.write_str("This code has expired"),
+      Rejection::Exhausted => f.write_str("This code has been used up"),
+      Rejection::UserLimitReached => f.write_str("You already used this code"),
+      Rejection::BelowMinimum(minimum) => write!(f, "This code requires a subtotal of at least {} {}", minimum.amount, minimum.currency),
+      Rejection::NoEligibleItems => f.write_str("This code does not apply to any item of the cart"),
+    }
+  }
+}
+
+// Spreads `amount` over the eligible lines in proportion to their amount, see `allocate`. The amount
+// is capped at the eligible total so that no line gets more off than it costs.
+fn spread(amount: &BigDecimal, lines: &[CartLine], eligible: &[bool], scale: i64) -> Vec<BigDecimal> {
+  let eligible_total: BigDecimal = lines.iter()
+    .zip(eligible)
+    .filter(|(_, eligible)| **eligible)
+    .map(|(line, _)| line.amount())
+    .sum();
+  if eligible_total.is_zero() {
+    return vec![BigDecimal::zero(); lines.len()];
+  }
+  let amount = if *amount > eligible_total { eligible_total.clone() } else { amount.clone() };
+
+  let exact: Vec<BigDecimal> = lines.iter()
+    .zip(eligible)
+    .map(|(line, eligible)| if *eligible { &amount * line.amount() / &eligible_total } else { BigDecimal::zero() })
+    .collect();
+  allocate(&amount, &exact, scale)
+}
+
+// Computes what `coupon` takes off `cart`, or why it does not apply. `scope` lists the items the coupon
+// is limited to, empty when it applies to every item. The coupon's amounts are in the base currency and
+// converted to the cart's; results are rounded to the currency's minor unit.
+pub fn evaluate(coupon: &Coupon, scope: &[i32], cart: &Cart, usage: &Usage, now: NaiveDateTime) -> Result<Discount, Rejection> {
+  if coupon.starts_at.is_some_and(|starts_at| now < starts_at) {
+    return Err(Rejection::NotStarted);
+  }
+  if coupon.ends_at.is_some_and(|ends_at| now >= ends_at) {
+    return Err(Rejection::Expired);
+  }
+  if coupon.max_redemptions.is_some_and(|max| usage.total >= max as i64) {
+    return Err(Rejection::Exhausted);
+  }
+  if coupon.max_redemptions_per_user.is_some_and(|max| usage.by_user >= max as i64) {
+    return Err(Rejection::UserLimitReached);
+  }
+  if let Some(minimum) = &coupon.min_subtotal {
+    let minimum = cart.converter.convert(minimum);
+    if cart.subtotal() < minimum.amount {
+      return Err(Rejection::BelowMinimum(minimum));
+    }
+  }
+
+  let eligible: Vec<bool> = cart.lines.iter()
+    .map(|line| scope.is_empty() || scope.contains(&line.item_id))
+    .collect();
+  if !eligible.contains(&true) {
+    return Err(Rejection::NoEligibleItems);
+  }
+
+  let scale = cart.converter.currency.minor_units();
+  let (amounts, shipping) = match coupon.kind {
+    CouponKind::Percentage => {
+      let amounts = cart.lines.iter()
+        .zip(&eligible)
+        .map(|(line, eligible)| if *eligible { cart.converter.round(&(line.amount() * &coupon.value / BigDecimal::from(100))) } else { BigDecimal::zero() })
+        .collect();
+      (amounts, BigDecimal::zero())
+    },
+    CouponKind::FixedAmount => (spread(&cart.converter.convert(&coupon.value).amount, &cart.lines, &eligible, scale), BigDecimal::zero()),
+    CouponKind::FreeShipping => (vec![BigDecimal::zero(); cart.lines.len()], cart.shipping.clone()),
+  };
+
+  let total = amounts.iter().sum::<BigDecimal>() + &shipping;
+  Ok(Discount {
+    lines: cart.lines.iter()
+      .zip(amounts)
+      .map(|(line, amount)| LineDiscount { item_id: line.item_id, amount: amount.with_scale(scale) })
+      .collect(),
+    shipping: shipping.with_scale(scale),
+    total: total.with_scale(scale),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn decimal(value: &str) -> BigDecimal {
+    value.parse().unwrap()
+  }
+
+  fn line(item_id: i32, unit_price: &str, quantity: i32) -> CartLine {
+    CartLine { item_id, unit_price: decimal(unit_price), quantity, tax_class: "standard".to_string() }
+  }
+
+  fn cart(lines: Vec<CartLine>, shipping: &str) -> Cart {
+    let converter = CurrencyConverter { currency: "EUR".parse().unwrap(), rate: BigDecimal::from(1) };
+    Cart { lines, shipping: decimal(shipping), converter }
+  }
+
+  fn coupon(kind: CouponKind, value: &str) -> Coupon {
+    Coupon {
+      id: 1,
+      code: "TEST".to_string(),
+      kind,
+      value: decimal(value),
+      starts_at: None,
+      ends_at: None,
+      min_subtotal: None,
+      max_redemptions: None,
+      max_redemptions_per_user: None,
+      created_at: NaiveDateTime::default(),
+    }
+  }
+
+  fn unused() -> Usage {
+    Usage { total: 0, by_user: 0 }
+  }
+
+  fn amounts(discount: &Discount) -> Vec<String> {
+    discount.lines.iter().map(|line| line.amount.to_string()).collect()
+  }
+
+  #[test]
+  fn spread_is_exact_to_the_cent() {
+    let lines = [line(1, "10.00", 1), line(2, "10.00", 1), line(3, "10.00", 1)];
+    let parts = spread(&decimal("10.00"), &lines, &[true, true, true], 2);
+    assert_eq!(parts, vec![decimal("3.34"), decimal("3.33"), decimal("3.33")]);
+
+    let parts = spread(&decimal("5.00"), &lines, &[true, false, true], 2);
+    assert_eq!(parts, vec![decimal("2.50"), decimal("0"), decimal("2.50")]);
+  }
+
+  #[test]
+  fn fixed_amount_is_spread_in_proportion() {
+    let cart = cart(vec![line(1, "19.99", 2), line(2, "5.01", 1), line(3, "0.99", 3)], "4.90");
+    let discount = evaluate(&coupon(CouponKind::FixedAmount, "7.00"), &[], &cart, &unused(), NaiveDateTime::default()).unwrap();
+
+    // Shares of 7.00 over 47.96: 5.8353, 0.7312, 0.4335, the left over cent goes to the first
+    assert_eq!(amounts(&discount), ["5.84", "0.73", "0.43"]);
+    assert_eq!(discount.total.to_string(), "7.00");
+    assert_eq!(discount.shipping.to_string(), "0.00");
+  }
+
+  #[test]
+  fn fixed_amount_is_capped_at_the_eligible_total() {
+    let cart = cart(vec![line(1, "3.00", 1), line(2, "2.00", 1), line(3, "50.00", 1)], "0");
+    let discount = evaluate(&coupon(CouponKind::FixedAmount, "20.00"), &[1, 2], &cart, &unused(), NaiveDateTime::default()).unwrap();
+
+    assert_eq!(amounts(&discount), ["3.00", "2.00", "0.00"]);
+    assert_eq!(discount.total, decimal("5.00"));
+  }
+
+  #[test]
+  fn percentage_rounds_each_line() {
+    let cart = cart(vec![line(1, "0.99", 1), line(2, "10.05", 1)], "0");
+    let discount = evaluate(&coupon(CouponKind::Percentage, "15"), &[], &cart, &unused(), NaiveDateTime::default()).unwrap();
+
+    // 0.1485 and 1.5075
+    assert_eq!(amounts(&discount), ["0.15", "1.51"]);
+    assert_eq!(discount.total, decimal("1.66"));
+  }
+
+  #[test]
+  fn rejections() {
+    let cart = cart(vec![line(1, "10.00", 1)], "4.90");
+    let now = NaiveDateTime::default();
+
+    let mut limited = coupon(CouponKind::FreeShipping, "0");
+    limited.max_redemptions_per_user = Some(1);
+    assert_eq!(evaluate(&limited, &[], &cart, &Usage { total: 5, by_user: 1 }, now).err(), Some(Rejection::UserLimitReached));
+
+    let mut minimum = coupon(CouponKind::FreeShipping, "0");
+    minimum.min_subtotal = Some(decimal("10.01"));
+    assert_eq!(evaluate(&minimum, &[], &cart, &unused(), now).err().map(|rejection| rejection.code()), Some("below_minimum"));
+
+    let scoped = coupon(CouponKind::Percentage, "10");
+    assert_eq!(evaluate(&scoped, &[2], &cart, &unused(), now).err(), Some(Rejection::NoEligibleItems));
+
+    let free_shipping = evaluate(&coupon(CouponKind::FreeShipping, "0"), &[], &cart, &unused(), now).unwrap();
+    assert_eq!(free_shipping.total, decimal("4.90"));
+  }
+}
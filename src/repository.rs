@@ -1,10 +1,65 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+
+use bigdecimal::BigDecimal;
+use chrono::{Datelike, Duration, NaiveDateTime, Utc};
 use diesel::{PgConnection, QueryResult};
+use diesel::pg::CopyFormat;
 use diesel::prelude::*;
+use diesel::result::Error;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
 
+use crate::image_processing::mime_type;
 use crate::schema::*;
-use crate::models::{Item, NewItem, NewRole, Role, RoleCode, User, NewUser, UserRole, Image, NewImage, ItemsImage, NewItemsImage};
+use crate::storage::{generate_key, ImageStore, StoreError};
+use crate::invoices::InvoiceDocument;
+use crate::taxes::STANDARD_TAX_CLASS;
+use crate::promotions::{evaluate, Cart, Discount, Rejection, Usage};
+use crate::money::{Currency, Money};
+use crate::shipping::ShippingTable;
+use crate::models::{slugify, NewNotification, NewStockSubscription, StockSubscription, NewWishlist, NewWishlistItem, Notification, NotificationKind, Wishlist, WishlistItem, Invoice, NewInvoice, NewInvoiceItem, NewReview, Review, ReviewStatus, InventoryAdjustment, InventoryReason, NewInventoryAdjustment, NewRefund, NewReturn, NewReturnLine, ReceivedLine, Refund, RefundStatus, Return, ReturnLine, ReturnStatus, Address, NewAddress, NewShippingMethod, NewShippingMethodCountry, NewShippingRate, ShippingMethod, ShippingMethodCountry, ShippingRate, BatchMode, ExchangeRate, ItemPrice, NewExchangeRate, NewItemPrice, NewTaxClass, NewTaxRate, TaxClass, TaxRate, Coupon, CouponRedemption, NewCoupon, NewCouponItem, NewCouponRedemption, Item, ItemOperation, NewItem, NewItemRow, NewPriceHistory, NewScheduledPrice, PriceChangeSource, PriceHistory, ScheduledPrice, NewItemSlugRedirect, NewRole, Role, RoleCode, User, NewUser, UserRole, NewUserRole, Image, NewImage, ItemsImage, NewItemsImage, ImageRendition, NewImageRendition};
+
+// The items `ItemRepository::copy_all` read, one `COPY` text row per line
+pub struct CopiedItems(Vec<u8>);
+
+impl CopiedItems {
+  pub fn items(&self) -> impl Iterator<Item = QueryResult<Item>> + '_ {
+    self.0.split(|byte| *byte == b'\n')
+      .filter(|line| !line.is_empty())
+      .map(|line| {
+        let line = std::str::from_utf8(line).map_err(|e| Error::DeserializationError(Box::new(e)))?;
+        ItemRepository::from_copy_row(line).map_err(Error::DeserializationError)
+      })
+  }
+}
 
-use self::items_images::image_id;
+// Undoes the escaping of a `COPY` text field, `\N` being NULL
+fn copy_field(field: &str) -> Option<String> {
+  if field == "\\N" {
+    return None;
+  }
+  let mut value = String::with_capacity(field.len());
+  let mut chars = field.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      value.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('b') => value.push('\u{8}'),
+      Some('f') => value.push('\u{c}'),
+      Some('n') => value.push('\n'),
+      Some('r') => value.push('\r'),
+      Some('t') => value.push('\t'),
+      Some('v') => value.push('\u{b}'),
+      Some(escaped) => value.push(escaped),
+      None => value.push('\\'),
+    }
+  }
+  Some(value)
+}
 
 pub struct ItemRepository;
 
@@ -13,35 +68,328 @@ impl ItemRepository {
     items::table.find(id).get_result(c)
   }
 
+  // Locks the item until the transaction ends, so that concurrent changes see each other's stock and price
+  fn find_for_update(c: &mut PgConnection, id: i32) -> QueryResult<Item> {
+    items::table.find(id).for_update().get_result(c)
+  }
+
   pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<Item>> {
     items::table.load(c)
   }
 
-  pub fn find_by_name(c: &mut PgConnection, name: &str) -> QueryResult<Item> {
-    items::table.filter(items::name.eq(name)).first(c)
+  pub fn find_by_ids(c: &mut PgConnection, ids: Vec<i32>) -> QueryResult<Vec<Item>> {
+    items::table.filter(items::id.eq_any(ids)).load(c)
+  }
+
+  pub fn find_by_sku(c: &mut PgConnection, sku: &str) -> QueryResult<Item> {
+    items::table.filter(items::sku.eq(sku)).first(c)
+  }
+
+  pub fn find_by_skus(c: &mut PgConnection, skus: Vec<&str>) -> QueryResult<Vec<Item>> {
+    items::table.filter(items::sku.eq_any(skus)).load(c)
+  }
+
+  pub fn find_by_names(c: &mut PgConnection, names: Vec<&str>) -> QueryResult<Vec<Item>> {
+    items::table.filter(items::name.eq_any(names)).load(c)
+  }
+
+  // Reads every item through `COPY`, for exports too large to load as rows. The output is buffered so
+  // that the connection is free again while the items are written out. The rows are read as text
+  // since diesel's binary `COPY` reader misplaces the columns following a NULL.
+  pub fn copy_all(c: &mut PgConnection) -> QueryResult<CopiedItems> {
+    let columns = (
+      items::id, items::name, items::description, items::price, items::created_at, items::quantity, items::slug, items::sku,
+      items::was_price, items::tax_class, items::weight_grams, items::length_mm, items::width_mm, items::height_mm,
+      items::rating_count, items::rating_sum,
+    );
+    let mut raw = Vec::new();
+    diesel::copy_to(columns)
+      .with_format(CopyFormat::Text)
+      .load_raw(c)?
+      .read_to_end(&mut raw)
+      .map_err(|e| Error::DeserializationError(Box::new(e)))?;
+    Ok(CopiedItems(raw))
+  }
+
+  // Reads a row of `copy_all`, whose columns are listed there in this order. The text format writes
+  // NULL as `\N` and escapes backslashes, so an empty description stays distinct from a missing one.
+  fn from_copy_row(row: &str) -> Result<Item, Box<dyn std::error::Error + Send + Sync>> {
+    let mut fields = row.split('\t').map(copy_field);
+    let mut optional = || fields.next().ok_or("missing column in COPY row");
+    let required = |value: Option<String>| value.ok_or("unexpected NULL in COPY row");
+    let item = Item {
+      id: required(optional()?)?.parse()?,
+      name: required(optional()?)?,
+      description: optional()?,
+      price: required(optional()?)?.parse()?,
+      created_at: optional()?
+        .map(|value| NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S%.f"))
+        .transpose()?,
+      quantity: required(optional()?)?.parse()?,
+      slug: required(optional()?)?,
+      sku: optional()?,
+      was_price: optional()?.map(|value| value.parse()).transpose()?,
+      tax_class: required(optional()?)?,
+      weight_grams: optional()?.map(|value| value.parse()).transpose()?,
+      length_mm: optional()?.map(|value| value.parse()).transpose()?,
+      width_mm: optional()?.map(|value| value.parse()).transpose()?,
+      height_mm: optional()?.map(|value| value.parse()).transpose()?,
+      rating_count: required(optional()?)?.parse()?,
+      rating_sum: required(optional()?)?.parse()?,
+    };
+    Ok(item)
+  }
+
+  pub fn find_by_slug(c: &mut PgConnection, slug: &str) -> QueryResult<Item> {
+    items::table.filter(items::slug.eq(slug)).first(c)
+  }
+
+  // Finds the item a slug used to belong to before it was renamed
+  pub fn find_by_previous_slug(c: &mut PgConnection, slug: &str) -> QueryResult<Item> {
+    item_slug_redirects::table
+      .inner_join(items::table)
+      .filter(item_slug_redirects::slug.eq(slug))
+      .select(items::all_columns)
+      .first(c)
+  }
+
+  // Slugs stay reserved by their item after a rename, the item itself can take them back
+  fn unique_slug(c: &mut PgConnection, name: &str, item_id: Option<i32>) -> QueryResult<String> {
+    let base = slugify(name);
+    let mut candidate = base.clone();
+    for suffix in 2.. {
+      let item_owner = items::table
+        .filter(items::slug.eq(&candidate))
+        .select(items::id)
+        .first::<i32>(c)
+        .optional()?;
+      let redirect_owner = item_slug_redirects::table
+        .filter(item_slug_redirects::slug.eq(&candidate))
+        .select(item_slug_redirects::item_id)
+        .first::<i32>(c)
+        .optional()?;
+      if [item_owner, redirect_owner].iter().all(|owner| owner.is_none() || *owner == item_id) {
+        break;
+      }
+      candidate = format!("{}-{}", base, suffix);
+    }
+    Ok(candidate)
+  }
+
+  // Same as `unique_slug` for many new items at once, with a single query when the slugs are free
+  fn unique_slugs(c: &mut PgConnection, names: &[&str]) -> QueryResult<Vec<String>> {
+    let bases: Vec<String> = names.iter().map(|name| slugify(name)).collect();
+    let mut taken: HashSet<String> = items::table
+      .filter(items::slug.eq_any(&bases))
+      .select(items::slug)
+      .union(item_slug_redirects::table
+        .filter(item_slug_redirects::slug.eq_any(&bases))
+        .select(item_slug_redirects::slug))
+      .load::<String>(c)?
+      .into_iter()
+      .collect();
+
+    let mut slugs = Vec::with_capacity(bases.len());
+    for base in bases {
+      let candidate = if taken.contains(&base) {
+        // Suffixed slugs are rare enough to be looked up one by one
+        let mut suffix = 2;
+        loop {
+          let candidate = format!("{}-{}", base, suffix);
+          if !taken.contains(&candidate) && Self::unique_slug(c, &candidate, None)? == candidate {
+            break candidate;
+          }
+          suffix += 1;
+        }
+      } else {
+        base
+      };
+      taken.insert(candidate.clone());
+      slugs.push(candidate);
+    }
+    Ok(slugs)
   }
 
   pub fn create(c: &mut PgConnection, new_item: NewItem) -> QueryResult<Item> {
-    diesel::insert_into(items::table)
-      .values(new_item)
-      .get_result(c)
+    c.transaction(|c| {
+      let slug = Self::unique_slug(c, &new_item.name, None)?;
+      let item: Item = diesel::insert_into(items::table)
+        .values((new_item, items::slug.eq(slug)))
+        .get_result(c)?;
+      PriceHistoryRepository::record(c, item.id, None, item.price.clone(), PriceChangeSource::Created)?;
+      Ok(item)
+    })
+  }
+
+  // Inserts items through `COPY`, for imports of large catalogs
+  pub fn create_many(c: &mut PgConnection, new_items: Vec<NewItem>) -> QueryResult<usize> {
+    c.transaction(|c| {
+      let names: Vec<&str> = new_items.iter().map(|new_item| new_item.name.as_str()).collect();
+      let slugs = Self::unique_slugs(c, &names)?;
+      let rows: Vec<_> = new_items.into_iter()
+        .zip(slugs.iter().cloned())
+        .map(|(new_item, slug)| NewItemRow {
+          name: new_item.name,
+          description: new_item.description,
+          price: new_item.price,
+          quantity: new_item.quantity,
+          sku: new_item.sku,
+          slug,
+          tax_class: new_item.tax_class.unwrap_or_else(|| STANDARD_TAX_CLASS.to_string()),
+          weight_grams: new_item.weight_grams,
+          length_mm: new_item.length_mm,
+          width_mm: new_item.width_mm,
+          height_mm: new_item.height_mm,
+        })
+        .collect();
+      let created = diesel::copy_from(items::table)
+        .from_insertable(rows)
+        .execute(c)?;
+
+      // Slugs are unique, so they find the rows `COPY` just inserted
+      diesel::insert_into(price_history::table)
+        .values(items::table
+          .filter(items::slug.eq_any(&slugs))
+          .select((items::id, items::price, PriceChangeSource::Created.as_str().into_sql::<diesel::sql_types::Text>())))
+        .into_columns((price_history::item_id, price_history::price, price_history::source))
+        .execute(c)?;
+      Ok(created)
+    })
   }
 
   pub fn delete(c: &mut PgConnection, id: i32) -> QueryResult<usize> {
     diesel::delete(items::table.find(id)).execute(c)
   }
 
+  fn apply(c: &mut PgConnection, operation: ItemOperation) -> QueryResult<Option<Item>> {
+    match operation {
+      ItemOperation::Create { item } => Self::create(c, item).map(Some),
+      ItemOperation::Update { id, changes } => {
+        let current = Self::find(c, id)?;
+        Self::update(c, id, changes.apply(current)).map(Some)
+      },
+      ItemOperation::Delete { id } => match Self::delete(c, id)? {
+        0 => Err(Error::NotFound),
+        _ => Ok(None),
+      },
+    }
+  }
+
+  // Applies the operations in order within one transaction, each in its own savepoint so that a
+  // failure can be undone alone. Returns one outcome per operation; see `BatchMode`.
+  pub fn apply_batch(c: &mut PgConnection, operations: Vec<ItemOperation>, mode: BatchMode) -> QueryResult<Vec<OperationOutcome>> {
+    let count = operations.len();
+    let mut outcomes = Vec::with_capacity(count);
+    let result = c.transaction(|c| {
+      for operation in operations {
+        match c.transaction(|c| Self::apply(c, operation)) {
+          Ok(item) => outcomes.push(OperationOutcome::Applied(item.map(Box::new))),
+          Err(e) => {
+            outcomes.push(OperationOutcome::Failed(e));
+            if mode == BatchMode::AllOrNothing {
+              return Err(Error::RollbackTransaction);
+            }
+          },
+        }
+      }
+      Ok(())
+    });
+
+    match result {
+      Ok(()) => Ok(outcomes),
+      Err(Error::RollbackTransaction) => {
+        for outcome in outcomes.iter_mut() {
+          if let OperationOutcome::Applied(_) = outcome {
+            *outcome = OperationOutcome::RolledBack;
+          }
+        }
+        outcomes.resize_with(count, || OperationOutcome::Skipped);
+        Ok(outcomes)
+      },
+      Err(e) => Err(e),
+    }
+  }
+
+  // Renaming an item gives it a new slug and keeps the old one as a redirect. A price change is
+  // recorded in the price history and ends the display of a running sale's "was" price. Price drops
+  // notify the users wishing for the item, restocks also those subscribed to it.
   pub fn update(c: &mut PgConnection, id: i32, item: Item) -> QueryResult<Item> {
-    diesel::update(items::table.find(id))
-      .set((
-        items::name.eq(item.name),
-        items::description.eq(item.description),
-        items::price.eq(item.price),
-        items::quantity.eq(item.quantity),
-        items::created_at.eq(item.created_at),
-      ))
-      .get_result(c)
+    c.transaction(|c| {
+      let current = Self::find_for_update(c, id)?;
+      if current.quantity <= 0 && item.quantity > 0 {
+        NotificationRepository::restocked(c, id)?;
+      }
+      let was_price = if current.price == item.price {
+        current.was_price
+      } else {
+        if item.price < current.price {
+          NotificationRepository::price_dropped(c, id, &current.price, &item.price)?;
+        }
+        PriceHistoryRepository::record(c, id, Some(current.price), item.price.clone(), PriceChangeSource::Updated)?;
+        None
+      };
+      let slug = if current.name == item.name {
+        current.slug.clone()
+      } else {
+        Self::unique_slug(c, &item.name, Some(id))?
+      };
+
+      if slug != current.slug {
+        diesel::delete(item_slug_redirects::table.filter(item_slug_redirects::slug.eq(&slug))).execute(c)?;
+        diesel::insert_into(item_slug_redirects::table)
+          .values(NewItemSlugRedirect {
+            item_id: id,
+            slug: current.slug,
+          })
+          .execute(c)?;
+      }
+
+      diesel::update(items::table.find(id))
+        .set((
+          items::name.eq(item.name),
+          items::description.eq(item.description),
+          items::price.eq(item.price),
+          items::quantity.eq(item.quantity),
+          items::created_at.eq(item.created_at),
+          items::slug.eq(slug),
+          items::sku.eq(item.sku),
+          items::was_price.eq(was_price),
+          items::tax_class.eq(item.tax_class),
+          items::weight_grams.eq(item.weight_grams),
+          items::length_mm.eq(item.length_mm),
+          items::width_mm.eq(item.width_mm),
+          items::height_mm.eq(item.height_mm),
+        ))
+        .get_result(c)
+    })
   }
+
+  // Changes only the price and the "was" price shown next to it, recording the change
+  pub fn set_price(c: &mut PgConnection, id: i32, price: BigDecimal, was_price: Option<BigDecimal>, source: PriceChangeSource) -> QueryResult<Item> {
+    c.transaction(|c| {
+      let current = Self::find_for_update(c, id)?;
+      if price < current.price {
+        NotificationRepository::price_dropped(c, id, &current.price, &price)?;
+      }
+      if current.price != price {
+        PriceHistoryRepository::record(c, id, Some(current.price), price.clone(), source)?;
+      }
+      diesel::update(items::table.find(id))
+        .set((items::price.eq(price), items::was_price.eq(was_price)))
+        .get_result(c)
+    })
+  }
+}
+
+// Outcome of one operation of `ItemRepository::apply_batch`
+pub enum OperationOutcome {
+  // The item as created or updated, None for deletions
+  Applied(Option<Box<Item>>),
+  Failed(Error),
+  // Succeeded, then undone because another operation of an all-or-nothing batch failed
+  RolledBack,
+  // Not attempted because an earlier operation of an all-or-nothing batch failed
+  Skipped,
 }
 
 pub struct RoleRepository;
@@ -98,6 +446,37 @@ impl UserRepository {
     users::table.filter(users::username.eq(username)).first(c)
   }
 
+  pub fn find_by_ids(c: &mut PgConnection, ids: Vec<i32>) -> QueryResult<Vec<User>> {
+    users::table.filter(users::id.eq_any(ids)).get_results(c)
+  }
+
+  // Assigning a role the user already has is a no-op
+  pub fn assign_role(c: &mut PgConnection, user: &User, role: &Role) -> QueryResult<usize> {
+    c.transaction(|c| {
+      let existing = UserRole::belonging_to(user)
+        .filter(users_roles::role_id.eq(role.id))
+        .count()
+        .get_result::<i64>(c)?;
+      if existing > 0 {
+        return Ok(0);
+      }
+      diesel::insert_into(users_roles::table)
+        .values(NewUserRole {
+          user_id: user.id,
+          role_id: role.id,
+        })
+        .execute(c)
+    })
+  }
+
+  pub fn revoke_role(c: &mut PgConnection, user: &User, role: &Role) -> QueryResult<usize> {
+    diesel::delete(
+      users_roles::table
+        .filter(users_roles::user_id.eq(user.id))
+        .filter(users_roles::role_id.eq(role.id))
+    ).execute(c)
+  }
+
   pub fn create(c: &mut PgConnection, new_user: NewUser) -> QueryResult<User> {
     diesel::insert_into(users::table)
       .values(new_user)
@@ -122,50 +501,1837 @@ impl UserRepository {
 
 pub struct ImageRepository;
 
+// Image operations span the database and the image store, so either can fail them
+#[derive(Debug)]
+pub enum ImageError {
+  Database(diesel::result::Error),
+  Storage(StoreError),
+}
+
+impl fmt::Display for ImageError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ImageError::Database(e) => e.fmt(f),
+      ImageError::Storage(e) => e.fmt(f),
+    }
+  }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<diesel::result::Error> for ImageError {
+  fn from(e: diesel::result::Error) -> Self {
+    ImageError::Database(e)
+  }
+}
+
+impl From<StoreError> for ImageError {
+  fn from(e: StoreError) -> Self {
+    ImageError::Storage(e)
+  }
+}
+
 /**
  * ImageRepository is a struct that contains methods for interacting with the images table in the database.
  * Should be able to find
  *  by id
- *  by url
+ *  by storage key
  *  by item association
  *  all
  * create
- *  a new image with an item association
+ *  a new image with an item association, along with its file
  * delete
- *  by id both the image and it's relations in the items_images table
+ *  by id both the image and it's relations in the items_images table, along with its files
+ *
+ * Files and rows are kept in sync: if one side of an operation fails, the other side is undone.
  */
 impl ImageRepository {
   pub fn find(c: &mut PgConnection, id: i32) -> QueryResult<Image> {
     images::table.find(id).get_result(c)
   }
 
-  pub fn find_by_url(c: &mut PgConnection, url: &str) -> QueryResult<Image> {
-    images::table.filter(images::url.eq(url)).first(c)
+  pub fn find_by_storage_key(c: &mut PgConnection, storage_key: &str) -> QueryResult<Image> {
+    images::table.filter(images::storage_key.eq(storage_key)).first(c)
   }
 
   pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<Image>> {
     images::table.load(c)
   }
 
-  pub fn create_with_item(c: &mut PgConnection, new_image: NewImage, item_id: i32) -> QueryResult<Image> {
+  pub fn find_all_storage_keys(c: &mut PgConnection) -> QueryResult<Vec<(i32, String)>> {
+    images::table.select((images::id, images::storage_key)).load(c)
+  }
+
+  // The file is written first and removed again if the rows cannot be committed
+  pub fn create_with_item(c: &mut PgConnection, store: &dyn ImageStore, new_image: NewImage, item_id: i32, bytes: &[u8]) -> Result<Image, ImageError> {
+    let key = new_image.storage_key.clone();
+    store.put(&key, bytes, &mime_type(&key))?;
+
+    let result = c.transaction(|c| {
+      let new_img_entry: Image = diesel::insert_into(images::table)
+        .values(new_image)
+        .get_result(c)?;
+
+      ItemsImageRepository::attach(c, item_id, new_img_entry.id, None)?;
+
+      Ok(new_img_entry)
+    });
+
+    if result.is_err() {
+      if let Err(e) = store.delete(&key) {
+        log::error!("Failed to remove {} after its image could not be recorded: {}", key, e);
+      }
+    }
+    result.map_err(ImageError::Database)
+  }
+
+  pub fn set_dimensions(c: &mut PgConnection, id: i32, width: i32, height: i32) -> QueryResult<Image> {
+    diesel::update(images::table.find(id))
+      .set((
+        images::width.eq(width),
+        images::height.eq(height),
+      ))
+      .get_result(c)
+  }
+
+  // Files are removed before the rows are committed; they are put back if anything fails afterwards
+  pub fn delete(c: &mut PgConnection, store: &dyn ImageStore, id: i32) -> Result<usize, ImageError> {
+    let mut removed_files: Vec<(String, Vec<u8>)> = Vec::new();
+
+    let result = c.transaction(|c| {
+      let renditions: Vec<ImageRendition> = diesel::delete(image_renditions::table.filter(image_renditions::image_id.eq(id))).get_results(c)?;
+      diesel::delete(items_images::table.filter(items_images::image_id.eq(id))).execute(c)?;
+      let images: Vec<Image> = diesel::delete(images::table.find(id)).get_results(c)?;
+      let deleted = images.len();
+
+      let keys = images.into_iter().map(|i| i.storage_key)
+        .chain(renditions.into_iter().map(|r| r.storage_key));
+      for key in keys {
+        match store.get(&key) {
+          Ok(bytes) => {
+            store.delete(&key)?;
+            removed_files.push((key, bytes));
+          },
+          // Already gone, there is nothing to remove nor to restore
+          Err(StoreError::NotFound(_)) => (),
+          Err(e) => return Err(ImageError::Storage(e)),
+        }
+      }
+
+      Ok(deleted)
+    });
+
+    if result.is_err() {
+      for (key, bytes) in &removed_files {
+        if let Err(e) = store.put(key, bytes, &mime_type(key)) {
+          log::error!("Failed to restore {} after its image could not be deleted: {}", key, e);
+        }
+      }
+    }
+    result
+  }
+}
+
+pub struct ImageRenditionRepository;
+
+impl ImageRenditionRepository {
+  pub fn find_by_image(c: &mut PgConnection, image: &Image) -> QueryResult<Vec<ImageRendition>> {
+    ImageRendition::belonging_to(image).get_results(c)
+  }
+
+  // Without a format, the rendition in the format of the original comes first as it was created first
+  pub fn find_by_kind(c: &mut PgConnection, image_id: i32, kind: &str, format: Option<&str>) -> QueryResult<ImageRendition> {
+    let mut query = image_renditions::table
+      .filter(image_renditions::image_id.eq(image_id))
+      .filter(image_renditions::kind.eq(kind))
+      .into_boxed();
+    if let Some(format) = format {
+      query = query.filter(image_renditions::format.eq(format));
+    }
+    query.order(image_renditions::id).first(c)
+  }
+
+  pub fn find_all_storage_keys(c: &mut PgConnection) -> QueryResult<Vec<(i32, String)>> {
+    image_renditions::table.select((image_renditions::id, image_renditions::storage_key)).load(c)
+  }
 
-    let new_img_entry: Image = diesel::insert_into(images::table)
-      .values(new_image)
-      .get_result(c).unwrap();
+  pub fn create_many(c: &mut PgConnection, new_renditions: Vec<NewImageRendition>) -> QueryResult<Vec<ImageRendition>> {
+    diesel::insert_into(image_renditions::table)
+      .values(new_renditions)
+      .get_results(c)
+  }
+}
+pub struct ItemsImageRepository;
 
+/**
+ * ItemsImageRepository manages the gallery of each item, i.e. its rows in the items_images table.
+ * Galleries are ordered by position and have at most one primary image.
+ */
+impl ItemsImageRepository {
+  pub fn find(c: &mut PgConnection, item_id: i32, image_id: i32) -> QueryResult<ItemsImage> {
+    items_images::table
+      .filter(items_images::item_id.eq(item_id))
+      .filter(items_images::image_id.eq(image_id))
+      .first(c)
+  }
+
+  pub fn find_gallery(c: &mut PgConnection, item: &Item) -> QueryResult<Vec<(ItemsImage, Image)>> {
+    ItemsImage::belonging_to(item)
+      .inner_join(images::table)
+      .order(items_images::position)
+      .load(c)
+  }
+
+  // Every gallery entry ordered by item and position, for catalog exports
+  pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<(ItemsImage, Image)>> {
+    items_images::table
+      .inner_join(images::table)
+      .order((items_images::item_id, items_images::position))
+      .load(c)
+  }
+
+  // Loads the galleries of several items at once, in the same order as `items`
+  pub fn find_galleries(c: &mut PgConnection, items: &[Item]) -> QueryResult<Vec<Vec<(ItemsImage, Image)>>> {
+    let entries: Vec<(ItemsImage, Image)> = ItemsImage::belonging_to(items)
+      .inner_join(images::table)
+      .order(items_images::position)
+      .load(c)?;
+    Ok(entries.grouped_by(items))
+  }
+
+  // Appends the image to the end of the gallery, the first image of a gallery becomes its primary image
+  pub fn attach(c: &mut PgConnection, item_id: i32, image_id: i32, alt_text: Option<String>) -> QueryResult<ItemsImage> {
+    c.transaction(|c| {
+      let last_position: Option<i32> = items_images::table
+        .filter(items_images::item_id.eq(item_id))
+        .order(items_images::position.desc())
+        .select(items_images::position)
+        .first(c)
+        .optional()?;
+
+      diesel::insert_into(items_images::table)
+        .values(NewItemsImage {
+          item_id,
+          image_id,
+          position: last_position.map_or(0, |p| p + 1),
+          is_primary: last_position.is_none(),
+          alt_text,
+        })
+        .get_result(c)
+    })
+  }
+
+  // Removes the image from the gallery, promoting the next image if it was the primary one
+  pub fn detach(c: &mut PgConnection, item_id: i32, image_id: i32) -> QueryResult<usize> {
+    c.transaction(|c| {
+      let removed: Vec<ItemsImage> = diesel::delete(
+        items_images::table
+          .filter(items_images::item_id.eq(item_id))
+          .filter(items_images::image_id.eq(image_id))
+      ).get_results(c)?;
+
+      if removed.iter().any(|entry| entry.is_primary) {
+        let next = items_images::table
+          .filter(items_images::item_id.eq(item_id))
+          .order(items_images::position)
+          .select(items_images::id)
+          .first::<i32>(c)
+          .optional()?;
+        if let Some(next_id) = next {
+          diesel::update(items_images::table.find(next_id))
+            .set(items_images::is_primary.eq(true))
+            .execute(c)?;
+        }
+      }
+
+      Ok(removed.len())
+    })
+  }
+
+  // Moves the given images to the front of the gallery in that order, the others keep their relative order after them
+  pub fn reorder(c: &mut PgConnection, item_id: i32, image_ids: Vec<i32>) -> QueryResult<Vec<ItemsImage>> {
+    c.transaction(|c| {
+      let gallery: Vec<ItemsImage> = items_images::table
+        .filter(items_images::item_id.eq(item_id))
+        .order(items_images::position)
+        .load(c)?;
+
+      if image_ids.iter().any(|id| !gallery.iter().any(|entry| entry.image_id == *id)) {
+        return Err(diesel::result::Error::NotFound);
+      }
+
+      // An image listed twice takes its first place
+      let mut seen = HashSet::new();
+      let mut ordered_ids: Vec<i32> = image_ids.iter().copied().filter(|id| seen.insert(*id)).collect();
+      ordered_ids.extend(gallery.iter()
+        .map(|entry| entry.image_id)
+        .filter(|id| !seen.contains(id)));
+
+      for (position, id) in ordered_ids.iter().enumerate() {
+        diesel::update(
+          items_images::table
+            .filter(items_images::item_id.eq(item_id))
+            .filter(items_images::image_id.eq(id))
+        )
+          .set(items_images::position.eq(position as i32))
+          .execute(c)?;
+      }
+
+      items_images::table
+        .filter(items_images::item_id.eq(item_id))
+        .order(items_images::position)
+        .load(c)
+    })
+  }
+
+  pub fn set_primary(c: &mut PgConnection, item_id: i32, image_id: i32) -> QueryResult<ItemsImage> {
+    c.transaction(|c| {
+      // Fail before touching the current primary image if the image is not in the gallery
+      Self::find(c, item_id, image_id)?;
+
+      diesel::update(items_images::table.filter(items_images::item_id.eq(item_id)))
+        .set(items_images::is_primary.eq(false))
+        .execute(c)?;
+
+      diesel::update(
+        items_images::table
+          .filter(items_images::item_id.eq(item_id))
+          .filter(items_images::image_id.eq(image_id))
+      )
+        .set(items_images::is_primary.eq(true))
+        .get_result(c)
+    })
+  }
+
+  pub fn update_alt_text(c: &mut PgConnection, item_id: i32, image_id: i32, alt_text: Option<String>) -> QueryResult<ItemsImage> {
+    diesel::update(
+      items_images::table
+        .filter(items_images::item_id.eq(item_id))
+        .filter(items_images::image_id.eq(image_id))
+    )
+      .set(items_images::alt_text.eq(alt_text))
+      .get_result(c)
+  }
+}
+
+pub struct PriceHistoryRepository;
+
+impl PriceHistoryRepository {
+  pub fn find_by_item(c: &mut PgConnection, item_id: i32) -> QueryResult<Vec<PriceHistory>> {
+    price_history::table
+      .filter(price_history::item_id.eq(item_id))
+      .order((price_history::changed_at, price_history::id))
+      .load(c)
+  }
 
-    diesel::insert_into(items_images::table)
-      .values(NewItemsImage {
+  pub fn record(c: &mut PgConnection, item_id: i32, previous_price: Option<BigDecimal>, price: BigDecimal, source: PriceChangeSource) -> QueryResult<usize> {
+    diesel::insert_into(price_history::table)
+      .values(NewPriceHistory {
         item_id,
-        image_id: new_img_entry.id,
+        previous_price,
+        price,
+        source: source.as_str().to_string(),
       })
-      .execute(c)?;
+      .execute(c)
+  }
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+  Database(Error),
+  // Id of the pending or running schedule of the same item the new one would overlap
+  Overlaps(i32),
+}
+
+impl fmt::Display for ScheduleError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ScheduleError::Database(e) => e.fmt(f),
+      ScheduleError::Overlaps(id) => write!(f, "Overlaps the scheduled price {}", id),
+    }
+  }
+}
+
+impl std::error::Error for ScheduleError {}
+
+impl From<Error> for ScheduleError {
+  fn from(e: Error) -> Self {
+    ScheduleError::Database(e)
+  }
+}
+
+pub struct ScheduledPriceRepository;
+
+/**
+ * ScheduledPriceRepository manages future prices of items. `apply_due` is run periodically by the
+ * `price_schedule` job: it starts the schedules whose time has come and ends the sales that are over.
+ * Schedules of an item never overlap, a schedule without end counts as the instant it starts at.
+ */
+impl ScheduledPriceRepository {
+  pub fn find_by_item(c: &mut PgConnection, item_id: i32) -> QueryResult<Vec<ScheduledPrice>> {
+    scheduled_prices::table
+      .filter(scheduled_prices::item_id.eq(item_id))
+      .order((scheduled_prices::starts_at, scheduled_prices::id))
+      .load(c)
+  }
+
+  fn interval(starts_at: NaiveDateTime, ends_at: Option<NaiveDateTime>) -> (NaiveDateTime, NaiveDateTime) {
+    (starts_at, ends_at.unwrap_or(starts_at + Duration::microseconds(1)))
+  }
+
+  pub fn create(c: &mut PgConnection, new_schedule: NewScheduledPrice) -> Result<ScheduledPrice, ScheduleError> {
+    c.transaction(|c| {
+      // Lock the item so that concurrent schedules of it are checked one after the other
+      items::table.find(new_schedule.item_id).for_update().select(items::id).first::<i32>(c)?;
+
+      let (start, end) = Self::interval(new_schedule.starts_at, new_schedule.ends_at);
+      let overlapping = scheduled_prices::table
+        .filter(scheduled_prices::item_id.eq(new_schedule.item_id))
+        .filter(scheduled_prices::ended_at.is_null())
+        .load::<ScheduledPrice>(c)?
+        .into_iter()
+        .find(|other| {
+          let (other_start, other_end) = Self::interval(other.starts_at, other.ends_at);
+          start < other_end && other_start < end
+        });
+      if let Some(other) = overlapping {
+        return Err(ScheduleError::Overlaps(other.id));
+      }
+
+      Ok(diesel::insert_into(scheduled_prices::table)
+        .values(new_schedule)
+        .get_result(c)?)
+    })
+  }
+
+  // Pending schedules are deleted, a running sale ends right away. Returns None once deleted.
+  pub fn cancel(c: &mut PgConnection, item_id: i32, id: i32, now: NaiveDateTime) -> QueryResult<Option<ScheduledPrice>> {
+    c.transaction(|c| {
+      let schedule: ScheduledPrice = scheduled_prices::table
+        .filter(scheduled_prices::item_id.eq(item_id))
+        .find(id)
+        .for_update()
+        .first(c)?;
+      match (schedule.started_at, schedule.ended_at) {
+        (None, _) => {
+          diesel::delete(scheduled_prices::table.find(id)).execute(c)?;
+          Ok(None)
+        },
+        (Some(_), None) => Self::end(c, schedule, now).map(Some),
+        (Some(_), Some(_)) => Ok(Some(schedule)),
+      }
+    })
+  }
+
+  fn start(c: &mut PgConnection, schedule: ScheduledPrice, now: NaiveDateTime) -> QueryResult<ScheduledPrice> {
+    let item = ItemRepository::find(c, schedule.item_id)?;
+    let missed = schedule.ends_at.is_some_and(|ends_at| ends_at <= now);
+    if missed {
+      log::warn!("Scheduled price {} of item {} ended before it could start", schedule.id, item.id);
+    } else {
+      // A sale shows the regular price as "was"; a schedule without end is a plain price change
+      let was_price = schedule.ends_at.map(|_| item.price.clone());
+      ItemRepository::set_price(c, item.id, schedule.price.clone(), was_price, PriceChangeSource::Scheduled)?;
+    }
+    diesel::update(scheduled_prices::table.find(schedule.id))
+      .set((
+        scheduled_prices::previous_price.eq(item.price),
+        scheduled_prices::started_at.eq(now),
+        scheduled_prices::ended_at.eq(if missed || schedule.ends_at.is_none() { Some(now) } else { None }),
+      ))
+      .get_result(c)
+  }
+
+  fn end(c: &mut PgConnection, schedule: ScheduledPrice, now: NaiveDateTime) -> QueryResult<ScheduledPrice> {
+    let item = ItemRepository::find(c, schedule.item_id)?;
+    // A price set by hand during the sale is kept
+    match (&schedule.previous_price, item.price == schedule.price) {
+      (Some(previous_price), true) => {
+        ItemRepository::set_price(c, item.id, previous_price.clone(), None, PriceChangeSource::SaleEnded)?;
+      },
+      _ => {
+        ItemRepository::set_price(c, item.id, item.price, None, PriceChangeSource::SaleEnded)?;
+      },
+    }
+    diesel::update(scheduled_prices::table.find(schedule.id))
+      .set(scheduled_prices::ended_at.eq(now))
+      .get_result(c)
+  }
+
+  // Ends the sales that are over, then starts the schedules that are due. Returns how many of each.
+  // Rows are locked and skipped when locked, so several servers can run it at once. Each schedule is
+  // applied in its own savepoint: one that fails is logged and left for the next run.
+  pub fn apply_due(c: &mut PgConnection, now: NaiveDateTime) -> QueryResult<(usize, usize)> {
+    c.transaction(|c| {
+      let ending: Vec<ScheduledPrice> = scheduled_prices::table
+        .filter(scheduled_prices::started_at.is_not_null())
+        .filter(scheduled_prices::ended_at.is_null())
+        .filter(scheduled_prices::ends_at.le(now))
+        .order(scheduled_prices::ends_at)
+        .for_update()
+        .skip_locked()
+        .load(c)?;
+      let mut ended = 0;
+      for schedule in ending {
+        let (id, item_id) = (schedule.id, schedule.item_id);
+        match c.transaction(|c| Self::end(c, schedule, now)) {
+          Ok(_) => ended += 1,
+          Err(e) => log::error!("Failed to end scheduled price {} of item {}: {}", id, item_id, e),
+        }
+      }
+
+      let starting: Vec<ScheduledPrice> = scheduled_prices::table
+        .filter(scheduled_prices::started_at.is_null())
+        .filter(scheduled_prices::starts_at.le(now))
+        .order(scheduled_prices::starts_at)
+        .for_update()
+        .skip_locked()
+        .load(c)?;
+      let mut started = 0;
+      for schedule in starting {
+        let (id, item_id) = (schedule.id, schedule.item_id);
+        match c.transaction(|c| Self::start(c, schedule, now)) {
+          Ok(_) => started += 1,
+          Err(e) => log::error!("Failed to start scheduled price {} of item {}: {}", id, item_id, e),
+        }
+      }
+
+      Ok((started, ended))
+    })
+  }
+}
+
+#[derive(Debug)]
+pub enum PromotionError {
+  Database(Error),
+  UnknownCode,
+  Rejected(Rejection),
+}
+
+impl fmt::Display for PromotionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PromotionError::Database(e) => e.fmt(f),
+      PromotionError::UnknownCode => f.write_str("Unknown discount code"),
+      PromotionError::Rejected(rejection) => rejection.fmt(f),
+    }
+  }
+}
+
+impl std::error::Error for PromotionError {}
+
+impl From<Error> for PromotionError {
+  fn from(e: Error) -> Self {
+    PromotionError::Database(e)
+  }
+}
+
+pub struct CouponRepository;
 
-    Ok(new_img_entry)
+/**
+ * CouponRepository manages discount codes and their redemptions. Codes are matched case-insensitively
+ * by storing them upper case. `redeem` is meant to run in the transaction creating the order: it locks
+ * the coupon so that concurrent orders cannot exceed its limits.
+ */
+impl CouponRepository {
+  pub fn find(c: &mut PgConnection, id: i32) -> QueryResult<Coupon> {
+    coupons::table.find(id).get_result(c)
+  }
+
+  pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<Coupon>> {
+    coupons::table.order(coupons::id).load(c)
+  }
+
+  pub fn find_by_code(c: &mut PgConnection, code: &str) -> QueryResult<Coupon> {
+    coupons::table.filter(coupons::code.eq(code.trim().to_uppercase())).get_result(c)
+  }
+
+  // The items the coupon is limited to, empty when it applies to every item
+  pub fn find_scope(c: &mut PgConnection, coupon_id: i32) -> QueryResult<Vec<i32>> {
+    coupons_items::table
+      .filter(coupons_items::coupon_id.eq(coupon_id))
+      .select(coupons_items::item_id)
+      .order(coupons_items::item_id)
+      .load(c)
+  }
+
+  pub fn find_redemptions(c: &mut PgConnection, coupon_id: i32) -> QueryResult<Vec<CouponRedemption>> {
+    coupon_redemptions::table
+      .filter(coupon_redemptions::coupon_id.eq(coupon_id))
+      .order(coupon_redemptions::id)
+      .load(c)
+  }
+
+  pub fn create(c: &mut PgConnection, new_coupon: NewCoupon, item_ids: Vec<i32>) -> QueryResult<Coupon> {
+    c.transaction(|c| {
+      let coupon: Coupon = diesel::insert_into(coupons::table)
+        .values(NewCoupon { code: new_coupon.code.trim().to_uppercase(), ..new_coupon })
+        .get_result(c)?;
+      let scope: Vec<NewCouponItem> = item_ids.into_iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|item_id| NewCouponItem { coupon_id: coupon.id, item_id })
+        .collect();
+      diesel::insert_into(coupons_items::table).values(scope).execute(c)?;
+      Ok(coupon)
+    })
   }
 
   pub fn delete(c: &mut PgConnection, id: i32) -> QueryResult<usize> {
-    diesel::delete(items_images::table.filter(image_id.eq(id))).execute(c)?;
-    diesel::delete(images::table.find(id)).execute(c)
+    diesel::delete(coupons::table.find(id)).execute(c)
+  }
+
+  fn usage(c: &mut PgConnection, coupon_id: i32, user_id: i32) -> QueryResult<Usage> {
+    let redemptions = coupon_redemptions::table.filter(coupon_redemptions::coupon_id.eq(coupon_id));
+    Ok(Usage {
+      total: redemptions.count().get_result(c)?,
+      by_user: redemptions.filter(coupon_redemptions::user_id.eq(user_id)).count().get_result(c)?,
+    })
+  }
+
+  fn find_applicable(c: &mut PgConnection, code: &str, lock: bool) -> Result<Coupon, PromotionError> {
+    let query = coupons::table.filter(coupons::code.eq(code.trim().to_uppercase()));
+    let coupon = if lock { query.for_update().get_result(c) } else { query.get_result(c) };
+    coupon.optional()?.ok_or(PromotionError::UnknownCode)
   }
-}
\ No newline at end of file
+
+  // What the code would take off the cart of `user_id`, without redeeming it
+  pub fn evaluate(c: &mut PgConnection, code: &str, user_id: i32, cart: &Cart, now: NaiveDateTime) -> Result<(Coupon, Discount), PromotionError> {
+    let coupon = Self::find_applicable(c, code, false)?;
+    let scope = Self::find_scope(c, coupon.id)?;
+    let usage = Self::usage(c, coupon.id, user_id)?;
+    let discount = evaluate(&coupon, &scope, cart, &usage, now).map_err(PromotionError::Rejected)?;
+    Ok((coupon, discount))
+  }
+
+  // Evaluates the code and records its use by the order. An order redeems a coupon at most once.
+  pub fn redeem(c: &mut PgConnection, code: &str, user_id: i32, order_reference: &str, cart: &Cart, now: NaiveDateTime) -> Result<(CouponRedemption, Discount), PromotionError> {
+    c.transaction(|c| {
+      let coupon = Self::find_applicable(c, code, true)?;
+      let scope = Self::find_scope(c, coupon.id)?;
+      let usage = Self::usage(c, coupon.id, user_id)?;
+      let discount = evaluate(&coupon, &scope, cart, &usage, now).map_err(PromotionError::Rejected)?;
+      let redemption = diesel::insert_into(coupon_redemptions::table)
+        .values(NewCouponRedemption {
+          coupon_id: coupon.id,
+          user_id: Some(user_id),
+          order_reference: order_reference.to_string(),
+          discount: discount.total.clone(),
+        })
+        .get_result(c)?;
+      Ok((redemption, discount))
+    })
+  }
+}
+
+pub struct TaxClassRepository;
+
+impl TaxClassRepository {
+  pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<TaxClass>> {
+    tax_classes::table.order(tax_classes::id).load(c)
+  }
+
+  pub fn create(c: &mut PgConnection, new_tax_class: NewTaxClass) -> QueryResult<TaxClass> {
+    diesel::insert_into(tax_classes::table)
+      .values(new_tax_class)
+      .get_result(c)
+  }
+
+  // Fails with a foreign key violation while items use the class; its rates go with it
+  pub fn delete(c: &mut PgConnection, code: &str) -> QueryResult<usize> {
+    diesel::delete(tax_classes::table.filter(tax_classes::code.eq(code))).execute(c)
+  }
+}
+
+pub struct TaxRateRepository;
+
+/**
+ * TaxRateRepository manages the rates of the tax classes by country, and by region within a country.
+ * Countries and regions are stored upper case.
+ */
+impl TaxRateRepository {
+  pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<TaxRate>> {
+    tax_rates::table
+      .order((tax_rates::country, tax_rates::region, tax_rates::tax_class))
+      .load(c)
+  }
+
+  // The country-wide rates of the country and the rates of the region, see `TaxCalculator::new`
+  pub fn find_for_address(c: &mut PgConnection, country: &str, region: Option<&str>) -> QueryResult<Vec<TaxRate>> {
+    let regions = match region {
+      Some(region) => vec![String::new(), region.trim().to_uppercase()],
+      None => vec![String::new()],
+    };
+    tax_rates::table
+      .filter(tax_rates::country.eq(country.trim().to_uppercase()))
+      .filter(tax_rates::region.eq_any(regions))
+      .load(c)
+  }
+
+  pub fn create(c: &mut PgConnection, new_tax_rate: NewTaxRate) -> QueryResult<TaxRate> {
+    diesel::insert_into(tax_rates::table)
+      .values(NewTaxRate {
+        country: new_tax_rate.country.trim().to_uppercase(),
+        region: new_tax_rate.region.trim().to_uppercase(),
+        ..new_tax_rate
+      })
+      .get_result(c)
+  }
+
+  pub fn delete(c: &mut PgConnection, id: i32) -> QueryResult<usize> {
+    diesel::delete(tax_rates::table.find(id)).execute(c)
+  }
+}
+
+pub struct ExchangeRateRepository;
+
+impl ExchangeRateRepository {
+  pub fn find(c: &mut PgConnection, currency: &Currency) -> QueryResult<ExchangeRate> {
+    exchange_rates::table.find(currency).get_result(c)
+  }
+
+  pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<ExchangeRate>> {
+    exchange_rates::table.order(exchange_rates::currency).load(c)
+  }
+
+  // Creates or replaces the rate of the currency
+  pub fn set(c: &mut PgConnection, new_exchange_rate: NewExchangeRate) -> QueryResult<ExchangeRate> {
+    diesel::insert_into(exchange_rates::table)
+      .values(&new_exchange_rate)
+      .on_conflict(exchange_rates::currency)
+      .do_update()
+      .set((
+        exchange_rates::rate.eq(&new_exchange_rate.rate),
+        exchange_rates::updated_at.eq(diesel::dsl::now),
+      ))
+      .get_result(c)
+  }
+
+  pub fn delete(c: &mut PgConnection, currency: &Currency) -> QueryResult<usize> {
+    diesel::delete(exchange_rates::table.find(currency)).execute(c)
+  }
+}
+
+pub struct ItemPriceRepository;
+
+/**
+ * ItemPriceRepository manages the price lists of items in other currencies. Prices are stored as
+ * `money_amount`, so the currency is matched inside the composite value.
+ */
+impl ItemPriceRepository {
+  fn in_currency(currency: &Currency) -> Box<dyn BoxableExpression<item_prices::table, diesel::pg::Pg, SqlType = diesel::sql_types::Bool>> {
+    Box::new(diesel::dsl::sql::<diesel::sql_types::Bool>("(price).currency = ").bind::<diesel::sql_types::Text, _>(currency.code().to_string()))
+  }
+
+  pub fn find_by_item(c: &mut PgConnection, item_id: i32) -> QueryResult<Vec<ItemPrice>> {
+    item_prices::table
+      .filter(item_prices::item_id.eq(item_id))
+      .order(item_prices::id)
+      .load(c)
+  }
+
+  // Entries of the items in the currency
+  pub fn find_by_items(c: &mut PgConnection, items: &[Item], currency: &Currency) -> QueryResult<Vec<ItemPrice>> {
+    item_prices::table
+      .filter(item_prices::item_id.eq_any(items.iter().map(|item| item.id)))
+      .filter(Self::in_currency(currency))
+      .load(c)
+  }
+
+  // Creates or replaces the item's price in the currency of `price`
+  pub fn set(c: &mut PgConnection, item_id: i32, price: Money) -> QueryResult<ItemPrice> {
+    c.transaction(|c| {
+      diesel::delete(item_prices::table.filter(item_prices::item_id.eq(item_id)).filter(Self::in_currency(&price.currency))).execute(c)?;
+      diesel::insert_into(item_prices::table)
+        .values(NewItemPrice { item_id, price })
+        .get_result(c)
+    })
+  }
+
+  pub fn delete(c: &mut PgConnection, item_id: i32, currency: &Currency) -> QueryResult<usize> {
+    diesel::delete(item_prices::table.filter(item_prices::item_id.eq(item_id)).filter(Self::in_currency(currency))).execute(c)
+  }
+}
+
+pub struct AddressRepository;
+
+/**
+ * AddressRepository manages the address books of customers. Every lookup is scoped to the owner, so that
+ * another user's address is not found rather than forbidden. A user has at most one default shipping
+ * and one default billing address; their first address is both.
+ */
+impl AddressRepository {
+  pub fn find(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<Address> {
+    addresses::table
+      .filter(addresses::user_id.eq(user_id))
+      .find(id)
+      .get_result(c)
+  }
+
+  pub fn find_by_user(c: &mut PgConnection, user_id: i32) -> QueryResult<Vec<Address>> {
+    addresses::table
+      .filter(addresses::user_id.eq(user_id))
+      .order(addresses::id)
+      .load(c)
+  }
+
+  pub fn find_default_shipping(c: &mut PgConnection, user_id: i32) -> QueryResult<Address> {
+    addresses::table
+      .filter(addresses::user_id.eq(user_id))
+      .filter(addresses::is_default_shipping.eq(true))
+      .get_result(c)
+  }
+
+  pub fn find_default_billing(c: &mut PgConnection, user_id: i32) -> QueryResult<Address> {
+    addresses::table
+      .filter(addresses::user_id.eq(user_id))
+      .filter(addresses::is_default_billing.eq(true))
+      .get_result(c)
+  }
+
+  // Takes the default flags set on `address` away from the user's addresses, before it is written with them
+  fn clear_defaults(c: &mut PgConnection, address: &NewAddress) -> QueryResult<()> {
+    let owned = addresses::table.filter(addresses::user_id.eq(address.user_id));
+    if address.is_default_shipping {
+      diesel::update(owned).set(addresses::is_default_shipping.eq(false)).execute(c)?;
+    }
+    if address.is_default_billing {
+      diesel::update(owned).set(addresses::is_default_billing.eq(false)).execute(c)?;
+    }
+    Ok(())
+  }
+
+  pub fn create(c: &mut PgConnection, new_address: NewAddress) -> QueryResult<Address> {
+    c.transaction(|c| {
+      let first = addresses::table
+        .filter(addresses::user_id.eq(new_address.user_id))
+        .count()
+        .get_result::<i64>(c)? == 0;
+      let new_address = NewAddress {
+        is_default_shipping: new_address.is_default_shipping || first,
+        is_default_billing: new_address.is_default_billing || first,
+        ..new_address
+      };
+      Self::clear_defaults(c, &new_address)?;
+      diesel::insert_into(addresses::table)
+        .values(new_address)
+        .get_result(c)
+    })
+  }
+
+  // Replaces the address. Orders keep their own copy of it, see `AddressSnapshot`.
+  pub fn update(c: &mut PgConnection, id: i32, address: NewAddress) -> QueryResult<Address> {
+    c.transaction(|c| {
+      Self::find(c, address.user_id, id)?;
+      Self::clear_defaults(c, &address)?;
+      diesel::update(addresses::table.find(id))
+        .set(address)
+        .get_result(c)
+    })
+  }
+
+  pub fn delete(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<usize> {
+    diesel::delete(addresses::table.filter(addresses::user_id.eq(user_id)).find(id)).execute(c)
+  }
+}
+
+pub struct ShippingMethodRepository;
+
+/**
+ * ShippingMethodRepository manages shipping methods with the countries they ship to and their rate
+ * brackets, which are always written together with the method.
+ */
+impl ShippingMethodRepository {
+  pub fn find(c: &mut PgConnection, id: i32) -> QueryResult<ShippingMethod> {
+    shipping_methods::table.find(id).get_result(c)
+  }
+
+  pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<ShippingMethod>> {
+    shipping_methods::table.order(shipping_methods::id).load(c)
+  }
+
+  fn tables(c: &mut PgConnection, methods: Vec<ShippingMethod>) -> QueryResult<Vec<ShippingTable>> {
+    let countries = ShippingMethodCountry::belonging_to(&methods)
+      .order(shipping_method_countries::country)
+      .load::<ShippingMethodCountry>(c)?
+      .grouped_by(&methods);
+    let rates = ShippingRate::belonging_to(&methods)
+      .order(shipping_rates::min_value)
+      .load::<ShippingRate>(c)?
+      .grouped_by(&methods);
+    Ok(methods.into_iter()
+      .zip(countries)
+      .zip(rates)
+      .map(|((method, countries), rates)| ShippingTable {
+        method,
+        countries: countries.into_iter().map(|country| country.country).collect(),
+        rates,
+      })
+      .collect())
+  }
+
+  pub fn find_table(c: &mut PgConnection, id: i32) -> QueryResult<ShippingTable> {
+    let method = Self::find(c, id)?;
+    Self::tables(c, vec![method])?.pop().ok_or(Error::NotFound)
+  }
+
+  // The active methods shipping to the country
+  pub fn find_tables_for(c: &mut PgConnection, country: &str) -> QueryResult<Vec<ShippingTable>> {
+    let methods = shipping_methods::table
+      .filter(shipping_methods::active.eq(true))
+      .order(shipping_methods::id)
+      .load(c)?;
+    Ok(Self::tables(c, methods)?
+      .into_iter()
+      .filter(|table| table.ships_to(country))
+      .collect())
+  }
+
+  fn set_table(c: &mut PgConnection, id: i32, countries: Vec<String>, rates: Vec<NewShippingRate>) -> QueryResult<()> {
+    diesel::delete(shipping_method_countries::table.filter(shipping_method_countries::shipping_method_id.eq(id))).execute(c)?;
+    diesel::delete(shipping_rates::table.filter(shipping_rates::shipping_method_id.eq(id))).execute(c)?;
+    let countries: Vec<NewShippingMethodCountry> = countries.into_iter()
+      .map(|country| country.trim().to_uppercase())
+      .collect::<HashSet<_>>()
+      .into_iter()
+      .map(|country| NewShippingMethodCountry { shipping_method_id: id, country })
+      .collect();
+    diesel::insert_into(shipping_method_countries::table).values(countries).execute(c)?;
+    let rates: Vec<NewShippingRate> = rates.into_iter()
+      .map(|rate| NewShippingRate { shipping_method_id: id, ..rate })
+      .collect();
+    diesel::insert_into(shipping_rates::table).values(rates).execute(c)?;
+    Ok(())
+  }
+
+  pub fn create(c: &mut PgConnection, new_method: NewShippingMethod, countries: Vec<String>, rates: Vec<NewShippingRate>) -> QueryResult<ShippingMethod> {
+    c.transaction(|c| {
+      let method: ShippingMethod = diesel::insert_into(shipping_methods::table)
+        .values(new_method)
+        .get_result(c)?;
+      Self::set_table(c, method.id, countries, rates)?;
+      Ok(method)
+    })
+  }
+
+  // Replaces the method, its countries and its rates
+  pub fn update(c: &mut PgConnection, id: i32, method: NewShippingMethod, countries: Vec<String>, rates: Vec<NewShippingRate>) -> QueryResult<ShippingMethod> {
+    c.transaction(|c| {
+      let method: ShippingMethod = diesel::update(shipping_methods::table.find(id))
+        .set(method)
+        .get_result(c)?;
+      Self::set_table(c, id, countries, rates)?;
+      Ok(method)
+    })
+  }
+
+  pub fn delete(c: &mut PgConnection, id: i32) -> QueryResult<usize> {
+    diesel::delete(shipping_methods::table.find(id)).execute(c)
+  }
+}
+
+#[derive(Debug)]
+pub enum InventoryError {
+  Database(Error),
+  // The stock the item has, less than the quantity taken out
+  InsufficientStock(i32),
+}
+
+impl fmt::Display for InventoryError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      InventoryError::Database(e) => e.fmt(f),
+      InventoryError::InsufficientStock(quantity) => write!(f, "Only {} in stock", quantity),
+    }
+  }
+}
+
+impl std::error::Error for InventoryError {}
+
+impl From<Error> for InventoryError {
+  fn from(e: Error) -> Self {
+    InventoryError::Database(e)
+  }
+}
+
+pub struct InventoryRepository;
+
+/**
+ * InventoryRepository changes the stock of items outside of item updates and keeps a ledger of the
+ * changes. Adjustments are relative, so concurrent ones add up instead of overwriting each other.
+ */
+impl InventoryRepository {
+  pub fn find_by_item(c: &mut PgConnection, item_id: i32) -> QueryResult<Vec<InventoryAdjustment>> {
+    inventory_adjustments::table
+      .filter(inventory_adjustments::item_id.eq(item_id))
+      .order(inventory_adjustments::id)
+      .load(c)
+  }
+
+  // Adds `delta`, negative to take items out, to the item's stock. The stock never goes below zero.
+  pub fn adjust(c: &mut PgConnection, item_id: i32, delta: i32, reason: InventoryReason, reference: Option<String>) -> Result<InventoryAdjustment, InventoryError> {
+    c.transaction(|c| {
+      let quantity: i32 = diesel::update(items::table.find(item_id))
+        .set(items::quantity.eq(items::quantity + delta))
+        .returning(items::quantity)
+        .get_result(c)?;
+      if quantity < 0 {
+        return Err(InventoryError::InsufficientStock(quantity - delta));
+      }
+      if quantity > 0 && quantity - delta <= 0 {
+        NotificationRepository::restocked(c, item_id)?;
+      }
+      Ok(diesel::insert_into(inventory_adjustments::table)
+        .values(NewInventoryAdjustment { item_id, delta, quantity, reason: reason.as_str().to_string(), reference })
+        .get_result(c)?)
+    })
+  }
+}
+
+#[derive(Debug)]
+pub enum ReturnError {
+  Database(Error),
+  // The return's status does not allow the change
+  InvalidStatus(ReturnStatus),
+  // Id of a line that is not part of the return, or would be received more times than it was returned
+  InvalidLine(i32),
+  // What is left to refund, in the refundable currency
+  ExceedsRefundable(Money),
+  RefundIssued,
+  Inventory(InventoryError),
+  // The customer has no invoice with the return's order reference
+  UnknownOrder(String),
+  // Id of an item the order's invoice does not bill
+  NotOrdered(i32),
+  // Id of an item and how many of it are left to return, after the order's earlier returns
+  ExceedsOrdered(i32, i32),
+}
+
+impl fmt::Display for ReturnError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ReturnError::Database(e) => e.fmt(f),
+      ReturnError::InvalidStatus(status) => write!(f, "Not possible while the return is {}", status.as_str()),
+      ReturnError::InvalidLine(id) => write!(f, "Line {} is not part of the return or was received already", id),
+      ReturnError::ExceedsRefundable(left) => write!(f, "At most {} {} is left to refund", left.amount, left.currency),
+      ReturnError::RefundIssued => f.write_str("The refund was issued already"),
+      ReturnError::Inventory(e) => e.fmt(f),
+      ReturnError::UnknownOrder(order_reference) => write!(f, "You have no order `{}`", order_reference),
+      ReturnError::NotOrdered(item_id) => write!(f, "Item {} is not part of the order", item_id),
+      ReturnError::ExceedsOrdered(item_id, left) => write!(f, "At most {} of item {} can still be returned", left, item_id),
+    }
+  }
+}
+
+impl std::error::Error for ReturnError {}
+
+impl From<Error> for ReturnError {
+  fn from(e: Error) -> Self {
+    ReturnError::Database(e)
+  }
+}
+
+impl From<InventoryError> for ReturnError {
+  fn from(e: InventoryError) -> Self {
+    ReturnError::Inventory(e)
+  }
+}
+
+pub struct ReturnRepository;
+
+/**
+ * ReturnRepository runs the return workflow: customers request returns of order lines, the merchant
+ * approves them with the amount that can be refunded or rejects them, receives the items back, restocking
+ * those fit for sale, and records refunds until the refundable amount is reached. Refunds are recorded
+ * pending and marked issued once the payment provider paid them back.
+ */
+impl ReturnRepository {
+  pub fn find(c: &mut PgConnection, id: i32) -> QueryResult<Return> {
+    returns::table.find(id).get_result(c)
+  }
+
+  pub fn find_for_user(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<Return> {
+    returns::table
+      .filter(returns::user_id.eq(user_id))
+      .find(id)
+      .get_result(c)
+  }
+
+  pub fn find_by_user(c: &mut PgConnection, user_id: i32) -> QueryResult<Vec<Return>> {
+    returns::table
+      .filter(returns::user_id.eq(user_id))
+      .order(returns::id.desc())
+      .load(c)
+  }
+
+  pub fn find_all(c: &mut PgConnection, status: Option<ReturnStatus>) -> QueryResult<Vec<Return>> {
+    let mut query = returns::table.order(returns::id).into_boxed();
+    if let Some(status) = status {
+      query = query.filter(returns::status.eq(status));
+    }
+    query.load(c)
+  }
+
+  pub fn find_lines(c: &mut PgConnection, return_id: i32) -> QueryResult<Vec<ReturnLine>> {
+    return_lines::table
+      .filter(return_lines::return_id.eq(return_id))
+      .order(return_lines::id)
+      .load(c)
+  }
+
+  pub fn find_refunds(c: &mut PgConnection, return_id: i32) -> QueryResult<Vec<Refund>> {
+    refunds::table
+      .filter(refunds::return_id.eq(return_id))
+      .order(refunds::id)
+      .load(c)
+  }
+
+  // How many of each item the customer's returns of the order claim, rejected returns aside
+  fn find_returned_quantities(c: &mut PgConnection, user_id: Option<i32>, order_reference: &str) -> QueryResult<HashMap<i32, i64>> {
+    let returned: Vec<(Option<i32>, Option<i64>)> = return_lines::table
+      .inner_join(returns::table)
+      .filter(returns::user_id.eq(user_id))
+      .filter(returns::order_reference.eq(order_reference))
+      .filter(returns::status.ne(ReturnStatus::Rejected))
+      .group_by(return_lines::item_id)
+      .select((return_lines::item_id, diesel::dsl::sum(return_lines::quantity)))
+      .load(c)?;
+    Ok(returned.into_iter()
+      .filter_map(|(item_id, quantity)| Some((item_id?, quantity.unwrap_or(0))))
+      .collect())
+  }
+
+  // The order must be invoiced to the customer, and bill every item returned at least as many times as this
+  // and its earlier returns claim. The invoice stays locked until the return is recorded, so that returns
+  // of the same order are checked one after the other.
+  pub fn create(c: &mut PgConnection, new_return: NewReturn, lines: Vec<NewReturnLine>) -> Result<Return, ReturnError> {
+    c.transaction(|c| {
+      let invoice = InvoiceRepository::lock_by_order(c, new_return.user_id, &new_return.order_reference)
+        .optional()?
+        .ok_or_else(|| ReturnError::UnknownOrder(new_return.order_reference.clone()))?;
+      let invoiced: HashMap<i32, Option<i32>> = InvoiceRepository::find_quantities(c, invoice.id)?.into_iter().collect();
+      let returned = Self::find_returned_quantities(c, new_return.user_id, &new_return.order_reference)?;
+      for line in &lines {
+        let Some(item_id) = line.item_id else { continue };
+        // Lines of invoices that did not record quantities cannot be returned
+        let invoiced = invoiced.get(&item_id).ok_or(ReturnError::NotOrdered(item_id))?.unwrap_or(0);
+        let left = i64::from(invoiced) - returned.get(&item_id).copied().unwrap_or(0);
+        if i64::from(line.quantity) > left {
+          return Err(ReturnError::ExceedsOrdered(item_id, left.max(0) as i32));
+        }
+      }
+
+      let created: Return = diesel::insert_into(returns::table)
+        .values(new_return)
+        .get_result(c)?;
+      let lines: Vec<NewReturnLine> = lines.into_iter()
+        .map(|line| NewReturnLine { return_id: created.id, ..line })
+        .collect();
+      diesel::insert_into(return_lines::table).values(lines).execute(c)?;
+      Ok(created)
+    })
+  }
+
+  // Locks the return and checks that its status is one of `expected`
+  fn find_in(c: &mut PgConnection, id: i32, expected: &[ReturnStatus]) -> Result<Return, ReturnError> {
+    let found: Return = returns::table.find(id).for_update().get_result(c)?;
+    if !expected.contains(&found.status) {
+      return Err(ReturnError::InvalidStatus(found.status));
+    }
+    Ok(found)
+  }
+
+  fn set_status(c: &mut PgConnection, id: i32, status: ReturnStatus) -> QueryResult<Return> {
+    diesel::update(returns::table.find(id))
+      .set((returns::status.eq(status), returns::updated_at.eq(diesel::dsl::now)))
+      .get_result(c)
+  }
+
+  pub fn approve(c: &mut PgConnection, id: i32, refundable: Money, note: Option<String>) -> Result<Return, ReturnError> {
+    c.transaction(|c| {
+      Self::find_in(c, id, &[ReturnStatus::Requested])?;
+      diesel::update(returns::table.find(id))
+        .set((returns::refundable.eq(refundable), returns::note.eq(note)))
+        .execute(c)?;
+      Ok(Self::set_status(c, id, ReturnStatus::Approved)?)
+    })
+  }
+
+  pub fn reject(c: &mut PgConnection, id: i32, note: Option<String>) -> Result<Return, ReturnError> {
+    c.transaction(|c| {
+      Self::find_in(c, id, &[ReturnStatus::Requested])?;
+      diesel::update(returns::table.find(id)).set(returns::note.eq(note)).execute(c)?;
+      Ok(Self::set_status(c, id, ReturnStatus::Rejected)?)
+    })
+  }
+
+  // Records items arriving back, possibly over several deliveries, and restocks those asked to
+  pub fn receive(c: &mut PgConnection, id: i32, received: Vec<ReceivedLine>) -> Result<Return, ReturnError> {
+    c.transaction(|c| {
+      let found = Self::find_in(c, id, &[ReturnStatus::Approved, ReturnStatus::Received, ReturnStatus::Refunded])?;
+      let lines = Self::find_lines(c, id)?;
+      for received in received {
+        let line = lines.iter()
+          .find(|line| line.id == received.line_id)
+          .filter(|line| received.quantity > 0 && line.received_quantity + received.quantity <= line.quantity)
+          .ok_or(ReturnError::InvalidLine(received.line_id))?;
+        let restocked = match (received.restock, line.item_id) {
+          (true, Some(item_id)) => {
+            InventoryRepository::adjust(c, item_id, received.quantity, InventoryReason::Return, Some(format!("RMA-{}", id)))?;
+            received.quantity
+          },
+          _ => 0,
+        };
+        diesel::update(return_lines::table.find(line.id))
+          .set((
+            return_lines::received_quantity.eq(return_lines::received_quantity + received.quantity),
+            return_lines::restocked_quantity.eq(return_lines::restocked_quantity + restocked),
+          ))
+          .execute(c)?;
+      }
+      // A refunded return stays refunded when its items arrive after the money went back
+      match found.status {
+        ReturnStatus::Refunded => Ok(Self::find(c, id)?),
+        _ => Ok(Self::set_status(c, id, ReturnStatus::Received)?),
+      }
+    })
+  }
+
+  // Records a refund of part or all of what is left to refund. The return is refunded once nothing is left.
+  pub fn refund(c: &mut PgConnection, id: i32, amount: Money) -> Result<Refund, ReturnError> {
+    c.transaction(|c| {
+      let found = Self::find_in(c, id, &[ReturnStatus::Approved, ReturnStatus::Received])?;
+      let refundable = found.refundable.ok_or(ReturnError::InvalidStatus(found.status))?;
+      let refunded: BigDecimal = Self::find_refunds(c, id)?
+        .iter()
+        .map(|refund| &refund.amount.amount)
+        .sum();
+      let left = Money::new(&(&refundable.amount - refunded), &refundable.currency);
+      let amount = Money::new(&amount.amount, &amount.currency);
+      if amount.currency != refundable.currency || amount.amount > left.amount {
+        return Err(ReturnError::ExceedsRefundable(left));
+      }
+
+      let refund: Refund = diesel::insert_into(refunds::table)
+        .values(NewRefund { return_id: id, amount })
+        .get_result(c)?;
+      if refund.amount.amount == left.amount {
+        Self::set_status(c, id, ReturnStatus::Refunded)?;
+      }
+      Ok(refund)
+    })
+  }
+
+  // Marks a pending refund paid back by the payment provider
+  pub fn issue_refund(c: &mut PgConnection, return_id: i32, refund_id: i32, provider_reference: String) -> Result<Refund, ReturnError> {
+    c.transaction(|c| {
+      let refund: Refund = refunds::table
+        .filter(refunds::return_id.eq(return_id))
+        .find(refund_id)
+        .for_update()
+        .get_result(c)?;
+      if refund.status == RefundStatus::Issued {
+        return Err(ReturnError::RefundIssued);
+      }
+      Ok(diesel::update(refunds::table.find(refund_id))
+        .set((
+          refunds::status.eq(RefundStatus::Issued),
+          refunds::provider_reference.eq(provider_reference),
+          refunds::issued_at.eq(diesel::dsl::now),
+        ))
+        .get_result(c)?)
+    })
+  }
+}
+
+#[derive(Debug)]
+pub enum InvoiceError {
+  Database(Error),
+}
+
+impl fmt::Display for InvoiceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      InvoiceError::Database(e) => e.fmt(f),
+    }
+  }
+}
+
+impl std::error::Error for InvoiceError {}
+
+impl From<Error> for InvoiceError {
+  fn from(e: Error) -> Self {
+    InvoiceError::Database(e)
+  }
+}
+
+pub struct InvoiceRepository;
+
+/**
+ * InvoiceRepository issues invoices and finds them again. Invoices are numbered per year without gaps:
+ * the number is taken in the transaction that records the invoice, and given back if anything fails.
+ * Their PDF and HTML documents are rendered once and kept in the invoice store; the invoice keeps what
+ * they say, so that documents the store lost or never received can be rendered again.
+ */
+impl InvoiceRepository {
+  pub fn find(c: &mut PgConnection, id: i32) -> QueryResult<Invoice> {
+    invoices::table.find(id).get_result(c)
+  }
+
+  pub fn find_for_user(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<Invoice> {
+    invoices::table
+      .filter(invoices::user_id.eq(user_id))
+      .find(id)
+      .get_result(c)
+  }
+
+  pub fn find_by_user(c: &mut PgConnection, user_id: i32) -> QueryResult<Vec<Invoice>> {
+    invoices::table
+      .filter(invoices::user_id.eq(user_id))
+      .order(invoices::id.desc())
+      .load(c)
+  }
+
+  pub fn find_all(c: &mut PgConnection) -> QueryResult<Vec<Invoice>> {
+    invoices::table.order((invoices::year, invoices::sequence)).load(c)
+  }
+
+  // Keys of both documents of every invoice
+  pub fn find_all_storage_keys(c: &mut PgConnection) -> QueryResult<Vec<String>> {
+    let keys: Vec<(String, String)> = invoices::table.select((invoices::pdf_key, invoices::html_key)).load(c)?;
+    Ok(keys.into_iter().flat_map(|(pdf_key, html_key)| [pdf_key, html_key]).collect())
+  }
+
+  // The invoice of an order, when it was issued to `user_id`, locked until the transaction ends
+  pub fn lock_by_order(c: &mut PgConnection, user_id: Option<i32>, order_reference: &str) -> QueryResult<Invoice> {
+    invoices::table
+      .filter(invoices::user_id.eq(user_id))
+      .filter(invoices::order_reference.eq(order_reference))
+      .for_update()
+      .get_result(c)
+  }
+
+  // The items the invoice bills with how many of each, but not those deleted since. Quantities are None
+  // for invoices issued before they were recorded.
+  pub fn find_quantities(c: &mut PgConnection, invoice_id: i32) -> QueryResult<Vec<(i32, Option<i32>)>> {
+    invoice_items::table
+      .filter(invoice_items::invoice_id.eq(invoice_id))
+      .filter(invoice_items::item_id.is_not_null())
+      .select((invoice_items::item_id.assume_not_null(), invoice_items::quantity))
+      .load(c)
+  }
+
+  // Whether the user was invoiced for the item
+  pub fn has_invoiced(c: &mut PgConnection, user_id: i32, item_id: i32) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+      invoice_items::table
+        .inner_join(invoices::table)
+        .filter(invoices::user_id.eq(user_id))
+        .filter(invoice_items::item_id.eq(item_id))
+    ))
+      .get_result(c)
+  }
+
+  // Numbers and dates the document and records it, with the items it bills, then stores its renditions. Issuing
+  // holds the year's counter until the invoice commits, so invoices are issued one at a time; storing comes after
+  // and does not fail the invoice, a document missing from the store is rendered again when downloaded.
+  pub fn issue(c: &mut PgConnection, store: &dyn ImageStore, user_id: Option<i32>, mut document: InvoiceDocument) -> Result<Invoice, InvoiceError> {
+    let invoice = c.transaction(|c| {
+      let issued_at = Utc::now().naive_utc();
+      let year = issued_at.year();
+      let sequence: i32 = diesel::insert_into(invoice_sequences::table)
+        .values((invoice_sequences::year.eq(year), invoice_sequences::last_number.eq(1)))
+        .on_conflict(invoice_sequences::year)
+        .do_update()
+        .set(invoice_sequences::last_number.eq(invoice_sequences::last_number + 1))
+        .returning(invoice_sequences::last_number)
+        .get_result(c)?;
+      document.number = format!("{}-{:06}", year, sequence);
+      document.issued_on = issued_at.date();
+
+      let invoice: Invoice = diesel::insert_into(invoices::table)
+        .values(NewInvoice {
+          number: document.number.clone(),
+          year,
+          sequence,
+          user_id,
+          order_reference: document.order_reference.clone(),
+          total: Money::new(&document.gross, &document.currency),
+          tax: Money::new(&document.tax, &document.currency),
+          pdf_key: generate_key(&document.file_name("pdf")),
+          html_key: generate_key(&document.file_name("html")),
+          issued_at,
+          document: serde_json::to_value(&document).map_err(|e| Error::SerializationError(Box::new(e)))?,
+        })
+        .get_result(c)?;
+      let mut quantities: BTreeMap<i32, i32> = BTreeMap::new();
+      for line in &document.lines {
+        if let Some(item_id) = line.item_id {
+          *quantities.entry(item_id).or_default() += line.quantity;
+        }
+      }
+      let invoice_items: Vec<NewInvoiceItem> = quantities.into_iter()
+        .map(|(item_id, quantity)| NewInvoiceItem { invoice_id: invoice.id, item_id, quantity })
+        .collect();
+      diesel::insert_into(invoice_items::table).values(invoice_items).execute(c)?;
+      QueryResult::Ok(invoice)
+    })?;
+
+    if let Err(e) = document.store(store, &invoice) {
+      log::error!("Invoice {} is issued but its documents could not be stored, they are rendered when downloaded: {}", invoice.number, e);
+    }
+    let unprintable = document.unprintable();
+    if !unprintable.is_empty() {
+      log::warn!("The PDF of invoice {} shows {:?} as `?`, its HTML document has them", invoice.number, unprintable);
+    }
+    Ok(invoice)
+  }
+}
+
+pub struct ReviewRepository;
+
+/**
+ * ReviewRepository keeps reviews and the rating of their items in step: an item's `rating_count` and
+ * `rating_sum` cover its approved reviews, and change in the transaction that approves, edits or deletes one.
+ * Authors only reach their own reviews; editing one sends it back to moderation.
+ */
+impl ReviewRepository {
+  pub fn find(c: &mut PgConnection, id: i32) -> QueryResult<Review> {
+    reviews::table.find(id).get_result(c)
+  }
+
+  pub fn find_by_user(c: &mut PgConnection, user_id: i32) -> QueryResult<Vec<Review>> {
+    reviews::table
+      .filter(reviews::user_id.eq(user_id))
+      .order(reviews::id.desc())
+      .load(c)
+  }
+
+  // A page of the item's approved reviews, newest first, and how many there are in all
+  pub fn find_approved(c: &mut PgConnection, item_id: i32, limit: i64, offset: i64) -> QueryResult<(Vec<Review>, i64)> {
+    let approved = reviews::table
+      .filter(reviews::item_id.eq(item_id))
+      .filter(reviews::status.eq(ReviewStatus::Approved));
+    let total = approved.count().get_result(c)?;
+    let page = approved
+      .order((reviews::created_at.desc(), reviews::id.desc()))
+      .limit(limit)
+      .offset(offset)
+      .load(c)?;
+    Ok((page, total))
+  }
+
+  // A page of the reviews in `status`, oldest first so that moderators work through them in order
+  pub fn find_by_status(c: &mut PgConnection, status: ReviewStatus, limit: i64, offset: i64) -> QueryResult<(Vec<Review>, i64)> {
+    let total = reviews::table.filter(reviews::status.eq(status)).count().get_result(c)?;
+    let page = reviews::table
+      .filter(reviews::status.eq(status))
+      .order((reviews::created_at, reviews::id))
+      .limit(limit)
+      .offset(offset)
+      .load(c)?;
+    Ok((page, total))
+  }
+
+  // Adds or, with `sign` -1, removes an approved review from its item's rating
+  fn rate(c: &mut PgConnection, review: &Review, sign: i32) -> QueryResult<()> {
+    diesel::update(items::table.find(review.item_id))
+      .set((
+        items::rating_count.eq(items::rating_count + sign),
+        items::rating_sum.eq(items::rating_sum + sign * review.rating),
+      ))
+      .execute(c)?;
+    Ok(())
+  }
+
+  fn find_own_for_update(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<Review> {
+    reviews::table
+      .filter(reviews::user_id.eq(user_id))
+      .find(id)
+      .for_update()
+      .get_result(c)
+  }
+
+  // The review waits for moderation. It is a verified purchase when its author was invoiced for the item.
+  pub fn create(c: &mut PgConnection, new_review: NewReview) -> QueryResult<Review> {
+    let verified_purchase = match new_review.user_id {
+      Some(user_id) => InvoiceRepository::has_invoiced(c, user_id, new_review.item_id)?,
+      None => false,
+    };
+    diesel::insert_into(reviews::table)
+      .values(NewReview { verified_purchase, ..new_review })
+      .get_result(c)
+  }
+
+  // Replaces the rating and text of one of the user's reviews, which goes back to moderation
+  pub fn update(c: &mut PgConnection, user_id: i32, id: i32, review: NewReview) -> QueryResult<Review> {
+    c.transaction(|c| {
+      let current = Self::find_own_for_update(c, user_id, id)?;
+      if current.status == ReviewStatus::Approved {
+        Self::rate(c, &current, -1)?;
+      }
+      let verified_purchase = current.verified_purchase || InvoiceRepository::has_invoiced(c, user_id, current.item_id)?;
+      diesel::update(reviews::table.find(id))
+        .set((
+          reviews::rating.eq(review.rating),
+          reviews::title.eq(review.title),
+          reviews::body.eq(review.body),
+          reviews::status.eq(ReviewStatus::Pending),
+          reviews::verified_purchase.eq(verified_purchase),
+          reviews::moderation_note.eq(None::<String>),
+          reviews::updated_at.eq(diesel::dsl::now),
+        ))
+        .get_result(c)
+    })
+  }
+
+  pub fn delete(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<usize> {
+    c.transaction(|c| {
+      let current = match Self::find_own_for_update(c, user_id, id) {
+        Ok(current) => current,
+        Err(Error::NotFound) => return Ok(0),
+        Err(e) => return Err(e),
+      };
+      if current.status == ReviewStatus::Approved {
+        Self::rate(c, &current, -1)?;
+      }
+      diesel::delete(reviews::table.find(id)).execute(c)
+    })
+  }
+
+  pub fn moderate(c: &mut PgConnection, id: i32, status: ReviewStatus, note: Option<String>) -> QueryResult<Review> {
+    c.transaction(|c| {
+      let current: Review = reviews::table.find(id).for_update().get_result(c)?;
+      match (current.status == ReviewStatus::Approved, status == ReviewStatus::Approved) {
+        (true, false) => Self::rate(c, &current, -1)?,
+        (false, true) => Self::rate(c, &current, 1)?,
+        _ => (),
+      }
+      diesel::update(reviews::table.find(id))
+        .set((
+          reviews::status.eq(status),
+          reviews::moderation_note.eq(note),
+          reviews::updated_at.eq(diesel::dsl::now),
+        ))
+        .get_result(c)
+    })
+  }
+}
+
+// A token for a public link to a wishlist, long enough not to be guessed
+fn share_token() -> String {
+  rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(32)
+    .map(char::from)
+    .collect()
+}
+
+pub struct WishlistRepository;
+
+/**
+ * WishlistRepository manages the named wishlists of customers. Like addresses, lists are scoped to their owner,
+ * except through their share token, which anyone who was given the link may use until the owner stops sharing.
+ * An item is at most once on a list and remembers its price when it was added.
+ */
+impl WishlistRepository {
+  pub fn find(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<Wishlist> {
+    wishlists::table
+      .filter(wishlists::user_id.eq(user_id))
+      .find(id)
+      .get_result(c)
+  }
+
+  pub fn find_by_user(c: &mut PgConnection, user_id: i32) -> QueryResult<Vec<Wishlist>> {
+    wishlists::table
+      .filter(wishlists::user_id.eq(user_id))
+      .order(wishlists::id)
+      .load(c)
+  }
+
+  pub fn find_shared(c: &mut PgConnection, share_token: &str) -> QueryResult<Wishlist> {
+    wishlists::table
+      .filter(wishlists::share_token.eq(share_token))
+      .get_result(c)
+  }
+
+  // The list's items, oldest first
+  pub fn find_items(c: &mut PgConnection, wishlist_id: i32) -> QueryResult<Vec<(WishlistItem, Item)>> {
+    wishlist_items::table
+      .inner_join(items::table)
+      .filter(wishlist_items::wishlist_id.eq(wishlist_id))
+      .order((wishlist_items::added_at, wishlist_items::item_id))
+      .load(c)
+  }
+
+  pub fn create(c: &mut PgConnection, new_wishlist: NewWishlist) -> QueryResult<Wishlist> {
+    diesel::insert_into(wishlists::table)
+      .values(new_wishlist)
+      .get_result(c)
+  }
+
+  pub fn rename(c: &mut PgConnection, user_id: i32, id: i32, name: String) -> QueryResult<Wishlist> {
+    diesel::update(wishlists::table.filter(wishlists::user_id.eq(user_id)).find(id))
+      .set(wishlists::name.eq(name))
+      .get_result(c)
+  }
+
+  pub fn delete(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<usize> {
+    diesel::delete(wishlists::table.filter(wishlists::user_id.eq(user_id)).find(id)).execute(c)
+  }
+
+  // Gives the list a share token, keeping the one it has so that links given out keep working
+  pub fn share(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<Wishlist> {
+    c.transaction(|c| {
+      let wishlist: Wishlist = wishlists::table
+        .filter(wishlists::user_id.eq(user_id))
+        .find(id)
+        .for_update()
+        .get_result(c)?;
+      if wishlist.share_token.is_some() {
+        return Ok(wishlist);
+      }
+      diesel::update(wishlists::table.find(id))
+        .set(wishlists::share_token.eq(share_token()))
+        .get_result(c)
+    })
+  }
+
+  // Drops the share token, links given out stop working
+  pub fn unshare(c: &mut PgConnection, user_id: i32, id: i32) -> QueryResult<Wishlist> {
+    diesel::update(wishlists::table.filter(wishlists::user_id.eq(user_id)).find(id))
+      .set(wishlists::share_token.eq(None::<String>))
+      .get_result(c)
+  }
+
+  // Adds the item to one of the user's lists at its current price. An item already on the list takes
+  // the new quantity and keeps the price it was added at.
+  pub fn add_item(c: &mut PgConnection, user_id: i32, id: i32, item_id: i32, quantity: i32) -> QueryResult<WishlistItem> {
+    c.transaction(|c| {
+      Self::find(c, user_id, id)?;
+      let price = items::table.find(item_id).select(items::price).get_result(c)?;
+      diesel::insert_into(wishlist_items::table)
+        .values(NewWishlistItem { wishlist_id: id, item_id, quantity, added_price: price })
+        .on_conflict((wishlist_items::wishlist_id, wishlist_items::item_id))
+        .do_update()
+        .set(wishlist_items::quantity.eq(quantity))
+        .get_result(c)
+    })
+  }
+
+  // Takes the item off one of the user's lists, NotFound when the list does not have it
+  pub fn remove_item(c: &mut PgConnection, user_id: i32, id: i32, item_id: i32) -> QueryResult<WishlistItem> {
+    c.transaction(|c| {
+      Self::find(c, user_id, id)?;
+      diesel::delete(wishlist_items::table.find((id, item_id))).get_result(c)
+    })
+  }
+}
+
+pub struct NotificationRepository;
+
+/**
+ * NotificationRepository is the outbox of messages to customers. Changes that customers may want to hear
+ * about enqueue notifications in their own transaction, so a notification exists exactly when its change
+ * was committed; a sender then delivers the undelivered ones and marks them.
+ */
+impl NotificationRepository {
+  // The user's notifications, newest first, and how many there are
+  pub fn find_by_user(c: &mut PgConnection, user_id: i32, limit: i64, offset: i64) -> QueryResult<(Vec<Notification>, i64)> {
+    let total = notifications::table
+      .filter(notifications::user_id.eq(user_id))
+      .count()
+      .get_result(c)?;
+    let page = notifications::table
+      .filter(notifications::user_id.eq(user_id))
+      .order((notifications::created_at.desc(), notifications::id.desc()))
+      .limit(limit)
+      .offset(offset)
+      .load(c)?;
+    Ok((page, total))
+  }
+
+  // The oldest notifications waiting to be sent
+  pub fn find_undelivered(c: &mut PgConnection, limit: i64) -> QueryResult<Vec<Notification>> {
+    notifications::table
+      .filter(notifications::delivered_at.is_null())
+      .order(notifications::id)
+      .limit(limit)
+      .load(c)
+  }
+
+  // Marks notifications as sent, returns how many were waiting
+  pub fn mark_delivered(c: &mut PgConnection, ids: Vec<i32>) -> QueryResult<usize> {
+    diesel::update(notifications::table
+      .filter(notifications::id.eq_any(ids))
+      .filter(notifications::delivered_at.is_null()))
+      .set(notifications::delivered_at.eq(diesel::dsl::now))
+      .execute(c)
+  }
+
+  // Tells the users with the item on a wishlist that its price went down
+  pub fn price_dropped(c: &mut PgConnection, item_id: i32, previous_price: &BigDecimal, price: &BigDecimal) -> QueryResult<usize> {
+    use diesel::sql_types::{Nullable, Numeric, Text};
+    diesel::insert_into(notifications::table)
+      .values(wishlist_items::table
+        .inner_join(wishlists::table)
+        .filter(wishlist_items::item_id.eq(item_id))
+        .select((
+          wishlists::user_id,
+          wishlist_items::item_id,
+          NotificationKind::PriceDrop.as_str().into_sql::<Text>(),
+          previous_price.into_sql::<Nullable<Numeric>>(),
+          price.into_sql::<Nullable<Numeric>>(),
+        ))
+        .distinct())
+      .into_columns((notifications::user_id, notifications::item_id, notifications::kind, notifications::previous_price, notifications::price))
+      .execute(c)
+  }
+
+  // Tells the users subscribed to the item or with it on a wishlist that it is in stock again, once each,
+  // and ends the subscriptions. Called when the stock goes from none to some, with the item's row locked.
+  pub fn restocked(c: &mut PgConnection, item_id: i32) -> QueryResult<usize> {
+    let subscribers: Vec<i32> = diesel::delete(stock_subscriptions::table.filter(stock_subscriptions::item_id.eq(item_id)))
+      .returning(stock_subscriptions::user_id)
+      .get_results(c)?;
+    let wishers: Vec<i32> = wishlist_items::table
+      .inner_join(wishlists::table)
+      .filter(wishlist_items::item_id.eq(item_id))
+      .select(wishlists::user_id)
+      .load(c)?;
+    let notifications: Vec<_> = subscribers.into_iter()
+      .chain(wishers)
+      .collect::<BTreeSet<_>>()
+      .into_iter()
+      .map(|user_id| NewNotification { user_id, item_id, kind: NotificationKind::BackInStock.as_str().to_string() })
+      .collect();
+    diesel::insert_into(notifications::table)
+      .values(notifications)
+      .execute(c)
+  }
+}
+
+#[derive(Debug)]
+pub enum SubscriptionError {
+  Database(Error),
+  // The item can be bought now
+  InStock,
+}
+
+impl fmt::Display for SubscriptionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SubscriptionError::Database(e) => e.fmt(f),
+      SubscriptionError::InStock => f.write_str("The item is in stock"),
+    }
+  }
+}
+
+impl std::error::Error for SubscriptionError {}
+
+impl From<Error> for SubscriptionError {
+  fn from(e: Error) -> Self {
+    SubscriptionError::Database(e)
+  }
+}
+
+pub struct StockSubscriptionRepository;
+
+/**
+ * StockSubscriptionRepository keeps the customers waiting for out-of-stock items. A subscription lasts until
+ * the next restock, see `NotificationRepository::restocked`, which every change that adds stock goes through.
+ */
+impl StockSubscriptionRepository {
+  pub fn find_by_user(c: &mut PgConnection, user_id: i32) -> QueryResult<Vec<(StockSubscription, Item)>> {
+    stock_subscriptions::table
+      .inner_join(items::table)
+      .filter(stock_subscriptions::user_id.eq(user_id))
+      .order(stock_subscriptions::id)
+      .load(c)
+  }
+
+  // Subscribes the user to an out-of-stock item, keeping the subscription the user may have. The item stays
+  // locked until the subscription is recorded, so a restock either sees it or comes before and fails it.
+  pub fn subscribe(c: &mut PgConnection, user_id: i32, item_id: i32) -> Result<StockSubscription, SubscriptionError> {
+    c.transaction(|c| {
+      let quantity: i32 = items::table.find(item_id).select(items::quantity).for_update().get_result(c)?;
+      if quantity > 0 {
+        return Err(SubscriptionError::InStock);
+      }
+      diesel::insert_into(stock_subscriptions::table)
+        .values(NewStockSubscription { user_id, item_id })
+        .on_conflict_do_nothing()
+        .execute(c)?;
+      Ok(stock_subscriptions::table
+        .filter(stock_subscriptions::user_id.eq(user_id))
+        .filter(stock_subscriptions::item_id.eq(item_id))
+        .get_result(c)?)
+    })
+  }
+
+  pub fn unsubscribe(c: &mut PgConnection, user_id: i32, item_id: i32) -> QueryResult<usize> {
+    diesel::delete(stock_subscriptions::table
+      .filter(stock_subscriptions::user_id.eq(user_id))
+      .filter(stock_subscriptions::item_id.eq(item_id)))
+      .execute(c)
+  }
+
+  pub fn count_by_item(c: &mut PgConnection, item_id: i32) -> QueryResult<i64> {
+    stock_subscriptions::table
+      .filter(stock_subscriptions::item_id.eq(item_id))
+      .count()
+      .get_result(c)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn schedules_without_end_last_an_instant() {
+    let starts_at = NaiveDateTime::default();
+    let ends_at = starts_at + Duration::days(7);
+    assert_eq!(ScheduledPriceRepository::interval(starts_at, Some(ends_at)), (starts_at, ends_at));
+    assert_eq!(ScheduledPriceRepository::interval(starts_at, None), (starts_at, starts_at + Duration::microseconds(1)));
+  }
+
+  #[test]
+  fn copy_fields_are_unescaped() {
+    assert_eq!(copy_field("\\N"), None);
+    assert_eq!(copy_field("").as_deref(), Some(""));
+    assert_eq!(copy_field("\\\\N").as_deref(), Some("\\N"));
+    assert_eq!(copy_field("a\\tb\\nc\\\\d").as_deref(), Some("a\tb\nc\\d"));
+  }
+
+  #[test]
+  fn copy_rows_keep_empty_and_missing_text_apart() {
+    let row = "7\tMug\t\t4.50\t2024-05-09 10:22:33.123456\t3\tmug\t\\N\t5.00\tstandard\t250\t\\N\t80\t\\N\t2\t9";
+    let item = ItemRepository::from_copy_row(row).unwrap();
+    assert_eq!(item.id, 7);
+    assert_eq!(item.name, "Mug");
+    assert_eq!(item.description.as_deref(), Some(""));
+    assert_eq!(item.price.to_string(), "4.50");
+    assert_eq!(item.created_at.unwrap().to_string(), "2024-05-09 10:22:33.123456");
+    assert_eq!(item.quantity, 3);
+    assert_eq!(item.slug, "mug");
+    assert_eq!(item.sku, None);
+    assert_eq!(item.was_price.unwrap().to_string(), "5.00");
+    assert_eq!(item.tax_class, "standard");
+    assert_eq!((item.weight_grams, item.length_mm, item.width_mm, item.height_mm), (Some(250), None, Some(80), None));
+    assert_eq!((item.rating_count, item.rating_sum), (2, 9));
+  }
+
+  #[test]
+  fn copy_rows_refuse_missing_columns_and_nulls() {
+    assert!(ItemRepository::from_copy_row("7\tMug\t\\N\t4.50").is_err());
+    assert!(ItemRepository::from_copy_row("7\t\\N\t\\N\t4.50\t\\N\t3\tmug\t\\N\t\\N\tstandard\t\\N\t\\N\t\\N\t\\N\t0\t0").is_err());
+  }
+
+  #[test]
+  fn copied_items_are_read_line_by_line() {
+    let copied = CopiedItems(b"1\tA\t\\N\t1.00\t\\N\t0\ta\t\\N\t\\N\tstandard\t\\N\t\\N\t\\N\t\\N\t0\t0\n2\tLine\\nbreak\t\\N\t2.00\t\\N\t0\tb\t\\N\t\\N\tstandard\t\\N\t\\N\t\\N\t\\N\t0\t0\n".to_vec());
+    let names: Vec<String> = copied.items().map(|item| item.unwrap().name).collect();
+    assert_eq!(names, ["A", "Line\nbreak"]);
+  }
+}
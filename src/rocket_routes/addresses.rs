@@ -0,0 +1,90 @@
+use diesel::result::Error;
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status};
+
+use crate::addresses::validate;
+use crate::models::{NewAddress, User};
+use crate::repository::AddressRepository;
+use crate::rocket_routes::DbConn;
+
+use super::{server_error, not_found_error};
+
+pub fn check_country(country: &str) -> Result<(), Custom<Value>> {
+    if country.trim().len() != 2 || !country.trim().chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "country must be an ISO 3166-1 alpha-2 code" })));
+    }
+    Ok(())
+}
+
+// The country and region an order ships to: those of one of the user's addresses, the ones given, or
+// those of the user's default shipping address
+pub async fn destination(db: &DbConn, user_id: i32, address_id: Option<i32>, country: Option<String>, region: Option<String>) -> Result<(String, Option<String>), Custom<Value>> {
+    let address = match (address_id, country) {
+        (None, Some(country)) => return check_country(&country).map(|_| (country, region)),
+        (Some(id), _) => db.run(move |c| AddressRepository::find(c, user_id, id)).await,
+        (None, None) => db.run(move |c| AddressRepository::find_default_shipping(c, user_id)).await,
+    };
+    address
+        .map(|address| (address.country, address.region))
+        .map_err(|e| match (e, address_id) {
+            (Error::NotFound, Some(_)) => Custom(Status::NotFound, json!({ "error": "Unknown address" })),
+            (Error::NotFound, None) => Custom(Status::UnprocessableEntity, json!({ "error": "Give an address_id or a country, there is no default shipping address" })),
+            (e, _) => server_error(e.into()),
+        })
+}
+
+fn address_error(e: Error) -> Custom<Value> {
+    match e {
+        Error::NotFound => not_found_error("Unknown address".into()),
+        _ => server_error(e.into()),
+    }
+}
+
+fn validated(address: NewAddress, user: &User) -> Result<NewAddress, Custom<Value>> {
+    validate(NewAddress { user_id: user.id, ..address })
+        .map_err(|e| Custom(Status::UnprocessableEntity, json!({ "error": e })))
+}
+
+#[rocket::get("/addresses")]
+pub async fn get_addresses(db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| AddressRepository::find_by_user(c, user.id))
+        .await
+        .map(|addresses| Json(json!(addresses)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Addresses of other users are not found
+#[rocket::get("/addresses/<id>")]
+pub async fn get_address(id: i32, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| AddressRepository::find(c, user.id, id))
+        .await
+        .map(|address| Json(json!(address)))
+        .map_err(address_error)
+}
+
+#[rocket::post("/addresses", format = "json", data = "<address>")]
+pub async fn create_address(address: Json<NewAddress>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let address = validated(address.into_inner(), &user)?;
+    db.run(move |c| AddressRepository::create(c, address))
+        .await
+        .map(|address| Json(json!(address)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::put("/addresses/<id>", format = "json", data = "<address>")]
+pub async fn update_address(id: i32, address: Json<NewAddress>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let address = validated(address.into_inner(), &user)?;
+    db.run(move |c| AddressRepository::update(c, id, address))
+        .await
+        .map(|address| Json(json!(address)))
+        .map_err(address_error)
+}
+
+// Deleting a default address leaves the user without a default of that kind
+#[rocket::delete("/addresses/<id>")]
+pub async fn delete_address(id: i32, db: DbConn, user: User) -> Result<NoContent, Custom<Value>> {
+    match db.run(move |c| AddressRepository::delete(c, user.id, id)).await {
+        Ok(0) => Err(not_found_error("Unknown address".into())),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(server_error(e.into())),
+    }
+}
@@ -4,7 +4,7 @@ use rocket::serde::json::Json;
 use rocket_db_pools::Connection;
 use rocket_db_pools::deadpool_redis::redis::AsyncCommands;
 use serde_json::json;
-use crate::auth::{Credentials, authorize_user};
+use crate::auth::{Credentials, authorize_user, session_key};
 
 use crate::repository::UserRepository;
 
@@ -27,7 +27,7 @@ pub async fn login(credentials: Json<Credentials>, db: DbConn, mut cache: Connec
     let session_id = authorize_user(&user, &credentials)
         .map_err(|_| Custom(Status::Unauthorized, json!("Wrong Credentials")))?;
 
-    cache.set_ex::<_, _, ()>(format!("sessions/{}", session_id), 
+    cache.set_ex::<_, _, ()>(session_key(&session_id), 
         user.id, 
         3*60*60
     ).await
@@ -0,0 +1,121 @@
+use bigdecimal::BigDecimal;
+use chrono::Utc;
+use diesel::result::Error;
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::Custom, http::Status, State};
+
+use crate::models::User;
+use crate::money::BaseCurrency;
+use crate::promotions::{Cart, CartItem, Discount};
+use crate::repository::{CouponRepository, ShippingMethodRepository};
+use crate::rocket_routes::DbConn;
+use crate::shipping::Parcel;
+use crate::taxes::{PriceMode, TaxableLine, TaxBreakdown, TaxCalculator, STANDARD_TAX_CLASS};
+
+use super::server_error;
+use super::addresses::destination;
+use super::currencies::converter;
+use super::promotions::promotion_error;
+use super::shipping::unavailable_error;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct CartRequest {
+    pub items: Vec<CartItem>,
+    // Ignored when a `shipping_method` is given
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub shipping: BigDecimal,
+    // A shipping method to quote the shipping with, see `/shipping/quote`
+    pub shipping_method: Option<i32>,
+    // A discount code to apply
+    pub code: Option<String>,
+    // Where the order ships to, deciding the tax rates: one of the user's addresses, a country and region,
+    // or the user's default shipping address
+    pub address_id: Option<i32>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    // ISO 4217 code of the currency to price the cart in, `shipping` included; the base currency by default
+    pub currency: Option<String>,
+}
+
+pub fn check_cart(items: &[CartItem], shipping: &BigDecimal) -> Result<(), Custom<Value>> {
+    if items.iter().any(|item| item.quantity <= 0) || *shipping < BigDecimal::from(0) {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "Quantities must be positive and shipping not negative" })));
+    }
+    Ok(())
+}
+
+// A cart priced for an order: its shipping, discount and taxes
+pub struct PricedCart {
+    pub cart: Cart,
+    pub discount: Option<Discount>,
+    pub taxes: TaxBreakdown,
+}
+
+// Prices a cart of `user_id` at the items' current prices: the shipping of `shipping_method`, the discount
+// of `code`, then the taxes of the address. Shipping is taxed in the standard class. Nothing is redeemed.
+pub async fn price_cart(db: &DbConn, user_id: i32, request: CartRequest, mode: PriceMode, base: &BaseCurrency) -> Result<PricedCart, Custom<Value>> {
+    let CartRequest { items, shipping, shipping_method, code, address_id, country, region, currency } = request;
+    check_cart(&items, &shipping)?;
+    let (country, region) = destination(db, user_id, address_id, country, region).await?;
+    let converter = converter(db, base, currency.as_deref()).await?;
+    let table = match shipping_method {
+        Some(id) => Some(db.run(move |c| ShippingMethodRepository::find_table(c, id))
+            .await
+            .map_err(|e| match e {
+                Error::NotFound => Custom(Status::NotFound, json!({ "error": "Unknown shipping method" })),
+                _ => server_error(e.into()),
+            })?),
+        None => None,
+    };
+    db.run(move |c| {
+        let mut cart = Cart::price(c, &items, shipping, converter).map_err(|e| promotion_error(e.into()))?;
+        if let Some(table) = table {
+            let parcel = Parcel::load(c, &cart).map_err(|e| server_error(e.into()))?;
+            cart.shipping = table.quote(&cart, &parcel, &country).map_err(unavailable_error)?.price;
+        }
+        let discount = match code {
+            Some(code) => Some(CouponRepository::evaluate(c, &code, user_id, &cart, Utc::now().naive_utc()).map_err(promotion_error)?.1),
+            None => None,
+        };
+
+        let mut taxable: Vec<TaxableLine> = cart.lines.iter()
+            .enumerate()
+            .map(|(i, line)| TaxableLine {
+                item_id: Some(line.item_id),
+                tax_class: line.tax_class.clone(),
+                amount: match &discount {
+                    Some(discount) => line.amount() - &discount.lines[i].amount,
+                    None => line.amount(),
+                },
+            })
+            .collect();
+        taxable.push(TaxableLine {
+            item_id: None,
+            tax_class: STANDARD_TAX_CLASS.to_string(),
+            amount: match &discount {
+                Some(discount) => &cart.shipping - &discount.shipping,
+                None => cart.shipping.clone(),
+            },
+        });
+        let taxes = TaxCalculator::for_address(c, mode, &country, region.as_deref())
+            .map_err(|e| server_error(e.into()))?
+            .calculate(&taxable, &cart.converter.currency);
+        Ok(PricedCart { cart, discount, taxes })
+    })
+        .await
+}
+
+// Totals of a cart at the items' current prices, see `price_cart`
+#[rocket::post("/cart/totals", format = "json", data = "<request>")]
+pub async fn cart_totals(request: Json<CartRequest>, db: DbConn, mode: &State<PriceMode>, base: &State<BaseCurrency>, user: User) -> Result<Json<Value>, Custom<Value>> {
+    price_cart(&db, user.id, request.into_inner(), *mode.inner(), base).await
+        .map(|PricedCart { cart, discount, taxes }| Json(json!({
+            "currency": cart.converter.currency,
+            "subtotal": cart.subtotal(),
+            "lines": cart.lines,
+            "shipping": cart.shipping,
+            "discount": discount,
+            "total": taxes.gross,
+            "taxes": taxes,
+        })))
+}
@@ -0,0 +1,167 @@
+use std::io::{self, BufWriter, Cursor, Read, Write};
+
+use rocket::{data::{ByteUnit, Data, DataStream, Limits, ToByteUnit}, http::{ContentType, Header, Status}, response::{self, Responder, Response, status::Custom, stream::ByteStream}, serde::json::{Json, Value, serde_json::json}, Request, State};
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::tokio::{io::AsyncReadExt, sync::mpsc, task::spawn_blocking};
+
+use crate::catalog::{self, CatalogFormat};
+use crate::rocket_routes::{AdminUser, DbConn};
+use crate::storage::SharedImageStore;
+
+use super::server_error;
+
+// Catalogs are imported while they upload, up to this size; raise the `catalog` limit for larger files
+const DEFAULT_IMPORT_LIMIT_MIB: u64 = 32;
+
+// Size of the chunks passed between the connection and the database thread, and how many may wait
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+fn catalog_format(format: Option<&str>, content_type: Option<&ContentType>) -> Result<CatalogFormat, Custom<Value>> {
+    match (format, content_type) {
+        (Some(format), _) => format.parse().map_err(|e: String| Custom(Status::BadRequest, json!({ "error": e }))),
+        (None, Some(content_type)) if content_type.is_csv() => Ok(CatalogFormat::Csv),
+        (None, Some(content_type)) if content_type.is_json() => Ok(CatalogFormat::Json),
+        _ => Err(Custom(Status::BadRequest, json!({ "error": "Send text/csv or application/json, or pass ?format=csv|json" }))),
+    }
+}
+
+// The blocking end of an upload: the importer reads the chunks `upload` receives
+struct UploadReader {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Cursor<Vec<u8>>,
+}
+
+impl Read for UploadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = Read::read(&mut self.chunk, buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.chunk = Cursor::new(chunk?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+// Passes the body on to the importer as it arrives, returns whether it was larger than `limit`.
+// The stream is opened one byte past the limit so that a body of exactly `limit` is complete.
+async fn upload(mut body: DataStream<'_>, limit: ByteUnit, chunks: mpsc::Sender<io::Result<Vec<u8>>>) -> bool {
+    let mut received = 0;
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = match body.read(&mut chunk).await {
+            Ok(0) => return false,
+            Ok(read) => read,
+            Err(e) => {
+                let _ = chunks.send(Err(e)).await;
+                return false;
+            },
+        };
+        received += read as u64;
+        if received > limit.as_u64() {
+            let too_large = io::Error::new(io::ErrorKind::InvalidData, format!("the catalog is larger than {}", limit));
+            let _ = chunks.send(Err(too_large)).await;
+            return true;
+        }
+        chunk.truncate(read);
+        // The importer stops reading on invalid JSON
+        if chunks.send(Ok(chunk)).await.is_err() {
+            return false;
+        }
+    }
+}
+
+// Rows read before the limit stay imported, the 413 answer reports them
+#[rocket::post("/items/import?<format>", data = "<data>")]
+pub async fn import_items(format: Option<&str>, content_type: Option<&ContentType>, limits: &Limits, data: Data<'_>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let format = catalog_format(format, content_type)?;
+    let limit = limits.get("catalog").unwrap_or(DEFAULT_IMPORT_LIMIT_MIB.mebibytes());
+    let (sender, receiver) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let reader = UploadReader { chunks: receiver, chunk: Cursor::new(Vec::new()) };
+
+    let (report, too_large) = rocket::tokio::join!(
+        db.run(move |c| catalog::import(c, format, reader)),
+        upload(data.open(limit + 1.bytes()), limit, sender),
+    );
+    if too_large {
+        return Err(Custom(Status::PayloadTooLarge, json!({ "error": format!("Catalogs are limited to {}", limit), "report": report })));
+    }
+    Ok(Json(json!(report)))
+}
+
+// The blocking end of a download: the export writes the chunks the response sends
+struct DownloadWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for DownloadWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.blocking_send(buf.to_vec())
+            .map(|_| buf.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the client went away"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct CatalogExport {
+    content_type: ContentType,
+    body: ByteStream<BoxStream<'static, Vec<u8>>>,
+    disposition: Header<'static>,
+}
+
+impl<'r> Responder<'r, 'r> for CatalogExport {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        Response::build_from(self.body.respond_to(request)?)
+            .header(self.content_type)
+            .header(self.disposition)
+            .ok()
+    }
+}
+
+fn catalog_export(format: CatalogFormat, chunks: BoxStream<'static, Vec<u8>>) -> CatalogExport {
+    CatalogExport {
+        content_type: ContentType::parse_flexible(format.mime_type()).unwrap_or(ContentType::Binary),
+        body: ByteStream(chunks),
+        disposition: Header::new("Content-Disposition", format!("attachment; filename=\"catalog.{}\"", format)),
+    }
+}
+
+// The items are read up front, then sent while they are encoded so that the pool connection is not
+// held for the whole download. A failure before the first chunk answers 500; after it, the response
+// can only end early, which leaves a JSON export without its closing bracket.
+#[rocket::get("/items/export?<format>")]
+pub async fn export_items(format: Option<&str>, db: DbConn, store: &State<SharedImageStore>, _user: AdminUser) -> Result<CatalogExport, Custom<Value>> {
+    let format = catalog_format(Some(format.unwrap_or("csv")), None)?;
+    let snapshot = db.run(catalog::snapshot).await.map_err(|e| server_error(e.into()))?;
+    let store = store.inner().clone();
+    let (sender, mut chunks) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let export = spawn_blocking(move || {
+        let writer = BufWriter::with_capacity(CHUNK_SIZE, DownloadWriter(sender));
+        snapshot.write(store.as_ref(), format, writer)
+    });
+
+    let first = match chunks.recv().await {
+        Some(first) => first,
+        // A CSV export without items has no header either
+        None => match export.await {
+            Ok(Ok(())) => return Ok(catalog_export(format, stream::empty().boxed())),
+            Ok(Err(e)) => return Err(server_error(e.into())),
+            Err(e) => return Err(server_error(e.into())),
+        },
+    };
+    rocket::tokio::spawn(async move {
+        match export.await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => log::error!("Catalog export failed while sending: {}", e),
+            Err(e) => log::error!("Catalog export panicked: {}", e),
+        }
+    });
+
+    let rest = stream::unfold(chunks, |mut chunks| async move { chunks.recv().await.map(|chunk| (chunk, chunks)) });
+    Ok(catalog_export(format, stream::once(async { first }).chain(rest).boxed()))
+}
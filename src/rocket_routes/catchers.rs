@@ -0,0 +1,75 @@
+use rocket::http::Status;
+use rocket::Request;
+use rocket::response::status::Custom;
+use rocket::serde::json::{serde_json::json, Value};
+
+use super::AuthError;
+
+// Same shape as `server_error` and the other route errors
+fn error_body(status: Status, message: &str) -> Custom<Value> {
+  Custom(status, json!({ "error": message }))
+}
+
+// Prefers the reason given by a failed authentication guard over the generic message
+fn auth_error_body(status: Status, request: &Request, message: &str) -> Custom<Value> {
+  match AuthError::of(request) {
+    Some(e) if e.status() == status => Custom(status, json!({ "error": e.message(), "reason": format!("{:?}", e) })),
+    _ => error_body(status, message),
+  }
+}
+
+#[rocket::catch(400)]
+pub fn bad_request(_request: &Request) -> Custom<Value> {
+  error_body(Status::BadRequest, "The request could not be understood")
+}
+
+#[rocket::catch(401)]
+pub fn unauthorized(request: &Request) -> Custom<Value> {
+  auth_error_body(Status::Unauthorized, request, "A valid session token is required")
+}
+
+#[rocket::catch(403)]
+pub fn forbidden(request: &Request) -> Custom<Value> {
+  auth_error_body(Status::Forbidden, request, "You are not allowed to access this resource")
+}
+
+#[rocket::catch(404)]
+pub fn not_found(_request: &Request) -> Custom<Value> {
+  error_body(Status::NotFound, "The requested resource could not be found")
+}
+
+#[rocket::catch(409)]
+pub fn conflict(_request: &Request) -> Custom<Value> {
+  error_body(Status::Conflict, "The request conflicts with the current state of the resource")
+}
+
+#[rocket::catch(413)]
+pub fn payload_too_large(_request: &Request) -> Custom<Value> {
+  error_body(Status::PayloadTooLarge, "The request body is too large")
+}
+
+#[rocket::catch(422)]
+pub fn unprocessable_entity(_request: &Request) -> Custom<Value> {
+  error_body(Status::UnprocessableEntity, "The request body is not valid")
+}
+
+#[rocket::catch(429)]
+pub fn too_many_requests(_request: &Request) -> Custom<Value> {
+  error_body(Status::TooManyRequests, "Too many requests, please retry later")
+}
+
+#[rocket::catch(500)]
+pub fn internal_server_error(_request: &Request) -> Custom<Value> {
+  error_body(Status::InternalServerError, "Something went wrong")
+}
+
+#[rocket::catch(503)]
+pub fn service_unavailable(request: &Request) -> Custom<Value> {
+  auth_error_body(Status::ServiceUnavailable, request, "The service is temporarily unavailable")
+}
+
+// Any other status gets its reason phrase as message
+#[rocket::catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> Custom<Value> {
+  error_body(status, status.reason().unwrap_or("Unknown error"))
+}
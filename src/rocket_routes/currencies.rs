@@ -0,0 +1,112 @@
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status, State};
+
+use crate::models::NewExchangeRate;
+use crate::money::{BaseCurrency, Currency, CurrencyConverter, Money};
+use crate::repository::{ExchangeRateRepository, ItemPriceRepository};
+use crate::rocket_routes::{AdminUser, DbConn};
+
+use super::{server_error, not_found_error};
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct ExchangeRateData {
+    // Units of the currency per unit of the base currency
+    #[schemars(with = "String")]
+    pub rate: bigdecimal::BigDecimal,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct ItemPriceData {
+    #[schemars(with = "String")]
+    pub amount: bigdecimal::BigDecimal,
+}
+
+fn parse_currency(code: &str) -> Result<Currency, Custom<Value>> {
+    code.parse().map_err(|e: String| Custom(Status::UnprocessableEntity, json!({ "error": e })))
+}
+
+// The converter to the selected currency, the base currency when none is selected. Currencies
+// without an exchange rate answer 422.
+pub async fn converter(db: &DbConn, base: &BaseCurrency, currency: Option<&str>) -> Result<CurrencyConverter, Custom<Value>> {
+    let currency = match currency {
+        Some(code) => parse_currency(code)?,
+        None => base.0.clone(),
+    };
+    let base = base.0.clone();
+    let selected = currency.clone();
+    db.run(move |c| CurrencyConverter::load(c, &base, &selected))
+        .await
+        .map_err(|e| server_error(e.into()))?
+        .ok_or_else(|| Custom(Status::UnprocessableEntity, json!({ "error": format!("Unsupported currency {}", currency) })))
+}
+
+#[rocket::get("/exchange-rates")]
+pub async fn get_exchange_rates(db: DbConn, base: &State<BaseCurrency>, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let base = base.0.clone();
+    db.run(ExchangeRateRepository::find_all)
+        .await
+        .map(|rates| Json(json!({ "base_currency": base, "rates": rates })))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Creates or replaces the rate of a currency, which can be selected from then on
+#[rocket::put("/exchange-rates/<currency>", format = "json", data = "<data>")]
+pub async fn set_exchange_rate(currency: &str, data: Json<ExchangeRateData>, db: DbConn, base: &State<BaseCurrency>, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let currency = parse_currency(currency)?;
+    if currency == base.0 {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "The base currency has no exchange rate" })));
+    }
+    let new_exchange_rate = NewExchangeRate { currency, rate: data.into_inner().rate };
+    db.run(move |c| ExchangeRateRepository::set(c, new_exchange_rate))
+        .await
+        .map(|rate| Json(json!(rate)))
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::CheckViolation, _) => Custom(Status::UnprocessableEntity, json!({ "error": "rate must be positive" })),
+            _ => server_error(e.into()),
+        })
+}
+
+#[rocket::delete("/exchange-rates/<currency>")]
+pub async fn delete_exchange_rate(currency: &str, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    let currency = parse_currency(currency)?;
+    db.run(move |c| ExchangeRateRepository::delete(c, &currency))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| server_error(e.into()))
+}
+
+// Ranked after `/items/by-slug/<slug>`, which would otherwise collide on `/items/by-slug/currency-prices`
+#[rocket::get("/items/<id>/currency-prices", rank = 2)]
+pub async fn get_item_prices(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| ItemPriceRepository::find_by_item(c, id))
+        .await
+        .map(|prices| Json(json!(prices)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Sets the item's price in a currency by hand, instead of converting its base price
+#[rocket::put("/items/<id>/currency-prices/<currency>", format = "json", data = "<data>")]
+pub async fn set_item_price(id: i32, currency: &str, data: Json<ItemPriceData>, db: DbConn, base: &State<BaseCurrency>, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let currency = parse_currency(currency)?;
+    if currency == base.0 {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "Change the item's price for the base currency" })));
+    }
+    let price = Money::new(&data.into_inner().amount, &currency);
+    db.run(move |c| ItemPriceRepository::set(c, id, price))
+        .await
+        .map(|price| Json(json!(price)))
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => not_found_error(e.into()),
+            Error::DatabaseError(DatabaseErrorKind::CheckViolation, _) => Custom(Status::UnprocessableEntity, json!({ "error": "amount must not be negative" })),
+            _ => server_error(e.into()),
+        })
+}
+
+#[rocket::delete("/items/<id>/currency-prices/<currency>")]
+pub async fn delete_item_price(id: i32, currency: &str, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    let currency = parse_currency(currency)?;
+    db.run(move |c| ItemPriceRepository::delete(c, id, &currency))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| server_error(e.into()))
+}
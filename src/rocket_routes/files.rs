@@ -0,0 +1,177 @@
+use std::io::Cursor;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use sha2::{Digest, Sha256};
+
+// How long clients and proxies may reuse a file before revalidating it with its ETag
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+// A file read from a store, answering conditional (`If-None-Match`) and partial (`Range`) requests.
+// The whole object is held in `bytes`, so a range request still reads all of it from the store and
+// only trims what is sent.
+pub struct StoredFile {
+  pub bytes: Vec<u8>,
+  pub content_type: ContentType,
+}
+
+impl StoredFile {
+  pub fn new(bytes: Vec<u8>, mime_type: &str) -> Self {
+    StoredFile {
+      bytes,
+      content_type: ContentType::parse_flexible(mime_type).unwrap_or(ContentType::Binary),
+    }
+  }
+
+  // Strong validator derived from the content itself, so it is identical across backends and restarts
+  pub fn etag(&self) -> String {
+    format!("\"{}\"", hex::encode(Sha256::digest(&self.bytes)))
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+  Satisfiable { start: usize, end: usize },
+  Unsatisfiable,
+}
+
+// Parses a single `bytes=` range against a body of `len` bytes. Multiple ranges and other
+// units return `None`, in which case the whole body is served as allowed by RFC 9110.
+pub fn parse_range(header: &str, len: usize) -> Option<ByteRange> {
+  let spec = header.trim().strip_prefix("bytes=")?;
+  if spec.contains(',') {
+    return None;
+  }
+  let (start, end) = spec.split_once('-')?;
+  let (start, end) = (start.trim(), end.trim());
+
+  let range = match (start.is_empty(), end.is_empty()) {
+    // `bytes=-500` is the last 500 bytes
+    (true, false) => {
+      let suffix: usize = end.parse().ok()?;
+      if suffix == 0 || len == 0 {
+        return Some(ByteRange::Unsatisfiable);
+      }
+      ByteRange::Satisfiable { start: len.saturating_sub(suffix), end: len - 1 }
+    },
+    (false, _) => {
+      let start: usize = start.parse().ok()?;
+      let end: usize = if end.is_empty() { usize::MAX } else { end.parse().ok()? };
+      if end < start {
+        return None;
+      }
+      if start >= len {
+        return Some(ByteRange::Unsatisfiable);
+      }
+      ByteRange::Satisfiable { start, end: end.min(len - 1) }
+    },
+    (true, true) => return None,
+  };
+  Some(range)
+}
+
+// `If-None-Match` uses the weak comparison, so `W/` prefixed tags match as well
+fn matches_etag(header: &str, etag: &str) -> bool {
+  header.split(',')
+    .map(|tag| tag.trim())
+    .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+impl<'r> Responder<'r, 'static> for StoredFile {
+  fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+    let etag = self.etag();
+    let mut response = Response::build();
+    response
+      .header(Header::new("ETag", etag.clone()))
+      .header(Header::new("Cache-Control", CACHE_CONTROL))
+      .header(Header::new("Accept-Ranges", "bytes"));
+
+    if let Some(if_none_match) = request.headers().get_one("If-None-Match") {
+      if matches_etag(if_none_match, &etag) {
+        return response.status(Status::NotModified).ok();
+      }
+    }
+
+    let len = self.bytes.len();
+    let range = request.headers().get_one("Range").and_then(|h| parse_range(h, len));
+    match range {
+      Some(ByteRange::Satisfiable { start, end }) => {
+        let body = self.bytes[start..=end].to_vec();
+        response
+          .status(Status::PartialContent)
+          .header(self.content_type)
+          .header(Header::new("Content-Range", format!("bytes {}-{}/{}", start, end, len)))
+          .sized_body(body.len(), Cursor::new(body))
+          .ok()
+      },
+      Some(ByteRange::Unsatisfiable) => response
+        .status(Status::RangeNotSatisfiable)
+        .header(Header::new("Content-Range", format!("bytes */{}", len)))
+        .ok(),
+      None => response
+        .header(self.content_type)
+        .sized_body(len, Cursor::new(self.bytes))
+        .ok(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn suffix_ranges_count_from_the_end() {
+    assert_eq!(parse_range("bytes=-3", 10), Some(ByteRange::Satisfiable { start: 7, end: 9 }));
+    assert_eq!(parse_range("bytes=-30", 10), Some(ByteRange::Satisfiable { start: 0, end: 9 }));
+    assert_eq!(parse_range("bytes=-0", 10), Some(ByteRange::Unsatisfiable));
+    assert_eq!(parse_range("bytes=-3", 0), Some(ByteRange::Unsatisfiable));
+  }
+
+  #[test]
+  fn open_ranges_run_to_the_end() {
+    assert_eq!(parse_range("bytes=4-", 10), Some(ByteRange::Satisfiable { start: 4, end: 9 }));
+    assert_eq!(parse_range("bytes=0-", 1), Some(ByteRange::Satisfiable { start: 0, end: 0 }));
+  }
+
+  #[test]
+  fn closed_ranges_are_clamped_to_the_body() {
+    assert_eq!(parse_range("bytes=2-5", 10), Some(ByteRange::Satisfiable { start: 2, end: 5 }));
+    assert_eq!(parse_range(" bytes= 2 - 50 ", 10), Some(ByteRange::Satisfiable { start: 2, end: 9 }));
+  }
+
+  #[test]
+  fn invalid_ranges_serve_the_whole_body() {
+    assert_eq!(parse_range("bytes=5-2", 10), None);
+    assert_eq!(parse_range("bytes=-", 10), None);
+    assert_eq!(parse_range("bytes=a-b", 10), None);
+    assert_eq!(parse_range("items=0-5", 10), None);
+    assert_eq!(parse_range("bytes=0-1,4-5", 10), None);
+  }
+
+  #[test]
+  fn ranges_past_the_end_are_unsatisfiable() {
+    assert_eq!(parse_range("bytes=10-", 10), Some(ByteRange::Unsatisfiable));
+    assert_eq!(parse_range("bytes=12-20", 10), Some(ByteRange::Unsatisfiable));
+    assert_eq!(parse_range("bytes=0-", 0), Some(ByteRange::Unsatisfiable));
+  }
+
+  #[test]
+  fn etags_use_the_weak_comparison() {
+    let etag = "\"abc\"";
+    assert!(matches_etag("\"abc\"", etag));
+    assert!(matches_etag("W/\"abc\"", etag));
+    assert!(matches_etag("\"xyz\", W/\"abc\"", etag));
+    assert!(matches_etag("*", etag));
+    assert!(!matches_etag("abc", etag));
+    assert!(!matches_etag("\"abcd\"", etag));
+  }
+
+  #[test]
+  fn etags_follow_the_content() {
+    let file = StoredFile::new(b"hello".to_vec(), "text/plain");
+    assert_eq!(file.etag(), "\"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824\"");
+    assert_eq!(file.etag(), StoredFile::new(b"hello".to_vec(), "image/png").etag());
+  }
+}
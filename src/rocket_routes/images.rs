@@ -1,43 +1,118 @@
-use std::fs;
-
-use rocket::{data::Data, http::ContentType, serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status};
+use rocket::{data::Data, http::ContentType, serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status, State};
+use rocket::tokio::task::spawn_blocking;
 use rocket_multipart_form_data::{MultipartFormData, MultipartFormDataField, MultipartFormDataOptions};
 
-use crate::{models::{Image, NewImage}, repository::ImageRepository, rocket_routes::AdminUser};
+use diesel::result::{DatabaseErrorKind, Error};
+
+use crate::{models::{Image, NewImage}, repository::{ImageError, ImageRepository, ImageRenditionRepository}, rocket_routes::AdminUser};
+use crate::image_processing::{mime_type, RenditionJob, RenditionQueue};
 use crate::rocket_routes::DbConn;
+use crate::storage::{generate_key, ImageStore, SharedImageStore, StoreError};
 
-use super::server_error;
+use super::{files::StoredFile, server_error, not_found_error};
+
+// Serializes an image along with the URL its store serves it from
+pub fn image_json(image: &Image, store: &dyn ImageStore) -> Value {
+    let mut value = json!(image);
+    value["url"] = json!(image.url(store));
+    value
+}
+
+fn image_error(e: ImageError) -> Custom<Value> {
+    match e {
+        ImageError::Database(Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => not_found_error(e.into()),
+        _ => server_error(e.into())
+    }
+}
 
 #[rocket::post("/images/new/<item_id>", data = "<data>")]
-pub async fn upload_image(content_type: &ContentType, data: Data<'_>, db: DbConn, _user: AdminUser, item_id: i32) -> Result<Json<Value>, Custom<Value>> {
+pub async fn upload_image(content_type: &ContentType, data: Data<'_>, db: DbConn, store: &State<SharedImageStore>, renditions: &State<RenditionQueue>, _user: AdminUser, item_id: i32) -> Result<Json<Value>, Custom<Value>> {
     let options = MultipartFormDataOptions::with_multipart_form_data_fields(vec![
         MultipartFormDataField::file("media"),
     ]);
 
-    let mut multipart_form_data = MultipartFormData::parse(content_type, data, options).await.unwrap();
+    let mut multipart_form_data = MultipartFormData::parse(content_type, data, options)
+        .await
+        .map_err(|e| Custom(Status::BadRequest, json!({ "error": e.to_string() })))?;
 
     let photo = multipart_form_data.raw.remove("media");
 
-    if let Some(mut file_fields) = photo {
-        let raw_field = file_fields.remove(0);
+    if let Some(raw_field) = photo.and_then(|file_fields| file_fields.into_iter().next()) {
         let file_name = match &raw_field.file_name {
             Some(file_name) => file_name,
-            None => return Err(Custom(Status::BadRequest, "No file name provided".into()))
+            None => return Err(Custom(Status::BadRequest, json!({ "error": "No file name provided" })))
         };
-        let content_type = &raw_field.content_type;
-        let raw_file = &raw_field.raw;
+        let raw_file = raw_field.raw;
 
         let image_db_entry = NewImage {
-            url: file_name.to_string(),
+            storage_key: generate_key(file_name),
         };
 
-        fs::write(format!("images/{}", &image_db_entry.url), raw_file).map_err(|e| server_error(e.into()))?; // Save the file to the images folder
-
-        return db.run(move |c| ImageRepository::create_with_item(c, image_db_entry, item_id))
+        // Save the file to the configured store along with its rows
+        let bytes = raw_file.clone();
+        let writer = store.inner().clone();
+        let image = db.run(move |c| ImageRepository::create_with_item(c, writer.as_ref(), image_db_entry, item_id, &bytes))
             .await
-            .map(|image| Json(json!(image)))
-            .map_err(|e| server_error(e.into()))
+            .map_err(image_error)?;
+
+        // Thumbnails, resized variants and WebP copies are generated in the background
+        renditions.enqueue(RenditionJob {
+            image_id: image.id,
+            storage_key: image.storage_key.clone(),
+            bytes: raw_file,
+        }).await;
+
+        return Ok(Json(image_json(&image, store.as_ref())))
     }
 
-    Err(Custom(Status::BadRequest, "No file provided".into()))
-}
\ No newline at end of file
+    Err(Custom(Status::BadRequest, json!({ "error": "No file provided" })))
+}
+
+// Reads a stored file off the async executor, mapping missing files to 404
+async fn read_stored_file(store: &SharedImageStore, key: String) -> Result<StoredFile, Custom<Value>> {
+    let reader = store.clone();
+    spawn_blocking(move || reader.get(&key).map(|bytes| StoredFile::new(bytes, &mime_type(&key))))
+        .await
+        .map_err(|e| server_error(e.into()))?
+        .map_err(|e| match e {
+            StoreError::NotFound(_) => not_found_error(e.into()),
+            _ => server_error(e.into())
+        })
+}
+
+#[rocket::get("/images/<id>")]
+pub async fn get_image(id: i32, db: DbConn, store: &State<SharedImageStore>) -> Result<StoredFile, Custom<Value>> {
+    let image = db.run(move |c| ImageRepository::find(c, id))
+        .await
+        .map_err(|e| match e {
+            Error::NotFound => not_found_error(e.into()),
+            _ => server_error(e.into())
+        })?;
+
+    read_stored_file(store, image.storage_key).await
+}
+
+#[rocket::get("/images/<id>/<kind>?<format>")]
+pub async fn get_image_rendition(id: i32, kind: String, format: Option<String>, db: DbConn, store: &State<SharedImageStore>) -> Result<StoredFile, Custom<Value>> {
+    let rendition = db.run(move |c| ImageRenditionRepository::find_by_kind(c, id, &kind, format.as_deref()))
+        .await
+        .map_err(|e| match e {
+            Error::NotFound => not_found_error(e.into()),
+            _ => server_error(e.into())
+        })?;
+
+    read_stored_file(store, rendition.storage_key).await
+}
+
+// Deletes the image everywhere: its galleries, its renditions and its files
+#[rocket::delete("/images/<id>")]
+pub async fn delete_image(id: i32, db: DbConn, store: &State<SharedImageStore>, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    let store = store.inner().clone();
+    db.run(move |c| ImageRepository::delete(c, store.as_ref(), id))
+        .await
+        .map_err(image_error)
+        .and_then(|deleted| match deleted {
+            0 => Err(not_found_error(Error::NotFound.into())),
+            _ => Ok(NoContent)
+        })
+}
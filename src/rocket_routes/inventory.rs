@@ -0,0 +1,45 @@
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::Custom, http::Status};
+
+use crate::models::InventoryReason;
+use crate::repository::{InventoryError, InventoryRepository};
+use crate::rocket_routes::{AdminUser, DbConn};
+
+use super::{server_error, not_found_error};
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct AdjustmentRequest {
+    // Added to the stock, negative to take items out
+    pub delta: i32,
+    pub reason: InventoryReason,
+    // Delivery note, stock count or other document behind the adjustment
+    pub reference: Option<String>,
+}
+
+pub fn inventory_error(e: InventoryError) -> Custom<Value> {
+    match e {
+        InventoryError::InsufficientStock(_) => Custom(Status::Conflict, json!({ "error": e.to_string() })),
+        InventoryError::Database(Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => not_found_error(e.into()),
+        _ => server_error(e.into()),
+    }
+}
+
+#[rocket::get("/items/<id>/inventory", rank = 2)]
+pub async fn get_inventory_adjustments(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| InventoryRepository::find_by_item(c, id))
+        .await
+        .map(|adjustments| Json(json!(adjustments)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::post("/items/<id>/inventory", format = "json", data = "<adjustment>")]
+pub async fn adjust_inventory(id: i32, adjustment: Json<AdjustmentRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let AdjustmentRequest { delta, reason, reference } = adjustment.into_inner();
+    if delta == 0 {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "delta must not be zero" })));
+    }
+    db.run(move |c| InventoryRepository::adjust(c, id, delta, reason, reference))
+        .await
+        .map(|adjustment| Json(json!(adjustment)))
+        .map_err(inventory_error)
+}
@@ -0,0 +1,145 @@
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::Custom, http::{ContentType, Header, Status}, State};
+use rocket::tokio::task::spawn_blocking;
+
+use crate::addresses::AddressSnapshot;
+use crate::invoices::{InvoiceDocument, Issuer};
+use crate::models::{Invoice, User};
+use crate::money::BaseCurrency;
+use crate::repository::{AddressRepository, InvoiceError, InvoiceRepository, ItemRepository};
+use crate::rocket_routes::{AdminUser, DbConn};
+use crate::storage::{InvoiceStore, StoreError};
+use crate::taxes::PriceMode;
+
+use super::{server_error, not_found_error};
+use super::cart::{price_cart, CartRequest, PricedCart};
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct InvoiceRequest {
+    // The customer who placed the order
+    pub user_id: i32,
+    pub order_reference: String,
+    // One of the customer's addresses, their default billing address otherwise
+    pub billing_address_id: Option<i32>,
+    // What was ordered, priced like `/cart/totals` for the customer
+    #[serde(flatten)]
+    pub cart: CartRequest,
+}
+
+// A rendered invoice, downloaded rather than displayed and kept out of shared caches
+#[derive(rocket::Responder)]
+pub struct InvoiceFile {
+    body: (ContentType, Vec<u8>),
+    disposition: Header<'static>,
+    cache_control: Header<'static>,
+}
+
+fn invoice_error(e: InvoiceError) -> Custom<Value> {
+    match e {
+        InvoiceError::Database(Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => not_found_error(e.into()),
+        InvoiceError::Database(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) =>
+            Custom(Status::Conflict, json!({ "error": "This order has an invoice already" })),
+        _ => server_error(e.into()),
+    }
+}
+
+// A document the store does not have is rendered again from what the invoice kept, and stored for next time
+async fn invoice_file(invoice: Invoice, format: Option<&str>, store: &InvoiceStore) -> Result<InvoiceFile, Custom<Value>> {
+    let (content_type, extension) = match format.unwrap_or("pdf") {
+        "pdf" => (ContentType::PDF, "pdf"),
+        "html" => (ContentType::HTML, "html"),
+        _ => return Err(Custom(Status::BadRequest, json!({ "error": "Pass ?format=pdf|html" }))),
+    };
+    let disposition = Header::new("Content-Disposition", format!("attachment; filename=\"invoice-{}.{}\"", invoice.number, extension));
+    let InvoiceStore(store) = store.clone();
+    let bytes = spawn_blocking(move || {
+        let key = if extension == "pdf" { &invoice.pdf_key } else { &invoice.html_key };
+        match (store.get(key), InvoiceDocument::of(&invoice)) {
+            (Err(StoreError::NotFound(_)), Some(document)) => {
+                if let Err(e) = document.store(store.as_ref(), &invoice) {
+                    log::error!("Cannot store the documents of invoice {}: {}", invoice.number, e);
+                }
+                Ok(if extension == "pdf" { document.render_pdf() } else { document.render_html().into_bytes() })
+            },
+            (bytes, _) => bytes,
+        }
+    })
+        .await
+        .map_err(|e| server_error(e.into()))?
+        .map_err(|e| match e {
+            StoreError::NotFound(_) => not_found_error(e.into()),
+            _ => server_error(e.into()),
+        })?;
+    Ok(InvoiceFile {
+        body: (content_type, bytes),
+        disposition,
+        cache_control: Header::new("Cache-Control", "private, no-store"),
+    })
+}
+
+// Issues the invoice of an order: the next number of the year, the documents rendered and stored
+#[rocket::post("/invoices", format = "json", data = "<request>")]
+pub async fn issue_invoice(request: Json<InvoiceRequest>, db: DbConn, mode: &State<PriceMode>, base: &State<BaseCurrency>, issuer: &State<Issuer>, store: &State<InvoiceStore>, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let InvoiceRequest { user_id, order_reference, billing_address_id, cart } = request.into_inner();
+    let order_reference = order_reference.trim().to_string();
+    if order_reference.is_empty() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "order_reference must not be empty" })));
+    }
+    let PricedCart { cart, discount, taxes } = price_cart(&db, user_id, cart, *mode.inner(), base).await?;
+
+    let billing = db.run(move |c| match billing_address_id {
+        Some(id) => AddressRepository::find(c, user_id, id),
+        None => AddressRepository::find_default_billing(c, user_id),
+    })
+        .await
+        .map_err(|e| match (e, billing_address_id) {
+            (Error::NotFound, Some(_)) => Custom(Status::NotFound, json!({ "error": "Unknown billing address" })),
+            (Error::NotFound, None) => Custom(Status::UnprocessableEntity, json!({ "error": "Give a billing_address_id, the customer has no default billing address" })),
+            (e, _) => server_error(e.into()),
+        })?;
+
+    let issuer = issuer.inner().clone();
+    let InvoiceStore(store) = store.inner().clone();
+    db.run(move |c| {
+        let ids = cart.lines.iter().map(|line| line.item_id).collect();
+        let items = ItemRepository::find_by_ids(c, ids)?;
+        let document = InvoiceDocument::new(issuer, order_reference, AddressSnapshot::from(&billing), &cart, &items, discount.as_ref(), taxes);
+        InvoiceRepository::issue(c, store.as_ref(), Some(user_id), document)
+    })
+        .await
+        .map(|invoice| Json(json!(invoice)))
+        .map_err(invoice_error)
+}
+
+#[rocket::get("/invoices")]
+pub async fn get_invoices(db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| InvoiceRepository::find_by_user(c, user.id))
+        .await
+        .map(|invoices| Json(json!(invoices)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Invoices of other users are not found
+#[rocket::get("/invoices/<id>?<format>")]
+pub async fn download_invoice(id: i32, format: Option<&str>, db: DbConn, store: &State<InvoiceStore>, user: User) -> Result<InvoiceFile, Custom<Value>> {
+    let invoice = db.run(move |c| InvoiceRepository::find_for_user(c, user.id, id))
+        .await
+        .map_err(|e| invoice_error(e.into()))?;
+    invoice_file(invoice, format, store).await
+}
+
+#[rocket::get("/invoices/all")]
+pub async fn get_all_invoices(db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(InvoiceRepository::find_all)
+        .await
+        .map(|invoices| Json(json!(invoices)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::get("/invoices/all/<id>?<format>")]
+pub async fn download_any_invoice(id: i32, format: Option<&str>, db: DbConn, store: &State<InvoiceStore>, _user: AdminUser) -> Result<InvoiceFile, Custom<Value>> {
+    let invoice = db.run(move |c| InvoiceRepository::find(c, id))
+        .await
+        .map_err(|e| invoice_error(e.into()))?;
+    invoice_file(invoice, format, store).await
+}
@@ -1,35 +1,97 @@
-use diesel::result::Error;
-use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status};
+use std::collections::HashSet;
 
-use crate::{models::{Item, NewItem, User}, repository::ItemRepository, rocket_routes::AdminUser};
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::{Redirect, Responder, status::{Custom, NoContent}}, http::Status, State};
+
+use crate::{models::{BatchMode, Item, ItemOperation, NewItem, User}, repository::{ItemPriceRepository, ItemRepository, ItemsImageRepository, OperationOutcome}, rocket_routes::AdminUser};
+use crate::money::{BaseCurrency, Money};
 use crate::rocket_routes::DbConn;
+use crate::catalog::item_json;
+use crate::storage::SharedImageStore;
 
 use super::{server_error, not_found_error};
+use super::currencies::converter;
+
+#[derive(Responder)]
+pub enum ItemBySlug {
+    Found(Json<Value>),
+    Moved(Box<Redirect>),
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct GalleryImageData {
+    pub alt_text: Option<String>,
+}
+
+// Larger batches should be split, they hold their transaction open while they run
+const MAX_BATCH_OPERATIONS: usize = 1000;
 
-#[rocket::get("/items")]
-pub async fn get_items(db: DbConn, _user: User) -> Result<Json<Value>, Custom<Value>> {
-    db.run(|c| ItemRepository::find_all(c))
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct BatchRequest {
+    #[serde(default = "default_batch_mode")]
+    pub mode: BatchMode,
+    pub operations: Vec<ItemOperation>,
+}
+
+fn default_batch_mode() -> BatchMode {
+    BatchMode::AllOrNothing
+}
+
+// Adds the prices to show in the selected currency, `price` stays in the base currency
+fn with_display_price(mut value: Value, (price, was_price): (Money, Option<Money>)) -> Value {
+    value["display_price"] = json!(price);
+    value["display_was_price"] = json!(was_price);
+    value
+}
+
+// Gallery changes fail on missing items or images and on images attached twice
+fn gallery_error(e: Error) -> Custom<Value> {
+    match e {
+        Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => not_found_error(e.into()),
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Custom(Status::Conflict, json!({ "error": e.to_string() })),
+        _ => server_error(e.into())
+    }
+}
+
+#[rocket::get("/items?<currency>")]
+pub async fn get_items(currency: Option<&str>, db: DbConn, store: &State<SharedImageStore>, base: &State<BaseCurrency>, _user: User) -> Result<Json<Value>, Custom<Value>> {
+    let converter = converter(&db, base, currency).await?;
+    db.run(move |c| {
+        let items = ItemRepository::find_all(c)?;
+        let galleries = ItemsImageRepository::find_galleries(c, &items)?;
+        let prices = converter.item_prices(&items, ItemPriceRepository::find_by_items(c, &items, &converter.currency)?);
+        Ok(items.into_iter().zip(galleries).zip(prices).collect::<Vec<_>>())
+    })
         .await
-        .map(|items| Json(json!(items)))
-        .map_err(|e| server_error(e.into()))
+        .map(|items| Json(items.into_iter()
+            .map(|((item, gallery), price)| with_display_price(item_json(&item, &gallery, store.as_ref()), price))
+            .collect()))
+        .map_err(|e: Error| server_error(e.into()))
 }
 
-#[rocket::get("/items/<id>")]
-pub async fn get_item(id: i32, db: DbConn, _user: User) -> Result<Json<Value>, Custom<Value>> {
-    db.run(move |c| ItemRepository::find(c, id))
+#[rocket::get("/items/<id>?<currency>")]
+pub async fn get_item(id: i32, currency: Option<&str>, db: DbConn, store: &State<SharedImageStore>, base: &State<BaseCurrency>, _user: User) -> Result<Json<Value>, Custom<Value>> {
+    let converter = converter(&db, base, currency).await?;
+    db.run(move |c| {
+        let item = ItemRepository::find(c, id)?;
+        let gallery = ItemsImageRepository::find_gallery(c, &item)?;
+        let list_prices = ItemPriceRepository::find_by_items(c, std::slice::from_ref(&item), &converter.currency)?;
+        let price = converter.item_price(&item, list_prices.first().map(|entry| &entry.price));
+        Ok((item, gallery, price))
+    })
         .await
-        .map(|item| Json(json!(item)))
-        .map_err(|e| match e {
+        .map(|(item, gallery, price)| Json(with_display_price(item_json(&item, &gallery, store.as_ref()), price)))
+        .map_err(|e: Error| match e {
             Error::NotFound => not_found_error(e.into()),
             _ => server_error(e.into())
         })
 }
 
 #[rocket::post("/items", format = "json", data = "<new_item>")]
-pub async fn create_item(new_item: Json<NewItem>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+pub async fn create_item(new_item: Json<NewItem>, db: DbConn, store: &State<SharedImageStore>, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
     db.run(move |c| ItemRepository::create(c, new_item.into_inner()))
         .await
-        .map(|item| Json(json!(item)))
+        .map(|item| Json(item_json(&item, &[], store.as_ref())))
         .map_err(|e| server_error(e.into()))
 }
 
@@ -42,20 +104,136 @@ pub async fn delete_item(id: i32, db: DbConn, _user: AdminUser) -> Result<NoCont
 }
 
 #[rocket::put("/items/<id>", format = "json", data = "<item>")]
-pub async fn update_item(id: i32, item: Json<Item>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
-    db.run(move |c| ItemRepository::update(c, id, item.into_inner()))
+pub async fn update_item(id: i32, item: Json<Item>, db: DbConn, store: &State<SharedImageStore>, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| {
+        let item = ItemRepository::update(c, id, item.into_inner())?;
+        let gallery = ItemsImageRepository::find_gallery(c, &item)?;
+        Ok((item, gallery))
+    })
         .await
-        .map(|item| Json(json!(item)))
-        .map_err(|e| server_error(e.into()))
+        .map(|(item, gallery)| Json(item_json(&item, &gallery, store.as_ref())))
+        .map_err(|e: Error| server_error(e.into()))
+}
+
+// Creates, updates and deletes many items in one transaction and reports the outcome of each operation.
+// A failed all-or-nothing batch answers 422 with the same report.
+#[rocket::post("/items/batch", format = "json", data = "<batch>")]
+pub async fn batch_items(batch: Json<BatchRequest>, db: DbConn, store: &State<SharedImageStore>, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let BatchRequest { mode, operations } = batch.into_inner();
+    if operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": format!("A batch holds at most {} operations", MAX_BATCH_OPERATIONS) })));
+    }
+
+    let (outcomes, galleries) = db.run(move |c| {
+        let outcomes = ItemRepository::apply_batch(c, operations, mode)?;
+        let items: Vec<Item> = outcomes.iter()
+            .filter_map(|outcome| match outcome {
+                OperationOutcome::Applied(Some(item)) => Some(item.as_ref().clone()),
+                _ => None,
+            })
+            .collect();
+        let galleries = ItemsImageRepository::find_galleries(c, &items)?;
+        Ok((outcomes, galleries))
+    })
+        .await
+        .map_err(|e: Error| server_error(e.into()))?;
+
+    let mut galleries = galleries.into_iter();
+    let committed = mode == BatchMode::BestEffort || !outcomes.iter().any(|outcome| matches!(outcome, OperationOutcome::Failed(_)));
+    let results: Vec<Value> = outcomes.iter().enumerate()
+        .map(|(index, outcome)| match outcome {
+            OperationOutcome::Applied(Some(item)) => {
+                let gallery = galleries.next().unwrap_or_default();
+                json!({ "index": index, "status": "applied", "item": item_json(item, &gallery, store.as_ref()) })
+            },
+            OperationOutcome::Applied(None) => json!({ "index": index, "status": "applied" }),
+            OperationOutcome::Failed(e) => json!({ "index": index, "status": "failed", "error": e.to_string() }),
+            OperationOutcome::RolledBack => json!({ "index": index, "status": "rolled_back" }),
+            OperationOutcome::Skipped => json!({ "index": index, "status": "skipped" }),
+        })
+        .collect();
+
+    let report = json!({ "committed": committed, "results": results });
+    match committed {
+        true => Ok(Json(report)),
+        false => Err(Custom(Status::UnprocessableEntity, report)),
+    }
 }
 
-#[rocket::get("/items/<name>")]
-pub async fn get_item_by_name(name: String, db: DbConn, _user: User) -> Result<Json<Value>, Custom<Value>> {
-    db.run(move |c| ItemRepository::find_by_name(c, &name))
+// Old slugs of renamed items redirect permanently to the current one
+#[rocket::get("/items/by-slug/<slug>?<currency>")]
+pub async fn get_item_by_slug(slug: String, currency: Option<&str>, db: DbConn, store: &State<SharedImageStore>, base: &State<BaseCurrency>, _user: User) -> Result<ItemBySlug, Custom<Value>> {
+    let converter = converter(&db, base, currency).await?;
+    let selected = currency.map(str::to_string);
+    db.run(move |c| {
+        match ItemRepository::find_by_slug(c, &slug) {
+            Ok(item) => {
+                let gallery = ItemsImageRepository::find_gallery(c, &item)?;
+                let list_prices = ItemPriceRepository::find_by_items(c, std::slice::from_ref(&item), &converter.currency)?;
+                let price = converter.item_price(&item, list_prices.first().map(|entry| &entry.price));
+                Ok(Ok((item, gallery, price)))
+            },
+            Err(Error::NotFound) => ItemRepository::find_by_previous_slug(c, &slug).map(|item| Err(item.slug)),
+            Err(e) => Err(e)
+        }
+    })
         .await
-        .map(|item| Json(json!(item)))
+        .map(|lookup| match lookup {
+            Ok((item, gallery, price)) => ItemBySlug::Found(Json(with_display_price(item_json(&item, &gallery, store.as_ref()), price))),
+            Err(current_slug) => ItemBySlug::Moved(Box::new(Redirect::permanent(rocket::uri!(get_item_by_slug(current_slug, selected))))),
+        })
         .map_err(|e| match e {
             Error::NotFound => not_found_error(e.into()),
             _ => server_error(e.into())
         })
-}
\ No newline at end of file
+}
+
+// Attaches an already uploaded image to the end of the item's gallery
+#[rocket::post("/items/<id>/images/<image_id>", data = "<data>")]
+pub async fn attach_image(id: i32, image_id: i32, data: Option<Json<GalleryImageData>>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let alt_text = data.and_then(|d| d.into_inner().alt_text);
+    db.run(move |c| ItemsImageRepository::attach(c, id, image_id, alt_text))
+        .await
+        .map(|entry| Json(json!(entry)))
+        .map_err(gallery_error)
+}
+
+#[rocket::delete("/items/<id>/images/<image_id>")]
+pub async fn detach_image(id: i32, image_id: i32, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| ItemsImageRepository::detach(c, id, image_id))
+        .await
+        .map_err(gallery_error)
+        .and_then(|removed| match removed {
+            0 => Err(not_found_error(Error::NotFound.into())),
+            _ => Ok(NoContent)
+        })
+}
+
+// Takes image ids in their new display order; images left out keep their order after the listed ones
+#[rocket::put("/items/<id>/images", format = "json", data = "<image_ids>")]
+pub async fn reorder_images(id: i32, image_ids: Json<Vec<i32>>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let mut seen = HashSet::new();
+    if !image_ids.iter().all(|image_id| seen.insert(*image_id)) {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "Each image may be listed once" })));
+    }
+    db.run(move |c| ItemsImageRepository::reorder(c, id, image_ids.into_inner()))
+        .await
+        .map(|gallery| Json(json!(gallery)))
+        .map_err(gallery_error)
+}
+
+#[rocket::put("/items/<id>/images/<image_id>/primary")]
+pub async fn set_primary_image(id: i32, image_id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| ItemsImageRepository::set_primary(c, id, image_id))
+        .await
+        .map(|entry| Json(json!(entry)))
+        .map_err(gallery_error)
+}
+
+#[rocket::put("/items/<id>/images/<image_id>", format = "json", data = "<data>")]
+pub async fn update_image_alt_text(id: i32, image_id: i32, data: Json<GalleryImageData>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| ItemsImageRepository::update_alt_text(c, id, image_id, data.into_inner().alt_text))
+        .await
+        .map(|entry| Json(json!(entry)))
+        .map_err(gallery_error)
+}
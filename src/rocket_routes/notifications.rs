@@ -0,0 +1,45 @@
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::Custom};
+
+use crate::models::User;
+use crate::repository::NotificationRepository;
+use crate::rocket_routes::{AdminUser, DbConn, Pagination};
+
+use super::server_error;
+
+const MAX_BATCH: i64 = 100;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct DeliveredRequest {
+    pub ids: Vec<i32>,
+}
+
+// The user's notifications, newest first, sent or not
+#[rocket::get("/notifications?<page>&<per_page>")]
+pub async fn get_notifications(page: Option<i64>, per_page: Option<i64>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let pagination = Pagination::new(page, per_page);
+    let (limit, offset) = (pagination.per_page, pagination.offset());
+    db.run(move |c| NotificationRepository::find_by_user(c, user.id, limit, offset))
+        .await
+        .map(|(notifications, total)| Json(pagination.json(json!(notifications), total)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// The oldest notifications waiting to be sent, for the service sending them
+#[rocket::get("/notifications/outbox?<limit>")]
+pub async fn get_outbox(limit: Option<i64>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let limit = limit.unwrap_or(MAX_BATCH).clamp(1, MAX_BATCH);
+    db.run(move |c| NotificationRepository::find_undelivered(c, limit))
+        .await
+        .map(|notifications| Json(json!(notifications)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Takes sent notifications out of the outbox; `delivered` counts those that were still in it
+#[rocket::post("/notifications/outbox/delivered", format = "json", data = "<request>")]
+pub async fn mark_delivered(request: Json<DeliveredRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let ids = request.into_inner().ids;
+    db.run(move |c| NotificationRepository::mark_delivered(c, ids))
+        .await
+        .map(|delivered| Json(json!({ "delivered": delivered })))
+        .map_err(|e| server_error(e.into()))
+}
@@ -0,0 +1,541 @@
+use rocket::{Route, State};
+use rocket::fairing::AdHoc;
+use rocket::http::Method;
+use rocket::response::content::RawHtml;
+use rocket::serde::json::{Json, Value, serde_json::{json, Map}};
+use schemars::JsonSchema;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use serde::Serialize;
+
+use crate::auth::Credentials;
+use crate::catalog::{CatalogRow, ImportReport};
+use crate::models::{Address, InventoryAdjustment, Invoice, NewAddress, ReceivedLine, Refund, Return, ReturnLine, Review, ShippingMethod, ShippingRate, Coupon, CouponKind, CouponRedemption, ExchangeRate, ItemPrice, Image, Item, ItemsImage, NewItem, NewReview, NewWishlist, Notification, StockSubscription, NewScheduledPrice, NewTaxClass, NewTaxRate, PriceHistory, ScheduledPrice, TaxClass, TaxRate, Wishlist, WishlistItem};
+use crate::money::{Currency, Money};
+use crate::promotions::{CartLine, Discount};
+use crate::shipping::ShippingQuote;
+use crate::taxes::TaxBreakdown;
+
+use super::cart::CartRequest;
+use super::currencies::{ExchangeRateData, ItemPriceData};
+use super::inventory::AdjustmentRequest;
+use super::invoices::InvoiceRequest;
+use super::items::{BatchRequest, GalleryImageData};
+use super::promotions::{CouponRequest, EvaluationRequest};
+use super::reviews::ModerationRequest;
+use super::wishlists::{CartItems, WishlistItemRequest};
+use super::notifications::DeliveredRequest;
+use super::returns::{ApprovalRequest, IssueRequest, RejectionRequest, ReturnRequest};
+use super::shipping::{QuoteRequest, ShippingMethodRequest};
+
+// The shapes below only document responses that routes build with `json!`
+
+#[derive(Serialize, JsonSchema)]
+struct ErrorBody {
+  error: String,
+  // Set when an authentication guard failed, e.g. `ExpiredSession`
+  reason: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SessionToken {
+  token: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct GalleryImage {
+  id: i32,
+  url: String,
+  width: Option<i32>,
+  height: Option<i32>,
+  alt_text: Option<String>,
+  position: i32,
+  is_primary: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ItemWithGallery {
+  #[serde(flatten)]
+  item: Item,
+  // Of the approved reviews, none without reviews
+  rating_average: Option<String>,
+  images: Vec<GalleryImage>,
+}
+
+// An item as listed in the catalog, with its prices in the selected currency
+#[derive(Serialize, JsonSchema)]
+struct CatalogItem {
+  #[serde(flatten)]
+  item: ItemWithGallery,
+  display_price: Money,
+  display_was_price: Option<Money>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ExchangeRates {
+  base_currency: Currency,
+  rates: Vec<ExchangeRate>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ImageWithUrl {
+  #[serde(flatten)]
+  image: Image,
+  url: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct OperationResult {
+  index: usize,
+  // `applied`, `failed`, `rolled_back` or `skipped`
+  status: String,
+  // The created or updated item
+  item: Option<ItemWithGallery>,
+  error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct BatchReport {
+  committed: bool,
+  results: Vec<OperationResult>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct CouponWithScope {
+  #[serde(flatten)]
+  coupon: Coupon,
+  item_ids: Vec<i32>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct Evaluation {
+  code: String,
+  kind: CouponKind,
+  currency: Currency,
+  subtotal: String,
+  lines: Vec<CartLine>,
+  discount: Discount,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct CartTotals {
+  currency: Currency,
+  subtotal: String,
+  lines: Vec<CartLine>,
+  // Quoted when a `shipping_method` is given
+  shipping: String,
+  discount: Option<Discount>,
+  // What the customer pays, `taxes.gross`
+  total: String,
+  taxes: TaxBreakdown,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ShippingMethodWithTable {
+  #[serde(flatten)]
+  method: ShippingMethod,
+  countries: Vec<String>,
+  rates: Vec<ShippingRate>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ShippingQuotes {
+  currency: Currency,
+  country: String,
+  subtotal: String,
+  methods: Vec<ShippingQuote>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ReturnWithLines {
+  #[serde(flatten)]
+  rma: Return,
+  lines: Vec<ReturnLine>,
+  refunds: Vec<Refund>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ReviewPage {
+  entries: Vec<Review>,
+  page: i64,
+  per_page: i64,
+  total: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ItemReviews {
+  #[serde(flatten)]
+  page: ReviewPage,
+  rating_count: i32,
+  rating_average: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct WishlistEntry {
+  #[serde(flatten)]
+  entry: WishlistItem,
+  name: String,
+  slug: String,
+  price: String,
+  was_price: Option<String>,
+  in_stock: bool,
+  // The price is lower than when the item was added
+  price_dropped: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct WishlistWithItems {
+  #[serde(flatten)]
+  wishlist: Wishlist,
+  items: Vec<WishlistEntry>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct SharedWishlist {
+  name: String,
+  items: Vec<WishlistEntry>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct NotificationPage {
+  entries: Vec<Notification>,
+  page: i64,
+  per_page: i64,
+  total: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct Delivered {
+  delivered: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct StockSubscriptionWithItem {
+  #[serde(flatten)]
+  subscription: StockSubscription,
+  name: String,
+  slug: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct Subscribers {
+  subscribers: i64,
+}
+
+enum Access {
+  Public,
+  User,
+  Admin,
+}
+
+enum Body {
+  Json(Value),
+  Multipart(Value),
+  Binary,
+  Empty,
+}
+
+struct Operation {
+  summary: &'static str,
+  access: Access,
+  request: Option<Body>,
+  response: Body,
+}
+
+fn json_of<T: JsonSchema>(gen: &mut SchemaGenerator) -> Body {
+  Body::Json(json!(gen.subschema_for::<T>()))
+}
+
+// Documentation of each handler, looked up by the handler's name. Routes missing here are still
+// listed, with what can be read from the route itself.
+fn describe(name: &str, gen: &mut SchemaGenerator) -> Option<Operation> {
+  let operation = |summary, access, request, response| Some(Operation { summary, access, request, response });
+  match name {
+    "login" => operation("Log in and get a session token", Access::Public, Some(json_of::<Credentials>(gen)), json_of::<SessionToken>(gen)),
+    "get_items" => operation("List items with their galleries and prices in `currency` (ISO 4217, base currency by default)", Access::User, None, json_of::<Vec<CatalogItem>>(gen)),
+    "get_item" => operation("Get an item with its gallery and prices in `currency`", Access::User, None, json_of::<CatalogItem>(gen)),
+    "get_item_by_slug" => operation("Get an item by slug with prices in `currency`, former slugs redirect to the current one", Access::User, None, json_of::<CatalogItem>(gen)),
+    "create_item" => operation("Create an item", Access::Admin, Some(json_of::<NewItem>(gen)), json_of::<ItemWithGallery>(gen)),
+    "update_item" => operation("Update an item", Access::Admin, Some(json_of::<Item>(gen)), json_of::<ItemWithGallery>(gen)),
+    "delete_item" => operation("Delete an item", Access::Admin, None, Body::Empty),
+    "batch_items" => operation("Create, update and delete items in one transaction, a failed all-or-nothing batch answers 422", Access::Admin, Some(json_of::<BatchRequest>(gen)), json_of::<BatchReport>(gen)),
+    "attach_image" => operation("Attach an uploaded image to the item's gallery", Access::Admin, Some(json_of::<GalleryImageData>(gen)), json_of::<ItemsImage>(gen)),
+    "detach_image" => operation("Remove an image from the item's gallery", Access::Admin, None, Body::Empty),
+    "reorder_images" => operation("Reorder the item's gallery by image ids, repeated ids answer 422", Access::Admin, Some(json_of::<Vec<i32>>(gen)), json_of::<Vec<ItemsImage>>(gen)),
+    "set_primary_image" => operation("Make an image the item's primary image", Access::Admin, None, json_of::<ItemsImage>(gen)),
+    "update_image_alt_text" => operation("Change the alt text of an image in the item's gallery", Access::Admin, Some(json_of::<GalleryImageData>(gen)), json_of::<ItemsImage>(gen)),
+    "upload_image" => operation("Upload an image to an item's gallery", Access::Admin, Some(Body::Multipart(json!({
+      "type": "object",
+      "properties": { "media": { "type": "string", "format": "binary" } },
+      "required": ["media"],
+    }))), json_of::<ImageWithUrl>(gen)),
+    "get_image" => operation("Download an image, supports `If-None-Match` and `Range`", Access::Public, None, Body::Binary),
+    "get_image_rendition" => operation("Download a rendition (thumbnail, medium, large, original) of an image", Access::Public, None, Body::Binary),
+    "delete_image" => operation("Delete an image, its renditions and its files", Access::Admin, None, Body::Empty),
+    "get_price_history" => operation("List the price changes of an item, oldest first", Access::Admin, None, json_of::<Vec<PriceHistory>>(gen)),
+    "get_price_schedules" => operation("List the scheduled prices of an item", Access::Admin, None, json_of::<Vec<ScheduledPrice>>(gen)),
+    "create_price_schedule" => operation("Schedule a price change, or a sale when `ends_at` is set (UTC); overlapping schedules answer 409", Access::Admin, Some(json_of::<NewScheduledPrice>(gen)), json_of::<ScheduledPrice>(gen)),
+    "cancel_price_schedule" => operation("Drop a pending scheduled price, or end a running sale now", Access::Admin, None, Body::Empty),
+    "get_coupons" => operation("List discount codes", Access::Admin, None, json_of::<Vec<Coupon>>(gen)),
+    "get_coupon" => operation("Get a discount code with the items it is limited to", Access::Admin, None, json_of::<CouponWithScope>(gen)),
+    "create_coupon" => operation("Create a discount code, optionally limited to some items; codes are case-insensitive", Access::Admin, Some(json_of::<CouponRequest>(gen)), json_of::<Coupon>(gen)),
+    "delete_coupon" => operation("Delete a discount code, redeemed codes answer 409", Access::Admin, None, Body::Empty),
+    "get_coupon_redemptions" => operation("List the orders that redeemed a discount code", Access::Admin, None, json_of::<Vec<CouponRedemption>>(gen)),
+    "evaluate_promotion" => operation("Preview the discount of a code on a cart at current prices, rejected codes answer 422 with a `reason`", Access::User, Some(json_of::<EvaluationRequest>(gen)), json_of::<Evaluation>(gen)),
+    "get_tax_classes" => operation("List tax classes", Access::Admin, None, json_of::<Vec<TaxClass>>(gen)),
+    "create_tax_class" => operation("Create a tax class, items refer to it by code", Access::Admin, Some(json_of::<NewTaxClass>(gen)), json_of::<TaxClass>(gen)),
+    "delete_tax_class" => operation("Delete a tax class and its rates, classes used by items answer 409", Access::Admin, None, Body::Empty),
+    "get_tax_rates" => operation("List tax rates by country and region", Access::Admin, None, json_of::<Vec<TaxRate>>(gen)),
+    "create_tax_rate" => operation("Set the rate of a tax class in a country, or in a region of it", Access::Admin, Some(json_of::<NewTaxRate>(gen)), json_of::<TaxRate>(gen)),
+    "delete_tax_rate" => operation("Delete a tax rate", Access::Admin, None, Body::Empty),
+    "cart_totals" => operation("Compute a cart's shipping, discount, taxes and total at current prices for a shipping address", Access::User, Some(json_of::<CartRequest>(gen)), json_of::<CartTotals>(gen)),
+    "get_exchange_rates" => operation("List the base currency and the exchange rates of the other selectable currencies", Access::Admin, None, json_of::<ExchangeRates>(gen)),
+    "set_exchange_rate" => operation("Create or replace a currency's exchange rate, in units per unit of the base currency", Access::Admin, Some(json_of::<ExchangeRateData>(gen)), json_of::<ExchangeRate>(gen)),
+    "delete_exchange_rate" => operation("Delete a currency's exchange rate, it can no longer be selected", Access::Admin, None, Body::Empty),
+    "get_item_prices" => operation("List the prices set by hand for an item in other currencies", Access::Admin, None, json_of::<Vec<ItemPrice>>(gen)),
+    "set_item_price" => operation("Set an item's price in a currency instead of converting it", Access::Admin, Some(json_of::<ItemPriceData>(gen)), json_of::<ItemPrice>(gen)),
+    "delete_item_price" => operation("Go back to converting an item's price in a currency", Access::Admin, None, Body::Empty),
+    "get_addresses" => operation("List the user's addresses", Access::User, None, json_of::<Vec<Address>>(gen)),
+    "get_address" => operation("Get one of the user's addresses", Access::User, None, json_of::<Address>(gen)),
+    "create_address" => operation("Add an address to the user's address book, the first one becomes the default", Access::User, Some(json_of::<NewAddress>(gen)), json_of::<Address>(gen)),
+    "update_address" => operation("Replace one of the user's addresses, past orders keep their copy", Access::User, Some(json_of::<NewAddress>(gen)), json_of::<Address>(gen)),
+    "delete_address" => operation("Delete one of the user's addresses", Access::User, None, Body::Empty),
+    "get_shipping_methods" => operation("List shipping methods", Access::Admin, None, json_of::<Vec<ShippingMethod>>(gen)),
+    "get_shipping_method" => operation("Get a shipping method with its countries and rates", Access::Admin, None, json_of::<ShippingMethodWithTable>(gen)),
+    "create_shipping_method" => operation("Create a flat, weight-based or price-based shipping method, amounts in the base currency", Access::Admin, Some(json_of::<ShippingMethodRequest>(gen)), json_of::<ShippingMethod>(gen)),
+    "update_shipping_method" => operation("Replace a shipping method with its countries and rates", Access::Admin, Some(json_of::<ShippingMethodRequest>(gen)), json_of::<ShippingMethod>(gen)),
+    "delete_shipping_method" => operation("Delete a shipping method", Access::Admin, None, Body::Empty),
+    "quote_shipping" => operation("Price the shipping methods able to ship a cart to an address or country, cheapest first", Access::User, Some(json_of::<QuoteRequest>(gen)), json_of::<ShippingQuotes>(gen)),
+    "get_inventory_adjustments" => operation("List the stock adjustments of an item, oldest first", Access::Admin, None, json_of::<Vec<InventoryAdjustment>>(gen)),
+    "adjust_inventory" => operation("Add to or take from an item's stock, answers 409 when taking more than is in stock", Access::Admin, Some(json_of::<AdjustmentRequest>(gen)), json_of::<InventoryAdjustment>(gen)),
+    "create_return" => operation("Request the return of items of an order", Access::User, Some(json_of::<ReturnRequest>(gen)), json_of::<Return>(gen)),
+    "get_returns" => operation("List the user's returns, newest first", Access::User, None, json_of::<Vec<Return>>(gen)),
+    "get_return" => operation("Get one of the user's returns with its lines and refunds", Access::User, None, json_of::<ReturnWithLines>(gen)),
+    "get_all_returns" => operation("List returns, optionally those with a `status`", Access::Admin, None, json_of::<Vec<Return>>(gen)),
+    "get_any_return" => operation("Get a return with its lines and refunds", Access::Admin, None, json_of::<ReturnWithLines>(gen)),
+    "approve_return" => operation("Approve a requested return with the amount that may be refunded", Access::Admin, Some(json_of::<ApprovalRequest>(gen)), json_of::<Return>(gen)),
+    "reject_return" => operation("Reject a requested return", Access::Admin, Some(json_of::<RejectionRequest>(gen)), json_of::<Return>(gen)),
+    "receive_return" => operation("Record items of an approved return arriving back, restocking those fit for sale", Access::Admin, Some(json_of::<Vec<ReceivedLine>>(gen)), json_of::<ReturnWithLines>(gen)),
+    "create_refund" => operation("Record a pending refund, up to what is left of the refundable amount", Access::Admin, Some(json_of::<Money>(gen)), json_of::<Refund>(gen)),
+    "issue_refund" => operation("Mark a pending refund paid back by the payment provider", Access::Admin, Some(json_of::<IssueRequest>(gen)), json_of::<Refund>(gen)),
+    "issue_invoice" => operation("Issue the invoice of an order, numbered per year, with its PDF and HTML documents", Access::Admin, Some(json_of::<InvoiceRequest>(gen)), json_of::<Invoice>(gen)),
+    "get_invoices" => operation("List the user's invoices, newest first", Access::User, None, json_of::<Vec<Invoice>>(gen)),
+    "download_invoice" => operation("Download one of the user's invoices as PDF (default) or HTML", Access::User, None, Body::Binary),
+    "get_all_invoices" => operation("List invoices by number", Access::Admin, None, json_of::<Vec<Invoice>>(gen)),
+    "download_any_invoice" => operation("Download an invoice as PDF (default) or HTML", Access::Admin, None, Body::Binary),
+    "get_item_reviews" => operation("List an item's approved reviews, newest first, with its rating", Access::User, None, json_of::<ItemReviews>(gen)),
+    "create_review" => operation("Review an item with a rating from 1 to 5, shown once approved; one review per item", Access::User, Some(json_of::<NewReview>(gen)), json_of::<Review>(gen)),
+    "get_my_reviews" => operation("List the user's reviews in any status", Access::User, None, json_of::<Vec<Review>>(gen)),
+    "update_review" => operation("Edit one of the user's reviews, it is moderated again", Access::User, Some(json_of::<NewReview>(gen)), json_of::<Review>(gen)),
+    "delete_review" => operation("Delete one of the user's reviews", Access::User, None, Body::Empty),
+    "get_reviews" => operation("List reviews in a `status`, pending by default, oldest first", Access::Admin, None, json_of::<ReviewPage>(gen)),
+    "moderate_review" => operation("Approve or reject a review, approved reviews count in the item's rating", Access::Admin, Some(json_of::<ModerationRequest>(gen)), json_of::<Review>(gen)),
+    "get_wishlists" => operation("List the user's wishlists", Access::User, None, json_of::<Vec<Wishlist>>(gen)),
+    "get_wishlist" => operation("Get one of the user's wishlists with its items at their current price and stock", Access::User, None, json_of::<WishlistWithItems>(gen)),
+    "create_wishlist" => operation("Create a named wishlist, names are unique per user", Access::User, Some(json_of::<NewWishlist>(gen)), json_of::<Wishlist>(gen)),
+    "rename_wishlist" => operation("Rename one of the user's wishlists", Access::User, Some(json_of::<NewWishlist>(gen)), json_of::<Wishlist>(gen)),
+    "delete_wishlist" => operation("Delete one of the user's wishlists with its items", Access::User, None, Body::Empty),
+    "share_wishlist" => operation("Give a wishlist a `share_token` for the public link `/wishlists/shared/<share_token>`", Access::User, None, json_of::<Wishlist>(gen)),
+    "unshare_wishlist" => operation("Stop sharing a wishlist, its link stops working", Access::User, None, json_of::<Wishlist>(gen)),
+    "get_shared_wishlist" => operation("Get a shared wishlist by its share token, without its owner", Access::Public, None, json_of::<SharedWishlist>(gen)),
+    "add_wishlist_item" => operation("Add an item to a wishlist, or set its quantity; the list notifies the user of price drops and restocks", Access::User, Some(json_of::<WishlistItemRequest>(gen)), json_of::<WishlistItem>(gen)),
+    "remove_wishlist_item" => operation("Take an item off a wishlist", Access::User, None, Body::Empty),
+    "move_to_cart" => operation("Take an item off a wishlist and into the cart sent, answers the cart to keep", Access::User, Some(json_of::<CartItems>(gen)), json_of::<CartItems>(gen)),
+    "save_for_later" => operation("Take an item out of the cart sent and onto a wishlist, answers the cart to keep", Access::User, Some(json_of::<CartItems>(gen)), json_of::<CartItems>(gen)),
+    "get_notifications" => operation("List the user's notifications, newest first", Access::User, None, json_of::<NotificationPage>(gen)),
+    "get_outbox" => operation("List the oldest notifications waiting to be sent, at most `limit` (100)", Access::Admin, None, json_of::<Vec<Notification>>(gen)),
+    "mark_delivered" => operation("Mark notifications as sent, taking them out of the outbox", Access::Admin, Some(json_of::<DeliveredRequest>(gen)), json_of::<Delivered>(gen)),
+    "get_stock_subscriptions" => operation("List the out-of-stock items the user waits for", Access::User, None, json_of::<Vec<StockSubscriptionWithItem>>(gen)),
+    "subscribe" => operation("Get notified once when an out-of-stock item is back in stock, items in stock answer 409", Access::User, None, json_of::<StockSubscription>(gen)),
+    "unsubscribe" => operation("Stop waiting for an item", Access::User, None, Body::Empty),
+    "get_item_subscribers" => operation("Count the customers waiting for an item", Access::Admin, None, json_of::<Subscribers>(gen)),
+    "import_items" => operation("Create or update items from a CSV or JSON catalog, matched by sku or name", Access::Admin, Some(Body::Json(json!({
+      "type": "array",
+      "items": gen.subschema_for::<CatalogRow>(),
+      "description": "A JSON array, or CSV with the same columns and a header row",
+    }))), json_of::<ImportReport>(gen)),
+    "export_items" => operation("Download the catalog with stock and image URLs as CSV (default) or JSON", Access::Admin, None, Body::Binary),
+    "openapi_json" => operation("This document", Access::Public, None, Body::Json(json!({ "type": "object" }))),
+    "api_docs" => operation("Interactive documentation of this API", Access::Public, None, Body::Empty),
+    _ => None,
+  }
+}
+
+// `/items/<id>/images/<image_id>` -> (`/items/{id}/images/{image_id}`, ["id", "image_id"])
+fn openapi_path(path: &str) -> (String, Vec<String>) {
+  let mut params = Vec::new();
+  let segments: Vec<String> = path.split('/')
+    .map(|segment| match segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+      Some(param) => {
+        let param = param.trim_end_matches("..").to_string();
+        let segment = format!("{{{}}}", param);
+        params.push(param);
+        segment
+      },
+      None => segment.to_string(),
+    })
+    .collect();
+  (segments.join("/"), params)
+}
+
+fn query_params(query: Option<&str>) -> Vec<String> {
+  query.unwrap_or_default()
+    .split('&')
+    .filter_map(|segment| segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')))
+    .map(|param| param.trim_end_matches("..").to_string())
+    .collect()
+}
+
+// Ids are the only numeric parameters of this API
+fn param_schema(name: &str) -> Value {
+  if name == "id" || name.ends_with("_id") {
+    json!({ "type": "integer", "format": "int32" })
+  } else {
+    json!({ "type": "string" })
+  }
+}
+
+fn content(body: &Body) -> Option<Value> {
+  match body {
+    Body::Json(schema) => Some(json!({ "application/json": { "schema": schema } })),
+    Body::Multipart(schema) => Some(json!({ "multipart/form-data": { "schema": schema } })),
+    Body::Binary => Some(json!({ "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } })),
+    Body::Empty => None,
+  }
+}
+
+fn operation_json(route: &Route, gen: &mut SchemaGenerator) -> Value {
+  let name = route.name.as_deref().unwrap_or_default();
+  let (_, path_params) = openapi_path(route.uri.path());
+  let parameters: Vec<Value> = path_params.iter()
+    .map(|param| json!({ "name": param, "in": "path", "required": true, "schema": param_schema(param) }))
+    .chain(query_params(route.uri.query()).iter()
+      .map(|param| json!({ "name": param, "in": "query", "required": false, "schema": param_schema(param) })))
+    .collect();
+
+  let error = json!({ "content": { "application/json": { "schema": gen.subschema_for::<ErrorBody>() } } });
+  let mut operation = json!({ "operationId": name, "parameters": parameters });
+  let mut responses = Map::new();
+
+  match describe(name, gen) {
+    Some(described) => {
+      operation["summary"] = json!(described.summary);
+      if let Some(request) = described.request.as_ref().and_then(content) {
+        operation["requestBody"] = json!({ "required": true, "content": request });
+      }
+      match content(&described.response) {
+        Some(response) => responses.insert("200".into(), json!({ "description": "Success", "content": response })),
+        None => responses.insert("204".into(), json!({ "description": "Success" })),
+      };
+      match described.access {
+        Access::Public => (),
+        Access::User | Access::Admin => {
+          operation["security"] = json!([{ "bearerAuth": [] }]);
+          responses.insert("401".into(), json!({ "description": "Missing, malformed or expired session token", "content": error["content"] }));
+          responses.insert("503".into(), json!({ "description": "Session backend unavailable", "content": error["content"] }));
+        },
+      }
+      if let Access::Admin = described.access {
+        responses.insert("403".into(), json!({ "description": "Admin role required", "content": error["content"] }));
+      }
+    },
+    None => {
+      responses.insert("default".into(), json!({ "description": "Undocumented response" }));
+    },
+  }
+  if !path_params.is_empty() {
+    responses.insert("404".into(), json!({ "description": "Not found", "content": error["content"] }));
+  }
+
+  operation["responses"] = Value::Object(responses);
+  operation
+}
+
+// Builds an OpenAPI 3 document out of the mounted routes and the models they exchange
+pub fn spec<'a>(routes: impl Iterator<Item = &'a Route>) -> Value {
+  let mut gen = SchemaSettings::openapi3().into_generator();
+  let mut paths = Map::new();
+
+  for route in routes {
+    let (path, _) = openapi_path(route.uri.path());
+    let method = match route.method {
+      Method::Get => "get",
+      Method::Post => "post",
+      Method::Put => "put",
+      Method::Delete => "delete",
+      Method::Patch => "patch",
+      Method::Head => "head",
+      Method::Options => "options",
+      _ => continue,
+    };
+    let operation = operation_json(route, &mut gen);
+    let entry = paths.entry(path).or_insert_with(|| json!({}));
+    entry[method] = operation;
+  }
+
+  json!({
+    "openapi": "3.0.3",
+    "info": {
+      "title": "eshop API",
+      "version": env!("CARGO_PKG_VERSION"),
+    },
+    "paths": paths,
+    "components": {
+      "schemas": gen.take_definitions(),
+      "securitySchemes": {
+        "bearerAuth": {
+          "type": "http",
+          "scheme": "bearer",
+          "description": "Session token returned by `POST /login`, valid for 3 hours",
+        },
+      },
+    },
+  })
+}
+
+pub struct OpenApiSpec(pub Value);
+
+// Generates the document once all routes are mounted; attach it after every fairing that mounts routes
+pub fn fairing() -> AdHoc {
+  AdHoc::on_ignite("OpenAPI document", |rocket| async {
+    let document = spec(rocket.routes());
+    rocket.manage(OpenApiSpec(document))
+  })
+}
+
+#[rocket::get("/openapi.json")]
+pub fn openapi_json(document: &State<OpenApiSpec>) -> Json<Value> {
+  Json(document.0.clone())
+}
+
+// The viewer is pinned to a release, so the script it loads only changes with this page
+#[rocket::get("/docs")]
+pub fn api_docs() -> RawHtml<&'static str> {
+  RawHtml(r#"<!doctype html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title>eshop API</title>
+    <script type="module" src="https://unpkg.com/rapidoc@9.3.8/dist/rapidoc-min.js"></script>
+  </head>
+  <body>
+    <rapi-doc spec-url="/openapi.json" render-style="read" allow-authentication="true"></rapi-doc>
+  </body>
+</html>"#)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rocket_routes::build_rocket;
+
+  #[test]
+  fn every_mounted_route_is_described() {
+    let rocket = build_rocket(rocket::Config::figment());
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let undescribed: Vec<String> = rocket.routes()
+      .filter(|route| describe(route.name.as_deref().unwrap_or_default(), &mut gen).is_none())
+      .map(|route| format!("{} {} ({})", route.method, route.uri, route.name.as_deref().unwrap_or_default()))
+      .collect();
+    assert!(undescribed.is_empty(), "routes without a `describe` entry: {:?}", undescribed);
+  }
+}
@@ -0,0 +1,56 @@
+use chrono::Utc;
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status};
+
+use crate::models::NewScheduledPrice;
+use crate::repository::{PriceHistoryRepository, ScheduleError, ScheduledPriceRepository};
+use crate::rocket_routes::{AdminUser, DbConn};
+
+use super::{server_error, not_found_error};
+
+fn schedule_error(e: ScheduleError) -> Custom<Value> {
+    match e {
+        ScheduleError::Overlaps(_) => Custom(Status::Conflict, json!({ "error": e.to_string() })),
+        ScheduleError::Database(Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => not_found_error(e.into()),
+        ScheduleError::Database(Error::DatabaseError(DatabaseErrorKind::CheckViolation, _)) =>
+            Custom(Status::UnprocessableEntity, json!({ "error": "ends_at must be after starts_at" })),
+        _ => server_error(e.into()),
+    }
+}
+
+// Ranked after `/items/by-slug/<slug>`, which would otherwise collide on `/items/by-slug/prices`
+#[rocket::get("/items/<id>/prices", rank = 2)]
+pub async fn get_price_history(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| PriceHistoryRepository::find_by_item(c, id))
+        .await
+        .map(|history| Json(json!(history)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::get("/items/<id>/price-schedules", rank = 2)]
+pub async fn get_price_schedules(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| ScheduledPriceRepository::find_by_item(c, id))
+        .await
+        .map(|schedules| Json(json!(schedules)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Schedules a price change, or a sale when `ends_at` is set. Times are UTC.
+#[rocket::post("/items/<id>/price-schedules", format = "json", data = "<schedule>")]
+pub async fn create_price_schedule(id: i32, schedule: Json<NewScheduledPrice>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let mut new_schedule = schedule.into_inner();
+    new_schedule.item_id = id;
+    db.run(move |c| ScheduledPriceRepository::create(c, new_schedule))
+        .await
+        .map(|schedule| Json(json!(schedule)))
+        .map_err(schedule_error)
+}
+
+// Drops a pending schedule, or ends a running sale now
+#[rocket::delete("/items/<id>/price-schedules/<schedule_id>")]
+pub async fn cancel_price_schedule(id: i32, schedule_id: i32, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| ScheduledPriceRepository::cancel(c, id, schedule_id, Utc::now().naive_utc()))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| schedule_error(e.into()))
+}
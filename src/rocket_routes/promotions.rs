@@ -0,0 +1,132 @@
+use bigdecimal::BigDecimal;
+use chrono::Utc;
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status, State};
+
+use crate::models::{NewCoupon, User};
+use crate::money::BaseCurrency;
+use crate::promotions::{Cart, CartItem};
+use crate::repository::{CouponRepository, PromotionError};
+use crate::rocket_routes::{AdminUser, DbConn};
+
+use super::{server_error, not_found_error};
+use super::cart::check_cart;
+use super::currencies::converter;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct CouponRequest {
+    #[serde(flatten)]
+    pub coupon: NewCoupon,
+    // Limits the coupon to these items, it applies to every item when empty
+    #[serde(default)]
+    pub item_ids: Vec<i32>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct EvaluationRequest {
+    pub code: String,
+    pub items: Vec<CartItem>,
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub shipping: BigDecimal,
+    // ISO 4217 code of the currency to price the cart in, `shipping` included; the base currency by default
+    pub currency: Option<String>,
+}
+
+fn coupon_error(e: Error) -> Custom<Value> {
+    match e {
+        Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => not_found_error(e.into()),
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Custom(Status::Conflict, json!({ "error": "A coupon with this code already exists" })),
+        Error::DatabaseError(DatabaseErrorKind::CheckViolation, _) => Custom(Status::UnprocessableEntity, json!({ "error": e.to_string() })),
+        _ => server_error(e.into()),
+    }
+}
+
+pub fn promotion_error(e: PromotionError) -> Custom<Value> {
+    match e {
+        PromotionError::UnknownCode => Custom(Status::NotFound, json!({ "error": e.to_string() })),
+        PromotionError::Rejected(ref rejection) => Custom(Status::UnprocessableEntity, json!({ "error": e.to_string(), "reason": rejection.code() })),
+        PromotionError::Database(Error::NotFound) => Custom(Status::NotFound, json!({ "error": "Unknown item in the cart" })),
+        PromotionError::Database(_) => server_error(e.into()),
+    }
+}
+
+#[rocket::get("/coupons")]
+pub async fn get_coupons(db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(CouponRepository::find_all)
+        .await
+        .map(|coupons| Json(json!(coupons)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::get("/coupons/<id>")]
+pub async fn get_coupon(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| {
+        let coupon = CouponRepository::find(c, id)?;
+        let item_ids = CouponRepository::find_scope(c, id)?;
+        Ok((coupon, item_ids))
+    })
+        .await
+        .map(|(coupon, item_ids)| {
+            let mut value = json!(coupon);
+            value["item_ids"] = json!(item_ids);
+            Json(value)
+        })
+        .map_err(coupon_error)
+}
+
+#[rocket::post("/coupons", format = "json", data = "<coupon>")]
+pub async fn create_coupon(coupon: Json<CouponRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let CouponRequest { coupon, item_ids } = coupon.into_inner();
+    if coupon.code.trim().is_empty() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "code must not be empty" })));
+    }
+    db.run(move |c| CouponRepository::create(c, coupon, item_ids))
+        .await
+        .map(|coupon| Json(json!(coupon)))
+        .map_err(coupon_error)
+}
+
+// Redeemed coupons are kept for accounting and answer 409, let them expire instead
+#[rocket::delete("/coupons/<id>")]
+pub async fn delete_coupon(id: i32, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| CouponRepository::delete(c, id))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Custom(Status::Conflict, json!({ "error": "The coupon was redeemed and cannot be deleted" })),
+            _ => server_error(e.into()),
+        })
+}
+
+#[rocket::get("/coupons/<id>/redemptions")]
+pub async fn get_coupon_redemptions(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| CouponRepository::find_redemptions(c, id))
+        .await
+        .map(|redemptions| Json(json!(redemptions)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Previews the discount of a code on a cart priced at the items' current prices. Nothing is redeemed,
+// rejected codes answer 422 with the `reason`.
+#[rocket::post("/promotions/evaluate", format = "json", data = "<request>")]
+pub async fn evaluate_promotion(request: Json<EvaluationRequest>, db: DbConn, base: &State<BaseCurrency>, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let EvaluationRequest { code, items, shipping, currency } = request.into_inner();
+    check_cart(&items, &shipping)?;
+    let converter = converter(&db, base, currency.as_deref()).await?;
+    db.run(move |c| {
+        let cart = Cart::price(c, &items, shipping, converter)?;
+        let (coupon, discount) = CouponRepository::evaluate(c, &code, user.id, &cart, Utc::now().naive_utc())?;
+        Ok((cart, coupon, discount))
+    })
+        .await
+        .map(|(cart, coupon, discount)| Json(json!({
+            "code": coupon.code,
+            "kind": coupon.kind,
+            "currency": cart.converter.currency,
+            "subtotal": cart.subtotal(),
+            "lines": cart.lines,
+            "discount": discount,
+        })))
+        .map_err(promotion_error)
+}
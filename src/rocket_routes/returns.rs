@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+use diesel::{PgConnection, QueryResult};
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::{self, json}}, response::status::Custom, http::Status};
+
+use crate::models::{NewReturn, NewReturnLine, ReceivedLine, Return, ReturnStatus, User};
+use crate::money::Money;
+use crate::repository::{ReturnError, ReturnRepository};
+use crate::rocket_routes::{AdminUser, DbConn};
+
+use super::{server_error, not_found_error};
+use super::inventory::inventory_error;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct ReturnRequest {
+    // Number of the order the items were bought in
+    pub order_reference: String,
+    pub lines: Vec<NewReturnLine>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct ApprovalRequest {
+    // What may be refunded in total, in the currency the order was paid in
+    pub refundable: Money,
+    pub note: Option<String>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct RejectionRequest {
+    // Why the return was rejected, shown to the customer
+    pub note: Option<String>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct IssueRequest {
+    // Identifier of the refund at the payment provider
+    pub provider_reference: String,
+}
+
+fn check_return(request: &ReturnRequest) -> Result<(), Custom<Value>> {
+    if request.order_reference.trim().is_empty() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "order_reference must not be empty" })));
+    }
+    if request.lines.is_empty() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "A return needs at least one line" })));
+    }
+    let mut item_ids = HashSet::new();
+    for line in &request.lines {
+        let item_id = line.item_id.ok_or_else(|| Custom(Status::UnprocessableEntity, json!({ "error": "Every line needs an item_id" })))?;
+        if line.quantity <= 0 {
+            return Err(Custom(Status::UnprocessableEntity, json!({ "error": "quantity must be positive" })));
+        }
+        if !item_ids.insert(item_id) {
+            return Err(Custom(Status::UnprocessableEntity, json!({ "error": format!("Item {} is on several lines", item_id) })));
+        }
+    }
+    Ok(())
+}
+
+fn return_error(e: ReturnError) -> Custom<Value> {
+    match e {
+        ReturnError::InvalidStatus(_) | ReturnError::RefundIssued => Custom(Status::Conflict, json!({ "error": e.to_string() })),
+        ReturnError::InvalidLine(_) | ReturnError::ExceedsRefundable(_) | ReturnError::UnknownOrder(_) | ReturnError::NotOrdered(_) | ReturnError::ExceedsOrdered(..) => Custom(Status::UnprocessableEntity, json!({ "error": e.to_string() })),
+        ReturnError::Inventory(e) => inventory_error(e),
+        ReturnError::Database(Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => not_found_error(e.into()),
+        ReturnError::Database(Error::DatabaseError(DatabaseErrorKind::CheckViolation, _)) => Custom(Status::UnprocessableEntity, json!({ "error": e.to_string() })),
+        _ => server_error(e.into()),
+    }
+}
+
+// The return with its lines and refunds
+fn with_lines(c: &mut PgConnection, found: Return) -> QueryResult<Value> {
+    let mut value = json!(found);
+    value["lines"] = json!(ReturnRepository::find_lines(c, found.id)?);
+    value["refunds"] = json!(ReturnRepository::find_refunds(c, found.id)?);
+    Ok(value)
+}
+
+#[rocket::post("/returns", format = "json", data = "<request>")]
+pub async fn create_return(request: Json<ReturnRequest>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    check_return(&request)?;
+    let ReturnRequest { order_reference, lines } = request.into_inner();
+    let new_return = NewReturn { user_id: Some(user.id), order_reference: order_reference.trim().to_string() };
+    db.run(move |c| ReturnRepository::create(c, new_return, lines))
+        .await
+        .map(|created| Json(json!(created)))
+        .map_err(return_error)
+}
+
+#[rocket::get("/returns")]
+pub async fn get_returns(db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| ReturnRepository::find_by_user(c, user.id))
+        .await
+        .map(|returns| Json(json!(returns)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Returns of other users are not found
+#[rocket::get("/returns/<id>")]
+pub async fn get_return(id: i32, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| {
+        let found = ReturnRepository::find_for_user(c, user.id, id)?;
+        with_lines(c, found)
+    })
+        .await
+        .map(Json)
+        .map_err(|e| return_error(e.into()))
+}
+
+#[rocket::get("/returns/all?<status>")]
+pub async fn get_all_returns(status: Option<&str>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let status = status
+        .map(|status| serde_json::from_value::<ReturnStatus>(json!(status)))
+        .transpose()
+        .map_err(|_| Custom(Status::UnprocessableEntity, json!({ "error": "status must be requested, approved, rejected, received or refunded" })))?;
+    db.run(move |c| ReturnRepository::find_all(c, status))
+        .await
+        .map(|returns| Json(json!(returns)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::get("/returns/all/<id>")]
+pub async fn get_any_return(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| {
+        let found = ReturnRepository::find(c, id)?;
+        with_lines(c, found)
+    })
+        .await
+        .map(Json)
+        .map_err(|e| return_error(e.into()))
+}
+
+#[rocket::post("/returns/<id>/approve", format = "json", data = "<approval>")]
+pub async fn approve_return(id: i32, approval: Json<ApprovalRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let ApprovalRequest { refundable, note } = approval.into_inner();
+    if refundable.amount < 0.into() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "refundable must not be negative" })));
+    }
+    db.run(move |c| ReturnRepository::approve(c, id, refundable, note))
+        .await
+        .map(|approved| Json(json!(approved)))
+        .map_err(return_error)
+}
+
+#[rocket::post("/returns/<id>/reject", format = "json", data = "<rejection>")]
+pub async fn reject_return(id: i32, rejection: Json<RejectionRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let note = rejection.into_inner().note;
+    db.run(move |c| ReturnRepository::reject(c, id, note))
+        .await
+        .map(|rejected| Json(json!(rejected)))
+        .map_err(return_error)
+}
+
+// Items may arrive over several deliveries, each line is received up to its quantity
+#[rocket::post("/returns/<id>/receive", format = "json", data = "<lines>")]
+pub async fn receive_return(id: i32, lines: Json<Vec<ReceivedLine>>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let lines = lines.into_inner();
+    db.run(move |c| {
+        let received = ReturnRepository::receive(c, id, lines)?;
+        Ok(with_lines(c, received)?)
+    })
+        .await
+        .map(Json)
+        .map_err(return_error)
+}
+
+#[rocket::post("/returns/<id>/refunds", format = "json", data = "<amount>")]
+pub async fn create_refund(id: i32, amount: Json<Money>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let amount = amount.into_inner();
+    if amount.amount <= 0.into() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "amount must be positive" })));
+    }
+    db.run(move |c| ReturnRepository::refund(c, id, amount))
+        .await
+        .map(|refund| Json(json!(refund)))
+        .map_err(return_error)
+}
+
+#[rocket::post("/returns/<id>/refunds/<refund_id>/issue", format = "json", data = "<issue>")]
+pub async fn issue_refund(id: i32, refund_id: i32, issue: Json<IssueRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let provider_reference = issue.into_inner().provider_reference;
+    if provider_reference.trim().is_empty() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "provider_reference must not be empty" })));
+    }
+    db.run(move |c| ReturnRepository::issue_refund(c, id, refund_id, provider_reference))
+        .await
+        .map(|refund| Json(json!(refund)))
+        .map_err(return_error)
+}
@@ -0,0 +1,130 @@
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::{self, json}}, response::status::{Custom, NoContent}, http::Status};
+
+use crate::models::{NewReview, ReviewStatus, User};
+use crate::repository::{ItemRepository, ReviewRepository};
+use crate::rocket_routes::{AdminUser, DbConn, Pagination};
+
+use super::{server_error, not_found_error};
+
+const MAX_TITLE_LENGTH: usize = 128;
+const MAX_BODY_LENGTH: usize = 5000;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct ModerationRequest {
+    // `approved` or `rejected`
+    pub status: ReviewStatus,
+    // Shown to the author, e.g. why the review was rejected
+    pub note: Option<String>,
+}
+
+fn review_error(e: Error) -> Custom<Value> {
+    match e {
+        Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => not_found_error("Unknown review or item".into()),
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Custom(Status::Conflict, json!({ "error": "You reviewed this item already, edit your review instead" })),
+        _ => server_error(e.into()),
+    }
+}
+
+// Trims the text, empty title and body are left out
+fn validated(review: NewReview) -> Result<NewReview, Custom<Value>> {
+    if !(1..=5).contains(&review.rating) {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "rating must be between 1 and 5" })));
+    }
+    let text = |value: Option<String>, field: &str, max_length: usize| {
+        let value = value.map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+        match value {
+            Some(value) if value.chars().count() > max_length =>
+                Err(Custom(Status::UnprocessableEntity, json!({ "error": format!("{} is longer than {} characters", field, max_length) }))),
+            value => Ok(value),
+        }
+    };
+    Ok(NewReview {
+        title: text(review.title, "title", MAX_TITLE_LENGTH)?,
+        body: text(review.body, "body", MAX_BODY_LENGTH)?,
+        ..review
+    })
+}
+
+// The item's approved reviews, newest first, with its rating
+#[rocket::get("/items/<id>/reviews?<page>&<per_page>", rank = 2)]
+pub async fn get_item_reviews(id: i32, page: Option<i64>, per_page: Option<i64>, db: DbConn, _user: User) -> Result<Json<Value>, Custom<Value>> {
+    let pagination = Pagination::new(page, per_page);
+    let (limit, offset) = (pagination.per_page, pagination.offset());
+    db.run(move |c| {
+        let item = ItemRepository::find(c, id)?;
+        let (reviews, total) = ReviewRepository::find_approved(c, id, limit, offset)?;
+        Ok((item, reviews, total))
+    })
+        .await
+        .map(|(item, reviews, total)| {
+            let mut value = pagination.json(json!(reviews), total);
+            value["rating_count"] = json!(item.rating_count);
+            value["rating_average"] = json!(item.rating_average());
+            Json(value)
+        })
+        .map_err(review_error)
+}
+
+#[rocket::post("/items/<id>/reviews", format = "json", data = "<review>")]
+pub async fn create_review(id: i32, review: Json<NewReview>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let review = validated(NewReview { item_id: id, user_id: Some(user.id), ..review.into_inner() })?;
+    db.run(move |c| ReviewRepository::create(c, review))
+        .await
+        .map(|review| Json(json!(review)))
+        .map_err(review_error)
+}
+
+// The user's reviews in any status, with the moderators' notes
+#[rocket::get("/reviews/mine")]
+pub async fn get_my_reviews(db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| ReviewRepository::find_by_user(c, user.id))
+        .await
+        .map(|reviews| Json(json!(reviews)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Edited reviews are moderated again, they leave the item's rating until they are approved
+#[rocket::put("/reviews/<id>", format = "json", data = "<review>")]
+pub async fn update_review(id: i32, review: Json<NewReview>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let review = validated(review.into_inner())?;
+    db.run(move |c| ReviewRepository::update(c, user.id, id, review))
+        .await
+        .map(|review| Json(json!(review)))
+        .map_err(review_error)
+}
+
+#[rocket::delete("/reviews/<id>")]
+pub async fn delete_review(id: i32, db: DbConn, user: User) -> Result<NoContent, Custom<Value>> {
+    match db.run(move |c| ReviewRepository::delete(c, user.id, id)).await {
+        Ok(0) => Err(not_found_error("Unknown review".into())),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(server_error(e.into())),
+    }
+}
+
+// Reviews in `status`, pending by default, oldest first
+#[rocket::get("/reviews?<status>&<page>&<per_page>")]
+pub async fn get_reviews(status: Option<&str>, page: Option<i64>, per_page: Option<i64>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let status = serde_json::from_value::<ReviewStatus>(json!(status.unwrap_or("pending")))
+        .map_err(|_| Custom(Status::UnprocessableEntity, json!({ "error": "status must be pending, approved or rejected" })))?;
+    let pagination = Pagination::new(page, per_page);
+    let (limit, offset) = (pagination.per_page, pagination.offset());
+    db.run(move |c| ReviewRepository::find_by_status(c, status, limit, offset))
+        .await
+        .map(|(reviews, total)| Json(pagination.json(json!(reviews), total)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Approved reviews are shown and counted in the item's rating, rejected ones are only shown to their author
+#[rocket::post("/reviews/<id>/moderate", format = "json", data = "<moderation>")]
+pub async fn moderate_review(id: i32, moderation: Json<ModerationRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let ModerationRequest { status, note } = moderation.into_inner();
+    if status == ReviewStatus::Pending {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "status must be approved or rejected" })));
+    }
+    db.run(move |c| ReviewRepository::moderate(c, id, status, note))
+        .await
+        .map(|review| Json(json!(review)))
+        .map_err(review_error)
+}
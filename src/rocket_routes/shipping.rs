@@ -0,0 +1,145 @@
+use bigdecimal::BigDecimal;
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status, State};
+
+use crate::models::{NewShippingMethod, NewShippingRate, ShippingMethodKind, User};
+use crate::money::BaseCurrency;
+use crate::promotions::{Cart, CartItem};
+use crate::repository::ShippingMethodRepository;
+use crate::rocket_routes::{AdminUser, DbConn};
+use crate::shipping::{Parcel, Unavailable};
+
+use super::{server_error, not_found_error};
+use super::addresses::{check_country, destination};
+use super::cart::check_cart;
+use super::currencies::converter;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct ShippingMethodRequest {
+    #[serde(flatten)]
+    pub method: NewShippingMethod,
+    // ISO 3166-1 alpha-2 codes, the method ships everywhere when empty
+    #[serde(default)]
+    pub countries: Vec<String>,
+    // Brackets of `weight` and `price` methods, `flat` ones have none
+    #[serde(default)]
+    pub rates: Vec<NewShippingRate>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct QuoteRequest {
+    pub items: Vec<CartItem>,
+    // One of the user's addresses or a `country`, the default shipping address otherwise
+    pub address_id: Option<i32>,
+    pub country: Option<String>,
+    // ISO 4217 code of the currency to quote in, the base currency by default
+    pub currency: Option<String>,
+}
+
+fn check_method(request: &ShippingMethodRequest) -> Result<(), Custom<Value>> {
+    if request.method.code.trim().is_empty() || request.method.name.trim().is_empty() {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "code and name must not be empty" })));
+    }
+    match (request.method.kind, request.rates.is_empty()) {
+        (ShippingMethodKind::Flat, false) => return Err(Custom(Status::UnprocessableEntity, json!({ "error": "flat methods have no rates, set their price" }))),
+        (ShippingMethodKind::Weight | ShippingMethodKind::Price, true) => return Err(Custom(Status::UnprocessableEntity, json!({ "error": "weight and price methods need at least one rate" }))),
+        _ => (),
+    }
+    for country in &request.countries {
+        check_country(country)?;
+    }
+    Ok(())
+}
+
+fn shipping_method_error(e: Error) -> Custom<Value> {
+    match e {
+        Error::NotFound => not_found_error(e.into()),
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Custom(Status::Conflict, json!({ "error": e.to_string() })),
+        Error::DatabaseError(DatabaseErrorKind::CheckViolation, _) => Custom(Status::UnprocessableEntity, json!({ "error": e.to_string() })),
+        _ => server_error(e.into()),
+    }
+}
+
+pub fn unavailable_error(e: Unavailable) -> Custom<Value> {
+    Custom(Status::UnprocessableEntity, json!({ "error": e.to_string(), "reason": e.code() }))
+}
+
+#[rocket::get("/shipping-methods")]
+pub async fn get_shipping_methods(db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(ShippingMethodRepository::find_all)
+        .await
+        .map(|methods| Json(json!(methods)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::get("/shipping-methods/<id>")]
+pub async fn get_shipping_method(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| ShippingMethodRepository::find_table(c, id))
+        .await
+        .map(|table| {
+            let mut value = json!(table.method);
+            value["countries"] = json!(table.countries);
+            value["rates"] = json!(table.rates);
+            Json(value)
+        })
+        .map_err(shipping_method_error)
+}
+
+#[rocket::post("/shipping-methods", format = "json", data = "<request>")]
+pub async fn create_shipping_method(request: Json<ShippingMethodRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    check_method(&request)?;
+    let ShippingMethodRequest { method, countries, rates } = request.into_inner();
+    db.run(move |c| ShippingMethodRepository::create(c, method, countries, rates))
+        .await
+        .map(|method| Json(json!(method)))
+        .map_err(shipping_method_error)
+}
+
+// Replaces the method with its countries and rates, set `active` to false to stop offering it
+#[rocket::put("/shipping-methods/<id>", format = "json", data = "<request>")]
+pub async fn update_shipping_method(id: i32, request: Json<ShippingMethodRequest>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    check_method(&request)?;
+    let ShippingMethodRequest { method, countries, rates } = request.into_inner();
+    db.run(move |c| ShippingMethodRepository::update(c, id, method, countries, rates))
+        .await
+        .map(|method| Json(json!(method)))
+        .map_err(shipping_method_error)
+}
+
+#[rocket::delete("/shipping-methods/<id>")]
+pub async fn delete_shipping_method(id: i32, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| ShippingMethodRepository::delete(c, id))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| server_error(e.into()))
+}
+
+// The methods able to ship the items to the address, cheapest first, priced at the items' current prices
+#[rocket::post("/shipping/quote", format = "json", data = "<request>")]
+pub async fn quote_shipping(request: Json<QuoteRequest>, db: DbConn, base: &State<BaseCurrency>, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let QuoteRequest { items, address_id, country, currency } = request.into_inner();
+    check_cart(&items, &BigDecimal::from(0))?;
+    let (country, _) = destination(&db, user.id, address_id, country, None).await?;
+    let converter = converter(&db, base, currency.as_deref()).await?;
+    db.run(move |c| {
+        let cart = Cart::price(c, &items, BigDecimal::from(0), converter)?;
+        let parcel = Parcel::load(c, &cart)?;
+        let mut quotes: Vec<_> = ShippingMethodRepository::find_tables_for(c, &country)?
+            .iter()
+            .filter_map(|table| table.quote(&cart, &parcel, &country).ok())
+            .collect();
+        quotes.sort_by(|a, b| a.price.cmp(&b.price));
+        Ok((cart, country, quotes))
+    })
+        .await
+        .map(|(cart, country, quotes)| Json(json!({
+            "currency": cart.converter.currency,
+            "country": country.trim().to_uppercase(),
+            "subtotal": cart.subtotal(),
+            "methods": quotes,
+        })))
+        .map_err(|e| match e {
+            Error::NotFound => Custom(Status::NotFound, json!({ "error": "Unknown item in the cart" })),
+            _ => server_error(e.into()),
+        })
+}
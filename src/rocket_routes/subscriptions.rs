@@ -0,0 +1,57 @@
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status};
+
+use crate::models::User;
+use crate::repository::{StockSubscriptionRepository, SubscriptionError};
+use crate::rocket_routes::{AdminUser, DbConn};
+
+use super::{server_error, not_found_error};
+
+fn subscription_error(e: SubscriptionError) -> Custom<Value> {
+    match e {
+        SubscriptionError::InStock => Custom(Status::Conflict, json!({ "error": e.to_string() })),
+        SubscriptionError::Database(Error::NotFound | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => not_found_error("Unknown item".into()),
+        _ => server_error(e.into()),
+    }
+}
+
+// The out-of-stock items the user waits for
+#[rocket::get("/stock-subscriptions")]
+pub async fn get_stock_subscriptions(db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| StockSubscriptionRepository::find_by_user(c, user.id))
+        .await
+        .map(|subscriptions| Json(json!(subscriptions.into_iter().map(|(subscription, item)| {
+            let mut value = json!(subscription);
+            value["name"] = json!(item.name);
+            value["slug"] = json!(item.slug);
+            value
+        }).collect::<Vec<_>>())))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Notifies the user once when the item is back in stock, then the subscription ends. Items in stock answer 409.
+#[rocket::post("/items/<id>/stock-subscription")]
+pub async fn subscribe(id: i32, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| StockSubscriptionRepository::subscribe(c, user.id, id))
+        .await
+        .map(|subscription| Json(json!(subscription)))
+        .map_err(subscription_error)
+}
+
+#[rocket::delete("/items/<id>/stock-subscription")]
+pub async fn unsubscribe(id: i32, db: DbConn, user: User) -> Result<NoContent, Custom<Value>> {
+    match db.run(move |c| StockSubscriptionRepository::unsubscribe(c, user.id, id)).await {
+        Ok(0) => Err(not_found_error("Not subscribed to the item".into())),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(server_error(e.into())),
+    }
+}
+
+// How many customers wait for the item, to help decide on restocking it
+#[rocket::get("/items/<id>/stock-subscriptions", rank = 2)]
+pub async fn get_item_subscribers(id: i32, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| StockSubscriptionRepository::count_by_item(c, id))
+        .await
+        .map(|subscribers| Json(json!({ "subscribers": subscribers })))
+        .map_err(|e| server_error(e.into()))
+}
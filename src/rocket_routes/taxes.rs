@@ -0,0 +1,76 @@
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status};
+
+use crate::models::{NewTaxClass, NewTaxRate};
+use crate::repository::{TaxClassRepository, TaxRateRepository};
+use crate::rocket_routes::{AdminUser, DbConn};
+use crate::taxes::STANDARD_TAX_CLASS;
+
+use super::{server_error, not_found_error};
+use super::addresses::check_country;
+
+fn tax_error(e: Error) -> Custom<Value> {
+    match e {
+        Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => not_found_error("Unknown tax class".into()),
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Custom(Status::Conflict, json!({ "error": e.to_string() })),
+        Error::DatabaseError(DatabaseErrorKind::CheckViolation, _) => Custom(Status::UnprocessableEntity, json!({ "error": "rate must not be negative" })),
+        _ => server_error(e.into()),
+    }
+}
+
+#[rocket::get("/tax-classes")]
+pub async fn get_tax_classes(db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(TaxClassRepository::find_all)
+        .await
+        .map(|tax_classes| Json(json!(tax_classes)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::post("/tax-classes", format = "json", data = "<tax_class>")]
+pub async fn create_tax_class(tax_class: Json<NewTaxClass>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| TaxClassRepository::create(c, tax_class.into_inner()))
+        .await
+        .map(|tax_class| Json(json!(tax_class)))
+        .map_err(tax_error)
+}
+
+// Classes still used by items answer 409
+#[rocket::delete("/tax-classes/<code>")]
+pub async fn delete_tax_class(code: String, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    if code == STANDARD_TAX_CLASS {
+        return Err(Custom(Status::Conflict, json!({ "error": "The standard tax class cannot be deleted" })));
+    }
+    db.run(move |c| TaxClassRepository::delete(c, &code))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Custom(Status::Conflict, json!({ "error": "Items still use this tax class" })),
+            _ => server_error(e.into()),
+        })
+}
+
+#[rocket::get("/tax-rates")]
+pub async fn get_tax_rates(db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    db.run(TaxRateRepository::find_all)
+        .await
+        .map(|tax_rates| Json(json!(tax_rates)))
+        .map_err(|e| server_error(e.into()))
+}
+
+#[rocket::post("/tax-rates", format = "json", data = "<tax_rate>")]
+pub async fn create_tax_rate(tax_rate: Json<NewTaxRate>, db: DbConn, _user: AdminUser) -> Result<Json<Value>, Custom<Value>> {
+    let tax_rate = tax_rate.into_inner();
+    check_country(&tax_rate.country)?;
+    db.run(move |c| TaxRateRepository::create(c, tax_rate))
+        .await
+        .map(|tax_rate| Json(json!(tax_rate)))
+        .map_err(tax_error)
+}
+
+#[rocket::delete("/tax-rates/<id>")]
+pub async fn delete_tax_rate(id: i32, db: DbConn, _user: AdminUser) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| TaxRateRepository::delete(c, id))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| server_error(e.into()))
+}
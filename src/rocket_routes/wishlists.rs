@@ -0,0 +1,210 @@
+use bigdecimal::BigDecimal;
+use diesel::Connection;
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::{serde::json::{Json, Value, serde_json::json}, response::status::{Custom, NoContent}, http::Status};
+
+use crate::models::{Item, NewWishlist, User, Wishlist, WishlistItem};
+use crate::promotions::CartItem;
+use crate::repository::WishlistRepository;
+use crate::rocket_routes::DbConn;
+
+use super::{server_error, not_found_error};
+use super::cart::check_cart;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct WishlistItemRequest {
+    pub item_id: i32,
+    // 1 when absent
+    pub quantity: Option<i32>,
+}
+
+// The items of the client's cart, as sent to `/cart`
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct CartItems {
+    pub items: Vec<CartItem>,
+}
+
+fn wishlist_error(e: Error) -> Custom<Value> {
+    match e {
+        Error::NotFound => not_found_error("Unknown wishlist or item".into()),
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Custom(Status::Conflict, json!({ "error": "You have a wishlist with this name already" })),
+        _ => server_error(e.into()),
+    }
+}
+
+fn validated(wishlist: NewWishlist, user: &User) -> Result<NewWishlist, Custom<Value>> {
+    let name = wishlist.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": format!("name must have 1 to {} characters", MAX_NAME_LENGTH) })));
+    }
+    Ok(NewWishlist { user_id: user.id, name })
+}
+
+// The list's items with their current price and stock; `price_dropped` tells whether the price is
+// lower than when the item was added
+fn items_json(items: Vec<(WishlistItem, Item)>) -> Value {
+    json!(items.into_iter().map(|(entry, item)| {
+        let mut value = json!(entry);
+        value["name"] = json!(item.name);
+        value["slug"] = json!(item.slug);
+        value["price"] = json!(item.price);
+        value["was_price"] = json!(item.was_price);
+        value["in_stock"] = json!(item.quantity > 0);
+        value["price_dropped"] = json!(item.price < entry.added_price);
+        value
+    }).collect::<Vec<_>>())
+}
+
+async fn wishlist_json(db: &DbConn, wishlist: Wishlist) -> Result<Value, Custom<Value>> {
+    let id = wishlist.id;
+    let items = db.run(move |c| WishlistRepository::find_items(c, id))
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    let mut value = json!(wishlist);
+    value["items"] = items_json(items);
+    Ok(value)
+}
+
+#[rocket::get("/wishlists")]
+pub async fn get_wishlists(db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| WishlistRepository::find_by_user(c, user.id))
+        .await
+        .map(|wishlists| Json(json!(wishlists)))
+        .map_err(|e| server_error(e.into()))
+}
+
+// Wishlists of other users are not found
+#[rocket::get("/wishlists/<id>")]
+pub async fn get_wishlist(id: i32, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let wishlist = db.run(move |c| WishlistRepository::find(c, user.id, id))
+        .await
+        .map_err(wishlist_error)?;
+    wishlist_json(&db, wishlist).await.map(Json)
+}
+
+#[rocket::post("/wishlists", format = "json", data = "<wishlist>")]
+pub async fn create_wishlist(wishlist: Json<NewWishlist>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let wishlist = validated(wishlist.into_inner(), &user)?;
+    db.run(move |c| WishlistRepository::create(c, wishlist))
+        .await
+        .map(|wishlist| Json(json!(wishlist)))
+        .map_err(wishlist_error)
+}
+
+#[rocket::put("/wishlists/<id>", format = "json", data = "<wishlist>")]
+pub async fn rename_wishlist(id: i32, wishlist: Json<NewWishlist>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let wishlist = validated(wishlist.into_inner(), &user)?;
+    db.run(move |c| WishlistRepository::rename(c, user.id, id, wishlist.name))
+        .await
+        .map(|wishlist| Json(json!(wishlist)))
+        .map_err(wishlist_error)
+}
+
+#[rocket::delete("/wishlists/<id>")]
+pub async fn delete_wishlist(id: i32, db: DbConn, user: User) -> Result<NoContent, Custom<Value>> {
+    match db.run(move |c| WishlistRepository::delete(c, user.id, id)).await {
+        Ok(0) => Err(not_found_error("Unknown wishlist".into())),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(server_error(e.into())),
+    }
+}
+
+// Gives the list a public link, `/wishlists/shared/<share_token>`; sharing a shared list keeps its link
+#[rocket::post("/wishlists/<id>/share")]
+pub async fn share_wishlist(id: i32, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| WishlistRepository::share(c, user.id, id))
+        .await
+        .map(|wishlist| Json(json!(wishlist)))
+        .map_err(wishlist_error)
+}
+
+// The links given out stop working, sharing the list again gives it a new one
+#[rocket::delete("/wishlists/<id>/share")]
+pub async fn unshare_wishlist(id: i32, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    db.run(move |c| WishlistRepository::unshare(c, user.id, id))
+        .await
+        .map(|wishlist| Json(json!(wishlist)))
+        .map_err(wishlist_error)
+}
+
+// A shared list as anyone with its link sees it, without its owner
+#[rocket::get("/wishlists/shared/<token>")]
+pub async fn get_shared_wishlist(token: String, db: DbConn) -> Result<Json<Value>, Custom<Value>> {
+    let wishlist = db.run(move |c| WishlistRepository::find_shared(c, &token))
+        .await
+        .map_err(|e| match e {
+            Error::NotFound => not_found_error("Unknown or no longer shared wishlist".into()),
+            _ => server_error(e.into()),
+        })?;
+    let value = wishlist_json(&db, wishlist).await?;
+    Ok(Json(json!({ "name": value["name"], "items": value["items"] })))
+}
+
+// Adds an item, or sets the quantity of one the list has
+#[rocket::post("/wishlists/<id>/items", format = "json", data = "<request>")]
+pub async fn add_wishlist_item(id: i32, request: Json<WishlistItemRequest>, db: DbConn, user: User) -> Result<Json<Value>, Custom<Value>> {
+    let WishlistItemRequest { item_id, quantity } = request.into_inner();
+    let quantity = quantity.unwrap_or(1);
+    if quantity <= 0 {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "quantity must be positive" })));
+    }
+    db.run(move |c| WishlistRepository::add_item(c, user.id, id, item_id, quantity))
+        .await
+        .map(|entry| Json(json!(entry)))
+        .map_err(wishlist_error)
+}
+
+#[rocket::delete("/wishlists/<id>/items/<item_id>")]
+pub async fn remove_wishlist_item(id: i32, item_id: i32, db: DbConn, user: User) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| WishlistRepository::remove_item(c, user.id, id, item_id))
+        .await
+        .map(|_| NoContent)
+        .map_err(wishlist_error)
+}
+
+fn quantity_too_large() -> Custom<Value> {
+    Custom(Status::UnprocessableEntity, json!({ "error": "The quantity of the item is too large" }))
+}
+
+// Takes the item off the list and puts it in the cart sent, returns the cart to keep
+#[rocket::post("/wishlists/<id>/items/<item_id>/move-to-cart", format = "json", data = "<cart>")]
+pub async fn move_to_cart(id: i32, item_id: i32, cart: Json<CartItems>, db: DbConn, user: User) -> Result<Json<CartItems>, Custom<Value>> {
+    let CartItems { mut items } = cart.into_inner();
+    check_cart(&items, &BigDecimal::from(0))?;
+    let items = db.run(move |c| c.transaction(|c| {
+        let entry = WishlistRepository::remove_item(c, user.id, id, item_id)?;
+        match items.iter_mut().find(|line| line.item_id == item_id) {
+            // Rolling back keeps the item on the list
+            Some(line) => line.quantity = line.quantity.checked_add(entry.quantity).ok_or(Error::RollbackTransaction)?,
+            None => items.push(CartItem { item_id, quantity: entry.quantity }),
+        }
+        Ok(items)
+    }))
+        .await
+        .map_err(|e| match e {
+            Error::RollbackTransaction => quantity_too_large(),
+            _ => wishlist_error(e),
+        })?;
+    Ok(Json(CartItems { items }))
+}
+
+// Takes the item out of the cart sent and saves it on the list with its cart quantity, returns the cart to keep
+#[rocket::post("/wishlists/<id>/items/<item_id>/save-for-later", format = "json", data = "<cart>")]
+pub async fn save_for_later(id: i32, item_id: i32, cart: Json<CartItems>, db: DbConn, user: User) -> Result<Json<CartItems>, Custom<Value>> {
+    let CartItems { mut items } = cart.into_inner();
+    check_cart(&items, &BigDecimal::from(0))?;
+    let quantity = items.iter()
+        .filter(|line| line.item_id == item_id)
+        .try_fold(0i32, |quantity, line| quantity.checked_add(line.quantity))
+        .ok_or_else(quantity_too_large)?;
+    if quantity == 0 {
+        return Err(Custom(Status::UnprocessableEntity, json!({ "error": "The item is not in the cart" })));
+    }
+    db.run(move |c| WishlistRepository::add_item(c, user.id, id, item_id, quantity))
+        .await
+        .map_err(wishlist_error)?;
+    items.retain(|line| line.item_id != item_id);
+    Ok(Json(CartItems { items }))
+}
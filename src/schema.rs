@@ -1,11 +1,183 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "money_amount"))]
+    pub struct MoneyAmount;
+}
+
+diesel::table! {
+    addresses (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 128]
+        name -> Varchar,
+        #[max_length = 255]
+        line1 -> Varchar,
+        #[max_length = 255]
+        line2 -> Nullable<Varchar>,
+        #[max_length = 128]
+        city -> Varchar,
+        #[max_length = 64]
+        region -> Nullable<Varchar>,
+        #[max_length = 16]
+        postal_code -> Nullable<Varchar>,
+        #[max_length = 2]
+        country -> Bpchar,
+        #[max_length = 32]
+        phone -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        is_default_shipping -> Bool,
+        is_default_billing -> Bool,
+    }
+}
+
+diesel::table! {
+    coupon_redemptions (id) {
+        id -> Int4,
+        coupon_id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 64]
+        order_reference -> Varchar,
+        discount -> Numeric,
+        redeemed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    coupons (id) {
+        id -> Int4,
+        #[max_length = 64]
+        code -> Varchar,
+        #[max_length = 32]
+        kind -> Varchar,
+        value -> Numeric,
+        starts_at -> Nullable<Timestamp>,
+        ends_at -> Nullable<Timestamp>,
+        min_subtotal -> Nullable<Numeric>,
+        max_redemptions -> Nullable<Int4>,
+        max_redemptions_per_user -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    coupons_items (id) {
+        id -> Int4,
+        coupon_id -> Int4,
+        item_id -> Int4,
+    }
+}
+
+diesel::table! {
+    exchange_rates (currency) {
+        #[max_length = 3]
+        currency -> Bpchar,
+        rate -> Numeric,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    image_renditions (id) {
+        id -> Int4,
+        image_id -> Int4,
+        #[max_length = 32]
+        kind -> Varchar,
+        #[max_length = 16]
+        format -> Varchar,
+        #[max_length = 255]
+        storage_key -> Varchar,
+        width -> Int4,
+        height -> Int4,
+        created_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     images (id) {
         id -> Int4,
         #[max_length = 255]
-        url -> Varchar,
+        storage_key -> Varchar,
         created_at -> Nullable<Timestamp>,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    inventory_adjustments (id) {
+        id -> Int4,
+        item_id -> Int4,
+        delta -> Int4,
+        quantity -> Int4,
+        #[max_length = 16]
+        reason -> Varchar,
+        #[max_length = 64]
+        reference -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    invoice_items (id) {
+        invoice_id -> Int4,
+        item_id -> Nullable<Int4>,
+        id -> Int4,
+        quantity -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    invoice_sequences (year) {
+        year -> Int4,
+        last_number -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MoneyAmount;
+
+    invoices (id) {
+        id -> Int4,
+        #[max_length = 32]
+        number -> Varchar,
+        year -> Int4,
+        sequence -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 64]
+        order_reference -> Varchar,
+        total -> MoneyAmount,
+        tax -> MoneyAmount,
+        #[max_length = 255]
+        pdf_key -> Varchar,
+        #[max_length = 255]
+        html_key -> Varchar,
+        issued_at -> Timestamp,
+        document -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MoneyAmount;
+
+    item_prices (id) {
+        id -> Int4,
+        item_id -> Int4,
+        price -> MoneyAmount,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    item_slug_redirects (id) {
+        id -> Int4,
+        item_id -> Int4,
+        #[max_length = 255]
+        slug -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
@@ -18,6 +190,19 @@ diesel::table! {
         price -> Numeric,
         created_at -> Nullable<Timestamp>,
         quantity -> Int4,
+        #[max_length = 255]
+        slug -> Varchar,
+        #[max_length = 64]
+        sku -> Nullable<Varchar>,
+        was_price -> Nullable<Numeric>,
+        #[max_length = 32]
+        tax_class -> Varchar,
+        weight_grams -> Nullable<Int4>,
+        length_mm -> Nullable<Int4>,
+        width_mm -> Nullable<Int4>,
+        height_mm -> Nullable<Int4>,
+        rating_count -> Int4,
+        rating_sum -> Int4,
     }
 }
 
@@ -27,6 +212,103 @@ diesel::table! {
         item_id -> Int4,
         image_id -> Int4,
         created_at -> Nullable<Timestamp>,
+        position -> Int4,
+        is_primary -> Bool,
+        #[max_length = 255]
+        alt_text -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    notifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        item_id -> Int4,
+        #[max_length = 32]
+        kind -> Varchar,
+        previous_price -> Nullable<Numeric>,
+        price -> Nullable<Numeric>,
+        created_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    price_history (id) {
+        id -> Int4,
+        item_id -> Int4,
+        previous_price -> Nullable<Numeric>,
+        price -> Numeric,
+        #[max_length = 32]
+        source -> Varchar,
+        changed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MoneyAmount;
+
+    refunds (id) {
+        id -> Int4,
+        return_id -> Int4,
+        amount -> MoneyAmount,
+        #[max_length = 16]
+        status -> Varchar,
+        #[max_length = 128]
+        provider_reference -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        issued_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    return_lines (id) {
+        id -> Int4,
+        return_id -> Int4,
+        item_id -> Nullable<Int4>,
+        quantity -> Int4,
+        #[max_length = 32]
+        reason -> Varchar,
+        comment -> Nullable<Text>,
+        received_quantity -> Int4,
+        restocked_quantity -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MoneyAmount;
+
+    returns (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 64]
+        order_reference -> Varchar,
+        #[max_length = 16]
+        status -> Varchar,
+        refundable -> Nullable<MoneyAmount>,
+        note -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    reviews (id) {
+        id -> Int4,
+        item_id -> Int4,
+        user_id -> Nullable<Int4>,
+        rating -> Int4,
+        #[max_length = 128]
+        title -> Nullable<Varchar>,
+        body -> Nullable<Text>,
+        #[max_length = 16]
+        status -> Varchar,
+        verified_purchase -> Bool,
+        moderation_note -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -41,6 +323,91 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    scheduled_prices (id) {
+        id -> Int4,
+        item_id -> Int4,
+        price -> Numeric,
+        starts_at -> Timestamp,
+        ends_at -> Nullable<Timestamp>,
+        previous_price -> Nullable<Numeric>,
+        started_at -> Nullable<Timestamp>,
+        ended_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    shipping_method_countries (id) {
+        id -> Int4,
+        shipping_method_id -> Int4,
+        #[max_length = 2]
+        country -> Bpchar,
+    }
+}
+
+diesel::table! {
+    shipping_methods (id) {
+        id -> Int4,
+        #[max_length = 32]
+        code -> Varchar,
+        #[max_length = 128]
+        name -> Varchar,
+        #[max_length = 16]
+        kind -> Varchar,
+        price -> Numeric,
+        free_above -> Nullable<Numeric>,
+        volumetric_divisor -> Nullable<Int4>,
+        active -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    shipping_rates (id) {
+        id -> Int4,
+        shipping_method_id -> Int4,
+        min_value -> Numeric,
+        price -> Numeric,
+    }
+}
+
+diesel::table! {
+    stock_subscriptions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        item_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    tax_classes (id) {
+        id -> Int4,
+        #[max_length = 32]
+        code -> Varchar,
+        #[max_length = 64]
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    tax_rates (id) {
+        id -> Int4,
+        #[max_length = 32]
+        tax_class -> Varchar,
+        #[max_length = 2]
+        country -> Bpchar,
+        #[max_length = 64]
+        region -> Varchar,
+        #[max_length = 64]
+        name -> Varchar,
+        rate -> Numeric,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -62,16 +429,94 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    wishlist_items (wishlist_id, item_id) {
+        wishlist_id -> Int4,
+        item_id -> Int4,
+        quantity -> Int4,
+        added_price -> Numeric,
+        added_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    wishlists (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 64]
+        share_token -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(addresses -> users (user_id));
+diesel::joinable!(coupon_redemptions -> coupons (coupon_id));
+diesel::joinable!(coupon_redemptions -> users (user_id));
+diesel::joinable!(coupons_items -> coupons (coupon_id));
+diesel::joinable!(coupons_items -> items (item_id));
+diesel::joinable!(image_renditions -> images (image_id));
+diesel::joinable!(inventory_adjustments -> items (item_id));
+diesel::joinable!(invoice_items -> invoices (invoice_id));
+diesel::joinable!(invoice_items -> items (item_id));
+diesel::joinable!(invoices -> users (user_id));
+diesel::joinable!(item_prices -> items (item_id));
+diesel::joinable!(item_slug_redirects -> items (item_id));
 diesel::joinable!(items_images -> images (image_id));
 diesel::joinable!(items_images -> items (item_id));
+diesel::joinable!(notifications -> items (item_id));
+diesel::joinable!(notifications -> users (user_id));
+diesel::joinable!(price_history -> items (item_id));
+diesel::joinable!(refunds -> returns (return_id));
+diesel::joinable!(return_lines -> items (item_id));
+diesel::joinable!(return_lines -> returns (return_id));
+diesel::joinable!(returns -> users (user_id));
+diesel::joinable!(reviews -> items (item_id));
+diesel::joinable!(reviews -> users (user_id));
+diesel::joinable!(scheduled_prices -> items (item_id));
+diesel::joinable!(shipping_method_countries -> shipping_methods (shipping_method_id));
+diesel::joinable!(shipping_rates -> shipping_methods (shipping_method_id));
+diesel::joinable!(stock_subscriptions -> items (item_id));
+diesel::joinable!(stock_subscriptions -> users (user_id));
 diesel::joinable!(users_roles -> roles (role_id));
 diesel::joinable!(users_roles -> users (user_id));
+diesel::joinable!(wishlist_items -> items (item_id));
+diesel::joinable!(wishlist_items -> wishlists (wishlist_id));
+diesel::joinable!(wishlists -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    addresses,
+    coupon_redemptions,
+    coupons,
+    coupons_items,
+    exchange_rates,
+    image_renditions,
     images,
+    inventory_adjustments,
+    invoice_items,
+    invoice_sequences,
+    invoices,
+    item_prices,
+    item_slug_redirects,
     items,
     items_images,
+    notifications,
+    price_history,
+    refunds,
+    return_lines,
+    returns,
+    reviews,
     roles,
+    scheduled_prices,
+    shipping_method_countries,
+    shipping_methods,
+    shipping_rates,
+    stock_subscriptions,
+    tax_classes,
+    tax_rates,
     users,
     users_roles,
+    wishlist_items,
+    wishlists,
 );
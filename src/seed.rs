@@ -0,0 +1,211 @@
+use std::io::Cursor;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
+use diesel::{OptionalExtension, PgConnection};
+use image::{ImageFormat, Rgb, RgbImage};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::Serialize;
+
+use crate::auth::hash_password;
+use crate::image_processing::{run_job, RenditionJob};
+use crate::models::{Item, NewImage, NewItem, NewRole, NewUser, RoleCode, User};
+use crate::repository::{ImageRepository, ItemRepository, ItemsImageRepository, RoleRepository, UserRepository};
+use crate::storage::{generate_key, ImageStore};
+
+pub type SeedError = Box<dyn std::error::Error + Send + Sync>;
+
+// The list lengths are pairwise coprime, so consecutive indexes vary every word and no
+// combination repeats before all of them were used
+const ADJECTIVES: [&str; 11] = [
+  "Classic", "Vintage", "Compact", "Deluxe", "Rugged", "Slim",
+  "Handmade", "Everyday", "Premium", "Lightweight", "Modern",
+];
+const MATERIALS: [&str; 10] = [
+  "Leather", "Cotton", "Oak", "Steel", "Ceramic", "Wool", "Bamboo", "Linen", "Glass", "Copper",
+];
+const PRODUCTS: [&str; 13] = [
+  "Backpack", "Mug", "Desk Lamp", "Wallet", "Scarf", "Cutting Board",
+  "Water Bottle", "Notebook", "Sneakers", "Teapot", "Watch", "Blanket", "Cushion",
+];
+
+// Fake users all share this password, hashed once since hashing is deliberately slow
+pub const FAKE_USER_PASSWORD: &str = "password";
+
+pub struct SeedConfig {
+  pub admin_username: String,
+  pub admin_email: String,
+  // Only used when the admin account does not exist yet
+  pub admin_password: String,
+  pub items: usize,
+  pub images_per_item: usize,
+  pub users: usize,
+}
+
+// Rows created by a run; a second run with the same configuration creates nothing
+#[derive(Serialize, Default, Debug)]
+pub struct SeedReport {
+  pub roles: usize,
+  pub admin_created: bool,
+  pub items: usize,
+  pub images: usize,
+  pub users: usize,
+}
+
+fn seed_sku(index: usize) -> String {
+  format!("SEED-{:05}", index + 1)
+}
+
+// Fake item `index`, always the same for the same index so that reruns find what earlier runs created
+pub fn fake_item(index: usize) -> NewItem {
+  let mut rng = StdRng::seed_from_u64(index as u64);
+  let combinations = ADJECTIVES.len() * MATERIALS.len() * PRODUCTS.len();
+  let material = MATERIALS[index % MATERIALS.len()];
+  let mut name = format!(
+    "{} {} {}",
+    ADJECTIVES[index % ADJECTIVES.len()],
+    material,
+    PRODUCTS[index % PRODUCTS.len()],
+  );
+  if index >= combinations {
+    name = format!("{} Mk {}", name, index / combinations + 1);
+  }
+
+  let cents: i64 = rng.gen_range(199..=49999);
+  NewItem {
+    description: Some(format!(
+      "Made from {}. Ships within {} business days.",
+      material.to_lowercase(),
+      rng.gen_range(1..=7),
+    )),
+    name,
+    price: BigDecimal::new(BigInt::from(cents), 2),
+    quantity: rng.gen_range(0..=250),
+    sku: Some(seed_sku(index)),
+    tax_class: None,
+    weight_grams: Some(rng.gen_range(50..=5000)),
+    length_mm: Some(rng.gen_range(50..=600)),
+    width_mm: Some(rng.gen_range(50..=400)),
+    height_mm: Some(rng.gen_range(10..=300)),
+  }
+}
+
+// A PNG gradient whose colours depend on the item and the image position
+fn fake_image(item_index: usize, position: usize) -> Result<Vec<u8>, SeedError> {
+  let mut rng = StdRng::seed_from_u64((item_index * 1000 + position) as u64);
+  let from: [u8; 3] = rng.gen();
+  let to: [u8; 3] = rng.gen();
+  let (width, height) = (800, 600);
+  let image = RgbImage::from_fn(width, height, |x, y| {
+    let t = (x + y) as f32 / (width + height) as f32;
+    Rgb([0, 1, 2].map(|i| (from[i] as f32 * (1.0 - t) + to[i] as f32 * t) as u8))
+  });
+
+  let mut bytes = Cursor::new(Vec::new());
+  image.write_to(&mut bytes, ImageFormat::Png)?;
+  Ok(bytes.into_inner())
+}
+
+fn hash(password: &str) -> Result<String, SeedError> {
+  hash_password(password).map_err(|e| e.to_string().into())
+}
+
+fn seed_roles(c: &mut PgConnection, report: &mut SeedReport) -> Result<(), SeedError> {
+  for (code, name) in [(RoleCode::Admin, "Admin"), (RoleCode::User, "User")] {
+    if RoleRepository::find_by_code(c, code.clone()).optional()?.is_none() {
+      RoleRepository::create(c, NewRole { code: code.to_string(), name: name.to_string() })?;
+      report.roles += 1;
+    }
+  }
+  Ok(())
+}
+
+// Creates the user with the roles, or finds it. Existing users are left as they are and must have the roles
+// already: a customer who registered under the admin's username must not become an administrator.
+// The password is only hashed for new users.
+fn seed_user(c: &mut PgConnection, username: String, email: String, password: impl FnOnce() -> Result<String, SeedError>, codes: &[RoleCode]) -> Result<(User, bool), SeedError> {
+  if let Some(user) = UserRepository::find_by_username(c, &username).optional()? {
+    let roles = RoleRepository::find_by_user(c, &user)?;
+    if let Some(missing) = codes.iter().find(|code| !roles.iter().any(|role| role.code == code.to_string())) {
+      return Err(format!("User `{}` exists without the {} role, seeding leaves existing users alone", username, missing).into());
+    }
+    return Ok((user, false));
+  }
+  let user = UserRepository::create(c, NewUser { username, email, password: password()? })?;
+  for code in codes {
+    let role = RoleRepository::find_by_code(c, code.clone())?;
+    UserRepository::assign_role(c, &user, &role)?;
+  }
+  Ok((user, true))
+}
+
+fn seed_item(c: &mut PgConnection, index: usize, report: &mut SeedReport) -> Result<Item, SeedError> {
+  let new_item = fake_item(index);
+  // Renamed items keep their sku, so reruns find them by it
+  match ItemRepository::find_by_sku(c, &seed_sku(index)).optional()? {
+    Some(item) => Ok(item),
+    None => {
+      report.items += 1;
+      Ok(ItemRepository::create(c, new_item)?)
+    },
+  }
+}
+
+fn seed_images(c: &mut PgConnection, store: &dyn ImageStore, index: usize, item: &Item, config: &SeedConfig, report: &mut SeedReport) -> Result<(), SeedError> {
+  let existing = ItemsImageRepository::find_gallery(c, item)?.len();
+  for position in existing..config.images_per_item {
+    let bytes = fake_image(index, position)?;
+    let new_image = NewImage {
+      storage_key: generate_key(&format!("{}-{}.png", item.slug, position + 1)),
+    };
+    let image = ImageRepository::create_with_item(c, store, new_image, item.id, &bytes)?;
+    // Renditions are generated inline since the server's worker may not be running
+    run_job(c, store, RenditionJob {
+      image_id: image.id,
+      storage_key: image.storage_key,
+      bytes,
+    })?;
+    report.images += 1;
+  }
+  Ok(())
+}
+
+// Bootstraps a development database: the roles `RoleCode` expects, an admin account and fake
+// items, images and users. Existing rows are kept, so running it again only fills in what is missing.
+pub fn seed(c: &mut PgConnection, store: &dyn ImageStore, config: &SeedConfig) -> Result<SeedReport, SeedError> {
+  let mut report = SeedReport::default();
+
+  seed_roles(c, &mut report)?;
+
+  let (_, admin_created) = seed_user(
+    c,
+    config.admin_username.clone(),
+    config.admin_email.clone(),
+    || hash(&config.admin_password),
+    &[RoleCode::User, RoleCode::Admin],
+  )?;
+  report.admin_created = admin_created;
+
+  for index in 0..config.items {
+    let item = seed_item(c, index, &mut report)?;
+    seed_images(c, store, index, &item, config, &mut report)?;
+  }
+
+  let mut password: Option<String> = None;
+  for index in 1..=config.users {
+    let (_, created) = seed_user(
+      c,
+      format!("user{:04}", index),
+      format!("user{:04}@example.com", index),
+      || match &password {
+        Some(hashed) => Ok(hashed.clone()),
+        None => Ok(password.insert(hash(FAKE_USER_PASSWORD)?).clone()),
+      },
+      &[RoleCode::User],
+    )?;
+    report.users += created as usize;
+  }
+
+  Ok(report)
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bigdecimal::BigDecimal;
+use diesel::{PgConnection, QueryResult};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::models::{ShippingMethod, ShippingMethodKind, ShippingRate};
+use crate::promotions::Cart;
+use crate::repository::ItemRepository;
+
+struct ParcelItem {
+  weight_grams: Option<i32>,
+  dimensions_mm: Option<(i32, i32, i32)>,
+  quantity: i32,
+}
+
+// The physical side of a cart, what weight-based methods are priced by
+pub struct Parcel {
+  items: Vec<ParcelItem>,
+}
+
+impl Parcel {
+  pub fn load(c: &mut PgConnection, cart: &Cart) -> QueryResult<Parcel> {
+    let ids = cart.lines.iter().map(|line| line.item_id).collect();
+    let items: HashMap<i32, _> = ItemRepository::find_by_ids(c, ids)?
+      .into_iter()
+      .map(|item| (item.id, item))
+      .collect();
+    let items = cart.lines.iter()
+      .filter_map(|line| items.get(&line.item_id).map(|item| ParcelItem {
+        weight_grams: item.weight_grams,
+        dimensions_mm: match (item.length_mm, item.width_mm, item.height_mm) {
+          (Some(length), Some(width), Some(height)) => Some((length, width, height)),
+          _ => None,
+        },
+        quantity: line.quantity,
+      }))
+      .collect();
+    Ok(Parcel { items })
+  }
+
+  // Weight in grams, None when an item has no weight. With a `volumetric_divisor` in cm³ per kg,
+  // each item weighs at least its volume divided by it: mm³ / divisor gives grams.
+  pub fn weight(&self, volumetric_divisor: Option<i32>) -> Option<i64> {
+    self.items.iter()
+      .map(|item| {
+        let weight = item.weight_grams? as i64;
+        let volumetric = match (volumetric_divisor, item.dimensions_mm) {
+          (Some(divisor), Some((length, width, height))) => {
+            let volume = length as i64 * width as i64 * height as i64;
+            (volume + divisor as i64 - 1) / divisor as i64
+          },
+          _ => 0,
+        };
+        Some(weight.max(volumetric) * item.quantity as i64)
+      })
+      .sum()
+  }
+}
+
+// Why a method cannot ship a cart
+#[derive(Debug, PartialEq)]
+pub enum Unavailable {
+  Inactive,
+  Country,
+  UnknownWeight,
+  NoRate,
+}
+
+impl Unavailable {
+  // Stable identifier for clients, the message may change
+  pub fn code(&self) -> &'static str {
+    match self {
+      Unavailable::Inactive => "inactive",
+      Unavailable::Country => "country",
+      Unavailable::UnknownWeight => "unknown_weight",
+      Unavailable::NoRate => "no_rate",
+    }
+  }
+}
+
+impl fmt::Display for Unavailable {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Unavailable::Inactive => f.write_str("This shipping method is not offered"),
+      Unavailable::Country => f.write_str("This shipping method does not ship to this country"),
+      Unavailable::UnknownWeight => f.write_str("Some items have no weight and cannot be shipped by weight"),
+      Unavailable::NoRate => f.write_str("This shipping method has no rate for this cart"),
+    }
+  }
+}
+
+// What a method costs for a cart, in the cart's currency
+#[derive(Serialize, JsonSchema)]
+pub struct ShippingQuote {
+  pub method_id: i32,
+  pub code: String,
+  pub name: String,
+  pub kind: ShippingMethodKind,
+  #[schemars(with = "String")]
+  pub price: BigDecimal,
+}
+
+// A method with the countries it ships to, all of them when empty, and its brackets by `min_value`
+pub struct ShippingTable {
+  pub method: ShippingMethod,
+  pub countries: Vec<String>,
+  pub rates: Vec<ShippingRate>,
+}
+
+impl ShippingTable {
+  pub fn ships_to(&self, country: &str) -> bool {
+    self.countries.is_empty() || self.countries.iter().any(|other| other.eq_ignore_ascii_case(country.trim()))
+  }
+
+  // Prices `cart` shipped to `country`. The method's amounts are in the base currency and converted to
+  // the cart's; the bracket and the free shipping threshold are compared to the subtotal before discounts.
+  pub fn quote(&self, cart: &Cart, parcel: &Parcel, country: &str) -> Result<ShippingQuote, Unavailable> {
+    if !self.method.active {
+      return Err(Unavailable::Inactive);
+    }
+    if !self.ships_to(country) {
+      return Err(Unavailable::Country);
+    }
+
+    let converter = &cart.converter;
+    let subtotal = cart.subtotal();
+    let bracket = |reached: &dyn Fn(&ShippingRate) -> bool| self.rates.iter()
+      .filter(|rate| reached(rate))
+      .max_by(|a, b| a.min_value.cmp(&b.min_value))
+      .map(|rate| rate.price.clone())
+      .ok_or(Unavailable::NoRate);
+    let rate = match self.method.kind {
+      ShippingMethodKind::Flat => BigDecimal::from(0),
+      ShippingMethodKind::Weight => {
+        let weight = BigDecimal::from(parcel.weight(self.method.volumetric_divisor).ok_or(Unavailable::UnknownWeight)?);
+        bracket(&|rate| rate.min_value <= weight)?
+      },
+      ShippingMethodKind::Price => bracket(&|rate| converter.convert(&rate.min_value).amount <= subtotal)?,
+    };
+
+    let free = self.method.free_above.as_ref().is_some_and(|free_above| subtotal >= converter.convert(free_above).amount);
+    let price = if free { BigDecimal::from(0) } else { &self.method.price + rate };
+    Ok(ShippingQuote {
+      method_id: self.method.id,
+      code: self.method.code.clone(),
+      name: self.method.name.clone(),
+      kind: self.method.kind,
+      price: converter.convert(&price).amount,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDateTime;
+
+  use super::*;
+  use crate::money::CurrencyConverter;
+  use crate::promotions::CartLine;
+
+  fn decimal(value: &str) -> BigDecimal {
+    value.parse().unwrap()
+  }
+
+  fn parcel_item(weight_grams: Option<i32>, dimensions_mm: Option<(i32, i32, i32)>, quantity: i32) -> ParcelItem {
+    ParcelItem { weight_grams, dimensions_mm, quantity }
+  }
+
+  // A cart of one line in `currency`, worth `rate` units of it per unit of the base currency
+  fn cart(subtotal: &str, currency: &str, rate: &str) -> Cart {
+    let converter = CurrencyConverter { currency: currency.parse().unwrap(), rate: decimal(rate) };
+    let lines = vec![CartLine { item_id: 1, unit_price: decimal(subtotal), quantity: 1, tax_class: "standard".to_string() }];
+    Cart { lines, shipping: BigDecimal::from(0), converter }
+  }
+
+  fn table(kind: ShippingMethodKind, price: &str, free_above: Option<&str>, rates: &[(&str, &str)]) -> ShippingTable {
+    ShippingTable {
+      method: ShippingMethod {
+        id: 1,
+        code: "standard".to_string(),
+        name: "Standard".to_string(),
+        kind,
+        price: decimal(price),
+        free_above: free_above.map(decimal),
+        volumetric_divisor: None,
+        active: true,
+        created_at: NaiveDateTime::default(),
+      },
+      countries: Vec::new(),
+      rates: rates.iter()
+        .enumerate()
+        .map(|(index, (min_value, price))| ShippingRate {
+          id: index as i32,
+          shipping_method_id: 1,
+          min_value: decimal(min_value),
+          price: decimal(price),
+        })
+        .collect(),
+    }
+  }
+
+  fn price(table: &ShippingTable, cart: &Cart, parcel: &Parcel) -> Result<String, Unavailable> {
+    table.quote(cart, parcel, "DE").map(|quote| quote.price.to_string())
+  }
+
+  #[test]
+  fn items_weigh_at_least_their_volume() {
+    // 300 × 200 × 100 mm is 6000 cm³, 1200 g at 5000 cm³ per kg
+    let parcel = Parcel { items: vec![parcel_item(Some(500), Some((300, 200, 100)), 2), parcel_item(Some(800), None, 1)] };
+    assert_eq!(parcel.weight(None), Some(1800));
+    assert_eq!(parcel.weight(Some(5000)), Some(3200));
+
+    let heavy = Parcel { items: vec![parcel_item(Some(2000), Some((300, 200, 100)), 1)] };
+    assert_eq!(heavy.weight(Some(5000)), Some(2000));
+    // Volumetric grams are rounded up
+    let small = Parcel { items: vec![parcel_item(Some(0), Some((1, 1, 1)), 3)] };
+    assert_eq!(small.weight(Some(5000)), Some(3));
+  }
+
+  #[test]
+  fn items_without_a_weight_have_no_parcel_weight() {
+    let parcel = Parcel { items: vec![parcel_item(Some(500), None, 1), parcel_item(None, Some((10, 10, 10)), 1)] };
+    assert_eq!(parcel.weight(Some(5000)), None);
+
+    let table = table(ShippingMethodKind::Weight, "0", None, &[("0", "4.90")]);
+    assert_eq!(price(&table, &cart("10", "EUR", "1"), &parcel), Err(Unavailable::UnknownWeight));
+  }
+
+  #[test]
+  fn weight_methods_use_the_highest_bracket_reached() {
+    let table = table(ShippingMethodKind::Weight, "1.00", None, &[("0", "4.90"), ("2000", "9.90"), ("1000", "6.90")]);
+    let parcel = |grams| Parcel { items: vec![parcel_item(Some(grams), None, 1)] };
+    let cart = cart("10", "EUR", "1");
+    assert_eq!(price(&table, &cart, &parcel(999)).unwrap(), "5.90");
+    assert_eq!(price(&table, &cart, &parcel(1000)).unwrap(), "7.90");
+    assert_eq!(price(&table, &cart, &parcel(5000)).unwrap(), "10.90");
+  }
+
+  #[test]
+  fn carts_below_every_bracket_have_no_rate() {
+    let table = table(ShippingMethodKind::Price, "0", None, &[("20", "4.90"), ("50", "2.90")]);
+    let parcel = Parcel { items: Vec::new() };
+    assert_eq!(price(&table, &cart("19.99", "EUR", "1"), &parcel), Err(Unavailable::NoRate));
+    assert_eq!(price(&table, &cart("20", "EUR", "1"), &parcel).unwrap(), "4.90");
+    assert_eq!(price(&table, &cart("75", "EUR", "1"), &parcel).unwrap(), "2.90");
+  }
+
+  #[test]
+  fn free_shipping_compares_in_the_cart_currency() {
+    // Free from 50 in the base currency, 75 at 1.5 units per unit
+    let flat = table(ShippingMethodKind::Flat, "4.90", Some("50"), &[]);
+    let parcel = Parcel { items: Vec::new() };
+    assert_eq!(price(&flat, &cart("74.99", "USD", "1.5"), &parcel).unwrap(), "7.35");
+    assert_eq!(price(&flat, &cart("75", "USD", "1.5"), &parcel).unwrap(), "0.00");
+    // Price brackets are converted the same way
+    let brackets = table(ShippingMethodKind::Price, "0", None, &[("0", "6"), ("40", "3")]);
+    assert_eq!(price(&brackets, &cart("59.99", "USD", "1.5"), &parcel).unwrap(), "9.00");
+    assert_eq!(price(&brackets, &cart("60", "USD", "1.5"), &parcel).unwrap(), "4.50");
+  }
+
+  #[test]
+  fn inactive_methods_and_other_countries_are_unavailable() {
+    let mut table = table(ShippingMethodKind::Flat, "4.90", None, &[]);
+    let (cart, parcel) = (cart("10", "EUR", "1"), Parcel { items: Vec::new() });
+    table.countries = vec!["fr".to_string()];
+    assert_eq!(table.quote(&cart, &parcel, " FR ").map(|quote| quote.price.to_string()).unwrap(), "4.90");
+    assert_eq!(price(&table, &cart, &parcel), Err(Unavailable::Country));
+    table.method.active = false;
+    assert_eq!(table.quote(&cart, &parcel, "FR").err(), Some(Unavailable::Inactive));
+  }
+}
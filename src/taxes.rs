@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{PgConnection, QueryResult};
+use rocket::fairing::AdHoc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::models::TaxRate;
+use crate::money::{allocate, Currency};
+use crate::repository::TaxRateRepository;
+
+// Items get this class unless told otherwise, it is created by the migration and cannot be renamed away
+pub const STANDARD_TAX_CLASS: &str = "standard";
+
+// Whether `items.price` already contains the tax. Set store-wide with `price_mode`.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceMode {
+  // Prices are net, tax is added on top (e.g. US sales tax)
+  Exclusive,
+  // Prices are what customers pay, tax is part of them (e.g. EU VAT)
+  Inclusive,
+}
+
+// Manages the configured `PriceMode`, exclusive by default
+pub fn fairing() -> AdHoc {
+  AdHoc::try_on_ignite("Price mode", |rocket| async {
+    let figment = rocket.figment();
+    let mode = match figment.find_value("price_mode") {
+      Ok(_) => figment.extract_inner::<PriceMode>("price_mode"),
+      Err(_) => Ok(PriceMode::Exclusive),
+    };
+    match mode {
+      Ok(mode) => Ok(rocket.manage(mode)),
+      Err(e) => {
+        log::error!("Invalid price_mode, expected `exclusive` or `inclusive`: {}", e);
+        Err(rocket)
+      }
+    }
+  })
+}
+
+// An amount to tax, in the configured `PriceMode`. Shipping has no item.
+pub struct TaxableLine {
+  pub item_id: Option<i32>,
+  pub tax_class: String,
+  pub amount: BigDecimal,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct LineTax {
+  pub item_id: Option<i32>,
+  pub tax_class: String,
+  #[schemars(with = "String")]
+  pub rate: BigDecimal,
+  #[schemars(with = "String")]
+  pub net: BigDecimal,
+  #[schemars(with = "String")]
+  pub tax: BigDecimal,
+  #[schemars(with = "String")]
+  pub gross: BigDecimal,
+}
+
+// Totals of the lines taxed at the same rate, as printed on invoices
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct RateTax {
+  pub tax_class: String,
+  // None for classes without a rate at the address, which are not taxed
+  pub name: Option<String>,
+  #[schemars(with = "String")]
+  pub rate: BigDecimal,
+  #[schemars(with = "String")]
+  pub net: BigDecimal,
+  #[schemars(with = "String")]
+  pub tax: BigDecimal,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TaxBreakdown {
+  pub mode: PriceMode,
+  pub currency: Currency,
+  pub lines: Vec<LineTax>,
+  pub rates: Vec<RateTax>,
+  #[schemars(with = "String")]
+  pub net: BigDecimal,
+  #[schemars(with = "String")]
+  pub tax: BigDecimal,
+  #[schemars(with = "String")]
+  pub gross: BigDecimal,
+}
+
+// Taxes amounts at the rates of one address. The tax of each rate is rounded once, on the total of its
+// lines, then spread over the lines to the minor unit: lines add up to the rate totals, which add up to the order.
+pub struct TaxCalculator {
+  mode: PriceMode,
+  // By tax class
+  rates: HashMap<String, TaxRate>,
+}
+
+impl TaxCalculator {
+  // `rates` may hold country-wide and regional rates of a class, the regional one wins
+  pub fn new(mode: PriceMode, rates: Vec<TaxRate>) -> Self {
+    let mut by_class: HashMap<String, TaxRate> = HashMap::new();
+    for rate in rates {
+      let replace = by_class.get(&rate.tax_class).is_none_or(|other| other.region.is_empty());
+      if replace {
+        by_class.insert(rate.tax_class.clone(), rate);
+      }
+    }
+    TaxCalculator { mode, rates: by_class }
+  }
+
+  pub fn for_address(c: &mut PgConnection, mode: PriceMode, country: &str, region: Option<&str>) -> QueryResult<Self> {
+    Ok(Self::new(mode, TaxRateRepository::find_for_address(c, country, region)?))
+  }
+
+  // Amounts are in `currency` and rounded to its minor unit
+  pub fn calculate(&self, lines: &[TaxableLine], currency: &Currency) -> TaxBreakdown {
+    let scale = currency.minor_units();
+    let hundred = BigDecimal::from(100);
+    let rate_of = |tax_class: &str| self.rates.get(tax_class).map(|rate| rate.rate.clone()).unwrap_or_default();
+    let exact: Vec<BigDecimal> = lines.iter()
+      .map(|line| {
+        let rate = rate_of(&line.tax_class);
+        match self.mode {
+          PriceMode::Exclusive => &line.amount * &rate / &hundred,
+          PriceMode::Inclusive => &line.amount * &rate / (&hundred + &rate),
+        }
+      })
+      .collect();
+
+    // Classes in order of first appearance, so that the breakdown follows the cart
+    let mut classes: Vec<&str> = Vec::new();
+    for line in lines {
+      if !classes.contains(&line.tax_class.as_str()) {
+        classes.push(&line.tax_class);
+      }
+    }
+
+    let mut taxes = vec![BigDecimal::zero(); lines.len()];
+    let mut rates = Vec::with_capacity(classes.len());
+    for tax_class in classes {
+      let indexes: Vec<usize> = (0..lines.len()).filter(|i| lines[*i].tax_class == tax_class).collect();
+      let class_exact: Vec<BigDecimal> = indexes.iter().map(|i| exact[*i].clone()).collect();
+      let tax = currency.round(&class_exact.iter().sum());
+      for (i, line_tax) in indexes.iter().zip(allocate(&tax, &class_exact, scale)) {
+        taxes[*i] = line_tax;
+      }
+
+      let amount: BigDecimal = indexes.iter().map(|i| &lines[*i].amount).sum();
+      rates.push(RateTax {
+        tax_class: tax_class.to_string(),
+        name: self.rates.get(tax_class).map(|rate| rate.name.clone()),
+        rate: rate_of(tax_class),
+        net: match self.mode {
+          PriceMode::Exclusive => amount,
+          PriceMode::Inclusive => amount - &tax,
+        }.with_scale(scale),
+        tax: tax.with_scale(scale),
+      });
+    }
+
+    let lines: Vec<LineTax> = lines.iter()
+      .zip(taxes)
+      .map(|(line, tax)| {
+        let (net, gross) = match self.mode {
+          PriceMode::Exclusive => (line.amount.clone(), &line.amount + &tax),
+          PriceMode::Inclusive => (&line.amount - &tax, line.amount.clone()),
+        };
+        LineTax {
+          item_id: line.item_id,
+          tax_class: line.tax_class.clone(),
+          rate: rate_of(&line.tax_class),
+          net: net.with_scale(scale),
+          tax: tax.with_scale(scale),
+          gross: gross.with_scale(scale),
+        }
+      })
+      .collect();
+
+    TaxBreakdown {
+      mode: self.mode,
+      currency: currency.clone(),
+      net: lines.iter().map(|line| &line.net).sum(),
+      tax: lines.iter().map(|line| &line.tax).sum(),
+      gross: lines.iter().map(|line| &line.gross).sum(),
+      lines,
+      rates,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDateTime;
+
+  use super::*;
+
+  fn decimal(value: &str) -> BigDecimal {
+    value.parse().unwrap()
+  }
+
+  fn rate(tax_class: &str, region: &str, rate: &str) -> TaxRate {
+    TaxRate {
+      id: 0,
+      tax_class: tax_class.to_string(),
+      country: "US".to_string(),
+      region: region.to_string(),
+      name: format!("{} {}", tax_class, region),
+      rate: decimal(rate),
+      created_at: NaiveDateTime::default(),
+    }
+  }
+
+  fn line(item_id: i32, tax_class: &str, amount: &str) -> TaxableLine {
+    TaxableLine { item_id: Some(item_id), tax_class: tax_class.to_string(), amount: decimal(amount) }
+  }
+
+  fn euro() -> Currency {
+    "EUR".parse().unwrap()
+  }
+
+  fn assert_adds_up(breakdown: &TaxBreakdown) {
+    for line in &breakdown.lines {
+      assert_eq!(&line.net + &line.tax, line.gross, "line of item {:?}", line.item_id);
+    }
+    assert_eq!(&breakdown.net + &breakdown.tax, breakdown.gross);
+    assert_eq!(breakdown.rates.iter().map(|rate| &rate.tax).sum::<BigDecimal>(), breakdown.tax);
+    assert_eq!(breakdown.rates.iter().map(|rate| &rate.net).sum::<BigDecimal>(), breakdown.net);
+  }
+
+  #[test]
+  fn inclusive_prices_split_into_net_and_tax() {
+    let calculator = TaxCalculator::new(PriceMode::Inclusive, vec![rate("standard", "", "19"), rate("reduced", "", "7")]);
+    let lines = [line(1, "standard", "9.99"), line(2, "standard", "19.99"), line(3, "reduced", "0.01"), line(4, "reduced", "4.35")];
+    let breakdown = calculator.calculate(&lines, &euro());
+
+    assert_adds_up(&breakdown);
+    for (line, taxed) in lines.iter().zip(&breakdown.lines) {
+      assert_eq!(taxed.gross, line.amount);
+    }
+    // 29.98 * 19 / 119 = 4.7866, 4.36 * 7 / 107 = 0.2852
+    assert_eq!(breakdown.rates[0].tax, decimal("4.79"));
+    assert_eq!(breakdown.rates[1].tax, decimal("0.29"));
+    assert_eq!(breakdown.gross, decimal("34.34"));
+  }
+
+  #[test]
+  fn exclusive_prices_get_tax_added() {
+    let calculator = TaxCalculator::new(PriceMode::Exclusive, vec![rate("standard", "", "10")]);
+    let lines = [line(1, "standard", "0.05"), line(2, "standard", "0.05"), line(3, "standard", "0.05")];
+    let breakdown = calculator.calculate(&lines, &euro());
+
+    assert_adds_up(&breakdown);
+    // Rounded once on the class total, 0.015, not per line
+    assert_eq!(breakdown.tax, decimal("0.02"));
+    assert_eq!(breakdown.lines.iter().map(|line| line.tax.to_string()).collect::<Vec<_>>(), ["0.01", "0.01", "0.00"]);
+    assert_eq!(breakdown.net, decimal("0.15"));
+  }
+
+  #[test]
+  fn classes_without_a_rate_are_not_taxed() {
+    let calculator = TaxCalculator::new(PriceMode::Exclusive, vec![rate("standard", "", "10")]);
+    let breakdown = calculator.calculate(&[line(1, "books", "12.00")], &euro());
+
+    assert_adds_up(&breakdown);
+    assert_eq!(breakdown.tax, decimal("0"));
+    assert_eq!(breakdown.rates[0].name, None);
+  }
+
+  #[test]
+  fn regional_rates_win_over_country_rates() {
+    for rates in [
+      vec![rate("standard", "", "5"), rate("standard", "CA", "7.25")],
+      vec![rate("standard", "CA", "7.25"), rate("standard", "", "5")],
+    ] {
+      let calculator = TaxCalculator::new(PriceMode::Exclusive, rates);
+      let breakdown = calculator.calculate(&[line(1, "standard", "100.00")], &euro());
+      assert_eq!(breakdown.lines[0].rate, decimal("7.25"));
+      assert_eq!(breakdown.tax, decimal("7.25"));
+      assert_eq!(breakdown.rates[0].name.as_deref(), Some("standard CA"));
+    }
+  }
+}
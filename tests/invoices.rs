@@ -0,0 +1,100 @@
+// Invoices issued in a transaction that is rolled back. Needs a migrated postgres in `TEST_DATABASE_URL`,
+// skipped when it is not set.
+use std::env;
+
+use bigdecimal::BigDecimal;
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+
+use diesel_eshop_db::addresses::AddressSnapshot;
+use diesel_eshop_db::invoices::{InvoiceDocument, InvoiceLine, Issuer};
+use diesel_eshop_db::repository::{InvoiceError, InvoiceRepository};
+use diesel_eshop_db::storage::{ImageStore, LocalImageStore};
+use diesel_eshop_db::taxes::PriceMode;
+
+fn connection() -> Option<PgConnection> {
+  let database_url = env::var("TEST_DATABASE_URL").ok()?;
+  let mut c = PgConnection::establish(&database_url).expect("the test database accepts connections");
+  c.begin_test_transaction().expect("a test transaction");
+  Some(c)
+}
+
+macro_rules! connection_or_skip {
+  () => {
+    match connection() {
+      Some(c) => c,
+      None => return eprintln!("TEST_DATABASE_URL is not set, skipping"),
+    }
+  };
+}
+
+fn store() -> LocalImageStore {
+  LocalImageStore::new(env::temp_dir().join("eshop-test-invoices"), "").expect("a local store")
+}
+
+fn document(order_reference: &str, name: &str) -> InvoiceDocument {
+  let amount = |value: i32| BigDecimal::from(value);
+  InvoiceDocument {
+    number: String::new(),
+    issued_on: NaiveDate::default(),
+    order_reference: order_reference.to_string(),
+    issuer: Issuer::default(),
+    billing: AddressSnapshot {
+      name: name.to_string(),
+      line1: "1 Main Street".to_string(),
+      line2: None,
+      city: "Springfield".to_string(),
+      region: None,
+      postal_code: None,
+      country: "US".to_string(),
+      phone: None,
+    },
+    currency: "USD".parse().unwrap(),
+    mode: PriceMode::Exclusive,
+    lines: vec![InvoiceLine {
+      item_id: None,
+      description: "Shipping".to_string(),
+      quantity: 1,
+      unit_price: amount(5),
+      discount: amount(0),
+      net: amount(5),
+      tax_rate: amount(0),
+      tax: amount(0),
+      gross: amount(5),
+    }],
+    rates: Vec::new(),
+    net: amount(5),
+    tax: amount(0),
+    gross: amount(5),
+  }
+}
+
+#[test]
+fn numbers_follow_each_other_when_an_invoice_fails() {
+  let mut c = connection_or_skip!();
+  let store = store();
+  let first = InvoiceRepository::issue(&mut c, &store, None, document("numbering-test-1", "Ada")).unwrap();
+  // The order has its invoice already, the number this one took is given back
+  let failed = InvoiceRepository::issue(&mut c, &store, None, document("numbering-test-1", "Ada"));
+  assert!(matches!(failed, Err(InvoiceError::Database(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)))));
+  let second = InvoiceRepository::issue(&mut c, &store, None, document("numbering-test-2", "Ada")).unwrap();
+
+  assert_eq!(second.year, first.year);
+  assert_eq!(second.sequence, first.sequence + 1);
+  assert_eq!(second.number, format!("{}-{:06}", second.year, second.sequence));
+}
+
+#[test]
+fn keeps_the_documents_of_any_script() {
+  let mut c = connection_or_skip!();
+  let store = store();
+  let invoice = InvoiceRepository::issue(&mut c, &store, None, document("numbering-test-3", "Иван Петров 東京")).unwrap();
+
+  let html = String::from_utf8(store.get(&invoice.html_key).unwrap()).unwrap();
+  assert!(html.contains("Иван Петров 東京"));
+  assert!(store.get(&invoice.pdf_key).unwrap().starts_with(b"%PDF"));
+  let kept = InvoiceDocument::of(&invoice).expect("the invoice keeps its document");
+  assert_eq!(kept.number, invoice.number);
+  assert_eq!(kept.billing.name, "Иван Петров 東京");
+}
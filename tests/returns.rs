@@ -0,0 +1,109 @@
+// Returns checked against what the order's invoice billed, in a transaction that is rolled back. Needs a
+// migrated postgres in `TEST_DATABASE_URL`, skipped when it is not set.
+use std::env;
+
+use bigdecimal::BigDecimal;
+use chrono::Utc;
+use diesel::prelude::*;
+
+use diesel_eshop_db::models::{NewInvoice, NewInvoiceItem, NewReturn, NewReturnLine, ReturnReason};
+use diesel_eshop_db::money::Money;
+use diesel_eshop_db::repository::{ReturnError, ReturnRepository};
+use diesel_eshop_db::schema::{invoice_items, invoices, items, users};
+
+fn connection() -> Option<PgConnection> {
+  let database_url = env::var("TEST_DATABASE_URL").ok()?;
+  let mut c = PgConnection::establish(&database_url).expect("the test database accepts connections");
+  c.begin_test_transaction().expect("a test transaction");
+  Some(c)
+}
+
+macro_rules! connection_or_skip {
+  () => {
+    match connection() {
+      Some(c) => c,
+      None => return eprintln!("TEST_DATABASE_URL is not set, skipping"),
+    }
+  };
+}
+
+// A customer with an invoice for `R-1` billing `quantity` of one item, given back as (user id, item id)
+fn invoiced(c: &mut PgConnection, quantity: Option<i32>) -> (i32, i32) {
+  let user_id: i32 = diesel::insert_into(users::table)
+    .values((users::username.eq("returns-test"), users::password.eq("-"), users::email.eq("returns-test@example.com")))
+    .returning(users::id)
+    .get_result(c)
+    .unwrap();
+  let item_id: i32 = diesel::insert_into(items::table)
+    .values((items::name.eq("Returns test"), items::slug.eq("returns-test"), items::price.eq(BigDecimal::from(10)), items::quantity.eq(0)))
+    .returning(items::id)
+    .get_result(c)
+    .unwrap();
+  let total = Money::new(&BigDecimal::from(10), &"USD".parse().unwrap());
+  let invoice_id: i32 = diesel::insert_into(invoices::table)
+    .values(NewInvoice {
+      number: "1999-000001".to_string(),
+      year: 1999,
+      sequence: 1,
+      user_id: Some(user_id),
+      order_reference: "R-1".to_string(),
+      tax: Money::new(&BigDecimal::from(0), &total.currency),
+      total,
+      pdf_key: "returns-test.pdf".to_string(),
+      html_key: "returns-test.html".to_string(),
+      issued_at: Utc::now().naive_utc(),
+      document: serde_json::Value::Null,
+    })
+    .returning(invoices::id)
+    .get_result(c)
+    .unwrap();
+  match quantity {
+    Some(quantity) => diesel::insert_into(invoice_items::table)
+      .values(NewInvoiceItem { invoice_id, item_id, quantity })
+      .execute(c),
+    None => diesel::insert_into(invoice_items::table)
+      .values((invoice_items::invoice_id.eq(invoice_id), invoice_items::item_id.eq(item_id)))
+      .execute(c),
+  }.unwrap();
+  (user_id, item_id)
+}
+
+fn request(c: &mut PgConnection, user_id: i32, item_id: i32, quantity: i32) -> Result<i32, ReturnError> {
+  let new_return = NewReturn { user_id: Some(user_id), order_reference: "R-1".to_string() };
+  let line = NewReturnLine { return_id: 0, item_id: Some(item_id), quantity, reason: ReturnReason::Damaged, comment: None };
+  ReturnRepository::create(c, new_return, vec![line]).map(|created| created.id)
+}
+
+#[test]
+fn refuses_more_than_was_invoiced() {
+  let mut c = connection_or_skip!();
+  let (user_id, item_id) = invoiced(&mut c, Some(1));
+  assert!(matches!(request(&mut c, user_id, item_id, 2), Err(ReturnError::ExceedsOrdered(id, 1)) if id == item_id));
+  assert!(request(&mut c, user_id, item_id, 1).is_ok());
+}
+
+#[test]
+fn counts_earlier_returns_of_the_order() {
+  let mut c = connection_or_skip!();
+  let (user_id, item_id) = invoiced(&mut c, Some(3));
+  request(&mut c, user_id, item_id, 2).unwrap();
+  assert!(matches!(request(&mut c, user_id, item_id, 2), Err(ReturnError::ExceedsOrdered(_, 1))));
+  request(&mut c, user_id, item_id, 1).unwrap();
+  assert!(matches!(request(&mut c, user_id, item_id, 1), Err(ReturnError::ExceedsOrdered(_, 0))));
+}
+
+#[test]
+fn rejected_returns_free_their_quantity() {
+  let mut c = connection_or_skip!();
+  let (user_id, item_id) = invoiced(&mut c, Some(1));
+  let first = request(&mut c, user_id, item_id, 1).unwrap();
+  ReturnRepository::reject(&mut c, first, None).unwrap();
+  assert!(request(&mut c, user_id, item_id, 1).is_ok());
+}
+
+#[test]
+fn refuses_items_without_an_invoiced_quantity() {
+  let mut c = connection_or_skip!();
+  let (user_id, item_id) = invoiced(&mut c, None);
+  assert!(matches!(request(&mut c, user_id, item_id, 1), Err(ReturnError::ExceedsOrdered(_, 0))));
+}
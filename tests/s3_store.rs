@@ -0,0 +1,48 @@
+// Round trip through an S3-compatible server, e.g. MinIO:
+//
+//   docker run -p 9000:9000 minio/minio server /data
+//   S3_TEST_ENDPOINT=http://localhost:9000 S3_TEST_BUCKET=eshop-test \
+//   S3_TEST_ACCESS_KEY_ID=minioadmin S3_TEST_SECRET_ACCESS_KEY=minioadmin cargo test --test s3_store
+//
+// The bucket must exist. Skipped when `S3_TEST_ENDPOINT` is not set.
+use std::env;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+
+use diesel_eshop_db::storage::{generate_key, ImageStore, S3ImageStore, StoreError};
+
+fn test_store() -> Option<S3ImageStore> {
+  let endpoint = env::var("S3_TEST_ENDPOINT").ok()?;
+  let variable = |name: &str| env::var(name).unwrap_or_else(|_| panic!("{} must be set along with S3_TEST_ENDPOINT", name));
+  let store = S3ImageStore::new(
+    &endpoint,
+    &variable("S3_TEST_BUCKET"),
+    &env::var("S3_TEST_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+    &variable("S3_TEST_ACCESS_KEY_ID"),
+    &variable("S3_TEST_SECRET_ACCESS_KEY"),
+    None,
+  ).unwrap();
+  Some(store.with_client(Client::builder().timeout(Duration::from_secs(10)).build().unwrap()))
+}
+
+#[test]
+fn put_get_list_delete() {
+  let Some(store) = test_store() else {
+    eprintln!("S3_TEST_ENDPOINT is not set, skipping");
+    return;
+  };
+  // Characters SigV4 must encode, in the path and in the listing
+  let key = generate_key("red shoe+1 (ß).png");
+  let bytes = b"\x89PNG not really".to_vec();
+
+  store.put(&key, &bytes, "image/png").unwrap();
+  assert_eq!(store.get(&key).unwrap(), bytes);
+  let listed = store.list().unwrap();
+  let object = listed.iter().find(|object| object.key == key).expect("the stored key is listed");
+  assert!(object.last_modified.is_some());
+
+  store.delete(&key).unwrap();
+  assert!(matches!(store.get(&key), Err(StoreError::NotFound(_))));
+  assert!(!store.list().unwrap().iter().any(|object| object.key == key));
+}
@@ -0,0 +1,140 @@
+// Scheduled prices started and ended in a transaction that is rolled back. Needs a migrated postgres in
+// `TEST_DATABASE_URL`, skipped when it is not set. Schedules are in 1990, before any real one is due.
+use std::env;
+
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+
+use diesel_eshop_db::models::{NewScheduledPrice, PriceChangeSource, ScheduledPrice};
+use diesel_eshop_db::repository::{ItemRepository, PriceHistoryRepository, ScheduleError, ScheduledPriceRepository};
+use diesel_eshop_db::schema::items;
+
+fn connection() -> Option<PgConnection> {
+  let database_url = env::var("TEST_DATABASE_URL").ok()?;
+  let mut c = PgConnection::establish(&database_url).expect("the test database accepts connections");
+  c.begin_test_transaction().expect("a test transaction");
+  Some(c)
+}
+
+macro_rules! connection_or_skip {
+  () => {
+    match connection() {
+      Some(c) => c,
+      None => return eprintln!("TEST_DATABASE_URL is not set, skipping"),
+    }
+  };
+}
+
+fn decimal(value: &str) -> BigDecimal {
+  value.parse().unwrap()
+}
+
+fn day(day: u32) -> NaiveDateTime {
+  NaiveDate::from_ymd_opt(1990, 1, day).unwrap().and_hms_opt(0, 0, 0).unwrap()
+}
+
+fn item(c: &mut PgConnection, slug: &str, price: &str) -> i32 {
+  diesel::insert_into(items::table)
+    .values((items::name.eq(slug), items::slug.eq(slug), items::price.eq(decimal(price)), items::quantity.eq(0)))
+    .returning(items::id)
+    .get_result(c)
+    .unwrap()
+}
+
+fn schedule(c: &mut PgConnection, item_id: i32, price: &str, starts_at: NaiveDateTime, ends_at: Option<NaiveDateTime>) -> Result<ScheduledPrice, ScheduleError> {
+  ScheduledPriceRepository::create(c, NewScheduledPrice { item_id, price: decimal(price), starts_at, ends_at })
+}
+
+fn price(c: &mut PgConnection, item_id: i32) -> (String, Option<String>) {
+  let item = ItemRepository::find(c, item_id).unwrap();
+  (item.price.to_string(), item.was_price.map(|was_price| was_price.to_string()))
+}
+
+#[test]
+fn schedules_of_an_item_do_not_overlap() {
+  let mut c = connection_or_skip!();
+  let item_id = item(&mut c, "schedule-test-overlap", "10.00");
+  let sale = schedule(&mut c, item_id, "8.00", day(10), Some(day(20))).unwrap();
+
+  assert!(matches!(schedule(&mut c, item_id, "7.00", day(19), Some(day(25))), Err(ScheduleError::Overlaps(id)) if id == sale.id));
+  assert!(matches!(schedule(&mut c, item_id, "7.00", day(15), None), Err(ScheduleError::Overlaps(_))));
+  assert!(matches!(schedule(&mut c, item_id, "7.00", day(10), None), Err(ScheduleError::Overlaps(_))));
+  // The end is excluded, a schedule may start when the sale ends
+  assert!(schedule(&mut c, item_id, "12.00", day(20), None).is_ok());
+  assert!(schedule(&mut c, item_id, "9.00", day(1), Some(day(10))).is_ok());
+  // Other items are not affected
+  let other_id = item(&mut c, "schedule-test-overlap-other", "10.00");
+  assert!(schedule(&mut c, other_id, "8.00", day(10), Some(day(20))).is_ok());
+}
+
+#[test]
+fn sales_start_and_end() {
+  let mut c = connection_or_skip!();
+  let item_id = item(&mut c, "schedule-test-sale", "10.00");
+  schedule(&mut c, item_id, "8.00", day(10), Some(day(20))).unwrap();
+
+  assert_eq!(ScheduledPriceRepository::apply_due(&mut c, day(9)).unwrap(), (0, 0));
+  assert_eq!(ScheduledPriceRepository::apply_due(&mut c, day(10)).unwrap(), (1, 0));
+  assert_eq!(price(&mut c, item_id), ("8.00".to_string(), Some("10.00".to_string())));
+  assert_eq!(ScheduledPriceRepository::apply_due(&mut c, day(20)).unwrap(), (0, 1));
+  assert_eq!(price(&mut c, item_id), ("10.00".to_string(), None));
+
+  let history = PriceHistoryRepository::find_by_item(&mut c, item_id).unwrap();
+  let sources: Vec<&str> = history.iter().map(|change| change.source.as_str()).collect();
+  assert_eq!(sources, [PriceChangeSource::Scheduled.as_str(), PriceChangeSource::SaleEnded.as_str()]);
+}
+
+#[test]
+fn schedules_without_end_change_the_price_for_good() {
+  let mut c = connection_or_skip!();
+  let item_id = item(&mut c, "schedule-test-change", "10.00");
+  let change = schedule(&mut c, item_id, "12.00", day(10), None).unwrap();
+
+  assert_eq!(ScheduledPriceRepository::apply_due(&mut c, day(11)).unwrap(), (1, 0));
+  assert_eq!(price(&mut c, item_id), ("12.00".to_string(), None));
+  let change = ScheduledPriceRepository::find_by_item(&mut c, item_id).unwrap().into_iter().find(|other| other.id == change.id).unwrap();
+  assert_eq!(change.previous_price, Some(decimal("10.00")));
+  assert_eq!(change.ended_at, Some(day(11)));
+}
+
+#[test]
+fn prices_set_by_hand_during_a_sale_are_kept() {
+  let mut c = connection_or_skip!();
+  let item_id = item(&mut c, "schedule-test-by-hand", "10.00");
+  schedule(&mut c, item_id, "8.00", day(10), Some(day(20))).unwrap();
+  ScheduledPriceRepository::apply_due(&mut c, day(10)).unwrap();
+
+  ItemRepository::set_price(&mut c, item_id, decimal("7.50"), Some(decimal("10.00")), PriceChangeSource::Updated).unwrap();
+  assert_eq!(ScheduledPriceRepository::apply_due(&mut c, day(20)).unwrap(), (0, 1));
+  assert_eq!(price(&mut c, item_id), ("7.50".to_string(), None));
+}
+
+#[test]
+fn sales_missed_entirely_leave_the_price_alone() {
+  let mut c = connection_or_skip!();
+  let item_id = item(&mut c, "schedule-test-missed", "10.00");
+  let sale = schedule(&mut c, item_id, "8.00", day(10), Some(day(20))).unwrap();
+
+  assert_eq!(ScheduledPriceRepository::apply_due(&mut c, day(21)).unwrap(), (1, 0));
+  assert_eq!(price(&mut c, item_id), ("10.00".to_string(), None));
+  let sale = ScheduledPriceRepository::find_by_item(&mut c, item_id).unwrap().into_iter().find(|other| other.id == sale.id).unwrap();
+  assert_eq!((sale.started_at, sale.ended_at), (Some(day(21)), Some(day(21))));
+}
+
+#[test]
+fn a_failing_schedule_does_not_hold_back_the_others() {
+  let mut c = connection_or_skip!();
+  // Makes starting a schedule at 13.13 fail; the constraint goes away with the transaction
+  diesel::sql_query("ALTER TABLE scheduled_prices ADD CONSTRAINT schedule_test_failure CHECK (started_at IS NULL OR price <> 13.13)")
+    .execute(&mut c)
+    .unwrap();
+  let failing_id = item(&mut c, "schedule-test-failing", "10.00");
+  let working_id = item(&mut c, "schedule-test-working", "10.00");
+  schedule(&mut c, failing_id, "13.13", day(10), None).unwrap();
+  schedule(&mut c, working_id, "12.00", day(11), None).unwrap();
+
+  assert_eq!(ScheduledPriceRepository::apply_due(&mut c, day(12)).unwrap(), (1, 0));
+  assert_eq!(price(&mut c, failing_id), ("10.00".to_string(), None));
+  assert_eq!(price(&mut c, working_id), ("12.00".to_string(), None));
+}
@@ -0,0 +1,100 @@
+// Requests against the server as `build_rocket` assembles it. Igniting connects to postgres, given by
+// `TEST_DATABASE_URL` (migrated, e.g. with `diesel migration run`), and redis, `TEST_REDIS_URL` or the
+// local default. Skipped when `TEST_DATABASE_URL` is not set.
+use std::env;
+
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json::Value;
+
+use diesel_eshop_db::rocket_routes::build_rocket;
+
+fn client() -> Option<Client> {
+  let database_url = env::var("TEST_DATABASE_URL").ok()?;
+  let redis_url = env::var("TEST_REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+  let images = env::temp_dir().join("eshop-test-images");
+  let invoices = env::temp_dir().join("eshop-test-invoices");
+  let figment = Figment::from(rocket::Config::figment())
+    .merge(("databases.postgres.url", database_url))
+    .merge(("databases.redis.url", redis_url))
+    .merge(("image_store", rocket::figment::util::map! {
+      "backend" => "local".to_string(),
+      "root" => images.to_string_lossy().into_owned(),
+      "public_url" => "/media".to_string(),
+    }))
+    .merge(("invoice_store", rocket::figment::util::map! {
+      "backend" => "local".to_string(),
+      "root" => invoices.to_string_lossy().into_owned(),
+    }))
+    .merge(("image_gc_interval", 0));
+  Some(Client::tracked(build_rocket(figment)).expect("the server ignites"))
+}
+
+macro_rules! client_or_skip {
+  () => {
+    match client() {
+      Some(client) => client,
+      None => return eprintln!("TEST_DATABASE_URL is not set, skipping"),
+    }
+  };
+}
+
+fn json_body(response: rocket::local::blocking::LocalResponse) -> Value {
+  assert_eq!(response.content_type(), Some(ContentType::JSON));
+  response.into_json().expect("a JSON body")
+}
+
+#[test]
+fn mounts_every_route_group() {
+  let client = client_or_skip!();
+  let routes: Vec<String> = client.rocket().routes().map(|route| format!("{} {}", route.method, route.uri)).collect();
+  for expected in [
+    "GET /items?<currency>",
+    "POST /items",
+    "GET /items/<id>?<currency>",
+    "GET /items/by-slug/<slug>?<currency>",
+    "POST /images/new/<item_id>",
+    "POST /cart/totals",
+    "POST /returns",
+    "GET /invoices/<id>?<format>",
+    "GET /items/<id>/reviews?<page>&<per_page>",
+    "GET /wishlists/shared/<token>",
+    "GET /openapi.json",
+  ] {
+    assert!(routes.iter().any(|route| route == expected), "{} is not mounted", expected);
+  }
+  let catchers: Vec<Option<u16>> = client.rocket().catchers().map(|catcher| catcher.code).collect();
+  for code in [400, 401, 403, 404, 409, 413, 422, 429, 500, 503] {
+    assert!(catchers.contains(&Some(code)), "no catcher for {}", code);
+  }
+  assert!(catchers.contains(&None), "no default catcher");
+}
+
+#[test]
+fn missing_session_answers_401_with_its_reason() {
+  let client = client_or_skip!();
+  let body = json_body(client.get("/wishlists").dispatch());
+  assert_eq!(body["reason"], "MissingHeader");
+  assert_eq!(body["error"], "Missing Authorization header");
+
+  let response = client.get("/wishlists").header(Header::new("Authorization", "Token abc")).dispatch();
+  assert_eq!(response.status(), Status::Unauthorized);
+  assert_eq!(json_body(response)["reason"], "MalformedToken");
+}
+
+#[test]
+fn unknown_paths_answer_404_json() {
+  let client = client_or_skip!();
+  let response = client.get("/no/such/route").dispatch();
+  assert_eq!(response.status(), Status::NotFound);
+  assert_eq!(json_body(response)["error"], "The requested resource could not be found");
+}
+
+#[test]
+fn shared_wishlists_need_no_session() {
+  let client = client_or_skip!();
+  let response = client.get("/wishlists/shared/unknown-token").dispatch();
+  assert_eq!(response.status(), Status::NotFound);
+  assert_eq!(json_body(response)["error"], "Unknown or no longer shared wishlist");
+}